@@ -0,0 +1,22 @@
+//! records the git revision and compiler version for exporter_build_info
+#![allow(clippy::needless_return)]
+
+use std::{env, process::Command};
+
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let out = Command::new(program).args(args).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    return Some(String::from_utf8_lossy(&out.stdout).trim().to_string());
+}
+
+fn main() {
+    let git_sha = output("git", &["rev-parse", "--short=12", "HEAD"]).unwrap_or_else(|| String::from("unknown"));
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    let rustc_version = output(&rustc, &["--version"]).unwrap_or_else(|| String::from("unknown"));
+    println!("cargo:rustc-env=GIT_SHA={}", git_sha);
+    println!("cargo:rustc-env=RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
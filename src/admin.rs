@@ -0,0 +1,122 @@
+//! module for the admin HTTP API changing the sensor's settings at runtime
+//! `GET /api/v1/settings` reads them, `PUT /api/v1/settings` with a JSON object of the ones to change
+//! (temperature_offset, altitude, ambient_pressure, automatic_self_calibration) applies them in turn.
+//! `POST /api/v1/calibrate` with `{"target_ppm": 420}` runs a forced recalibration and returns the
+//! correction. the sensor should have been measuring in a steady reference atmosphere for a few minutes.
+//! `POST /api/v1/self-test` runs the scd4x's self-test, answering 409 if it found a malfunction.
+//! `POST /api/v1/burst` starts a burst capture (see --burst), or answers 409 with Retry-After while one is
+//! requested, running or cooling down.
+//! the routes always need credentials, see --admin-api. --admin-socket serves them without on a unix socket only
+//! the exporter's user can connect to, which the maintenance subcommands go through while the exporter runs.
+use crate::{
+    burst,
+    clock::Clock,
+    control::{self, Action},
+    http::{Request, Response},
+    json::{self, Value},
+};
+
+/// handle /api/v1/settings
+pub(crate) fn settings(request: &Request) -> Response {
+    match request.method.as_str() {
+        "GET" => {}
+        "PUT" | "POST" => {
+            if let Err((status, e)) = change(&request.body) {
+                return error(status, &e);
+            }
+        }
+        _ => return error(405, "use GET or PUT"),
+    }
+    return match control::settings() {
+        Some(settings) => Response::json(settings.to_json()),
+        None => error(503, "the sensor hasn't started yet"),
+    };
+}
+
+/// handle /api/v1/calibrate
+pub(crate) fn calibrate(request: &Request) -> Response {
+    if request.method != "POST" {
+        return error(405, "use POST");
+    }
+    let target = match std::str::from_utf8(&request.body).map_err(|e| e.to_string()).and_then(|b| json::parse(b).map_err(|e| e.to_string())) {
+        Ok(body) => match body.get("target_ppm").and_then(Value::as_f64) {
+            Some(t) if (400.0..=2000.0).contains(&t) => t.round() as u16,
+            _ => return error(400, "target_ppm must be a number between 400 and 2000"),
+        },
+        Err(e) => return error(400, &e),
+    };
+    log::info!("admin api: forced recalibration to {} ppm", target);
+    return match control::submit(Action::ForceRecalibration(target)) {
+        Ok(correction) => Response::json(format!(r#"{{"target_ppm":{},"correction_ppm":{}}}"#, target, correction)),
+        Err(e) => error(409, &e),
+    };
+}
+
+/// handle `/api/v1/self-test`, which takes the sensor 10 s
+pub(crate) fn self_test(request: &Request) -> Response {
+    if request.method != "POST" {
+        return error(405, "use POST");
+    }
+    log::info!("admin api: self-test");
+    return match control::submit(Action::SelfTest) {
+        Ok(_) => Response::json(r#"{"passed":true}"#),
+        Err(e) => error(409, &e),
+    };
+}
+
+/// handle `/api/v1/burst`, starting a burst capture at the next measurement
+pub(crate) fn burst(request: &Request, clock: &dyn Clock) -> Response {
+    if request.method != "POST" {
+        return error(405, "use POST");
+    }
+    return match burst::request(clock.instant()) {
+        None => error(404, "burst capture is disabled, see --burst"),
+        Some(Ok(duration)) => {
+            log::info!("admin api: requested a burst capture");
+            Response::new(202, "application/json", format!(r#"{{"duration_s":{}}}"#, duration.as_secs()))
+        }
+        Some(Err(wait)) => {
+            let retry = wait.as_secs_f64().ceil() as u64;
+            error(409, &format!("a burst is requested, running or cooling down, retry in {} s", retry)).with_header("Retry-After", &retry.to_string())
+        }
+    };
+}
+
+fn error(status: u16, message: &str) -> Response {
+    return Response::new(status, "application/json", format!(r#"{{"error":{}}}"#, json::quote(message)));
+}
+
+/// the actions requested by `body`, all validated before any is applied
+fn parse(body: &[u8]) -> Result<Vec<Action>, String> {
+    let body = std::str::from_utf8(body).map_err(|_| "the body isn't UTF-8")?;
+    let Value::Object(members) = json::parse(body).map_err(|e| e.to_string())? else {
+        return Err(String::from("expected a JSON object"));
+    };
+    let number = |key: &str, value: &Value, range: std::ops::RangeInclusive<f64>| match value.as_f64() {
+        Some(v) if range.contains(&v) => Ok(v),
+        _ => Err(format!("{} must be a number between {} and {}", key, range.start(), range.end())),
+    };
+    let mut actions = Vec::new();
+    for (key, value) in &members {
+        let action = match key.as_str() {
+            "temperature_offset" => Action::SetTemperatureOffset(number(key, value, 0.0..=20.0)? as f32),
+            "altitude" => Action::SetAltitude(number(key, value, 0.0..=3000.0)?.round() as u16),
+            "ambient_pressure" => Action::SetAmbientPressure(number(key, value, 70000.0..=120000.0)? as f32),
+            "automatic_self_calibration" => match value {
+                Value::Bool(enabled) => Action::SetAutomaticSelfCalibration(*enabled),
+                _ => return Err(format!("{} must be true or false", key)),
+            },
+            _ => return Err(format!("unknown setting {}", key)),
+        };
+        actions.push(action);
+    }
+    return Ok(actions);
+}
+
+fn change(body: &[u8]) -> Result<(), (u16, String)> {
+    for action in parse(body).map_err(|e| (400, e))? {
+        log::info!("admin api: {:?}", action);
+        control::submit(action).map_err(|e| (409, e))?;
+    }
+    return Ok(());
+}
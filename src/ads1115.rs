@@ -0,0 +1,101 @@
+//! module for reading analog electrochemical gas sensors (SO2, NOx, ...) through an ADS1115 ADC
+//! see https://www.ti.com/lit/ds/symlink/ads1115.pdf
+//! each plugin reads one single-ended input and maps its voltage to a concentration with a
+//! piecewise linear calibration curve.
+use std::{fmt, sync::Mutex, thread, time::Duration};
+
+use embedded_hal::i2c;
+
+use crate::plugin::{Plugin, Reading};
+
+pub(crate) const DEFAULT_ADDR: u8 = 0x48;
+
+/// full scale range (V) used when none is given
+pub(crate) const DEFAULT_RANGE: f32 = 4.096;
+
+const REG_CONVERSION: u8 = 0x00;
+const REG_CONFIG: u8 = 0x01;
+
+/// plugins of different channels share the converter, so a conversion must not be interleaved
+static CONVERSION: Mutex<()> = Mutex::new(());
+
+/// PGA setting for the smallest full scale range covering `range` volts
+fn pga(range: f32) -> (u16, f32) {
+    const RANGES: [(u16, f32); 6] = [(0b101, 0.256), (0b100, 0.512), (0b011, 1.024), (0b010, 2.048), (0b001, 4.096), (0b000, 6.144)];
+    return RANGES.into_iter().find(|(_, fs)| *fs >= range).unwrap_or(RANGES[5]);
+}
+
+/// single-shot conversion of input `channel` against GND, returns volts
+pub(crate) fn read_voltage<I: i2c::I2c>(i2c: &mut I, addr: u8, channel: u8, range: f32) -> Result<f32, I::Error> {
+    let _conversion = CONVERSION.lock().unwrap_or_else(|e| e.into_inner());
+    let (gain, full_scale) = pga(range);
+    // start, AINx vs GND, gain, single-shot, 128 SPS, comparator off
+    let config: u16 = 0x8000 | (0b100 | channel as u16) << 12 | gain << 9 | 0x0100 | 0b100 << 5 | 0x0003;
+    let [hi, lo] = config.to_be_bytes();
+    i2c.write(addr, &[REG_CONFIG, hi, lo])?;
+    // a conversion takes 1/128 s
+    thread::sleep(Duration::from_millis(10));
+
+    let mut buf = [0; 2];
+    i2c.write_read(addr, &[REG_CONVERSION], &mut buf)?;
+    return Ok(i16::from_be_bytes(buf) as f32 * full_scale / 32768_f32);
+}
+
+/// parse a calibration curve given as `volts:value` points separated by `;`
+pub(crate) fn parse_curve(s: &str) -> Result<Vec<(f64, f64)>, String> {
+    let mut points = Vec::new();
+    for point in s.split(';').filter(|p| !p.is_empty()) {
+        let (v, c) = point.split_once(':').ok_or_else(|| format!("{} is not volts:value", point))?;
+        let v: f64 = v.trim().parse().map_err(|e| format!("{}: {}", point, e))?;
+        let c: f64 = c.trim().parse().map_err(|e| format!("{}: {}", point, e))?;
+        points.push((v, c));
+    }
+    if points.len() < 2 {
+        return Err(String::from("a curve needs at least two points"));
+    }
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    return Ok(points);
+}
+
+/// value of `curve` at `volts`, extrapolating the outer segments
+fn interpolate(curve: &[(f64, f64)], volts: f64) -> f64 {
+    let i = curve.iter().position(|(v, _)| *v > volts).unwrap_or(curve.len()).clamp(1, curve.len() - 1);
+    let ((v0, c0), (v1, c1)) = (curve[i - 1], curve[i]);
+    if v1 == v0 {
+        return c0;
+    }
+    return c0 + (volts - v0) * (c1 - c0) / (v1 - v0);
+}
+
+/// ads1115 plugin
+pub(crate) struct Ads1115<I> {
+    i2c: I,
+    addr: u8,
+    channel: u8,
+    range: f32,
+    curve: Vec<(f64, f64)>,
+}
+
+impl<I> Ads1115<I> {
+    pub(crate) fn new(i2c: I, addr: u8, channel: u8, range: f32, curve: Vec<(f64, f64)>) -> Self {
+        return Ads1115 { i2c, addr, channel, range, curve };
+    }
+}
+
+impl<I: i2c::I2c + fmt::Debug + Send> Plugin for Ads1115<I> {
+    fn start(&mut self) -> Result<(), String> {
+        let volts = read_voltage(&mut self.i2c, self.addr, self.channel, self.range).map_err(|e| format!("{:?}", e))?;
+        log::info!("ads1115 at 0x{:02x} AIN{} reads {:.4} V", self.addr, self.channel, volts);
+        return Ok(());
+    }
+
+    fn read(&mut self) -> Result<Vec<Reading>, String> {
+        let volts = read_voltage(&mut self.i2c, self.addr, self.channel, self.range).map_err(|e| format!("{:?}", e))? as f64;
+        let mut readings = vec![("ads1115_voltage_volts", volts)];
+        if !self.curve.is_empty() {
+            // electrochemical cells don't report negative concentrations, readings below zero are noise
+            readings.push(("gas_concentration_ppm", interpolate(&self.curve, volts).max(0_f64)));
+        }
+        return Ok(readings);
+    }
+}
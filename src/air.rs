@@ -0,0 +1,105 @@
+//! module for a plain air quality score and ventilation advice, for those who don't read ppm
+//! the score goes from 100 to 0 as CO2 rises from the good to the poor end of --air-co2-bands, and drops
+//! 5 points per %RH outside --air-humidity-band. the worse of the two is the score. ventilating is
+//! recommended from --ventilate-above, from the good band on when CO2 rises faster than --ventilate-trend,
+//! or when it's too humid, and until CO2 is back in the good band and the humidity in its band.
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+/// how far back the CO2 trend looks
+const TREND_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// the trend needs samples spanning this much
+const TREND_MIN_SPAN: Duration = Duration::from_secs(60);
+/// score lost per %RH outside the humidity band
+const HUMIDITY_PENALTY: f64 = 5.0;
+
+/// parse a band given as `LOW,HIGH`
+pub(crate) fn parse_band<T: FromStr + PartialOrd>(s: &str) -> Result<(T, T), String>
+where
+    T::Err: std::fmt::Display,
+{
+    let (low, high) = s.split_once(',').ok_or_else(|| format!("{} is not LOW,HIGH", s))?;
+    let parse = |v: &str| v.trim().parse::<T>().map_err(|e| format!("{}: {}", v, e));
+    let (low, high) = (parse(low)?, parse(high)?);
+    if low >= high {
+        return Err(format!("{} isn't ascending", s));
+    }
+    return Ok((low, high));
+}
+
+pub(crate) struct Config {
+    /// CO2 scoring 100 and 0
+    pub(crate) co2_bands: (u16, u16),
+    /// relative humidity scoring 100 in between
+    pub(crate) humidity_band: (f32, f32),
+    pub(crate) ventilate_above: u16,
+    /// CO2 rise in ppm per minute recommending ventilation early
+    pub(crate) ventilate_trend: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Assessment {
+    pub(crate) score: f64,
+    pub(crate) ventilate: bool,
+    /// ppm per minute, None until the samples span enough time
+    pub(crate) trend: Option<f64>,
+}
+
+pub(crate) struct Advisor {
+    config: Config,
+    recent: VecDeque<(Instant, f64)>,
+    ventilate: bool,
+}
+
+impl Advisor {
+    pub(crate) fn new(config: Config) -> Self {
+        return Advisor { config, recent: VecDeque::new(), ventilate: false };
+    }
+
+    /// assess a sample taken at `now`
+    pub(crate) fn assess(&mut self, now: Instant, co2: u16, humidity: f32) -> Assessment {
+        self.recent.push_back((now, co2 as f64));
+        while self.recent.front().is_some_and(|&(t, _)| now - t > TREND_WINDOW) {
+            self.recent.pop_front();
+        }
+        let trend = self.trend();
+
+        let (good, poor) = self.config.co2_bands;
+        let co2_score = match co2 {
+            c if c <= good => 100.0,
+            c if c >= poor => 0.0,
+            c => 100.0 * (poor - c) as f64 / (poor - good) as f64,
+        };
+        let (low, high) = self.config.humidity_band;
+        let outside = (low - humidity).max(humidity - high).max(0.0) as f64;
+        let humidity_score = (100.0 - HUMIDITY_PENALTY * outside).max(0.0);
+
+        let humid = humidity > high;
+        let rising = co2 >= good && trend.is_some_and(|t| t >= self.config.ventilate_trend);
+        if co2 >= self.config.ventilate_above || rising || humid {
+            self.ventilate = true;
+        } else if co2 <= good && !humid {
+            self.ventilate = false;
+        }
+        return Assessment { score: co2_score.min(humidity_score), ventilate: self.ventilate, trend };
+    }
+
+    /// least squares slope of the recent CO2 in ppm per minute
+    fn trend(&self) -> Option<f64> {
+        let (&(first, _), &(last, _)) = (self.recent.front()?, self.recent.back()?);
+        if last - first < TREND_MIN_SPAN {
+            return None;
+        }
+        let n = self.recent.len() as f64;
+        let points = self.recent.iter().map(|&(t, c)| ((t - first).as_secs_f64() / 60.0, c));
+        let (sx, sy, sxx, sxy) = points.fold((0.0, 0.0, 0.0, 0.0), |(sx, sy, sxx, sxy), (x, y)| (sx + x, sy + y, sxx + x * x, sxy + x * y));
+        let denominator = n * sxx - sx * sx;
+        if denominator <= 0.0 {
+            return None;
+        }
+        return Some((n * sxy - sx * sy) / denominator);
+    }
+}
@@ -0,0 +1,503 @@
+//! module for threshold alerts posted to webhooks and chat services, for setups without Alertmanager
+//! an alert is `name=NAME,metric=SERIES,above=|below=THRESHOLD[,for=DURATION][,webhook=URL][,template=FILE]`,
+//! e.g. `name=stuffy,metric=scd41_co2_ppm,above=1200,for=5m,webhook=https://ntfy.sh/office`. it fires once the
+//! condition held for the whole `for` and resolves when it stops holding. each time the template is rendered
+//! with {alert}, {state} (firing or resolved), {metric}, {value}, {threshold}, {location} and {sparkline}
+//! and POSTed to the webhook. {value} and {threshold} become JSON numbers (null if not a number), the others
+//! are escaped to go between the quotes of a JSON string.
+//! `slack=URL` and `discord=URL` take incoming webhook urls, `telegram=URL` the bot's sendMessage url with
+//! the chat, `https://api.telegram.org/bot<TOKEN>/sendMessage?chat_id=<CHAT>`. they get a readable message
+//! with the value, a sparkline of the recent samples and the location label. `email=ADDRESS` mails it
+//! through --smtp-url.
+//! `when=SERIES>THRESHOLD[:RESOLVE]&...` gives any number of conditions, all of which must hold, e.g.
+//! `when=scd41_co2_ppm>1000&scd41_humidity_rh>70`. a firing alert resolves only once a condition passes its
+//! RESOLVE value (`resolve=` for the metric= condition), so it doesn't flap around the threshold. after
+//! resolving it doesn't fire again for `cooldown=DURATION`. `actuate=buzzer` and `actuate=relay` sound the
+//! buzzer or switch on the relay while the alert is firing, in addition to their own CO2 thresholds.
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::Clock,
+    events,
+    http::{self, Url},
+    json,
+    shutdown::Pending,
+    smtp,
+};
+
+/// notifications waiting to be sent; newer ones are dropped beyond this
+const QUEUE: usize = 100;
+
+/// samples the sparkline shows
+const SPARKLINE: usize = 16;
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+const DEFAULT_TEMPLATE: &str = r#"{"alert":"{alert}","state":"{state}","metric":"{metric}","value":{value},"threshold":{threshold}}"#;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Above,
+    Below,
+}
+
+/// where notifications go
+#[derive(Debug, Clone)]
+enum Notifier {
+    /// url and template
+    Webhook(Url, String),
+    Slack(Url),
+    Discord(Url),
+    Telegram(Url),
+    Email(String),
+}
+
+/// outputs switched while an alert fires
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Actuator {
+    Buzzer,
+    Relay,
+}
+
+#[derive(Debug, Clone)]
+struct Condition {
+    metric: String,
+    comparison: Comparison,
+    threshold: f64,
+    /// the threshold a firing alert must pass to resolve, the threshold itself if None
+    resolve: Option<f64>,
+}
+
+impl Condition {
+    /// whether `value` meets the condition, `firing` at the resolve threshold
+    fn holds(&self, value: f64, firing: bool) -> bool {
+        let threshold = if firing { self.resolve.unwrap_or(self.threshold) } else { self.threshold };
+        return match self.comparison {
+            Comparison::Above => value > threshold,
+            Comparison::Below => value < threshold,
+        };
+    }
+
+    /// `value` for the messages, with the threshold while `firing`
+    fn describe(&self, value: f64, firing: bool) -> String {
+        let comparison = match self.comparison {
+            Comparison::Above => "above",
+            Comparison::Below => "below",
+        };
+        return match firing {
+            true => format!("{} is {}, {} {}", self.metric, value, comparison, self.threshold),
+            false => format!("{} is back at {}", self.metric, value),
+        };
+    }
+}
+
+/// parse a condition given as `SERIES>THRESHOLD[:RESOLVE]` or with `<`
+fn parse_condition(s: &str) -> Result<Condition, String> {
+    let (i, comparison) = match (s.find('>'), s.find('<')) {
+        (Some(i), None) => (i, Comparison::Above),
+        (None, Some(i)) => (i, Comparison::Below),
+        _ => return Err(format!("{} is not SERIES>THRESHOLD or SERIES<THRESHOLD", s)),
+    };
+    let (metric, rest) = (s[..i].trim(), &s[i + 1..]);
+    let (threshold, resolve) = match rest.split_once(':') {
+        Some((t, r)) => (t, Some(r.trim().parse().map_err(|e| format!("{}: resolve: {}", s, e))?)),
+        None => (rest, None),
+    };
+    let threshold = threshold.trim().parse().map_err(|e| format!("{}: {}", s, e))?;
+    if metric.is_empty() {
+        return Err(format!("{} has no series", s));
+    }
+    return Ok(Condition { metric: metric.to_string(), comparison, threshold, resolve });
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Alert {
+    name: String,
+    /// all must hold
+    conditions: Vec<Condition>,
+    /// how long the conditions must hold before firing
+    duration: Duration,
+    /// how long after resolving it can't fire again
+    cooldown: Duration,
+    notifiers: Vec<Notifier>,
+    actuators: Vec<Actuator>,
+}
+
+/// parse an alert given as comma separated `key=value` pairs
+pub(crate) fn parse_alert(s: &str) -> Result<Alert, String> {
+    let (mut name, mut metric, mut condition, mut duration, mut webhook, mut template) = (None, None, None, Duration::ZERO, None, None);
+    let (mut conditions, mut resolve, mut cooldown, mut notifiers, mut actuators) = (Vec::new(), None, Duration::ZERO, Vec::new(), Vec::new());
+    let url = |key: &str, value: &str| http::parse_url(value).map_err(|e| format!("{}: {}", key, e));
+    for pair in s.split(',').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').ok_or_else(|| format!("{} is not key=value", pair))?;
+        let value = value.trim();
+        match key.trim() {
+            "name" => name = Some(value.to_string()),
+            "metric" => metric = Some(value.to_string()),
+            "above" => condition = Some((Comparison::Above, value.parse().map_err(|e| format!("above: {}", e))?)),
+            "below" => condition = Some((Comparison::Below, value.parse().map_err(|e| format!("below: {}", e))?)),
+            "resolve" => resolve = Some(value.parse().map_err(|e| format!("resolve: {}", e))?),
+            "when" => {
+                for c in value.split('&') {
+                    conditions.push(parse_condition(c)?);
+                }
+            }
+            "for" => duration = humantime::parse_duration(value).map_err(|e| format!("for: {}", e))?,
+            "cooldown" => cooldown = humantime::parse_duration(value).map_err(|e| format!("cooldown: {}", e))?,
+            "actuate" => actuators.push(match value {
+                "buzzer" => Actuator::Buzzer,
+                "relay" => Actuator::Relay,
+                other => return Err(format!("actuate: {} is neither buzzer nor relay", other)),
+            }),
+            "webhook" => webhook = Some(url("webhook", value)?),
+            "slack" => notifiers.push(Notifier::Slack(url("slack", value)?)),
+            "discord" => notifiers.push(Notifier::Discord(url("discord", value)?)),
+            "telegram" => notifiers.push(Notifier::Telegram(url("telegram", value)?)),
+            "email" if value.contains('@') => notifiers.push(Notifier::Email(value.to_string())),
+            "email" => return Err(format!("email: {} is not an address", value)),
+            "template" => template = Some(std::fs::read_to_string(value).map_err(|e| format!("template: {}: {}", value, e))?),
+            other => return Err(format!("unknown key {}", other)),
+        }
+    }
+    match (metric, condition) {
+        (Some(metric), Some((comparison, threshold))) => conditions.insert(0, Condition { metric, comparison, threshold, resolve }),
+        (Some(_), None) => return Err(String::from("missing above or below")),
+        (None, Some(_)) => return Err(String::from("missing metric")),
+        (None, None) if resolve.is_some() => return Err(String::from("resolve= belongs to a metric= condition")),
+        (None, None) => {}
+    }
+    if conditions.is_empty() {
+        return Err(String::from("missing metric or when"));
+    }
+    if let Some(webhook) = webhook {
+        notifiers.push(Notifier::Webhook(webhook, template.unwrap_or_else(|| String::from(DEFAULT_TEMPLATE))));
+    }
+    return Ok(Alert {
+        name: name.unwrap_or_else(|| conditions[0].metric.clone()),
+        conditions,
+        duration,
+        cooldown,
+        notifiers,
+        actuators,
+    });
+}
+
+struct Tracked {
+    alert: Alert,
+    /// since when the condition holds
+    since: Option<Instant>,
+    firing: bool,
+    /// when it last resolved, for the cooldown
+    resolved: Option<Instant>,
+    /// the latest samples of the first condition's series for the sparkline
+    recent: VecDeque<f64>,
+    gauge: metrics::Gauge,
+}
+
+static ALERTS: Mutex<Vec<Tracked>> = Mutex::new(Vec::new());
+static NOTIFICATIONS: OnceLock<SyncSender<(Url, String)>> = OnceLock::new();
+static PENDING: Pending = Pending::new("alerts");
+static LOCATION: OnceLock<String> = OnceLock::new();
+
+/// name the place in the messages, from --label location=...
+pub(crate) fn set_location(location: &str) {
+    let _ = LOCATION.set(location.to_string());
+}
+
+/// evaluate `alerts`, replacing the ones evaluated so far. an alert of the same name keeps its state.
+pub(crate) fn init(alerts: Vec<Alert>) -> io::Result<()> {
+    let mails = alerts.iter().flat_map(|a| &a.notifiers).any(|n| matches!(n, Notifier::Email(_)));
+    if mails && !smtp::enabled() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "email alerts need --smtp-url"));
+    }
+    if alerts.iter().flat_map(|a| &a.notifiers).any(|n| !matches!(n, Notifier::Email(_))) && NOTIFICATIONS.get().is_none() {
+        let (tx, rx) = mpsc::sync_channel(QUEUE);
+        thread::Builder::new().name(String::from("alerts")).spawn(move || run(rx))?;
+        let _ = NOTIFICATIONS.set(tx);
+        PENDING.register();
+    }
+    let mut tracked = ALERTS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut old = std::mem::take(&mut *tracked);
+    for alert in alerts {
+        let t = match old.iter().position(|t| t.alert.name == alert.name) {
+            Some(i) => Tracked { alert, ..old.swap_remove(i) },
+            None => Tracked::new(alert),
+        };
+        t.gauge.set(if t.firing { 1 } else { 0 });
+        tracked.push(t);
+    }
+    // the removed don't fire anymore
+    for t in old {
+        t.gauge.set(0);
+    }
+    return Ok(());
+}
+
+impl Tracked {
+    fn new(alert: Alert) -> Self {
+        let gauge = metrics::gauge!("exporter_alert_firing", "alert" => alert.name.clone());
+        return Tracked { alert, since: None, firing: false, resolved: None, recent: VecDeque::new(), gauge };
+    }
+
+    /// check the alert on new samples
+    fn observe(&mut self, clock: &dyn Clock, values: &[(&str, f64)]) {
+        let now = clock.instant();
+        // every series of the conditions must be in the samples
+        let Some(current) = self.alert.conditions.iter().map(|c| values.iter().find(|(m, _)| *m == c.metric).map(|&(_, v)| v)).collect::<Option<Vec<f64>>>() else {
+            return;
+        };
+        if self.recent.len() == SPARKLINE {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(current[0]);
+        let holds = self.alert.conditions.iter().zip(&current).all(|(c, &v)| c.holds(v, self.firing));
+        if !holds {
+            self.since = None;
+            if self.firing {
+                self.firing = false;
+                self.resolved = Some(now);
+                notify(clock, self, &current);
+            }
+            return;
+        }
+        let since = *self.since.get_or_insert(now);
+        let cooled = self.resolved.is_none_or(|r| now - r >= self.alert.cooldown);
+        if !self.firing && now - since >= self.alert.duration && cooled {
+            self.firing = true;
+            notify(clock, self, &current);
+        }
+    }
+}
+
+/// check the alerts on new samples
+pub(crate) fn observe(clock: &dyn Clock, values: &[(&str, f64)]) {
+    let mut tracked = ALERTS.lock().unwrap_or_else(|e| e.into_inner());
+    for t in tracked.iter_mut() {
+        t.observe(clock, values);
+    }
+}
+
+/// whether an alert routed to `actuator` is firing
+pub(crate) fn actuating(actuator: Actuator) -> bool {
+    return ALERTS.lock().unwrap_or_else(|e| e.into_inner()).iter().any(|t| t.firing && t.alert.actuators.contains(&actuator));
+}
+
+/// report that `t` changed state at the `values` of its conditions
+fn notify(clock: &dyn Clock, t: &Tracked, values: &[f64]) {
+    let state = if t.firing { "firing" } else { "resolved" };
+    t.gauge.set(if t.firing { 1 } else { 0 });
+    let described: Vec<String> = t.alert.conditions.iter().zip(values).map(|(c, &v)| c.describe(v, t.firing)).collect();
+    events::record(clock, "alert", format!("{} {}, {}", t.alert.name, state, described.join(", ")));
+    let value = values[0];
+    let location = LOCATION.get().map(String::as_str).unwrap_or_default();
+    let sparkline = sparkline(&t.recent);
+    for notifier in &t.alert.notifiers {
+        let (url, body) = match notifier {
+            Notifier::Webhook(url, template) => (url.clone(), render(template, t, value, location, &sparkline)),
+            Notifier::Slack(url) => (url.clone(), format!(r#"{{"text":{}}}"#, json::quote(&message(t, &described, &sparkline)))),
+            Notifier::Discord(url) => (url.clone(), format!(r#"{{"content":{}}}"#, json::quote(&message(t, &described, &sparkline)))),
+            Notifier::Email(to) => {
+                smtp::queue(to, format!("{} {}", t.alert.name, state), message(t, &described, &sparkline));
+                continue;
+            }
+            Notifier::Telegram(url) => {
+                // the chat moves from the query into the body
+                let (path, chat) = match url.path.split_once("?chat_id=") {
+                    Some((path, chat)) => (path.to_string(), chat.to_string()),
+                    None => (url.path.clone(), String::new()),
+                };
+                let body = format!(r#"{{"chat_id":{},"text":{}}}"#, json::quote(&chat), json::quote(&message(t, &described, &sparkline)));
+                (Url { path, ..url.clone() }, body)
+            }
+        };
+        let Some(queue) = NOTIFICATIONS.get() else {
+            continue;
+        };
+        PENDING.add(1);
+        if queue.try_send((url, body)).is_err() {
+            PENDING.done(1);
+            log::warn!("too many alert notifications queued, drop the one of {}", t.alert.name);
+        }
+    }
+}
+
+/// the webhook `template` for `t` at `value` of its first condition
+fn render(template: &str, t: &Tracked, value: f64, location: &str, sparkline: &str) -> String {
+    let first = &t.alert.conditions[0];
+    return template
+        .replace("{alert}", &escape(&t.alert.name))
+        .replace("{state}", if t.firing { "firing" } else { "resolved" })
+        .replace("{metric}", &escape(&first.metric))
+        .replace("{value}", &json::number(value))
+        .replace("{threshold}", &json::number(first.threshold))
+        .replace("{location}", &escape(location))
+        .replace("{sparkline}", sparkline);
+}
+
+/// `s` escaped for the inside of a JSON string
+fn escape(s: &str) -> String {
+    let quoted = json::quote(s);
+    return quoted[1..quoted.len() - 1].to_string();
+}
+
+/// the chat message on `t` changing state, with its `described` conditions
+fn message(t: &Tracked, described: &[String], sparkline: &str) -> String {
+    let place = LOCATION.get().map(|l| format!(" in {}", l)).unwrap_or_default();
+    let state = if t.firing { "firing" } else { "resolved" };
+    return format!("{} {}{}: {}\n{}", t.alert.name, state, place, described.join(", "), sparkline);
+}
+
+/// `values` as bars scaled between their minimum and maximum
+fn sparkline(values: &VecDeque<f64>) -> String {
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    return values
+        .iter()
+        .map(|v| match max > min {
+            true => BARS[(((v - min) / (max - min)) * (BARS.len() - 1) as f64).round() as usize],
+            false => BARS[0],
+        })
+        .collect();
+}
+
+fn run(rx: Receiver<(Url, String)>) {
+    let failures = metrics::counter!("exporter_alert_notification_failures_total");
+    for (url, body) in rx {
+        match http::send("POST", &url, &[("Content-Type", "application/json")], body.as_bytes()) {
+            Ok((status, _)) if (200..300).contains(&status) => {}
+            Ok((status, _)) => {
+                log::warn!("alert webhook {}:{}{} returned {}", url.host, url.port, url.path, status);
+                failures.increment(1);
+            }
+            Err(e) => {
+                log::warn!("failed to post to alert webhook {}:{}{}: {:?}", url.host, url.port, url.path, e);
+                failures.increment(1);
+            }
+        }
+        PENDING.done(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::clock::FakeClock;
+
+    #[test]
+    fn conditions_parse() {
+        let c = parse_condition("scd41_co2_ppm > 1000:800").unwrap();
+        assert_eq!((c.metric.as_str(), c.comparison, c.threshold, c.resolve), ("scd41_co2_ppm", Comparison::Above, 1000.0, Some(800.0)));
+        let c = parse_condition("scd41_humidity_rh<30").unwrap();
+        assert_eq!((c.comparison, c.threshold, c.resolve), (Comparison::Below, 30.0, None));
+        for invalid in ["scd41_co2_ppm=1000", "a>1<2", ">1000", "scd41_co2_ppm>high", "scd41_co2_ppm>1000:low"] {
+            assert!(parse_condition(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn alerts_parse() {
+        let alert = parse_alert("name=stuffy,metric=scd41_co2_ppm,above=1200,resolve=1000,for=5m,cooldown=10m,actuate=relay,webhook=http://localhost:8080/hook").unwrap();
+        assert_eq!(alert.name, "stuffy");
+        assert_eq!((alert.duration, alert.cooldown), (Duration::from_secs(300), Duration::from_secs(600)));
+        assert_eq!(alert.actuators, [Actuator::Relay]);
+        assert!(matches!(&alert.notifiers[..], [Notifier::Webhook(_, template)] if template == DEFAULT_TEMPLATE));
+        // the metric= condition comes first, and names the alert without a name
+        let alert = parse_alert("when=scd41_humidity_rh>70,metric=scd41_co2_ppm,below=400").unwrap();
+        assert_eq!(alert.name, "scd41_co2_ppm");
+        assert_eq!(alert.conditions.iter().map(|c| c.metric.as_str()).collect::<Vec<_>>(), ["scd41_co2_ppm", "scd41_humidity_rh"]);
+
+        let errors = [
+            ("metric=scd41_co2_ppm", "missing above or below"),
+            ("above=1000", "missing metric"),
+            ("resolve=900,when=a>1", "resolve= belongs to a metric= condition"),
+            ("name=empty", "missing metric or when"),
+            ("metric=a,above=1,color=red", "unknown key color"),
+            ("metric=a,above=1,actuate=fan", "actuate: fan is neither buzzer nor relay"),
+            ("metric=a,above=1,email=nobody", "email: nobody is not an address"),
+            ("metric=a,above=1,nothing", "nothing is not key=value"),
+        ];
+        for (alert, error) in errors {
+            assert_eq!(parse_alert(alert).err().as_deref(), Some(error), "{}", alert);
+        }
+        assert!(parse_alert("metric=a,above=1,for=soon").unwrap_err().starts_with("for: "));
+        assert!(parse_alert("metric=a,above=1,webhook=ftp://host/").unwrap_err().starts_with("webhook: "));
+    }
+
+    #[test]
+    fn fires_and_resolves() {
+        let clock = FakeClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut t = Tracked::new(parse_alert("metric=scd41_co2_ppm,above=1200,resolve=1000,for=1m,cooldown=10m").unwrap());
+        let mut observe = |co2, elapsed| {
+            clock.advance(Duration::from_secs(elapsed));
+            t.observe(&clock, &[("scd41_co2_ppm", co2)]);
+            return t.firing;
+        };
+        // the condition must hold for the whole duration
+        assert!(!observe(1300.0, 0));
+        assert!(!observe(1300.0, 30));
+        assert!(!observe(1100.0, 20));
+        assert!(!observe(1300.0, 10));
+        assert!(!observe(1300.0, 50));
+        assert!(observe(1300.0, 10));
+        // once firing it resolves at the resolve threshold, not the firing one
+        assert!(observe(1100.0, 5));
+        assert!(observe(1001.0, 5));
+        assert!(!observe(1000.0, 5));
+        // the cooldown keeps it from firing again right away
+        assert!(!observe(1300.0, 5));
+        assert!(!observe(1300.0, 300));
+        assert!(!observe(1300.0, 290));
+        assert!(observe(1300.0, 5));
+        // samples without the series leave it alone
+        clock.advance(Duration::from_secs(5));
+        t.observe(&clock, &[("scd41_humidity_rh", 50.0)]);
+        assert!(t.firing);
+    }
+
+    #[test]
+    fn all_conditions_hold() {
+        let clock = FakeClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut t = Tracked::new(parse_alert("name=muggy,when=scd41_co2_ppm>1000&scd41_humidity_rh>70:60").unwrap());
+        t.observe(&clock, &[("scd41_co2_ppm", 1100.0), ("scd41_humidity_rh", 65.0)]);
+        assert!(!t.firing);
+        t.observe(&clock, &[("scd41_co2_ppm", 1100.0), ("scd41_humidity_rh", 75.0)]);
+        assert!(t.firing);
+        t.observe(&clock, &[("scd41_co2_ppm", 1100.0), ("scd41_humidity_rh", 65.0)]);
+        assert!(t.firing);
+        t.observe(&clock, &[("scd41_co2_ppm", 900.0), ("scd41_humidity_rh", 65.0)]);
+        assert!(!t.firing);
+    }
+
+    #[test]
+    fn sparkline_scaling() {
+        let values: VecDeque<f64> = (0..8).map(|v| 800.0 + v as f64 * 10.0).collect();
+        assert_eq!(sparkline(&values), "▁▂▃▄▅▆▇█");
+        let values: VecDeque<f64> = [400.0, 1600.0, 1000.0].into();
+        assert_eq!(sparkline(&values), "▁█▅");
+        assert_eq!(sparkline(&[800.0; 4].into()), "▁▁▁▁");
+        assert_eq!(sparkline(&VecDeque::new()), "");
+    }
+
+    #[test]
+    fn webhook_escapes() {
+        let mut t = Tracked::new(parse_alert(r#"name=say "hi",metric=scd41_co2_ppm,above=1200,webhook=http://localhost/"#).unwrap());
+        t.firing = true;
+        let body = render(DEFAULT_TEMPLATE, &t, 1300.0, "", "");
+        assert_eq!(body, r#"{"alert":"say \"hi\"","state":"firing","metric":"scd41_co2_ppm","value":1300,"threshold":1200}"#);
+        assert!(json::parse(&body).is_ok());
+        // a NaN still makes valid JSON
+        let body = render(r#"{"value":{value},"where":"{location}","trend":"{sparkline}"}"#, &t, f64::NAN, "room \\2\\\n", "▁█");
+        assert_eq!(body, r#"{"value":null,"where":"room \\2\\\n","trend":"▁█"}"#);
+        assert!(json::parse(&body).is_ok());
+    }
+}
@@ -0,0 +1,372 @@
+//! the driver awaiting the bus and the commands' execution times instead of blocking, behind the `async` feature
+//!
+//! [`Scd41`] here has the methods of the blocking [`crate::Scd41`], sending the same commands, with the same crc
+//! checks and conversions. [`I2c`] and [`DelayNs`] have the shape of embedded-hal-async's traits of the same names,
+//! over embedded-hal's [`ErrorType`] and [`Operation`], so an implementation of those maps onto them one to one.
+//!
+//! ```no_run
+//! # async fn run<I: scd4x::asynch::I2c, D: scd4x::asynch::DelayNs>(i2c: I, delay: D) -> Result<(), scd4x::Error<I::Error>> {
+//! let mut scd41 = scd4x::asynch::Scd41::new(i2c, delay);
+//! scd41.start_periodic_measurement().await?;
+//! if scd41.get_data_ready_status().await? {
+//!     let m = scd41.read_measurement().await?;
+//!     println!("{} ppm, {:.1} degC, {:.1} %RH", m.co2, m.temperature, m.humidity);
+//! }
+//! # return Ok(());
+//! # }
+//! ```
+use core::time::Duration;
+
+pub use embedded_hal::i2c::{ErrorType, Operation};
+
+use crate::{commands, correction, frame, measurement, offset_celsius, offset_word, ready, serial, validate, word, Cmd, Error, Measurement, Variant, I2C_ADDR};
+
+/// an I2C bus whose transactions are awaited
+#[allow(async_fn_in_trait)]
+pub trait I2c: ErrorType {
+    /// run `operations` on the device at `address` as one transaction
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error>;
+
+    /// read `read.len()` bytes from `address`
+    async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        return self.transaction(address, &mut [Operation::Read(read)]).await;
+    }
+
+    /// write `write` to `address`
+    async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        return self.transaction(address, &mut [Operation::Write(write)]).await;
+    }
+}
+
+impl<T: I2c + ?Sized> I2c for &mut T {
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        return T::transaction(self, address, operations).await;
+    }
+}
+
+/// a delay which is awaited
+#[allow(async_fn_in_trait)]
+pub trait DelayNs {
+    /// wait at least `ns` nanoseconds
+    async fn delay_ns(&mut self, ns: u32);
+
+    /// wait at least `us` microseconds, in steps of delay_ns
+    async fn delay_us(&mut self, mut us: u32) {
+        const MAX_US: u32 = u32::MAX / 1000;
+        while us > MAX_US {
+            self.delay_ns(MAX_US * 1000).await;
+            us -= MAX_US;
+        }
+        self.delay_ns(us * 1000).await;
+    }
+}
+
+impl<T: DelayNs + ?Sized> DelayNs for &mut T {
+    async fn delay_ns(&mut self, ns: u32) {
+        T::delay_ns(self, ns).await;
+    }
+}
+
+/// an SCD4x on the bus `I2C`, awaiting the commands on `D`
+pub struct Scd41<I2C, D> {
+    i2c: I2C,
+    delay: D,
+}
+
+impl<I2C, D> Scd41<I2C, D> {
+    /// the sensor on `i2c`
+    pub fn new(i2c: I2C, delay: D) -> Self {
+        return Scd41 { i2c, delay };
+    }
+
+    /// the bus, e.g. for other devices on it
+    pub fn i2c(&mut self) -> &mut I2C {
+        return &mut self.i2c;
+    }
+
+    /// give the bus and delay back
+    pub fn release(self) -> (I2C, D) {
+        return (self.i2c, self.delay);
+    }
+}
+
+impl<I2C: I2c, D: DelayNs> Scd41<I2C, D> {
+    async fn command(&mut self, command: Cmd) -> Result<(), Error<I2C::Error>> {
+        self.i2c.write(I2C_ADDR, &command.code.to_be_bytes()).await.map_err(Error::Write)?;
+        self.delay.delay_us(command.delay.as_micros() as u32).await;
+        return Ok(());
+    }
+
+    async fn command_with_arg(&mut self, command: Cmd, arg: u16) -> Result<(), Error<I2C::Error>> {
+        self.i2c.write(I2C_ADDR, &frame(command, arg)).await.map_err(Error::Write)?;
+        self.delay.delay_us(command.delay.as_micros() as u32).await;
+        return Ok(());
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(), Error<I2C::Error>> {
+        self.i2c.read(I2C_ADDR, buf).await.map_err(Error::Read)?;
+        return validate(buf);
+    }
+
+    async fn read_word(&mut self, command: Cmd) -> Result<u16, Error<I2C::Error>> {
+        self.command(command).await?;
+        let mut buf = [0; 3];
+        self.read(&mut buf).await?;
+        return Ok(word(&buf));
+    }
+
+    /// see [`crate::Scd41::clean_state`]
+    pub async fn clean_state(&mut self) {
+        let _ = self.wake_up().await.inspect_err(|e| log::trace!("wakeup error {:?}", e));
+        let _ = self.stop_periodic_measurement(Duration::from_millis(500)).await.inspect_err(|e| log::trace!("stop error {:?}", e));
+        let _ = self.reinit().await.inspect_err(|e| log::trace!("reinit error {:?}", e));
+    }
+
+    /// start_periodic_measurement (0x21B1), a measurement every 5s
+    pub async fn start_periodic_measurement(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::START_PERIODIC_MEASUREMENT).await;
+    }
+
+    /// start_low_power_periodic_measurement (0x21AC), a measurement every 30s
+    pub async fn start_low_power_periodic_measurement(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::START_LOW_POWER_PERIODIC_MEASUREMENT).await;
+    }
+
+    /// stop_periodic_measurement (0x3F86), `delay` is the variant's execution time, see [`crate::Quirks::stop_delay`]
+    pub async fn stop_periodic_measurement(&mut self, delay: Duration) -> Result<(), Error<I2C::Error>> {
+        return self.command(Cmd { delay, ..commands::STOP_PERIODIC_MEASUREMENT }).await;
+    }
+
+    /// get_data_ready_status (0xE4B8)
+    pub async fn get_data_ready_status(&mut self) -> Result<bool, Error<I2C::Error>> {
+        return self.read_word(commands::GET_DATA_READY_STATUS).await.map(ready);
+    }
+
+    /// read_measurement (0xEC05)
+    pub async fn read_measurement(&mut self) -> Result<Measurement, Error<I2C::Error>> {
+        self.command(commands::READ_MEASUREMENT).await?;
+        let mut buf = [0; 9];
+        self.read(&mut buf).await?;
+        return Ok(measurement(&buf));
+    }
+
+    /// measure_single_shot (0x219D), the sensor must be idle. read the result with [`Scd41::read_measurement`].
+    pub async fn measure_single_shot(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::MEASURE_SINGLE_SHOT).await;
+    }
+
+    /// measure_single_shot_rht_only (0x2196), like measure_single_shot without CO2, which reads as 0
+    pub async fn measure_single_shot_rht_only(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::MEASURE_SINGLE_SHOT_RHT_ONLY).await;
+    }
+
+    /// get_temperature_offset (0x2318) in degrees Celsius
+    pub async fn get_temperature_offset(&mut self) -> Result<f32, Error<I2C::Error>> {
+        return self.read_word(commands::GET_TEMPERATURE_OFFSET).await.map(offset_celsius);
+    }
+
+    /// set_temperature_offset (0x241D) in degrees Celsius, the sensor must be idle
+    pub async fn set_temperature_offset(&mut self, offset: f32) -> Result<(), Error<I2C::Error>> {
+        return self.command_with_arg(commands::SET_TEMPERATURE_OFFSET, offset_word(offset)).await;
+    }
+
+    /// get_sensor_altitude (0x2322) in meters above sea level
+    pub async fn get_sensor_altitude(&mut self) -> Result<u16, Error<I2C::Error>> {
+        return self.read_word(commands::GET_SENSOR_ALTITUDE).await;
+    }
+
+    /// set_sensor_altitude (0x2427) in meters above sea level, the sensor must be idle
+    pub async fn set_sensor_altitude(&mut self, meters: u16) -> Result<(), Error<I2C::Error>> {
+        return self.command_with_arg(commands::SET_SENSOR_ALTITUDE, meters).await;
+    }
+
+    /// get_ambient_pressure (0xE000) in Pa
+    pub async fn get_ambient_pressure(&mut self) -> Result<f32, Error<I2C::Error>> {
+        return self.read_word(commands::GET_AMBIENT_PRESSURE).await.map(|hpa| hpa as f32 * 100_f32);
+    }
+
+    /// set_ambient_pressure (0xE000), `pressure` in Pa, also while measuring. it overrides the altitude.
+    pub async fn set_ambient_pressure(&mut self, pressure: f32) -> Result<(), Error<I2C::Error>> {
+        return self.command_with_arg(commands::SET_AMBIENT_PRESSURE, (pressure / 100_f32) as u16).await;
+    }
+
+    /// perform_forced_recalibration (0x362F) to `target` ppm, the sensor must be idle.
+    /// returns the correction in ppm, None if the recalibration failed.
+    pub async fn perform_forced_recalibration(&mut self, target: u16) -> Result<Option<i16>, Error<I2C::Error>> {
+        self.command_with_arg(commands::PERFORM_FORCED_RECALIBRATION, target).await?;
+        let mut buf = [0; 3];
+        self.read(&mut buf).await?;
+        return Ok(correction(word(&buf)));
+    }
+
+    /// get_automatic_self_calibration_enabled (0x2313)
+    pub async fn get_automatic_self_calibration_enabled(&mut self) -> Result<bool, Error<I2C::Error>> {
+        return self.read_word(commands::GET_ASC_ENABLED).await.map(|word| word != 0);
+    }
+
+    /// set_automatic_self_calibration_enabled (0x2416), the sensor must be idle
+    pub async fn set_automatic_self_calibration_enabled(&mut self, enabled: bool) -> Result<(), Error<I2C::Error>> {
+        return self.command_with_arg(commands::SET_ASC_ENABLED, enabled as u16).await;
+    }
+
+    /// get_automatic_self_calibration_target (0x233F) in ppm
+    pub async fn get_automatic_self_calibration_target(&mut self) -> Result<u16, Error<I2C::Error>> {
+        return self.read_word(commands::GET_ASC_TARGET).await;
+    }
+
+    /// set_automatic_self_calibration_target (0x243A) in ppm, the sensor must be idle
+    pub async fn set_automatic_self_calibration_target(&mut self, ppm: u16) -> Result<(), Error<I2C::Error>> {
+        return self.command_with_arg(commands::SET_ASC_TARGET, ppm).await;
+    }
+
+    /// get_automatic_self_calibration_initial_period (0x2340) in hours
+    pub async fn get_automatic_self_calibration_initial_period(&mut self) -> Result<u16, Error<I2C::Error>> {
+        return self.read_word(commands::GET_ASC_INITIAL_PERIOD).await;
+    }
+
+    /// set_automatic_self_calibration_initial_period (0x2445) in hours, a multiple of 4. the sensor must be idle.
+    pub async fn set_automatic_self_calibration_initial_period(&mut self, hours: u16) -> Result<(), Error<I2C::Error>> {
+        return self.command_with_arg(commands::SET_ASC_INITIAL_PERIOD, hours).await;
+    }
+
+    /// get_automatic_self_calibration_standard_period (0x234B) in hours
+    pub async fn get_automatic_self_calibration_standard_period(&mut self) -> Result<u16, Error<I2C::Error>> {
+        return self.read_word(commands::GET_ASC_STANDARD_PERIOD).await;
+    }
+
+    /// set_automatic_self_calibration_standard_period (0x244E) in hours, a multiple of 4. the sensor must be idle.
+    pub async fn set_automatic_self_calibration_standard_period(&mut self, hours: u16) -> Result<(), Error<I2C::Error>> {
+        return self.command_with_arg(commands::SET_ASC_STANDARD_PERIOD, hours).await;
+    }
+
+    /// persist_settings (0x3615), writes the settings to the eeprom. it's specified for 2000 writes.
+    pub async fn persist_settings(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::PERSIST_SETTINGS).await;
+    }
+
+    /// get_serial_number (0x3682), 48 bits
+    pub async fn read_serial(&mut self) -> Result<u64, Error<I2C::Error>> {
+        self.command(commands::GET_SERIAL_NUMBER).await?;
+        let mut buf = [0; 9];
+        self.read(&mut buf).await?;
+        return Ok(serial(&buf));
+    }
+
+    /// perform_self_test (0x3639), the sensor must be idle. returns the result word, 0 if no malfunction was found.
+    pub async fn perform_self_test(&mut self) -> Result<u16, Error<I2C::Error>> {
+        self.command(commands::PERFORM_SELF_TEST).await?;
+        let mut buf = [0; 3];
+        self.read(&mut buf).await?;
+        return Ok(word(&buf));
+    }
+
+    /// perform_factory_reset (0x3632), erases the settings and calibration history in the eeprom
+    pub async fn perform_factory_reset(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::PERFORM_FACTORY_RESET).await;
+    }
+
+    /// reinit (0x3646), reloads the settings from the eeprom. the sensor must be idle.
+    pub async fn reinit(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::REINIT).await;
+    }
+
+    /// get_sensor_variant (0x202F)
+    pub async fn get_sensor_variant(&mut self) -> Result<Variant, Error<I2C::Error>> {
+        return self.get_sensor_variant_word().await.map(Variant::from_word);
+    }
+
+    /// get_sensor_variant (0x202F) as the raw word, the bits below the variant are undocumented
+    pub async fn get_sensor_variant_word(&mut self) -> Result<u16, Error<I2C::Error>> {
+        return self.read_word(commands::GET_SENSOR_VARIANT).await;
+    }
+
+    /// power_down (0x36E0), the sensor must be idle. wake_up brings it back.
+    pub async fn power_down(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::POWER_DOWN).await;
+    }
+
+    /// wake_up (0x36F6)
+    pub async fn wake_up(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::WAKE_UP).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use super::*;
+    use crate::{
+        crc8, SCD41,
+        tests::{words, MockI2c},
+    };
+
+    impl I2c for MockI2c {
+        async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+            return embedded_hal::i2c::I2c::transaction(self, address, operations);
+        }
+    }
+
+    /// delay recording the waits asked for instead of waiting
+    #[derive(Debug, Default)]
+    struct RecordingDelay(Vec<u32>);
+
+    impl DelayNs for RecordingDelay {
+        async fn delay_ns(&mut self, ns: u32) {
+            self.0.push(ns);
+        }
+    }
+
+    /// run `future`, which never waits on the mock bus and delay
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn read_measurement_converts_values() {
+        let mut i2c = MockI2c::default();
+        i2c.reads.push_back(words(&[0x01f4, 0x6667, 0x5eb9]));
+        let mut bad = words(&[0x01f4, 0x6667, 0x5eb9]);
+        bad[2] ^= 0xff;
+        i2c.reads.push_back(bad);
+
+        let mut scd41 = Scd41::new(&mut i2c, RecordingDelay::default());
+        let m = block_on(scd41.read_measurement()).unwrap();
+        assert_eq!(m.co2, 500);
+        assert!((m.temperature - 25.0).abs() < 0.01);
+        assert!((m.humidity - 37.0).abs() < 0.01);
+        assert!(matches!(block_on(scd41.read_measurement()), Err(Error::Crc)));
+        assert_eq!(i2c.writes, vec![vec![0xec, 0x05], vec![0xec, 0x05]]);
+    }
+
+    #[test]
+    fn commands_match_the_blocking_driver() {
+        let mut i2c = MockI2c::default();
+        i2c.reads.push_back(words(&[0xf896, 0x9f07, 0x3bbf]));
+        i2c.reads.push_back(words(&[0x7fe7]));
+        let mut delay = RecordingDelay::default();
+
+        let mut scd41 = Scd41::new(&mut i2c, &mut delay);
+        assert_eq!(block_on(scd41.read_serial()).unwrap(), 0xf896_9f07_3bbf);
+        block_on(scd41.set_temperature_offset(5.4)).unwrap();
+        assert_eq!(block_on(scd41.perform_forced_recalibration(400)).unwrap(), Some(-25));
+        block_on(scd41.measure_single_shot()).unwrap();
+        let data = 400_u16.to_be_bytes();
+        assert_eq!(
+            i2c.writes,
+            vec![vec![0x36, 0x82], vec![0x24, 0x1d, 0x07, 0xe6, 0x48], vec![0x36, 0x2f, data[0], data[1], crc8::calculate(&data)], vec![0x21, 0x9d]]
+        );
+        let total: u64 = delay.0.iter().map(|&ns| ns as u64).sum();
+        assert_eq!(total, (1 + 1 + 400 + SCD41.single_shot_delay.as_millis() as u64) * 1_000_000);
+    }
+}
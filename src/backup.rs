@@ -0,0 +1,115 @@
+//! module for snapshots of the scd41's readable configuration
+//! a snapshot is taken whenever the exporter changes the settings and written to
+//! `scd41-<serial>-<unix ms>.json`, unless it equals the serial's latest. `restore-config` applies a
+//! snapshot to a (replacement) sensor and persists it to the sensor's EEPROM.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use embedded_hal::{delay::DelayNs, i2c};
+use scd4x::{Command, Error, Scd41, Variant};
+
+use crate::json;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Snapshot {
+    pub(crate) serial: String,
+    /// degrees Celsius, rounded to the sensor's resolution
+    pub(crate) temperature_offset: f32,
+    pub(crate) altitude: u16,
+    pub(crate) asc_enabled: bool,
+    /// hours, None on variants without configurable ASC periods
+    pub(crate) asc_standard_period: Option<u16>,
+}
+
+impl Snapshot {
+    fn to_json(&self) -> String {
+        let period = self.asc_standard_period.map(|h| h.to_string()).unwrap_or_else(|| String::from("null"));
+        return format!(
+            "{{\n  \"serial\": {},\n  \"temperature_offset\": {:.2},\n  \"altitude\": {},\n  \"asc_enabled\": {},\n  \"asc_standard_period\": {}\n}}\n",
+            json::quote(&self.serial),
+            self.temperature_offset,
+            self.altitude,
+            self.asc_enabled,
+            period
+        );
+    }
+
+    fn from_json(s: &str) -> Result<Self, String> {
+        let value = json::parse(s).map_err(|e| e.to_string())?;
+        let number = |key| value.get(key).and_then(|v| v.as_f64()).ok_or(format!("missing {}", key));
+        return Ok(Snapshot {
+            serial: match value.get("serial") {
+                Some(json::Value::String(s)) => s.clone(),
+                _ => return Err(String::from("missing serial")),
+            },
+            temperature_offset: number("temperature_offset")? as f32,
+            altitude: number("altitude")? as u16,
+            asc_enabled: match value.get("asc_enabled") {
+                Some(json::Value::Bool(b)) => *b,
+                _ => return Err(String::from("missing asc_enabled")),
+            },
+            asc_standard_period: value.get("asc_standard_period").and_then(|v| v.as_f64()).map(|h| h as u16),
+        });
+    }
+}
+
+/// read the configuration of a sensor in idle mode
+pub(crate) fn read<I: i2c::I2c, D: DelayNs>(scd41: &mut Scd41<I, D>, serial: u64, variant: Variant) -> Result<Snapshot, Error<I::Error>> {
+    let offset = scd41.get_temperature_offset()?;
+    let asc_standard_period = match scd4x::check(variant, Command::AscPeriods) {
+        Ok(()) => Some(scd41.get_automatic_self_calibration_standard_period()?),
+        Err(_) => None,
+    };
+    return Ok(Snapshot {
+        serial: format!("0x{:x}", serial),
+        temperature_offset: (offset * 100_f32).round() / 100_f32,
+        altitude: scd41.get_sensor_altitude()?,
+        asc_enabled: scd41.get_automatic_self_calibration_enabled()?,
+        asc_standard_period,
+    });
+}
+
+/// write `snapshot` into `dir`, unless it equals the latest one of the same sensor
+pub(crate) fn store(dir: &Path, snapshot: &Snapshot) -> io::Result<Option<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let prefix = format!("scd41-{}-", snapshot.serial);
+    let mut latest: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with(&prefix) && n.ends_with(".json")))
+        .collect();
+    latest.sort();
+    if let Some(path) = latest.last() {
+        if fs::read_to_string(path).ok().and_then(|s| Snapshot::from_json(&s).ok()).as_ref() == Some(snapshot) {
+            return Ok(None);
+        }
+    }
+    let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or_default();
+    let path = dir.join(format!("{}{:016}.json", prefix, timestamp_ms));
+    let tmp = dir.join(format!(".{}{:016}.json", prefix, timestamp_ms));
+    fs::write(&tmp, snapshot.to_json())?;
+    fs::rename(&tmp, &path)?;
+    return Ok(Some(path));
+}
+
+pub(crate) fn load(path: &Path) -> Result<Snapshot, String> {
+    let s = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    return Snapshot::from_json(&s);
+}
+
+/// apply `snapshot` to a sensor in idle mode and persist it
+pub(crate) fn restore<I: i2c::I2c, D: DelayNs>(scd41: &mut Scd41<I, D>, variant: Variant, snapshot: &Snapshot) -> Result<(), Error<I::Error>> {
+    scd41.set_temperature_offset(snapshot.temperature_offset)?;
+    scd41.set_sensor_altitude(snapshot.altitude)?;
+    scd41.set_automatic_self_calibration_enabled(snapshot.asc_enabled)?;
+    match (snapshot.asc_standard_period, scd4x::check(variant, Command::AscPeriods)) {
+        (Some(hours), Ok(())) => scd41.set_automatic_self_calibration_standard_period(hours)?,
+        (Some(_), Err(e)) => log::warn!("skip asc standard period: {}", e),
+        (None, _) => {}
+    }
+    scd41.persist_settings()?;
+    return Ok(());
+}
@@ -0,0 +1,84 @@
+//! module for tracking the CO2 baseline to tell when the sensor drifts
+//! a room ventilated now and then, usually at night, falls to about outdoor CO2, so the lowest CO2 of the
+//! last --baseline-days is the baseline the sensor sees fresh air at. its difference to --outdoor-co2 is
+//! the drift, exported so a recalibration can be planned before the readings are obviously off. the
+//! minimum is of 10 minute means, single noisy samples don't move it. it's seeded from the history.
+use std::{collections::VecDeque, time::UNIX_EPOCH};
+
+use crate::{clock::Clock, history};
+
+const BUCKET_MS: u64 = 10 * 60 * 1000;
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+pub(crate) struct Baseline {
+    /// outdoor CO2 in ppm
+    reference: f64,
+    days: u64,
+    /// the lowest 10 minute mean of each day (unix days), the newest last
+    minima: VecDeque<(u64, f64)>,
+    /// start (unix ms), sum and count of the 10 minutes being averaged
+    bucket: Option<(u64, f64, u32)>,
+    baseline: metrics::Gauge,
+    drift: metrics::Gauge,
+    covered: metrics::Gauge,
+}
+
+impl Baseline {
+    /// `labels` are added to every series
+    pub(crate) fn new(reference: u16, days: u16, labels: &[(String, String)], clock: &dyn Clock) -> Self {
+        let mut baseline = Baseline {
+            reference: reference as f64,
+            days: days as u64,
+            minima: VecDeque::new(),
+            bucket: None,
+            baseline: metrics::gauge!("scd41_co2_baseline_ppm", labels),
+            drift: metrics::gauge!("scd41_co2_baseline_drift_ppm", labels),
+            covered: metrics::gauge!("scd41_co2_baseline_days", labels),
+        };
+        baseline.baseline.set(f64::NAN);
+        baseline.drift.set(f64::NAN);
+        baseline.covered.set(0);
+        let now = clock.now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default();
+        for p in history::export(clock, Some(now.saturating_sub(baseline.days * DAY_MS)), None).unwrap_or_default() {
+            baseline.add(p.timestamp_ms, p.co2 as f64);
+        }
+        return baseline;
+    }
+
+    /// add a CO2 sample taken at `timestamp_ms`
+    pub(crate) fn add(&mut self, timestamp_ms: u64, co2: f64) {
+        let start = timestamp_ms - timestamp_ms % BUCKET_MS;
+        match self.bucket.as_mut() {
+            Some((s, sum, count)) if *s == start => {
+                *sum += co2;
+                *count += 1;
+                return;
+            }
+            _ => {}
+        }
+        if let Some((s, sum, count)) = self.bucket.replace((start, co2, 1)) {
+            self.close(s, sum / count as f64);
+        }
+    }
+
+    /// the baseline in ppm, None before the first 10 minutes
+    pub(crate) fn value(&self) -> Option<f64> {
+        return self.minima.iter().map(|(_, min)| *min).reduce(f64::min);
+    }
+
+    /// fold the mean of the 10 minutes from `start` into its day's minimum
+    fn close(&mut self, start: u64, mean: f64) {
+        let day = start / DAY_MS;
+        match self.minima.back_mut() {
+            Some((d, min)) if *d == day => *min = min.min(mean),
+            _ => self.minima.push_back((day, mean)),
+        }
+        while self.minima.front().is_some_and(|(d, _)| d + self.days <= day) {
+            self.minima.pop_front();
+        }
+        let baseline = self.value().unwrap_or(mean);
+        self.baseline.set(baseline);
+        self.drift.set(baseline - self.reference);
+        self.covered.set(self.minima.len() as f64);
+    }
+}
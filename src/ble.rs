@@ -0,0 +1,235 @@
+//! module for broadcasting measurements in Bluetooth LE advertisements
+//! the exporter advertises the Environmental Sensing Service (0x181A) with service data carrying
+//! CO2 concentration (0x2B8C), temperature (0x2A6E) and humidity (0x2A6F), each as the 16-bit
+//! characteristic UUID followed by the value in its GATT format. phones and microcontrollers
+//! read the values by scanning, without connecting or any network.
+//! advertising is set up with HCI commands on a raw socket of the BlueZ kernel stack (needs CAP_NET_ADMIN).
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    mem,
+    os::fd::{FromRawFd, OwnedFd},
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::sensor::Measurement;
+
+const BTPROTO_HCI: libc::c_int = 1;
+const SOL_HCI: libc::c_int = 0;
+const HCI_FILTER: libc::c_int = 2;
+const HCI_COMMAND_PKT: u8 = 0x01;
+const HCI_EVENT_PKT: u8 = 0x04;
+const EVT_CMD_COMPLETE: u8 = 0x0E;
+const EVT_CMD_STATUS: u8 = 0x0F;
+
+const LE_SET_ADVERTISING_PARAMETERS: u16 = 0x2006;
+const LE_SET_ADVERTISING_DATA: u16 = 0x2008;
+const LE_SET_ADVERTISE_ENABLE: u16 = 0x200A;
+
+const ESS_UUID: u16 = 0x181A;
+const CO2_CONCENTRATION_UUID: u16 = 0x2B8C;
+const TEMPERATURE_UUID: u16 = 0x2A6E;
+const HUMIDITY_UUID: u16 = 0x2A6F;
+
+/// legacy advertising data is limited to 31 bytes
+const MAX_ADVERTISING_DATA: usize = 31;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    /// HCI device index, 0 for hci0
+    pub(crate) device: u16,
+    /// local name advertised along with the values, shortened to what fits
+    pub(crate) name: String,
+    pub(crate) interval: Duration,
+}
+
+#[repr(C)]
+struct SockaddrHci {
+    hci_family: libc::sa_family_t,
+    hci_dev: u16,
+    hci_channel: u16,
+}
+
+#[repr(C)]
+struct HciFilter {
+    type_mask: u32,
+    event_mask: [u32; 2],
+    opcode: u16,
+}
+
+static QUEUE: OnceLock<SyncSender<Measurement>> = OnceLock::new();
+
+/// start advertising from a background thread
+pub(crate) fn init(config: Config) -> io::Result<()> {
+    let mut hci = Hci::open(config.device)?;
+    hci.start(&config)?;
+    log::info!("advertise measurements on hci{}", config.device);
+    // only the newest measurement matters
+    let (tx, rx) = mpsc::sync_channel(1);
+    thread::Builder::new().name(String::from("ble")).spawn(move || run(hci, config, rx))?;
+    let _ = QUEUE.set(tx);
+    return Ok(());
+}
+
+/// advertise a measurement, if broadcasting is enabled
+pub(crate) fn advertise(m: &Measurement) {
+    if let Some(queue) = QUEUE.get() {
+        let _ = queue.try_send(*m);
+    }
+}
+
+fn run(mut hci: Hci, config: Config, rx: Receiver<Measurement>) {
+    let failures = metrics::counter!("exporter_ble_failures_total");
+    for m in rx {
+        let data = advertising_data(&config.name, Some(&m));
+        if let Err(e) = hci.command(LE_SET_ADVERTISING_DATA, &padded(&data)) {
+            log::warn!("failed to update advertising data: {:?}", e);
+            failures.increment(1);
+            // the controller may have been reset (e.g. bluetoothd restarted), so set it up again
+            let _ = hci.start(&config).inspect_err(|e| log::warn!("failed to restart advertising: {:?}", e));
+        }
+    }
+}
+
+/// advertising data structures: flags, the ESS uuid, service data with the values and the name
+fn advertising_data(name: &str, m: Option<&Measurement>) -> Vec<u8> {
+    let mut data = vec![2, 0x01, 0x06];
+    data.extend_from_slice(&[3, 0x03]);
+    data.extend_from_slice(&ESS_UUID.to_le_bytes());
+    if let Some(m) = m {
+        let mut service = Vec::new();
+        service.extend_from_slice(&ESS_UUID.to_le_bytes());
+        service.extend_from_slice(&CO2_CONCENTRATION_UUID.to_le_bytes());
+        service.extend_from_slice(&sfloat(m.co2 as f32).to_le_bytes());
+        service.extend_from_slice(&TEMPERATURE_UUID.to_le_bytes());
+        service.extend_from_slice(&((m.temperature * 100.0).round() as i16).to_le_bytes());
+        service.extend_from_slice(&HUMIDITY_UUID.to_le_bytes());
+        service.extend_from_slice(&((m.humidity.clamp(0.0, 100.0) * 100.0).round() as u16).to_le_bytes());
+        data.push(service.len() as u8 + 1);
+        data.push(0x16);
+        data.extend_from_slice(&service);
+    }
+    let room = MAX_ADVERTISING_DATA.saturating_sub(data.len() + 2);
+    if room > 0 && !name.is_empty() {
+        let shortened = name.len() > room;
+        let name = &name.as_bytes()[..name.len().min(room)];
+        data.push(name.len() as u8 + 1);
+        // 0x08 shortened local name, 0x09 complete
+        data.push(if shortened { 0x08 } else { 0x09 });
+        data.extend_from_slice(name);
+    }
+    return data;
+}
+
+/// length prefixed advertising data padded to 31 bytes, the parameter of LE Set Advertising Data
+fn padded(data: &[u8]) -> Vec<u8> {
+    let mut param = vec![0; MAX_ADVERTISING_DATA + 1];
+    param[0] = data.len() as u8;
+    param[1..=data.len()].copy_from_slice(data);
+    return param;
+}
+
+/// IEEE 11073 16-bit SFLOAT: 12-bit mantissa, 4-bit decimal exponent
+fn sfloat(value: f32) -> u16 {
+    let mut mantissa = value;
+    let mut exponent: i8 = 0;
+    while mantissa.abs() > 2047.0 && exponent < 7 {
+        mantissa /= 10.0;
+        exponent += 1;
+    }
+    let mantissa = mantissa.round() as i16;
+    return ((exponent as u16 & 0x0F) << 12) | (mantissa as u16 & 0x0FFF);
+}
+
+/// raw HCI socket bound to one controller
+struct Hci {
+    file: File,
+}
+
+impl Hci {
+    fn open(device: u16) -> io::Result<Hci> {
+        let fd = unsafe { libc::socket(libc::AF_BLUETOOTH, libc::SOCK_RAW | libc::SOCK_CLOEXEC, BTPROTO_HCI) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd is a freshly created socket nobody else owns
+        let owned = unsafe { OwnedFd::from_raw_fd(fd) };
+        let addr = SockaddrHci { hci_family: libc::AF_BLUETOOTH as libc::sa_family_t, hci_dev: device, hci_channel: 0 };
+        let bound = unsafe { libc::bind(fd, &addr as *const SockaddrHci as *const libc::sockaddr, mem::size_of::<SockaddrHci>() as libc::socklen_t) };
+        if bound != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // only the events answering commands
+        let filter = HciFilter {
+            type_mask: 1 << HCI_EVENT_PKT,
+            event_mask: [(1 << EVT_CMD_COMPLETE) | (1 << EVT_CMD_STATUS), 0],
+            opcode: 0,
+        };
+        let timeout = libc::timeval { tv_sec: 2, tv_usec: 0 };
+        let options = [
+            (SOL_HCI, HCI_FILTER, &filter as *const HciFilter as *const libc::c_void, mem::size_of::<HciFilter>()),
+            (libc::SOL_SOCKET, libc::SO_RCVTIMEO, &timeout as *const libc::timeval as *const libc::c_void, mem::size_of::<libc::timeval>()),
+        ];
+        for (level, name, value, len) in options {
+            if unsafe { libc::setsockopt(fd, level, name, value, len as libc::socklen_t) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        return Ok(Hci { file: File::from(owned) });
+    }
+
+    /// set the advertising parameters and enable non-connectable advertising
+    fn start(&mut self, config: &Config) -> io::Result<()> {
+        // the interval is in 0.625 ms units, 100 ms to 10.24 s for non-connectable advertising
+        let interval = (config.interval.as_micros() / 625).clamp(0x00A0, 0x4000) as u16;
+        let mut params = Vec::with_capacity(15);
+        params.extend_from_slice(&interval.to_le_bytes());
+        params.extend_from_slice(&interval.to_le_bytes());
+        // ADV_NONCONN_IND, public address, no peer, all channels, no filter
+        params.extend_from_slice(&[0x03, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0x07, 0x00]);
+        // disabling first lets the parameters be changed while a previous run's advertising is still on
+        let _ = self.command(LE_SET_ADVERTISE_ENABLE, &[0x00]);
+        self.command(LE_SET_ADVERTISING_PARAMETERS, &params)?;
+        self.command(LE_SET_ADVERTISING_DATA, &padded(&advertising_data(&config.name, None)))?;
+        self.command(LE_SET_ADVERTISE_ENABLE, &[0x01])?;
+        return Ok(());
+    }
+
+    /// send a command and wait for its completion, failing with the controller's status
+    fn command(&mut self, opcode: u16, params: &[u8]) -> io::Result<()> {
+        let mut packet = vec![HCI_COMMAND_PKT];
+        packet.extend_from_slice(&opcode.to_le_bytes());
+        packet.push(params.len() as u8);
+        packet.extend_from_slice(params);
+        self.file.write_all(&packet)?;
+
+        let mut buf = [0_u8; 260];
+        loop {
+            let n = self.file.read(&mut buf)?;
+            if n < 3 {
+                continue;
+            }
+            // packet type, event code, length, then the parameters
+            let (event, params) = (buf[1], &buf[3..n]);
+            let (answered, status) = match event {
+                // num packets, opcode, status
+                EVT_CMD_COMPLETE if params.len() >= 4 => (u16::from_le_bytes([params[1], params[2]]), params[3]),
+                // status, num packets, opcode
+                EVT_CMD_STATUS if params.len() >= 4 => (u16::from_le_bytes([params[2], params[3]]), params[0]),
+                _ => continue,
+            };
+            if answered != opcode {
+                continue;
+            }
+            return match status {
+                0 => Ok(()),
+                status => Err(io::Error::other(format!("hci command 0x{:04x} failed with status 0x{:02x}", opcode, status))),
+            };
+        }
+    }
+}
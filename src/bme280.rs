@@ -0,0 +1,110 @@
+//! module for the BME280 pressure sensor
+//! see https://www.bosch-sensortec.com/media/boschsensortec/downloads/datasheets/bst-bme280-ds002.pdf
+use std::{
+    fmt,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
+};
+
+use embedded_hal::i2c;
+
+/// how often the pressure is read and handed to the CO2 sensor
+const INTERVAL: Duration = Duration::from_secs(60);
+
+/// compensation parameters stored in the sensor's NVM
+struct Calibration {
+    t1: f64,
+    t2: f64,
+    t3: f64,
+    p: [f64; 9],
+}
+
+/// BME280 in normal mode
+pub(crate) struct Bme280<I> {
+    i2c: I,
+    addr: u8,
+    calibration: Calibration,
+}
+
+impl<I: i2c::I2c> Bme280<I> {
+    /// read the calibration and start normal mode (x1 oversampling, 1 s standby)
+    pub(crate) fn new(mut i2c: I, addr: u8) -> Result<Self, I::Error> {
+        let mut id = [0_u8; 1];
+        i2c.write_read(addr, &[0xD0], &mut id)?;
+        log::info!("bme280's chip id: 0x{:x}", id[0]);
+
+        let mut buf = [0_u8; 24];
+        i2c.write_read(addr, &[0x88], &mut buf)?;
+        let u = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]) as f64;
+        let s = |i: usize| i16::from_le_bytes([buf[i], buf[i + 1]]) as f64;
+        let calibration = Calibration {
+            t1: u(0),
+            t2: s(2),
+            t3: s(4),
+            p: [u(6), s(8), s(10), s(12), s(14), s(16), s(18), s(20), s(22)],
+        };
+
+        // ctrl_hum must be written before ctrl_meas to take effect
+        i2c.write(addr, &[0xF2, 0x01])?;
+        i2c.write(addr, &[0xF5, 0xA0])?;
+        i2c.write(addr, &[0xF4, 0x27])?;
+        return Ok(Bme280 { i2c, addr, calibration });
+    }
+
+    /// read the compensated pressure in Pa
+    pub(crate) fn read_pressure(&mut self) -> Result<f64, I::Error> {
+        let mut buf = [0_u8; 6];
+        self.i2c.write_read(self.addr, &[0xF7], &mut buf)?;
+        let raw_p = ((buf[0] as u32) << 12 | (buf[1] as u32) << 4 | (buf[2] as u32) >> 4) as f64;
+        let raw_t = ((buf[3] as u32) << 12 | (buf[4] as u32) << 4 | (buf[5] as u32) >> 4) as f64;
+
+        // floating point compensation from the datasheet (section 8.1)
+        let c = &self.calibration;
+        let var1 = (raw_t / 16384.0 - c.t1 / 1024.0) * c.t2;
+        let var2 = (raw_t / 131072.0 - c.t1 / 8192.0) * (raw_t / 131072.0 - c.t1 / 8192.0) * c.t3;
+        let t_fine = var1 + var2;
+
+        let p = &c.p;
+        let mut var1 = t_fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * p[5] / 32768.0;
+        var2 += var1 * p[4] * 2.0;
+        var2 = var2 / 4.0 + p[3] * 65536.0;
+        var1 = (p[2] * var1 * var1 / 524288.0 + p[1] * var1) / 524288.0;
+        var1 = (1.0 + var1 / 32768.0) * p[0];
+        if var1 == 0.0 {
+            return Ok(0.0);
+        }
+        let mut pressure = 1048576.0 - raw_p;
+        pressure = (pressure - var2 / 4096.0) * 6250.0 / var1;
+        let var1 = p[8] * pressure * pressure / 2147483648.0;
+        let var2 = pressure * p[7] / 32768.0;
+        return Ok(pressure + (var1 + var2 + p[6]) / 16.0);
+    }
+}
+
+/// export the pressure from a background thread and send each reading (in Pa) to the returned channel
+pub(crate) fn spawn<I: i2c::I2c + Send + 'static>(i2c: I, addr: u8) -> Result<Receiver<f32>, I::Error>
+where
+    I::Error: fmt::Debug,
+{
+    let mut bme280 = Bme280::new(i2c, addr)?;
+    let gauge = metrics::gauge!("bme280_pressure_pa");
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || loop {
+        // the first result is ready after one standby period
+        thread::sleep(Duration::from_secs(1));
+        match bme280.read_pressure() {
+            Err(e) => log::warn!("failed to read bme280 pressure: {:?}", e),
+            Ok(p) => {
+                gauge.set(p);
+                if tx.send(p as f32).is_err() {
+                    return;
+                }
+            }
+        }
+        thread::sleep(INTERVAL);
+    });
+    return Ok(rx);
+}
@@ -0,0 +1,218 @@
+//! module for publishing measurements to a message bus, NATS or Kafka
+//! measurements are handed to a background thread which keeps the connection, reconnecting with backoff.
+//! what arrives within --broker-batch-interval of the first message is sent together, as one flush on NATS
+//! and one produce request per partition on Kafka. a batch is kept until the broker took it, so messages are
+//! delivered at least once. the Kafka record key is the sensor's serial, which keeps a sensor on one partition.
+use std::{
+    io,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+        OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    http::{self, Url},
+    kafka, latest, nats,
+    sensor::Envelope,
+    shutdown::Pending,
+};
+
+/// (scheme, tls, default port) of broker urls
+const SCHEMES: &[(&str, bool, u16)] = &[("nats", false, 4222), ("kafka", false, 9092)];
+
+/// an idle connection is checked this often
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// measurements waiting while the broker is unreachable; newer ones are dropped beyond this
+const QUEUE: usize = 1000;
+
+/// messages sent together at most
+const BATCH: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Protocol {
+    Nats,
+    Kafka,
+}
+
+impl Protocol {
+    fn name(self) -> &'static str {
+        return match self {
+            Protocol::Nats => "NATS",
+            Protocol::Kafka => "Kafka",
+        };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Target {
+    pub(crate) protocol: Protocol,
+    pub(crate) url: Url,
+}
+
+/// parse nats://[user:password@]host[:port] or kafka://host[:port], the bootstrap broker
+pub(crate) fn parse_url(s: &str) -> Result<Target, String> {
+    let url = http::parse_url_with(s, SCHEMES)?;
+    let protocol = if s.starts_with("kafka:") { Protocol::Kafka } else { Protocol::Nats };
+    return Ok(Target { protocol, url });
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Format {
+    /// the measurement as a JSON object
+    Json,
+    /// the measurement as a protocol buffers message, see Envelope::to_protobuf
+    Protobuf,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) target: Target,
+    /// NATS subject or Kafka topic, `{node_id}` and `{serial}` are replaced
+    pub(crate) topic: String,
+    pub(crate) node_id: String,
+    pub(crate) client_id: String,
+    pub(crate) format: Format,
+    pub(crate) batch_interval: Duration,
+}
+
+pub(crate) struct Message {
+    pub(crate) topic: String,
+    /// the sensor's serial, None until it's read
+    pub(crate) key: Option<String>,
+    pub(crate) payload: Vec<u8>,
+    pub(crate) timestamp_ms: u64,
+}
+
+/// a connection to a broker of either protocol
+pub(crate) trait Connection {
+    /// send `batch`, the number of messages the broker rejected for good
+    fn publish(&mut self, batch: &[Message]) -> io::Result<usize>;
+    /// check the idle connection
+    fn ping(&mut self) -> io::Result<()>;
+}
+
+struct Output {
+    config: Config,
+    queue: SyncSender<Message>,
+    dropped: metrics::Counter,
+}
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+static PENDING: Pending = Pending::new("broker");
+
+/// connect to the broker in a background thread
+pub(crate) fn init(config: Config) -> io::Result<()> {
+    let (tx, rx) = mpsc::sync_channel(QUEUE);
+    let client = config.clone();
+    thread::Builder::new().name(String::from("broker")).spawn(move || run(client, rx))?;
+    let output = Output { config, queue: tx, dropped: metrics::counter!("exporter_broker_dropped_total") };
+    let _ = OUTPUT.set(output);
+    PENDING.register();
+    return Ok(());
+}
+
+/// publish a measurement, if the message bus output is enabled
+pub(crate) fn publish(envelope: &Envelope) {
+    let Some(output) = OUTPUT.get() else {
+        return;
+    };
+    let config = &output.config;
+    let serial = latest::serial();
+    let topic = config.topic.replace("{node_id}", &config.node_id).replace("{serial}", serial.as_deref().unwrap_or("unknown"));
+    let payload = match config.format {
+        Format::Json => envelope.to_json().into_bytes(),
+        Format::Protobuf => envelope.to_protobuf(serial.as_deref()),
+    };
+    let message = Message { topic, key: serial, payload, timestamp_ms: envelope.timestamp_ms };
+    PENDING.add(1);
+    if output.queue.try_send(message).is_err() {
+        PENDING.done(1);
+        output.dropped.increment(1);
+    }
+}
+
+fn connect(config: &Config) -> io::Result<Box<dyn Connection>> {
+    return match config.target.protocol {
+        Protocol::Nats => Ok(Box::new(nats::Session::connect(&config.target.url, &config.client_id)?)),
+        Protocol::Kafka => Ok(Box::new(kafka::Session::connect(&config.target.url, &config.client_id)?)),
+    };
+}
+
+/// the next batch: the first message and what follows within the batch interval, None once the queue is gone
+fn collect(rx: &Receiver<Message>, connection: &mut dyn Connection, interval: Duration) -> io::Result<Option<Vec<Message>>> {
+    let first = loop {
+        match rx.recv_timeout(KEEP_ALIVE) {
+            Ok(m) => break m,
+            Err(RecvTimeoutError::Timeout) => connection.ping()?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(None),
+        }
+    };
+    let deadline = Instant::now() + interval;
+    let mut batch = vec![first];
+    while batch.len() < BATCH {
+        match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(m) => batch.push(m),
+            Err(_) => break,
+        }
+    }
+    return Ok(Some(batch));
+}
+
+fn run(config: Config, rx: Receiver<Message>) {
+    let url = &config.target.url;
+    let published = metrics::counter!("exporter_broker_published_total");
+    let dropped = metrics::counter!("exporter_broker_dropped_total");
+    let connected = metrics::gauge!("exporter_broker_connected");
+    let mut backoff = Duration::from_secs(1);
+    let mut pending: Option<Vec<Message>> = None;
+    loop {
+        let mut connection = match connect(&config) {
+            Ok(c) => {
+                log::info!("connected to {} broker {}:{}", config.target.protocol.name(), url.host, url.port);
+                backoff = Duration::from_secs(1);
+                connected.set(1);
+                c
+            }
+            Err(e) => {
+                log::warn!("failed to connect to {} broker: {:?}, retry in {:?}", config.target.protocol.name(), e, backoff);
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(Duration::from_secs(60));
+                continue;
+            }
+        };
+        let result = (|| -> io::Result<()> {
+            loop {
+                let batch = match pending.take() {
+                    Some(b) => b,
+                    None => match collect(&rx, &mut *connection, config.batch_interval)? {
+                        Some(b) => b,
+                        None => return Ok(()),
+                    },
+                };
+                match connection.publish(&batch) {
+                    Ok(rejected) => {
+                        published.increment((batch.len() - rejected) as u64);
+                        dropped.increment(rejected as u64);
+                        PENDING.done(batch.len());
+                    }
+                    Err(e) => {
+                        // sent again after reconnecting, so some messages may arrive twice
+                        pending = Some(batch);
+                        return Err(e);
+                    }
+                }
+            }
+        })();
+        connected.set(0);
+        match result {
+            Ok(()) => return,
+            Err(e) => log::warn!("lost {} broker connection: {:?}", config.target.protocol.name(), e),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! module for high-resolution burst capture
+//! a burst exports unfiltered, unsmoothed values for a while and stores them in their own CSV file
+//! (replayable with --replay). bursts are triggered with SIGUSR1 or `POST /api/v1/burst` on the admin API.
+//! the sensor measures as often as it can during a burst, every 5 s in periodic mode, and goes back to
+//! its mode afterwards. triggers while a burst is requested, running or cooling down are turned away.
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::sensor::Envelope;
+
+struct Config {
+    duration: Duration,
+    /// after a burst, how long until the next can start
+    cooldown: Duration,
+    dir: Option<PathBuf>,
+    active: metrics::Gauge,
+}
+
+struct Burst {
+    until: Instant,
+    file: Option<File>,
+}
+
+#[derive(Default)]
+struct State {
+    burst: Option<Burst>,
+    /// when the last burst ended
+    ended: Option<Instant>,
+}
+
+impl State {
+    /// how long until a burst can start at `now`, None if one can
+    fn busy(&self, config: &Config, now: Instant) -> Option<Duration> {
+        if let Some(b) = &self.burst {
+            return Some(b.until.saturating_duration_since(now) + config.cooldown);
+        }
+        let ready = self.ended? + config.cooldown;
+        return (now < ready).then(|| ready - now);
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+static PENDING: AtomicBool = AtomicBool::new(false);
+static STATE: Mutex<State> = Mutex::new(State { burst: None, ended: None });
+
+extern "C" fn on_signal(_: libc::c_int) {
+    trigger();
+}
+
+/// enable bursts of `duration` at most every `cooldown` after the last, stored in `dir`, and trigger them on SIGUSR1
+pub(crate) fn init(duration: Duration, cooldown: Duration, dir: Option<PathBuf>) -> io::Result<()> {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    if unsafe { libc::signal(libc::SIGUSR1, handler) } == libc::SIG_ERR {
+        return Err(io::Error::last_os_error());
+    }
+    let _ = CONFIG.set(Config { duration, cooldown, dir, active: metrics::gauge!("scd41_burst_active") });
+    return Ok(());
+}
+
+/// start a burst at the next measurement. only touches an atomic, so it's safe in a signal handler.
+pub(crate) fn trigger() {
+    PENDING.store(true, Ordering::Relaxed);
+}
+
+/// start a burst at the next measurement if none is requested, running or cooling down at `now`.
+/// None if bursts are disabled, Err with how long until one can start otherwise.
+pub(crate) fn request(now: Instant) -> Option<Result<Duration, Duration>> {
+    let config = CONFIG.get()?;
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if PENDING.load(Ordering::Relaxed) {
+        return Some(Err(config.duration + config.cooldown));
+    }
+    if let Some(wait) = state.busy(config, now) {
+        return Some(Err(wait));
+    }
+    trigger();
+    return Some(Ok(config.duration));
+}
+
+/// whether a burst is requested or running, so the sensor should measure as often as it can
+pub(crate) fn active() -> bool {
+    return PENDING.load(Ordering::Relaxed) || STATE.lock().unwrap_or_else(|e| e.into_inner()).burst.is_some();
+}
+
+/// store `e` if a burst is running and return whether one is
+pub(crate) fn record(e: &Envelope) -> bool {
+    let Some(config) = CONFIG.get() else {
+        return false;
+    };
+    let now = e.instant;
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if PENDING.swap(false, Ordering::Relaxed) {
+        match state.busy(config, now) {
+            Some(wait) => log::info!("ignore the burst trigger, the next burst can start in {:?}", wait),
+            None => {
+                log::info!("start {:?} burst capture", config.duration);
+                let file = config.dir.as_ref().and_then(|dir| {
+                    return open_file(dir, e.timestamp_ms).inspect_err(|e| log::warn!("failed to create burst file: {:?}", e)).ok();
+                });
+                state.burst = Some(Burst { until: now + config.duration, file });
+                config.active.set(1);
+            }
+        }
+    }
+    if state.burst.as_ref().is_some_and(|b| now >= b.until) {
+        log::info!("burst capture finished");
+        state.burst = None;
+        state.ended = Some(now);
+        config.active.set(0);
+    }
+    let Some(b) = state.burst.as_mut() else {
+        return false;
+    };
+    if let Some(f) = &mut b.file {
+        let m = &e.measurement;
+        let _ = writeln!(f, "{},{},{},{},{},{}", e.seq, e.timestamp_ms, m.co2, m.temperature, m.humidity, e.quality.bits())
+            .inspect_err(|e| log::warn!("failed to write burst file: {:?}", e));
+    }
+    return true;
+}
+
+fn open_file(dir: &Path, timestamp_ms: u64) -> io::Result<File> {
+    let path = dir.join(format!("burst-{}.csv", timestamp_ms));
+    let mut file = File::create(&path)?;
+    writeln!(file, "seq,timestamp_ms,co2,temperature,humidity,quality")?;
+    log::info!("store burst in {}", path.display());
+    return Ok(file);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cooldown() {
+        let config = Config { duration: Duration::from_secs(60), cooldown: Duration::from_secs(300), dir: None, active: metrics::gauge!("scd41_burst_active") };
+        let start = Instant::now();
+        let mut state = State::default();
+        assert_eq!(state.busy(&config, start), None);
+        state.burst = Some(Burst { until: start + config.duration, file: None });
+        // a running burst waits for its end and the cooldown
+        assert_eq!(state.busy(&config, start + Duration::from_secs(20)), Some(Duration::from_secs(340)));
+        state.burst = None;
+        state.ended = Some(start + config.duration);
+        assert_eq!(state.busy(&config, start + Duration::from_secs(100)), Some(Duration::from_secs(260)));
+        assert_eq!(state.busy(&config, start + Duration::from_secs(360)), None);
+    }
+}
@@ -0,0 +1,136 @@
+//! module for sharing one I2C bus between several devices
+//! access is granted one owner at a time in priority order, so multi-transaction commands
+//! (write, wait, read) from different threads never interleave.
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, ThreadId},
+    time::Instant,
+};
+
+use embedded_hal::i2c::{self, ErrorType, Operation};
+
+/// priority of bus access, higher goes first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Priority {
+    Maintenance,
+    Admin,
+    Measurement,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    owner: Option<ThreadId>,
+    /// nested acquisitions by the owner
+    depth: usize,
+    /// waiting (priority, ticket). earlier tickets win within a priority.
+    waiting: BinaryHeap<(Priority, Reverse<u64>)>,
+    next_ticket: u64,
+}
+
+/// grants exclusive bus ownership to one thread at a time
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Arbiter {
+    inner: Arc<(Mutex<Queue>, Condvar)>,
+}
+
+/// exclusive bus ownership, released on drop
+pub(crate) struct BusGuard<'a> {
+    arbiter: &'a Arbiter,
+}
+
+impl Arbiter {
+    /// take the bus for the current thread. blocks while another thread owns it or higher priority requests are waiting.
+    pub(crate) fn acquire(&self, priority: Priority) -> BusGuard<'_> {
+        let (lock, cond) = &*self.inner;
+        let me = thread::current().id();
+        let mut queue = lock.lock().unwrap_or_else(|e| e.into_inner());
+        if queue.owner == Some(me) {
+            queue.depth += 1;
+            return BusGuard { arbiter: self };
+        }
+
+        let ticket = (priority, Reverse(queue.next_ticket));
+        queue.next_ticket += 1;
+        queue.waiting.push(ticket);
+        while queue.owner.is_some() || queue.waiting.peek() != Some(&ticket) {
+            queue = cond.wait(queue).unwrap_or_else(|e| e.into_inner());
+        }
+        queue.waiting.pop();
+        queue.owner = Some(me);
+        queue.depth = 1;
+        return BusGuard { arbiter: self };
+    }
+}
+
+impl Drop for BusGuard<'_> {
+    fn drop(&mut self) {
+        let (lock, cond) = &*self.arbiter.inner;
+        let mut queue = lock.lock().unwrap_or_else(|e| e.into_inner());
+        queue.depth -= 1;
+        if queue.depth == 0 {
+            queue.owner = None;
+            cond.notify_all();
+        }
+    }
+}
+
+/// cloneable handle to an I2C bus. transactions outside of an acquired [BusGuard] take the bus
+/// for themselves at the handle's priority.
+#[derive(Debug)]
+pub(crate) struct SharedI2c<I> {
+    bus: Arc<Mutex<I>>,
+    arbiter: Arbiter,
+    priority: Priority,
+}
+
+impl<I> SharedI2c<I> {
+    pub(crate) fn new(bus: I) -> Self {
+        return SharedI2c {
+            bus: Arc::new(Mutex::new(bus)),
+            arbiter: Arbiter::default(),
+            priority: Priority::Measurement,
+        };
+    }
+
+    /// another handle to the same bus using `priority`
+    pub(crate) fn with_priority(&self, priority: Priority) -> Self {
+        return SharedI2c {
+            bus: self.bus.clone(),
+            arbiter: self.arbiter.clone(),
+            priority,
+        };
+    }
+
+    pub(crate) fn arbiter(&self) -> Arbiter {
+        return self.arbiter.clone();
+    }
+}
+
+impl<I> Clone for SharedI2c<I> {
+    fn clone(&self) -> Self {
+        return self.with_priority(self.priority);
+    }
+}
+
+impl<I: ErrorType> ErrorType for SharedI2c<I> {
+    type Error = I::Error;
+}
+
+impl<I: i2c::I2c> i2c::I2c for SharedI2c<I> {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        let _span = tracing::trace_span!("i2c", addr = %format_args!("0x{:02x}", address)).entered();
+        let _guard = self.arbiter.acquire(self.priority);
+        // a panic while holding the bus doesn't leave it in a broken state, so ignore poisoning
+        let mut bus = self.bus.lock().unwrap_or_else(|e| e.into_inner());
+        let start = Instant::now();
+        let result = bus.transaction(address, operations);
+        metrics::histogram!("i2c_transaction_duration_seconds", "addr" => format!("0x{:02x}", address))
+            .record(start.elapsed().as_secs_f64());
+        if result.is_err() {
+            metrics::counter!("i2c_transaction_errors_total", "addr" => format!("0x{:02x}", address)).increment(1);
+        }
+        return result;
+    }
+}
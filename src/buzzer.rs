@@ -0,0 +1,155 @@
+//! module for an audible CO2 alarm on a piezo buzzer
+//! while CO2 is at or above --buzzer-threshold the buzzer plays --buzzer-pattern, alternating on and off
+//! durations, over and over. an active buzzer is simply switched, a passive one is driven with a square
+//! wave of --buzzer-frequency. it stays silent during --buzzer-quiet-hours (local time), quiet --power-profile
+//! spans and while muted with `POST /api/v1/buzzer?for=DURATION`, `for=0` unmutes. `GET /api/v1/buzzer` reads the state.
+//! an --alert with `actuate=buzzer` sounds it too.
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use rppal::gpio::{Gpio, OutputPin};
+
+use crate::{
+    alerts::{self, Actuator},
+    clock::DailySpan,
+    error::Error,
+    http::{Request, Response},
+    profile,
+};
+
+/// how often a silent buzzer checks whether to sound
+const IDLE: Duration = Duration::from_millis(100);
+
+static MUTED_UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+static ALARM: AtomicBool = AtomicBool::new(false);
+static QUIET: Mutex<Option<DailySpan>> = Mutex::new(None);
+
+fn muted_for() -> Duration {
+    let until = *MUTED_UNTIL.lock().unwrap_or_else(|e| e.into_inner());
+    return until.map(|u| u.saturating_duration_since(Instant::now())).unwrap_or_default();
+}
+
+fn quiet() -> bool {
+    return QUIET.lock().unwrap_or_else(|e| e.into_inner()).is_some_and(|q| q.contains_now()) || profile::quiet();
+}
+
+fn alarm() -> bool {
+    return ALARM.load(Ordering::Relaxed) || alerts::actuating(Actuator::Buzzer);
+}
+
+/// whether the buzzer should sound right now
+fn sounding() -> bool {
+    return alarm() && muted_for().is_zero() && !quiet();
+}
+
+pub(crate) struct Config {
+    pub(crate) pin: u8,
+    /// square wave frequency for a passive buzzer, None for an active one
+    pub(crate) frequency: Option<f64>,
+    pub(crate) threshold: u16,
+    /// alternating on and off durations
+    pub(crate) pattern: Vec<Duration>,
+    pub(crate) quiet_hours: Option<DailySpan>,
+}
+
+pub(crate) struct Buzzer {
+    threshold: u16,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Buzzer {
+    /// claim the buzzer's pin and start the thread playing the pattern
+    pub(crate) fn spawn(config: Config) -> Result<Self, Error> {
+        if config.pattern.is_empty() || config.pattern.iter().all(|d| d.is_zero()) {
+            return Err(Error::Config(String::from("the buzzer pattern needs a duration")));
+        }
+        let pin = Gpio::new()?.get(config.pin)?.into_output_low();
+        *QUIET.lock().unwrap_or_else(|e| e.into_inner()) = config.quiet_hours;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = thread::Builder::new().name(String::from("buzzer")).spawn({
+            let stop = stop.clone();
+            move || play(pin, config.frequency, config.pattern, &stop)
+        })?;
+        return Ok(Buzzer { threshold: config.threshold, stop, thread: Some(thread) });
+    }
+
+    /// sound the alarm for `co2` at or above the threshold, None silences it
+    pub(crate) fn update(&self, co2: Option<u16>) {
+        let alarm = co2.is_some_and(|c| c >= self.threshold);
+        if ALARM.swap(alarm, Ordering::Relaxed) != alarm {
+            log::info!("buzzer alarm {}", if alarm { "raised" } else { "cleared" });
+        }
+    }
+}
+
+impl Drop for Buzzer {
+    fn drop(&mut self) {
+        ALARM.store(false, Ordering::Relaxed);
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn play(mut pin: OutputPin, frequency: Option<f64>, pattern: Vec<Duration>, stop: &AtomicBool) {
+    let gauge = metrics::gauge!("exporter_buzzer_sounding");
+    let switch = |pin: &mut OutputPin, on: bool| match (on, frequency) {
+        (true, Some(hz)) => {
+            let _ = pin.set_pwm_frequency(hz, 0.5).inspect_err(|e| log::warn!("failed to drive the buzzer: {:?}", e));
+        }
+        (true, None) => pin.set_high(),
+        (false, _) => {
+            let _ = pin.clear_pwm();
+            pin.set_low();
+        }
+    };
+    while !stop.load(Ordering::Relaxed) {
+        if !sounding() {
+            gauge.set(0);
+            thread::sleep(IDLE);
+            continue;
+        }
+        gauge.set(1);
+        // finish a round of the pattern before looking again, so a beep isn't cut short
+        for (i, duration) in pattern.iter().enumerate() {
+            switch(&mut pin, i % 2 == 0);
+            thread::sleep(*duration);
+        }
+        switch(&mut pin, false);
+    }
+    gauge.set(0);
+}
+
+/// handle /api/v1/buzzer
+pub(crate) fn handle(request: &Request) -> Response {
+    match request.method.as_str() {
+        "GET" => {}
+        "POST" => {
+            let Some(duration) = request.param("for").and_then(|d| humantime::parse_duration(d).ok()) else {
+                return Response::text(400, "give the mute duration as for=DURATION, e.g. for=30m\n");
+            };
+            let mut until = MUTED_UNTIL.lock().unwrap_or_else(|e| e.into_inner());
+            *until = Some(Instant::now() + duration).filter(|_| !duration.is_zero());
+            match duration.is_zero() {
+                true => log::info!("buzzer unmuted"),
+                false => log::info!("buzzer muted for {}", humantime::format_duration(duration)),
+            }
+        }
+        _ => return Response::text(405, "use GET or POST\n"),
+    }
+    return Response::json(format!(
+        r#"{{"alarm":{},"sounding":{},"quiet_hours":{},"muted_seconds":{}}}"#,
+        alarm(),
+        sounding(),
+        quiet(),
+        muted_for().as_secs()
+    ));
+}
@@ -0,0 +1,217 @@
+//! module for time sources
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// source of wall-clock and monotonic time
+pub(crate) trait Clock: Sync {
+    /// current wall-clock time
+    fn now(&self) -> SystemTime;
+    /// current monotonic time
+    fn instant(&self) -> Instant;
+    /// block for `duration`
+    fn sleep(&self, duration: Duration);
+}
+
+/// clock backed by the host's system time
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        return SystemTime::now();
+    }
+
+    fn instant(&self) -> Instant {
+        return Instant::now();
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// a clock shared between threads
+impl<C: Clock + Send + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> SystemTime {
+        return (**self).now();
+    }
+
+    fn instant(&self) -> Instant {
+        return (**self).instant();
+    }
+
+    fn sleep(&self, duration: Duration) {
+        (**self).sleep(duration);
+    }
+}
+
+/// days since 1970-01-01 (see http://howardhinnant.github.io/date_algorithms.html)
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    return era * 146097 + doe - 719468;
+}
+
+/// a daily span of local time, from `start` to `end` minutes after midnight, wrapping past midnight
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct DailySpan {
+    start: u16,
+    end: u16,
+}
+
+impl DailySpan {
+    fn contains(&self, minute: u16) -> bool {
+        return match self.start <= self.end {
+            true => (self.start..self.end).contains(&minute),
+            false => minute >= self.start || minute < self.end,
+        };
+    }
+
+    /// whether the local time is within the span
+    pub(crate) fn contains_now(&self) -> bool {
+        return self.contains(local_minute());
+    }
+}
+
+/// parse a daily span given as `HH:MM-HH:MM`
+pub(crate) fn parse_daily_span(s: &str) -> Result<DailySpan, String> {
+    let minute = |t: &str| -> Result<u16, String> {
+        let (h, m) = t.trim().split_once(':').ok_or_else(|| format!("{} is not HH:MM", t))?;
+        let (h, m): (u16, u16) = (h.parse().map_err(|e| format!("{}: {}", t, e))?, m.parse().map_err(|e| format!("{}: {}", t, e))?);
+        if h > 23 || m > 59 {
+            return Err(format!("{} is not a time of day", t));
+        }
+        return Ok(h * 60 + m);
+    };
+    let (start, end) = s.split_once('-').ok_or_else(|| format!("{} is not HH:MM-HH:MM", s))?;
+    return Ok(DailySpan { start: minute(start)?, end: minute(end)? });
+}
+
+const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// local days of the week (0 is Sunday) and time of day something is to run at
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Schedule {
+    days: Vec<u8>,
+    /// minutes after midnight
+    minute: u16,
+}
+
+impl Schedule {
+    /// whether it's time to run, checked more than once a minute. `last` is when it last was, the minute lasts
+    /// many checks and a clock stepping back mustn't repeat it.
+    pub(crate) fn due(&self, clock: &dyn Clock, last: &mut Option<Instant>) -> bool {
+        let (day, minute) = local_weekday_minute_at(clock.now());
+        let now = clock.instant();
+        if !self.days.contains(&day) || minute != self.minute || last.is_some_and(|l| now - l < Duration::from_secs(2 * 60 * 60)) {
+            return false;
+        }
+        *last = Some(now);
+        return true;
+    }
+}
+
+/// parse `DAY,...@HH:MM`, or `HH:MM` for every day
+pub(crate) fn parse_schedule(s: &str) -> Result<Schedule, String> {
+    let (days, time) = match s.split_once('@') {
+        Some((days, time)) => {
+            let days = days
+                .split(',')
+                .map(|d| DAYS.iter().position(|n| d.trim().eq_ignore_ascii_case(n)).map(|i| i as u8).ok_or_else(|| format!("{} is not a day like sun or mon", d)))
+                .collect::<Result<Vec<u8>, String>>()?;
+            (days, time)
+        }
+        None => ((0..7).collect(), s),
+    };
+    let (h, m) = time.trim().split_once(':').ok_or_else(|| format!("{} is not HH:MM", time))?;
+    let (h, m): (u16, u16) = (h.parse().map_err(|e| format!("{}: {}", time, e))?, m.parse().map_err(|e| format!("{}: {}", time, e))?);
+    if h > 23 || m > 59 {
+        return Err(format!("{} is not a time of day", time));
+    }
+    return Ok(Schedule { days, minute: h * 60 + m });
+}
+
+/// minutes since local midnight
+fn local_minute() -> u16 {
+    return local_weekday_minute().1;
+}
+
+/// the local day of the week (0 is Sunday) and minutes since midnight
+pub(crate) fn local_weekday_minute() -> (u8, u16) {
+    return local_weekday_minute_at(SystemTime::now());
+}
+
+/// the local day of the week (0 is Sunday) and minutes since midnight at `time`
+pub(crate) fn local_weekday_minute_at(time: SystemTime) -> (u8, u16) {
+    let now = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&now, &mut tm) };
+    return (tm.tm_wday as u8, (tm.tm_hour * 60 + tm.tm_min) as u16);
+}
+
+/// clock for tests, its time only moves when told to or slept on
+#[cfg(test)]
+pub(crate) struct FakeClock {
+    now: std::sync::Mutex<(SystemTime, Instant)>,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub(crate) fn new(now: SystemTime) -> Self {
+        return FakeClock { now: std::sync::Mutex::new((now, Instant::now())) };
+    }
+
+    /// step the wall-clock time, as NTP or an operator would, leaving the monotonic time
+    pub(crate) fn set(&self, now: SystemTime) {
+        self.now.lock().unwrap().0 = now;
+    }
+
+    /// let `duration` pass on both clocks
+    pub(crate) fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now = (now.0 + duration, now.1 + duration);
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> SystemTime {
+        return self.now.lock().unwrap().0;
+    }
+
+    fn instant(&self) -> Instant {
+        return self.now.lock().unwrap().1;
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedule_runs_once_when_the_clock_steps_back() {
+        let clock = FakeClock::new(UNIX_EPOCH + Duration::from_secs(1_800_000_000));
+        let (day, minute) = local_weekday_minute_at(clock.now());
+        let schedule = Schedule { days: vec![day], minute };
+        let mut last = None;
+        assert!(schedule.due(&clock, &mut last));
+        assert!(!schedule.due(&clock, &mut last));
+        // stepping back a minute makes it the scheduled minute again
+        clock.advance(Duration::from_secs(60));
+        clock.set(clock.now() - Duration::from_secs(60));
+        assert!(!schedule.due(&clock, &mut last));
+        // a week later it's due again
+        clock.advance(Duration::from_secs(7 * 24 * 60 * 60));
+        assert!(schedule.due(&clock, &mut last));
+    }
+}
@@ -0,0 +1,456 @@
+//! module for serving the latest reading over CoAP
+//! see RFC 7252 (CoAP) and RFC 7641 (observing resources)
+//! low-power clients GET or observe `/latest` (the JSON of /api/v1/latest) or `/co2` (plain text
+//! ppm), and are notified of every measurement without polling. notifications are non-confirmable,
+//! except for every `CONFIRM_EVERY`th, which a client that went away doesn't acknowledge before
+//! the next one, ending its observation.
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicU16, AtomicU32, Ordering},
+        Mutex, OnceLock,
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::latest;
+
+const VERSION: u8 = 1;
+const CON: u8 = 0;
+const NON: u8 = 1;
+const ACK: u8 = 2;
+const RST: u8 = 3;
+
+const EMPTY: u8 = 0x00;
+const GET: u8 = 0x01;
+const CONTENT: u8 = 0x45;
+const BAD_OPTION: u8 = 0x82;
+const NOT_FOUND: u8 = 0x84;
+const METHOD_NOT_ALLOWED: u8 = 0x85;
+const SERVICE_UNAVAILABLE: u8 = 0xA3;
+
+const OBSERVE: u16 = 6;
+const URI_PATH: u16 = 11;
+const CONTENT_FORMAT: u16 = 12;
+
+const TEXT_PLAIN: u8 = 0;
+const LINK_FORMAT: u8 = 40;
+const JSON: u8 = 50;
+
+const MAX_OBSERVERS: usize = 32;
+const CONFIRM_EVERY: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Resource {
+    Latest,
+    Co2,
+}
+
+struct Observer {
+    peer: SocketAddr,
+    token: Vec<u8>,
+    resource: Resource,
+    notifications: u32,
+    /// message id of the last notification, which a client rejects with a reset
+    last: Option<u16>,
+    /// message id of a confirmable notification not acknowledged yet
+    unacknowledged: Option<u16>,
+}
+
+struct Server {
+    socket: UdpSocket,
+    observers: Mutex<Vec<Observer>>,
+    message_id: AtomicU16,
+    /// the Observe option of notifications, 24 bits
+    sequence: AtomicU32,
+    gauge: metrics::Gauge,
+}
+
+static SERVER: OnceLock<Server> = OnceLock::new();
+
+#[derive(Debug, Default)]
+struct Message {
+    kind: u8,
+    code: u8,
+    message_id: u16,
+    token: Vec<u8>,
+    /// (number, value), sorted by number
+    options: Vec<(u16, Vec<u8>)>,
+    payload: Vec<u8>,
+}
+
+/// listen on `addr` and answer from a background thread
+pub(crate) fn init(addr: &str) -> io::Result<()> {
+    let socket = UdpSocket::bind(addr)?;
+    log::info!("serve coap on {}", socket.local_addr()?);
+    let receiver = socket.try_clone()?;
+    let server = SERVER.get_or_init(|| Server {
+        socket,
+        observers: Mutex::new(Vec::new()),
+        message_id: AtomicU16::new(rand_u16()),
+        sequence: AtomicU32::new(0),
+        gauge: metrics::gauge!("exporter_coap_observers"),
+    });
+    thread::Builder::new().name(String::from("coap")).spawn(move || {
+        let requests = metrics::counter!("exporter_coap_requests_total");
+        let mut buf = [0_u8; 1152];
+        loop {
+            let (n, peer) = match receiver.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) => {
+                    log::warn!("failed to receive coap message: {:?}", e);
+                    continue;
+                }
+            };
+            let Some(message) = Message::decode(&buf[..n]) else {
+                log::debug!("ignore malformed coap message from {}", peer);
+                continue;
+            };
+            if message.code != EMPTY {
+                requests.increment(1);
+            }
+            if let Some(response) = server.handle(peer, message) {
+                let _ = receiver.send_to(&response.encode(), peer).inspect_err(|e| log::debug!("failed to answer {}: {:?}", peer, e));
+            }
+        }
+    })?;
+    return Ok(());
+}
+
+/// notify the observers of a new reading, if the server is enabled
+pub(crate) fn notify() {
+    let Some(server) = SERVER.get() else {
+        return;
+    };
+    let mut observers = server.observers.lock().unwrap_or_else(|e| e.into_inner());
+    if observers.is_empty() {
+        return;
+    }
+    let sequence = server.sequence.fetch_add(1, Ordering::Relaxed).wrapping_add(1) & 0xFF_FFFF;
+    // a client that didn't acknowledge the previous confirmable notification is gone
+    observers.retain(|o| {
+        let confirm = (o.notifications + 1) % CONFIRM_EVERY == 0;
+        if confirm && o.unacknowledged.is_some() {
+            log::debug!("coap observer {} stopped acknowledging", o.peer);
+            return false;
+        }
+        return true;
+    });
+    for observer in observers.iter_mut() {
+        observer.notifications += 1;
+        let message_id = server.next_message_id();
+        let kind = if observer.notifications % CONFIRM_EVERY == 0 {
+            observer.unacknowledged = Some(message_id);
+            CON
+        } else {
+            NON
+        };
+        observer.last = Some(message_id);
+        let (code, format, payload) = representation(observer.resource);
+        let notification = Message {
+            kind,
+            code,
+            message_id,
+            token: observer.token.clone(),
+            options: vec![(OBSERVE, uint(sequence)), (CONTENT_FORMAT, uint(format as u32))],
+            payload,
+        };
+        let _ = server
+            .socket
+            .send_to(&notification.encode(), observer.peer)
+            .inspect_err(|e| log::debug!("failed to notify {}: {:?}", observer.peer, e));
+    }
+    server.gauge.set(observers.len() as f64);
+}
+
+impl Server {
+    fn next_message_id(&self) -> u16 {
+        return self.message_id.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn handle(&self, peer: SocketAddr, request: Message) -> Option<Message> {
+        match (request.kind, request.code) {
+            // an observer acknowledged or rejected a notification
+            (ACK, EMPTY) | (RST, EMPTY) => {
+                let mut observers = self.observers.lock().unwrap_or_else(|e| e.into_inner());
+                let id = Some(request.message_id);
+                if request.kind == RST {
+                    observers.retain(|o| !(o.peer == peer && (o.last == id || o.unacknowledged == id)));
+                } else {
+                    for o in observers.iter_mut().filter(|o| o.peer == peer && o.unacknowledged == id) {
+                        o.unacknowledged = None;
+                    }
+                }
+                self.gauge.set(observers.len() as f64);
+                return None;
+            }
+            // CoAP ping
+            (CON, EMPTY) => return Some(Message { kind: RST, message_id: request.message_id, ..Default::default() }),
+            (CON | NON, _) => {}
+            _ => return None,
+        }
+
+        let response = self.respond(peer, &request);
+        let (kind, message_id) = match request.kind {
+            // piggybacked on the acknowledgement
+            CON => (ACK, request.message_id),
+            _ => (NON, self.next_message_id()),
+        };
+        return Some(Message { kind, message_id, token: request.token, ..response });
+    }
+
+    /// code, options and payload of the response to a request
+    fn respond(&self, peer: SocketAddr, request: &Message) -> Message {
+        let error = |code: u8, text: &str| Message { code, payload: text.as_bytes().to_vec(), ..Default::default() };
+        // unrecognized critical (odd) options must be rejected, known elective ones are ignored
+        if let Some((number, _)) = request.options.iter().find(|(n, _)| n % 2 == 1 && ![3, 7, 11, 15].contains(n)) {
+            return error(BAD_OPTION, &format!("unsupported option {}", number));
+        }
+        let path: Vec<String> = request
+            .options
+            .iter()
+            .filter(|(n, _)| *n == URI_PATH)
+            .map(|(_, v)| String::from_utf8_lossy(v).into_owned())
+            .collect();
+        let resource = match path.join("/").as_str() {
+            ".well-known/core" => None,
+            "latest" => Some(Resource::Latest),
+            "co2" => Some(Resource::Co2),
+            _ => return error(NOT_FOUND, "not found"),
+        };
+        if request.code != GET {
+            return error(METHOD_NOT_ALLOWED, "only GET is supported");
+        }
+        let Some(resource) = resource else {
+            let links = r#"</latest>;rt="scd41.latest";ct=50;obs,</co2>;rt="scd41.co2";ct=0;obs"#;
+            return Message {
+                code: CONTENT,
+                options: vec![(CONTENT_FORMAT, uint(LINK_FORMAT as u32))],
+                payload: links.as_bytes().to_vec(),
+                ..Default::default()
+            };
+        };
+        let (code, format, payload) = representation(resource);
+        let mut options = Vec::new();
+        let observe = request.options.iter().find(|(n, _)| *n == OBSERVE).map(|(_, v)| read_uint(v));
+        let mut observers = self.observers.lock().unwrap_or_else(|e| e.into_inner());
+        // registering again with the same token replaces the observation
+        observers.retain(|o| !(o.peer == peer && o.token == request.token));
+        match observe {
+            // only successful responses establish an observation
+            Some(0) if code == CONTENT => {
+                if observers.len() < MAX_OBSERVERS {
+                    let token = request.token.clone();
+                    observers.push(Observer { peer, token, resource, notifications: 0, last: None, unacknowledged: None });
+                    options.push((OBSERVE, uint(self.sequence.load(Ordering::Relaxed) & 0xFF_FFFF)));
+                } else {
+                    log::warn!("too many coap observers, answer {} without observing", peer);
+                }
+            }
+            _ => {}
+        }
+        self.gauge.set(observers.len() as f64);
+        options.push((CONTENT_FORMAT, uint(format as u32)));
+        return Message { code, options, payload, ..Default::default() };
+    }
+}
+
+/// code, content format and payload of a resource
+fn representation(resource: Resource) -> (u8, u8, Vec<u8>) {
+    let (envelope, _, _) = latest::get();
+    let Some(envelope) = envelope else {
+        return (SERVICE_UNAVAILABLE, JSON, br#"{"status":"starting"}"#.to_vec());
+    };
+    return match resource {
+        Resource::Latest => (CONTENT, JSON, latest::to_json().unwrap_or_default().into_bytes()),
+        Resource::Co2 => (CONTENT, TEXT_PLAIN, envelope.measurement.co2.to_string().into_bytes()),
+    };
+}
+
+/// the shortest big endian encoding, empty for 0
+fn uint(v: u32) -> Vec<u8> {
+    let bytes = v.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    return bytes[skip..].to_vec();
+}
+
+fn read_uint(v: &[u8]) -> u32 {
+    return v.iter().take(4).fold(0, |n, b| (n << 8) | *b as u32);
+}
+
+/// a start for the message ids, so a restarted server doesn't repeat recent ones
+fn rand_u16() -> u16 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or_default();
+    return (nanos ^ std::process::id()) as u16;
+}
+
+impl Message {
+    fn decode(buf: &[u8]) -> Option<Message> {
+        let (first, code) = (*buf.first()?, *buf.get(1)?);
+        let token_len = (first & 0x0F) as usize;
+        if first >> 6 != VERSION || token_len > 8 {
+            return None;
+        }
+        let message_id = u16::from_be_bytes([*buf.get(2)?, *buf.get(3)?]);
+        let token = buf.get(4..4 + token_len)?.to_vec();
+        let mut rest = &buf[4 + token_len..];
+        let mut options = Vec::new();
+        let mut number = 0_u16;
+        while let Some(&byte) = rest.first() {
+            if byte == 0xFF {
+                rest = &rest[1..];
+                // a payload marker must be followed by a payload
+                if rest.is_empty() {
+                    return None;
+                }
+                break;
+            }
+            rest = &rest[1..];
+            // 13 and 14 extend the nibble by one or two bytes, 15 is reserved
+            let mut extended = |nibble: u8| -> Option<u16> {
+                return match nibble {
+                    0..=12 => Some(nibble as u16),
+                    13 => {
+                        let v = *rest.first()? as u16 + 13;
+                        rest = &rest[1..];
+                        Some(v)
+                    }
+                    14 => {
+                        let v = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]).checked_add(269)?;
+                        rest = &rest[2..];
+                        Some(v)
+                    }
+                    _ => None,
+                };
+            };
+            let delta = extended(byte >> 4)?;
+            let len = extended(byte & 0x0F)? as usize;
+            number = number.checked_add(delta)?;
+            options.push((number, rest.get(..len)?.to_vec()));
+            rest = &rest[len..];
+        }
+        return Some(Message { kind: (first >> 4) & 0x03, code, message_id, token, options, payload: rest.to_vec() });
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![(VERSION << 6) | (self.kind << 4) | self.token.len() as u8, self.code];
+        out.extend_from_slice(&self.message_id.to_be_bytes());
+        out.extend_from_slice(&self.token);
+        let nibble = |v: usize| -> (u8, Vec<u8>) {
+            return match v {
+                0..=12 => (v as u8, Vec::new()),
+                13..=268 => (13, vec![(v - 13) as u8]),
+                _ => (14, ((v - 269) as u16).to_be_bytes().to_vec()),
+            };
+        };
+        let mut options = self.options.clone();
+        options.sort_by_key(|(n, _)| *n);
+        let mut previous = 0;
+        for (number, value) in &options {
+            let (delta, delta_ext) = nibble((number - previous) as usize);
+            let (len, len_ext) = nibble(value.len());
+            out.push((delta << 4) | len);
+            out.extend_from_slice(&delta_ext);
+            out.extend_from_slice(&len_ext);
+            out.extend_from_slice(value);
+            previous = *number;
+        }
+        if !self.payload.is_empty() {
+            out.push(0xFF);
+            out.extend_from_slice(&self.payload);
+        }
+        return out;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(options: Vec<(u16, Vec<u8>)>) -> Message {
+        return Message { kind: CON, code: GET, message_id: 0x1234, token: vec![0xAB, 0xCD], options, payload: Vec::new() };
+    }
+
+    fn server() -> Server {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        return Server { socket, observers: Mutex::new(Vec::new()), message_id: AtomicU16::new(0), sequence: AtomicU32::new(0), gauge: metrics::gauge!("test") };
+    }
+
+    #[test]
+    fn header_and_short_options() {
+        let message = Message { payload: b"hi".to_vec(), ..get(vec![(URI_PATH, b"co2".to_vec())]) };
+        let encoded = message.encode();
+        assert_eq!(encoded, [0x42, 0x01, 0x12, 0x34, 0xAB, 0xCD, 0xB3, b'c', b'o', b'2', 0xFF, b'h', b'i']);
+        let decoded = Message::decode(&encoded).unwrap();
+        assert_eq!((decoded.kind, decoded.code, decoded.message_id), (CON, GET, 0x1234));
+        assert_eq!(decoded.token, [0xAB, 0xCD]);
+        assert_eq!(decoded.options, message.options);
+        assert_eq!(decoded.payload, b"hi");
+    }
+
+    #[test]
+    fn option_deltas() {
+        // repeated numbers have a delta of 0, and deltas and lengths past 12 take one or two more bytes
+        let options = vec![
+            (URI_PATH, b"a".to_vec()),
+            (URI_PATH, b"b".to_vec()),
+            (CONTENT_FORMAT, Vec::new()),
+            (25, vec![1; 13]),
+            (268, vec![2; 268]),
+            (269, vec![3; 269]),
+            (2000, Vec::new()),
+            (u16::MAX, Vec::new()),
+        ];
+        let encoded = get(options.clone()).encode();
+        assert_eq!(encoded[6..10], [0xB1, b'a', 0x01, b'b']);
+        // 25 is 13 after 12 and 13 bytes long
+        assert_eq!(encoded[11..14], [0xDD, 0x00, 0x00]);
+        assert_eq!(Message::decode(&encoded).unwrap().options, options);
+        // a delta of 1731 past 269
+        let encoded = get(vec![(2000, Vec::new())]).encode();
+        assert_eq!(encoded[6..], [0xE0, 0x06, 0xC3]);
+    }
+
+    #[test]
+    fn malformed_messages() {
+        let header = [0x40, 0x01, 0x00, 0x01];
+        assert!(Message::decode(&header).is_some());
+        // version 2 and 9 byte tokens
+        assert!(Message::decode(&[0x80, 0x01, 0x00, 0x01]).is_none());
+        assert!(Message::decode(&[0x49, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_none());
+        // a payload marker without payload, the reserved nibble, and option numbers past 65535
+        assert!(Message::decode(&[&header[..], &[0xFF]].concat()).is_none());
+        assert!(Message::decode(&[&header[..], &[0xF0]].concat()).is_none());
+        assert!(Message::decode(&[&header[..], &[0x0F]].concat()).is_none());
+        assert!(Message::decode(&[&header[..], &[0xE0, 0xFF, 0xFF]].concat()).is_none());
+        assert!(Message::decode(&[&header[..], &[0xE0, 0xFE, 0xF2, 0x10, 0x00]].concat()).is_none());
+        // truncated anywhere within an option
+        let encoded = get(vec![(URI_PATH, b"latest".to_vec()), (2000, vec![7; 300])]).encode();
+        for len in 0..encoded.len() {
+            let complete = len == 6 || len == 13;
+            assert_eq!(Message::decode(&encoded[..len]).is_some(), complete, "truncated to {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn requests() {
+        let server = server();
+        let peer: SocketAddr = "127.0.0.1:5683".parse().unwrap();
+        // ping
+        let ping = Message { code: EMPTY, token: Vec::new(), ..get(Vec::new()) };
+        let pong = server.handle(peer, ping).unwrap();
+        assert_eq!((pong.kind, pong.message_id), (RST, 0x1234));
+        // piggybacked errors echo the token
+        let response = server.handle(peer, get(vec![(URI_PATH, b"nope".to_vec())])).unwrap();
+        assert_eq!((response.kind, response.code, response.message_id, response.token), (ACK, NOT_FOUND, 0x1234, vec![0xAB, 0xCD]));
+        let response = server.handle(peer, get(vec![(URI_PATH, b"co2".to_vec()), (9, Vec::new())])).unwrap();
+        assert_eq!(response.code, BAD_OPTION);
+        let discovery = server.handle(peer, get(vec![(URI_PATH, b".well-known".to_vec()), (URI_PATH, b"core".to_vec())])).unwrap();
+        assert_eq!(discovery.code, CONTENT);
+        assert_eq!(discovery.options, [(CONTENT_FORMAT, vec![LINK_FORMAT])]);
+        // resets for what we didn't send are no answer
+        assert!(server.handle(peer, Message { kind: RST, code: EMPTY, ..get(Vec::new()) }).is_none());
+    }
+}
@@ -0,0 +1,97 @@
+//! module for compensating the heat of a Pi sharing the sensor's case
+//! the sensor is warmed in proportion to how much warmer the SoC is than itself, so air = sensor - K (soc - sensor)
+//! with --self-heating K. --self-heating-learn fits K instead over a phase after a cold start, taking the air to
+//! hold the temperature the sensor started at while the Pi warms up. the humidity is corrected for the same air,
+//! and the uncompensated values are exported as scd41_*_uncompensated. while learning they're served as they are.
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use scd4x::Measurement;
+
+use crate::derived;
+
+/// fewest samples to fit the coefficient on
+const MIN_SAMPLES: u32 = 10;
+
+enum Coefficient {
+    Fixed(f32),
+    /// sums of x·y and x² of the fit, x being soc - sensor and y how far the sensor warmed
+    Learning { until: Instant, start: Option<f32>, xy: f64, xx: f64, samples: u32 },
+}
+
+pub(crate) struct Compensator {
+    soc_file: PathBuf,
+    coefficient: Coefficient,
+    coefficient_gauge: metrics::Gauge,
+    temperature: metrics::Gauge,
+    humidity: metrics::Gauge,
+}
+
+impl Compensator {
+    /// with a fixed `coefficient`, or learning it for `learn`. `labels` are added to every series
+    pub(crate) fn new(soc_file: PathBuf, coefficient: Option<f32>, learn: Duration, labels: &[(String, String)]) -> Self {
+        let coefficient = match coefficient {
+            Some(k) => Coefficient::Fixed(k),
+            None => {
+                log::info!("learn the self-heating coefficient over {}", humantime::format_duration(learn));
+                Coefficient::Learning { until: Instant::now() + learn, start: None, xy: 0.0, xx: 0.0, samples: 0 }
+            }
+        };
+        let compensator = Compensator {
+            soc_file,
+            coefficient,
+            coefficient_gauge: metrics::gauge!("scd41_self_heating_coefficient", labels),
+            temperature: metrics::gauge!("scd41_temperature_celsius_uncompensated", labels),
+            humidity: metrics::gauge!("scd41_humidity_rh_uncompensated", labels),
+        };
+        if let Coefficient::Fixed(k) = compensator.coefficient {
+            compensator.coefficient_gauge.set(k);
+        } else {
+            compensator.coefficient_gauge.set(f64::NAN);
+        }
+        return compensator;
+    }
+
+    /// no uncompensated values while the values are stale
+    pub(crate) fn clear(&self) {
+        self.temperature.set(f64::NAN);
+        self.humidity.set(f64::NAN);
+    }
+
+    fn soc_temperature(&self) -> Option<f32> {
+        let millidegrees: f32 = fs::read_to_string(&self.soc_file).ok()?.trim().parse().ok()?;
+        return Some(millidegrees / 1000.0);
+    }
+
+    /// `m` with the air's temperature and humidity, None while the coefficient is being learned or the SoC is unreadable
+    pub(crate) fn apply(&mut self, now: Instant, m: &Measurement) -> Option<Measurement> {
+        self.temperature.set(m.temperature);
+        self.humidity.set(m.humidity);
+        let soc = self.soc_temperature()?;
+        let x = soc - m.temperature;
+        let k = match &mut self.coefficient {
+            Coefficient::Fixed(k) => *k,
+            Coefficient::Learning { until, start, xy, xx, samples } if now < *until => {
+                let start = *start.get_or_insert(m.temperature);
+                *xy += (x * (m.temperature - start)) as f64;
+                *xx += (x * x) as f64;
+                *samples += 1;
+                return None;
+            }
+            Coefficient::Learning { xy, xx, samples, .. } => {
+                let k = if *samples >= MIN_SAMPLES && *xx > 0.0 { (*xy / *xx).max(0.0) as f32 } else { 0.0 };
+                log::info!("learned a self-heating coefficient of {:.3}, pass --self-heating {:.3} to skip learning", k, k);
+                self.coefficient = Coefficient::Fixed(k);
+                self.coefficient_gauge.set(k);
+                k
+            }
+        };
+        let temperature = m.temperature - k * x;
+        // the same water vapor in cooler air is a higher relative humidity
+        let humidity = m.humidity * derived::saturation_vapor_pressure(m.temperature) / derived::saturation_vapor_pressure(temperature);
+        return Some(Measurement { co2: m.co2, temperature, humidity: humidity.clamp(0.0, 100.0) });
+    }
+}
@@ -0,0 +1,151 @@
+//! module for changing the sensor's settings at runtime
+//! local interfaces (D-Bus, the admin HTTP API) queue requests to the measurement loop, which applies them between
+//! measurements while holding the bus, and wait for the outcome.
+//! the loop publishes the resulting settings, so reading them doesn't need the bus.
+use std::{
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use crate::{
+    clock::Clock,
+    events,
+    sensor::{Sensor, Settings},
+    state,
+};
+
+/// longest wait for the loop to apply a request, it may be busy recovering the sensor
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Action {
+    /// temperature offset in degC
+    SetTemperatureOffset(f32),
+    /// altitude in meters above sea level
+    SetAltitude(u16),
+    /// ambient pressure in Pa
+    SetAmbientPressure(f32),
+    SetAutomaticSelfCalibration(bool),
+    /// forced recalibration to a reference in ppm
+    ForceRecalibration(u16),
+    SelfTest,
+}
+
+/// the correction of a forced recalibration (ppm), 0 for other actions. a failed self-test is an error.
+pub(crate) type Outcome = Result<i16, String>;
+
+pub(crate) struct Request {
+    action: Action,
+    reply: SyncSender<Outcome>,
+}
+
+static QUEUE: OnceLock<SyncSender<Request>> = OnceLock::new();
+static SETTINGS: Mutex<Option<Settings>> = Mutex::new(None);
+
+/// queue for the measurement loop
+pub(crate) fn init() -> Receiver<Request> {
+    let (tx, rx) = mpsc::sync_channel(4);
+    let _ = QUEUE.set(tx);
+    return rx;
+}
+
+/// have the measurement loop apply `action`, blocking until it did
+pub(crate) fn submit(action: Action) -> Outcome {
+    let queue = QUEUE.get().ok_or("no sensor is running")?;
+    let (tx, rx) = mpsc::sync_channel(1);
+    queue.try_send(Request { action, reply: tx }).map_err(|_| "too many pending requests")?;
+    return rx.recv_timeout(TIMEOUT).map_err(|_| String::from("the sensor didn't answer in time"))?;
+}
+
+/// keep the sensor's current settings for `settings`
+pub(crate) fn publish(settings: Settings) {
+    state::applied(settings);
+    *SETTINGS.lock().unwrap_or_else(|e| e.into_inner()) = Some(settings);
+}
+
+/// the sensor's settings, None until it started
+pub(crate) fn settings() -> Option<Settings> {
+    return *SETTINGS.lock().unwrap_or_else(|e| e.into_inner());
+}
+
+/// apply a queued request, the caller holds the bus. true if the settings changed, so the next sample may still be settling.
+pub(crate) fn apply<S: Sensor>(sensor: &mut S, clock: &dyn Clock, request: Request) -> bool {
+    let outcome = perform(sensor, clock, request.action);
+    let changed = outcome.is_ok();
+    let _ = request.reply.send(outcome);
+    return changed;
+}
+
+/// apply `action` right away and publish the resulting settings, the caller holds the bus
+pub(crate) fn perform<S: Sensor>(sensor: &mut S, clock: &dyn Clock, action: Action) -> Outcome {
+    let _span = tracing::debug_span!("command", action = ?action).entered();
+    let outcome = match action {
+        Action::SetTemperatureOffset(offset) => match sensor.set_temperature_offset(offset) {
+            Ok(true) => {
+                events::record(clock, "offset", format!("temperature offset set to {} degC", offset));
+                Ok(0)
+            }
+            Ok(false) => Err(String::from("the sensor has no temperature offset")),
+            Err(e) => Err(format!("failed to set temperature offset: {:?}", e)),
+        },
+        Action::SetAltitude(meters) => match sensor.set_altitude(meters) {
+            Ok(true) => {
+                events::record(clock, "altitude", format!("altitude set to {} m", meters));
+                Ok(0)
+            }
+            Ok(false) => Err(String::from("the sensor has no altitude setting")),
+            Err(e) => Err(format!("failed to set altitude: {:?}", e)),
+        },
+        Action::SetAmbientPressure(pressure) => match sensor.set_ambient_pressure(pressure) {
+            // the default ignores the pressure, so only the settings tell whether it's used
+            Ok(()) if sensor.settings().ambient_pressure.is_none() => Err(String::from("the sensor has no pressure compensation")),
+            Ok(()) => {
+                events::record(clock, "pressure", format!("ambient pressure set to {} Pa", pressure));
+                Ok(0)
+            }
+            Err(e) => Err(format!("failed to set ambient pressure: {:?}", e)),
+        },
+        Action::SetAutomaticSelfCalibration(enabled) => match sensor.set_automatic_self_calibration(enabled) {
+            Ok(true) => {
+                events::record(clock, "asc", format!("automatic self-calibration {}", if enabled { "enabled" } else { "disabled" }));
+                Ok(0)
+            }
+            Ok(false) => Err(String::from("the sensor has no automatic self-calibration")),
+            Err(e) => Err(format!("failed to set automatic self-calibration: {:?}", e)),
+        },
+        Action::ForceRecalibration(target) => match sensor.force_recalibration(target) {
+            Ok(Some(correction)) => {
+                events::record(clock, "recalibration", format!("forced recalibration to {} ppm, corrected by {} ppm", target, correction));
+                state::recalibrated(clock, target, correction);
+                Ok(correction)
+            }
+            Ok(None) => Err(String::from("the sensor doesn't support or rejected forced recalibration")),
+            Err(e) => Err(format!("failed to recalibrate: {:?}", e)),
+        },
+        Action::SelfTest => match sensor.self_test() {
+            Ok(Some(word)) => {
+                state::self_tested(clock, word);
+                match word {
+                    0 => {
+                        events::record(clock, "self-test", String::from("self-test passed"));
+                        Ok(0)
+                    }
+                    _ => {
+                        events::record(clock, "self-test", format!("self-test found a malfunction (0x{:04x})", word));
+                        Err(format!("the self-test found a malfunction (0x{:04x})", word))
+                    }
+                }
+            }
+            Ok(None) => Err(String::from("the sensor has no self-test")),
+            Err(e) => Err(format!("failed to run the self-test: {:?}", e)),
+        },
+    };
+    if let Err(e) = &outcome {
+        log::warn!("{}", e);
+    }
+    publish(sensor.settings());
+    return outcome;
+}
@@ -0,0 +1,174 @@
+//! module for logging measurements to rotating CSV files
+//! for sites without network, where the files are collected by hand later. every measurement is
+//! appended to `<dir>/measurements-<start time>.csv` (replayable with --replay). a new file is
+//! started when the current one exceeds the size limit or, with daily rotation, when the UTC day
+//! changes; only the newest files are kept.
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        OnceLock,
+    },
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use crate::{sensor::Envelope, shutdown::Pending};
+
+const HEADER: &str = "timestamp,timestamp_ms,seq,source,co2,temperature,humidity,quality";
+/// lines waiting for a slow SD card; newer ones are dropped beyond this
+const QUEUE: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) dir: PathBuf,
+    /// start a new file beyond this size, None disables size rotation
+    pub(crate) max_bytes: Option<u64>,
+    /// start a new file when the UTC day changes
+    pub(crate) daily: bool,
+    /// number of files kept, including the current one
+    pub(crate) keep: usize,
+}
+
+struct Output {
+    queue: SyncSender<(u64, String)>,
+    dropped: metrics::Counter,
+}
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+static PENDING: Pending = Pending::new("csv log");
+
+/// write in a background thread
+pub(crate) fn init(config: Config) -> io::Result<()> {
+    fs::create_dir_all(&config.dir)?;
+    let (tx, rx) = mpsc::sync_channel(QUEUE);
+    log::info!("log measurements to {}", config.dir.display());
+    thread::Builder::new().name(String::from("csvlog")).spawn(move || run(config, rx))?;
+    let _ = OUTPUT.set(Output { queue: tx, dropped: metrics::counter!("exporter_csv_dropped_total") });
+    PENDING.register();
+    return Ok(());
+}
+
+/// append a measurement, if logging is enabled
+pub(crate) fn write(envelope: &Envelope) {
+    let Some(output) = OUTPUT.get() else {
+        return;
+    };
+    let m = &envelope.measurement;
+    let line = format!(
+        "{},{},{},{},{},{},{},{}\n",
+        rfc3339(envelope.timestamp_ms),
+        envelope.timestamp_ms,
+        envelope.seq,
+        envelope.source,
+        m.co2,
+        m.temperature,
+        m.humidity,
+        envelope.quality.bits()
+    );
+    PENDING.add(1);
+    if output.queue.try_send((envelope.timestamp_ms, line)).is_err() {
+        PENDING.done(1);
+        output.dropped.increment(1);
+    }
+}
+
+fn rfc3339(timestamp_ms: u64) -> String {
+    return humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_millis(timestamp_ms)).to_string();
+}
+
+/// the file currently written
+struct Current {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    /// UTC date (YYYY-MM-DD) the file was started on
+    day: String,
+}
+
+fn run(config: Config, rx: Receiver<(u64, String)>) {
+    let failures = metrics::counter!("exporter_csv_write_failures_total");
+    let mut current: Option<Current> = None;
+    for (timestamp_ms, line) in rx {
+        let day = rfc3339(timestamp_ms)[..10].to_string();
+        let rotate = current
+            .as_ref()
+            .is_some_and(|c| (config.daily && c.day != day) || config.max_bytes.is_some_and(|max| c.size >= max));
+        if rotate {
+            current = None;
+        }
+        if current.is_none() {
+            match open(&config, timestamp_ms, &day) {
+                Ok(c) => current = Some(c),
+                Err(e) => {
+                    log::warn!("failed to open csv log in {}: {:?}", config.dir.display(), e);
+                    failures.increment(1);
+                    PENDING.done(1);
+                    continue;
+                }
+            }
+            prune(&config.dir, config.keep);
+        }
+        let Some(c) = current.as_mut() else {
+            PENDING.done(1);
+            continue;
+        };
+        match c.file.write_all(line.as_bytes()) {
+            Ok(()) => c.size += line.len() as u64,
+            Err(e) => {
+                log::warn!("failed to write {}: {:?}", c.path.display(), e);
+                failures.increment(1);
+                // reopen, the card may have been remounted
+                current = None;
+            }
+        }
+        PENDING.done(1);
+    }
+}
+
+/// log files in `dir`, oldest first
+fn files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            let name = p.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            return name.starts_with("measurements-") && name.ends_with(".csv");
+        })
+        .collect();
+    // the start time in the name sorts chronologically
+    files.sort();
+    return Ok(files);
+}
+
+/// continue the newest file if it's still within the limits, otherwise start a new one
+fn open(config: &Config, timestamp_ms: u64, day: &str) -> io::Result<Current> {
+    if let Some(path) = files(&config.dir)?.pop() {
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        let file_day = name.trim_start_matches("measurements-").get(..10).unwrap_or_default().to_string();
+        let size = fs::metadata(&path)?.len();
+        if (!config.daily || file_day == day) && config.max_bytes.is_none_or(|max| size < max) {
+            let file = OpenOptions::new().append(true).open(&path)?;
+            log::debug!("continue csv log {}", path.display());
+            return Ok(Current { path, file, size, day: file_day });
+        }
+    }
+    // colons aren't allowed in file names on the FAT partitions the files are usually copied to
+    let path = config.dir.join(format!("measurements-{}.csv", rfc3339(timestamp_ms).replace(':', "-")));
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    file.write_all(format!("{}\n", HEADER).as_bytes())?;
+    log::info!("start csv log {}", path.display());
+    return Ok(Current { path, file, size: HEADER.len() as u64 + 1, day: day.to_string() });
+}
+
+/// remove all but the newest `keep` files
+fn prune(dir: &Path, keep: usize) {
+    let Ok(files) = files(dir) else {
+        return;
+    };
+    for path in files.iter().take(files.len().saturating_sub(keep)) {
+        log::info!("remove old csv log {}", path.display());
+        let _ = fs::remove_file(path).inspect_err(|e| log::warn!("failed to remove {}: {:?}", path.display(), e));
+    }
+}
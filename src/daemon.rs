@@ -0,0 +1,77 @@
+//! module for --daemonize and --pidfile, for init systems without service supervision (OpenRC, busybox init)
+//! the process forks into the background in a new session with stdin on /dev/null and stdout and stderr
+//! appended to --log-file. it stays in the working directory, so relative paths in the options still work.
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+static PIDFILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+fn fork() -> io::Result<libc::pid_t> {
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    return Ok(pid);
+}
+
+fn redirect(file: &File, fd: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::dup2(file.as_raw_fd(), fd) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    return Ok(());
+}
+
+/// move to the background. call it before any thread is started, only the calling thread survives a fork.
+pub(crate) fn daemonize(log_file: Option<&Path>) -> io::Result<()> {
+    // open before forking, so a bad path is still reported on the terminal
+    let null = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+    let log = match log_file {
+        Some(path) => OpenOptions::new().create(true).append(true).open(path)?,
+        None => null.try_clone()?,
+    };
+    if fork()? > 0 {
+        unsafe { libc::_exit(0) };
+    }
+    // leave the terminal's session, so its hangup doesn't reach us
+    if unsafe { libc::setsid() } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // a session leader could acquire a controlling terminal again
+    if fork()? > 0 {
+        unsafe { libc::_exit(0) };
+    }
+    redirect(&null, libc::STDIN_FILENO)?;
+    redirect(&log, libc::STDOUT_FILENO)?;
+    redirect(&log, libc::STDERR_FILENO)?;
+    return Ok(());
+}
+
+/// the pid in the pidfile `path` if it's of another running process
+pub(crate) fn running(path: &Path) -> Option<libc::pid_t> {
+    let pid = fs::read_to_string(path).ok()?.trim().parse::<libc::pid_t>().ok()?;
+    return (pid != std::process::id() as libc::pid_t && unsafe { libc::kill(pid, 0) } == 0).then_some(pid);
+}
+
+/// write our pid to `path`, refusing if the pid in it is of a running process
+pub(crate) fn write_pidfile(path: &Path) -> io::Result<()> {
+    if let Some(pid) = running(path) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} names running process {}", path.display(), pid)));
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, format!("{}\n", std::process::id()))?;
+    fs::rename(&tmp, path)?;
+    *PIDFILE.lock().unwrap_or_else(|e| e.into_inner()) = Some(path.to_path_buf());
+    return Ok(());
+}
+
+/// remove the pidfile written, if any
+pub(crate) fn remove_pidfile() {
+    if let Some(path) = PIDFILE.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        let _ = fs::remove_file(&path).inspect_err(|e| log::warn!("failed to remove {}: {:?}", path.display(), e));
+    }
+}
@@ -0,0 +1,853 @@
+//! module for the D-Bus interface
+//! see https://dbus.freedesktop.org/doc/dbus-specification.html
+//! the exporter owns `org.scd41.Exporter1` on the system or session bus and exports
+//! `/org/scd41/Exporter1` with the latest reading as properties, PropertiesChanged for every
+//! measurement and methods changing the calibration, so local daemons and desktop widgets can
+//! integrate without HTTP. on the system bus, a policy in /etc/dbus-1/system.d has to allow the
+//! exporter's user to own the name.
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixStream},
+    },
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex, OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    control::{self, Action},
+    latest,
+};
+
+const NAME: &str = "org.scd41.Exporter1";
+const PATH: &str = "/org/scd41/Exporter1";
+const INTERFACE: &str = "org.scd41.Exporter1";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+const INTROSPECTABLE: &str = "org.freedesktop.DBus.Introspectable";
+const PEER: &str = "org.freedesktop.DBus.Peer";
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const SIGNAL: u8 = 4;
+const NO_REPLY_EXPECTED: u8 = 0x01;
+/// larger messages aren't for this exporter
+const MAX_MESSAGE: usize = 1 << 20;
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect"><arg name="xml" type="s" direction="out"/></method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+    <method name="GetMachineId"><arg name="machine_uuid" type="s" direction="out"/></method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get">
+      <arg name="interface_name" type="s" direction="in"/>
+      <arg name="property_name" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface_name" type="s" direction="in"/>
+      <arg name="properties" type="a{sv}" direction="out"/>
+    </method>
+    <method name="Set">
+      <arg name="interface_name" type="s" direction="in"/>
+      <arg name="property_name" type="s" direction="in"/>
+      <arg name="value" type="v" direction="in"/>
+    </method>
+    <signal name="PropertiesChanged">
+      <arg name="interface_name" type="s"/>
+      <arg name="changed_properties" type="a{sv}"/>
+      <arg name="invalidated_properties" type="as"/>
+    </signal>
+  </interface>
+  <interface name="org.scd41.Exporter1">
+    <property name="Co2" type="q" access="read"/>
+    <property name="Temperature" type="d" access="read"/>
+    <property name="Humidity" type="d" access="read"/>
+    <property name="TimestampMs" type="t" access="read"/>
+    <property name="Seq" type="t" access="read"/>
+    <property name="Serial" type="s" access="read"/>
+    <property name="Status" type="s" access="read"/>
+    <method name="SetTemperatureOffset"><arg name="offset" type="d" direction="in"/></method>
+    <method name="ForceRecalibration">
+      <arg name="target" type="q" direction="in"/>
+      <arg name="correction" type="n" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum Bus {
+    System,
+    Session,
+}
+
+/// the connection shared by the serving thread and the measurement loop
+struct Connection {
+    /// None while reconnecting
+    stream: Mutex<Option<UnixStream>>,
+    serial: AtomicU32,
+    connected: metrics::Gauge,
+}
+
+static CONNECTION: OnceLock<Connection> = OnceLock::new();
+
+/// own the name on `bus` and serve calls from a background thread
+pub(crate) fn init(bus: Bus) -> io::Result<()> {
+    let connection = CONNECTION.get_or_init(|| Connection {
+        stream: Mutex::new(None),
+        serial: AtomicU32::new(1),
+        connected: metrics::gauge!("exporter_dbus_connected"),
+    });
+    let stream = connection.register(bus)?;
+    log::info!("serve {} on the {:?} bus", NAME, bus);
+    thread::Builder::new().name(String::from("dbus")).spawn(move || connection.run(bus, stream))?;
+    return Ok(());
+}
+
+/// emit PropertiesChanged with the latest reading, if the interface is enabled
+pub(crate) fn notify() {
+    let Some(connection) = CONNECTION.get() else {
+        return;
+    };
+    let mut body = Writer::default();
+    body.string(INTERFACE);
+    body.properties(&properties());
+    body.array(4, |_| {});
+    let signal = Message {
+        kind: SIGNAL,
+        path: Some(String::from(PATH)),
+        interface: Some(String::from(PROPERTIES)),
+        member: Some(String::from("PropertiesChanged")),
+        signature: String::from("sa{sv}as"),
+        body: body.buf,
+        ..Default::default()
+    };
+    let _ = connection.send(signal).inspect_err(|e| log::debug!("failed to emit PropertiesChanged: {:?}", e));
+}
+
+impl Connection {
+    fn send(&self, mut message: Message) -> io::Result<u32> {
+        message.serial = self.serial.fetch_add(1, Ordering::Relaxed);
+        let mut stream = self.stream.lock().unwrap_or_else(|e| e.into_inner());
+        let stream = stream.as_mut().ok_or(io::ErrorKind::NotConnected)?;
+        stream.write_all(&message.encode())?;
+        return Ok(message.serial);
+    }
+
+    /// connect, authenticate and request the name, returning the stream to read from
+    fn register(&self, bus: Bus) -> io::Result<UnixStream> {
+        let mut stream = connect(bus)?;
+        authenticate(&mut stream)?;
+        // a stuck bus must not stall the measurement loop emitting signals
+        stream.set_write_timeout(Some(Duration::from_secs(1)))?;
+        *self.stream.lock().unwrap_or_else(|e| e.into_inner()) = Some(stream.try_clone()?);
+
+        self.call(&mut stream, "Hello", "", Vec::new())?;
+        let mut args = Writer::default();
+        args.string(NAME);
+        // DBUS_NAME_FLAG_DO_NOT_QUEUE
+        args.u32(0x4);
+        let reply = self.call(&mut stream, "RequestName", "su", args.buf)?;
+        // 1 primary owner, 4 already the owner
+        match Reader::new(&reply.body, reply.big_endian).u32()? {
+            1 | 4 => {}
+            _ => return Err(io::Error::other(format!("{} is already owned by another process", NAME))),
+        }
+        self.connected.set(1);
+        return Ok(stream);
+    }
+
+    /// call a method of the bus itself and wait for its reply
+    fn call(&self, stream: &mut UnixStream, member: &str, signature: &str, body: Vec<u8>) -> io::Result<Message> {
+        let serial = self.send(Message {
+            kind: METHOD_CALL,
+            path: Some(String::from("/org/freedesktop/DBus")),
+            interface: Some(String::from("org.freedesktop.DBus")),
+            member: Some(member.to_string()),
+            destination: Some(String::from("org.freedesktop.DBus")),
+            signature: signature.to_string(),
+            body,
+            ..Default::default()
+        })?;
+        loop {
+            let message = Message::read(stream)?;
+            if message.reply_serial != Some(serial) {
+                continue;
+            }
+            if message.kind == ERROR {
+                let text = Reader::new(&message.body, message.big_endian).string().unwrap_or_default();
+                return Err(io::Error::other(format!("{} failed: {} {}", member, message.error_name.unwrap_or_default(), text)));
+            }
+            return Ok(message);
+        }
+    }
+
+    /// serve calls, reconnecting when the bus goes away (e.g. dbus-daemon restarted)
+    fn run(&self, bus: Bus, mut stream: UnixStream) {
+        loop {
+            if let Err(e) = self.serve(&mut stream) {
+                log::warn!("lost the d-bus connection: {:?}", e);
+            }
+            self.connected.set(0);
+            *self.stream.lock().unwrap_or_else(|e| e.into_inner()) = None;
+            let mut backoff = Duration::from_secs(1);
+            loop {
+                thread::sleep(backoff);
+                match self.register(bus) {
+                    Ok(s) => {
+                        log::info!("reconnected to the {:?} bus", bus);
+                        stream = s;
+                        break;
+                    }
+                    Err(e) => {
+                        log::debug!("failed to reconnect to the {:?} bus: {:?}", bus, e);
+                        backoff = (backoff * 2).min(Duration::from_secs(60));
+                    }
+                }
+            }
+        }
+    }
+
+    fn serve(&self, stream: &mut UnixStream) -> io::Result<()> {
+        loop {
+            let call = Message::read(stream)?;
+            if call.kind != METHOD_CALL {
+                continue;
+            }
+            let answer = match dispatch(&call) {
+                Ok((signature, body)) => Message { kind: METHOD_RETURN, signature: signature.to_string(), body, ..Default::default() },
+                Err((name, text)) => {
+                    log::debug!("d-bus call {:?} failed: {}", call.member, text);
+                    let mut body = Writer::default();
+                    body.string(&text);
+                    Message { kind: ERROR, error_name: Some(name.to_string()), signature: String::from("s"), body: body.buf, ..Default::default() }
+                }
+            };
+            if call.flags & NO_REPLY_EXPECTED != 0 {
+                continue;
+            }
+            self.send(Message { reply_serial: Some(call.serial), destination: call.sender.clone(), ..answer })?;
+        }
+    }
+}
+
+/// a reply body and its signature, or an error name and message
+type Answer = Result<(&'static str, Vec<u8>), (&'static str, String)>;
+
+fn dispatch(call: &Message) -> Answer {
+    let path = call.path.as_deref().unwrap_or_default();
+    let member = call.member.as_deref().unwrap_or_default();
+    // the interface is optional in method calls
+    let of = |interface: &str| call.interface.as_deref().is_none_or(|i| i == interface);
+    let invalid = |expected: &str| ("org.freedesktop.DBus.Error.InvalidArgs", format!("expected arguments ({})", expected));
+    let mut args = Reader::new(&call.body, call.big_endian);
+    let mut reply = Writer::default();
+
+    match member {
+        "Introspect" if of(INTROSPECTABLE) => {
+            reply.string(&introspect(path).ok_or(unknown_object(path))?);
+            return Ok(("s", reply.buf));
+        }
+        "Ping" if of(PEER) => return Ok(("", Vec::new())),
+        "GetMachineId" if of(PEER) => {
+            let id = fs::read_to_string("/etc/machine-id").map_err(|e| ("org.freedesktop.DBus.Error.FileNotFound", e.to_string()))?;
+            reply.string(id.trim());
+            return Ok(("s", reply.buf));
+        }
+        _ => {}
+    }
+    if path != PATH {
+        return Err(unknown_object(path));
+    }
+    match member {
+        "Get" if of(PROPERTIES) => {
+            if call.signature != "ss" {
+                return Err(invalid("ss"));
+            }
+            let (interface, name) = (args.string().map_err(|_| invalid("ss"))?, args.string().map_err(|_| invalid("ss"))?);
+            if interface != INTERFACE {
+                return Err(("org.freedesktop.DBus.Error.UnknownInterface", format!("no interface {}", interface)));
+            }
+            let (_, value) = properties()
+                .into_iter()
+                .find(|(n, _)| *n == name)
+                .ok_or(("org.freedesktop.DBus.Error.UnknownProperty", format!("no property {}", name)))?;
+            reply.variant(&value);
+            return Ok(("v", reply.buf));
+        }
+        "GetAll" if of(PROPERTIES) => {
+            if call.signature != "s" {
+                return Err(invalid("s"));
+            }
+            // other interfaces have no properties
+            if args.string().map_err(|_| invalid("s"))? == INTERFACE {
+                reply.properties(&properties());
+            } else {
+                reply.properties(&[]);
+            }
+            return Ok(("a{sv}", reply.buf));
+        }
+        "Set" if of(PROPERTIES) => {
+            return Err(("org.freedesktop.DBus.Error.PropertyReadOnly", String::from("all properties are read-only")));
+        }
+        "SetTemperatureOffset" if of(INTERFACE) => {
+            if call.signature != "d" {
+                return Err(invalid("d"));
+            }
+            let offset = args.f64().map_err(|_| invalid("d"))?;
+            if !(0.0..=20.0).contains(&offset) {
+                return Err(("org.freedesktop.DBus.Error.InvalidArgs", String::from("the offset must be between 0 and 20 degC")));
+            }
+            control::submit(Action::SetTemperatureOffset(offset as f32)).map_err(failed)?;
+            return Ok(("", Vec::new()));
+        }
+        "ForceRecalibration" if of(INTERFACE) => {
+            if call.signature != "q" {
+                return Err(invalid("q"));
+            }
+            let target = args.u16().map_err(|_| invalid("q"))?;
+            reply.i16(control::submit(Action::ForceRecalibration(target)).map_err(failed)?);
+            return Ok(("n", reply.buf));
+        }
+        _ => return Err(("org.freedesktop.DBus.Error.UnknownMethod", format!("no method {}", member))),
+    }
+}
+
+fn unknown_object(path: &str) -> (&'static str, String) {
+    return ("org.freedesktop.DBus.Error.UnknownObject", format!("no object at {}", path));
+}
+
+fn failed(text: String) -> (&'static str, String) {
+    return ("org.scd41.Exporter1.Error.Failed", text);
+}
+
+/// introspection data of `path`, the exported object or one of its parents
+fn introspect(path: &str) -> Option<String> {
+    if path == PATH {
+        return Some(String::from(INTROSPECTION));
+    }
+    let parent = if path == "/" { String::from("/") } else { format!("{}/", path) };
+    let child = PATH.strip_prefix(&parent)?.split('/').next()?;
+    return Some(format!("<node>\n  <node name=\"{}\"/>\n</node>\n", child));
+}
+
+enum Value {
+    U16(u16),
+    U64(u64),
+    F64(f64),
+    Str(String),
+}
+
+/// the properties of the interface from the latest reading
+fn properties() -> Vec<(&'static str, Value)> {
+    let (envelope, serial, status) = latest::get();
+    let m = envelope.as_ref().map(|e| e.measurement);
+    // two decimals like the other outputs, instead of the noise of the f32 conversion
+    let round = |v: f32| (v as f64 * 100.0).round() / 100.0;
+    return vec![
+        ("Co2", Value::U16(m.map(|m| m.co2).unwrap_or_default())),
+        ("Temperature", Value::F64(m.map(|m| round(m.temperature)).unwrap_or(f64::NAN))),
+        ("Humidity", Value::F64(m.map(|m| round(m.humidity)).unwrap_or(f64::NAN))),
+        ("TimestampMs", Value::U64(envelope.as_ref().map(|e| e.timestamp_ms).unwrap_or_default())),
+        ("Seq", Value::U64(envelope.as_ref().map(|e| e.seq).unwrap_or_default())),
+        ("Serial", Value::Str(serial.unwrap_or_default())),
+        ("Status", Value::Str(String::from(status.name()))),
+    ];
+}
+
+/// connect to the first usable unix socket in the bus address
+fn connect(bus: Bus) -> io::Result<UnixStream> {
+    let address = match bus {
+        Bus::System => env::var("DBUS_SYSTEM_BUS_ADDRESS").unwrap_or(String::from("unix:path=/run/dbus/system_bus_socket")),
+        Bus::Session => env::var("DBUS_SESSION_BUS_ADDRESS").map_err(|_| io::Error::other("DBUS_SESSION_BUS_ADDRESS is not set"))?,
+    };
+    let mut last = io::Error::other(format!("no unix socket in the bus address {}", address));
+    for transport in address.split(';') {
+        let Some(params) = transport.strip_prefix("unix:") else {
+            continue;
+        };
+        for param in params.split(',') {
+            let connected = match param.split_once('=') {
+                Some(("path", path)) => UnixStream::connect(String::from_utf8_lossy(&unescape(path)).as_ref()),
+                Some(("abstract", name)) => SocketAddr::from_abstract_name(unescape(name)).and_then(|a| UnixStream::connect_addr(&a)),
+                _ => continue,
+            };
+            match connected {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last = e,
+            }
+        }
+    }
+    return Err(last);
+}
+
+/// undo the %xx escaping of address values
+fn unescape(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let decoded = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match decoded {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    return out;
+}
+
+/// SASL EXTERNAL with the process's uid
+fn authenticate(stream: &mut UnixStream) -> io::Result<()> {
+    let uid = unsafe { libc::getuid() };
+    let hex: String = uid.to_string().bytes().map(|b| format!("{:02x}", b)).collect();
+    stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", hex).as_bytes())?;
+    // byte by byte, nothing after the line may be consumed
+    let mut line = Vec::new();
+    let mut byte = [0_u8; 1];
+    while !line.ends_with(b"\r\n") {
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+        if line.len() > 512 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "authentication reply too long"));
+        }
+    }
+    let line = String::from_utf8_lossy(&line);
+    if !line.starts_with("OK ") {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("authentication rejected: {}", line.trim_end())));
+    }
+    stream.write_all(b"BEGIN\r\n")?;
+    return Ok(());
+}
+
+#[derive(Debug, Default)]
+struct Message {
+    kind: u8,
+    flags: u8,
+    serial: u32,
+    path: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+    reply_serial: Option<u32>,
+    destination: Option<String>,
+    sender: Option<String>,
+    signature: String,
+    body: Vec<u8>,
+    /// the byte order of the body, messages are forwarded as their sender marshalled them
+    big_endian: bool,
+}
+
+impl Message {
+    /// the message in little endian
+    fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.u8(b'l');
+        w.u8(self.kind);
+        w.u8(self.flags);
+        w.u8(1);
+        w.u32(self.body.len() as u32);
+        w.u32(self.serial);
+        w.array(8, |w| {
+            let strings = [
+                (1, "o", &self.path),
+                (2, "s", &self.interface),
+                (3, "s", &self.member),
+                (4, "s", &self.error_name),
+                (6, "s", &self.destination),
+                (7, "s", &self.sender),
+            ];
+            for (code, signature, value) in strings {
+                if let Some(value) = value {
+                    w.align(8);
+                    w.u8(code);
+                    w.signature(signature);
+                    w.string(value);
+                }
+            }
+            if let Some(serial) = self.reply_serial {
+                w.align(8);
+                w.u8(5);
+                w.signature("u");
+                w.u32(serial);
+            }
+            if !self.signature.is_empty() {
+                w.align(8);
+                w.u8(8);
+                w.signature("g");
+                w.signature(&self.signature);
+            }
+        });
+        w.align(8);
+        w.buf.extend_from_slice(&self.body);
+        return w.buf;
+    }
+
+    fn read(stream: &mut impl Read) -> io::Result<Message> {
+        let invalid = |text: &str| io::Error::new(io::ErrorKind::InvalidData, text.to_string());
+        let mut fixed = [0_u8; 16];
+        stream.read_exact(&mut fixed)?;
+        let big_endian = match fixed[0] {
+            b'l' => false,
+            b'B' => true,
+            _ => return Err(invalid("unknown byte order")),
+        };
+        let mut header = Reader::new(&fixed, big_endian);
+        header.pos = 4;
+        let body_len = header.u32()? as usize;
+        let serial = header.u32()?;
+        let fields_len = header.u32()? as usize;
+        if body_len > MAX_MESSAGE || fields_len > MAX_MESSAGE {
+            return Err(invalid("message too large"));
+        }
+        let body_start = (16 + fields_len).div_ceil(8) * 8;
+        let mut buf = fixed.to_vec();
+        buf.resize(body_start + body_len, 0);
+        stream.read_exact(&mut buf[16..])?;
+
+        let mut message = Message {
+            kind: fixed[1],
+            flags: fixed[2],
+            serial,
+            body: buf[body_start..].to_vec(),
+            big_endian,
+            ..Default::default()
+        };
+        let mut fields = Reader::new(&buf[..16 + fields_len], big_endian);
+        fields.pos = 16;
+        while fields.pos < 16 + fields_len {
+            fields.align(8)?;
+            let code = fields.u8()?;
+            let signature = fields.signature()?;
+            match (code, signature.as_str()) {
+                (5, "u") => message.reply_serial = Some(fields.u32()?),
+                (8, "g") => message.signature = fields.signature()?,
+                (_, "s" | "o") => {
+                    let value = Some(fields.string()?);
+                    match code {
+                        1 => message.path = value,
+                        2 => message.interface = value,
+                        3 => message.member = value,
+                        4 => message.error_name = value,
+                        6 => message.destination = value,
+                        7 => message.sender = value,
+                        _ => {}
+                    }
+                }
+                (_, "u") => {
+                    fields.u32()?;
+                }
+                (_, "g") => {
+                    fields.signature()?;
+                }
+                _ => return Err(invalid("unknown header field type")),
+            }
+        }
+        return Ok(message);
+    }
+}
+
+/// marshals values in little endian, aligned from the start of the message (or the body)
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn align(&mut self, n: usize) {
+        while !self.buf.len().is_multiple_of(n) {
+            self.buf.push(0);
+        }
+    }
+
+    fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.align(2);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn i16(&mut self, v: i16) {
+        self.u16(v as u16);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.align(8);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    /// a string or an object path
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, s: &str) {
+        self.u8(s.len() as u8);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    /// an array of elements aligned to `align`, written by `elements`
+    fn array(&mut self, align: usize, elements: impl FnOnce(&mut Writer)) {
+        self.align(4);
+        let at = self.buf.len();
+        self.u32(0);
+        // the length doesn't include the padding before the first element
+        self.align(align);
+        let start = self.buf.len();
+        elements(self);
+        let len = (self.buf.len() - start) as u32;
+        self.buf[at..at + 4].copy_from_slice(&len.to_le_bytes());
+    }
+
+    fn variant(&mut self, value: &Value) {
+        match value {
+            Value::U16(v) => {
+                self.signature("q");
+                self.u16(*v);
+            }
+            Value::U64(v) => {
+                self.signature("t");
+                self.u64(*v);
+            }
+            Value::F64(v) => {
+                self.signature("d");
+                self.u64(v.to_bits());
+            }
+            Value::Str(v) => {
+                self.signature("s");
+                self.string(v);
+            }
+        }
+    }
+
+    /// a{sv}
+    fn properties(&mut self, properties: &[(&str, Value)]) {
+        self.array(8, |w| {
+            for (name, value) in properties {
+                w.align(8);
+                w.string(name);
+                w.variant(value);
+            }
+        });
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8], big_endian: bool) -> Self {
+        return Reader { buf, pos: 0, big_endian };
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let end = self.pos + n;
+        let bytes = self.buf.get(self.pos..end).ok_or(io::ErrorKind::UnexpectedEof)?;
+        self.pos = end;
+        return Ok(bytes);
+    }
+
+    fn align(&mut self, n: usize) -> io::Result<()> {
+        let padding = (n - self.pos % n) % n;
+        self.take(padding)?;
+        return Ok(());
+    }
+
+    /// `N` bytes aligned to `N`, in little endian order
+    fn fixed<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        self.align(N)?;
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(self.take(N)?);
+        if self.big_endian {
+            bytes.reverse();
+        }
+        return Ok(bytes);
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        return Ok(u16::from_le_bytes(self.fixed()?));
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        return Ok(u32::from_le_bytes(self.fixed()?));
+    }
+
+    fn f64(&mut self) -> io::Result<f64> {
+        return Ok(f64::from_le_bytes(self.fixed()?));
+    }
+
+    /// a string or an object path
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        self.take(1)?;
+        return String::from_utf8(bytes.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+
+    fn signature(&mut self) -> io::Result<String> {
+        let len = self.u8()? as usize;
+        let bytes = self.take(len)?;
+        self.take(1)?;
+        return String::from_utf8(bytes.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call() -> Message {
+        return Message {
+            kind: METHOD_CALL,
+            serial: 9,
+            path: Some(String::from(PATH)),
+            interface: Some(String::from(PROPERTIES)),
+            member: Some(String::from("Get")),
+            destination: Some(String::from(NAME)),
+            signature: String::from("ss"),
+            body: {
+                let mut w = Writer::default();
+                w.string(INTERFACE);
+                w.string("Co2");
+                w.buf
+            },
+            ..Default::default()
+        };
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let encoded = call().encode();
+        assert_eq!(encoded[..4], [b'l', METHOD_CALL, 0, 1]);
+        let fields_len = u32::from_le_bytes([encoded[12], encoded[13], encoded[14], encoded[15]]) as usize;
+        // the body starts 8-aligned after the header fields
+        let body_start = (16 + fields_len).div_ceil(8) * 8;
+        assert_eq!(encoded[body_start..], call().body);
+        let read = Message::read(&mut &encoded[..]).unwrap();
+        assert_eq!((read.kind, read.serial, read.big_endian), (METHOD_CALL, 9, false));
+        assert_eq!(read.path.as_deref(), Some(PATH));
+        assert_eq!(read.interface.as_deref(), Some(PROPERTIES));
+        assert_eq!(read.member.as_deref(), Some("Get"));
+        assert_eq!(read.destination.as_deref(), Some(NAME));
+        assert_eq!(read.signature, "ss");
+        let mut body = Reader::new(&read.body, false);
+        assert_eq!(body.string().unwrap(), INTERFACE);
+        assert_eq!(body.string().unwrap(), "Co2");
+    }
+
+    #[test]
+    fn big_endian_messages() {
+        let mut message = vec![b'B', METHOD_RETURN, 0, 1, 0, 0, 0, 4, 0, 0, 0, 7, 0, 0, 0, 15];
+        // reply serial 3, then the body signature "u", padded to 8
+        message.extend_from_slice(&[5, 1, b'u', 0, 0, 0, 0, 3, 8, 1, b'g', 0, 1, b'u', 0, 0]);
+        message.extend_from_slice(&42_u32.to_be_bytes());
+        let read = Message::read(&mut &message[..]).unwrap();
+        assert_eq!((read.kind, read.serial, read.reply_serial, read.big_endian), (METHOD_RETURN, 7, Some(3), true));
+        assert_eq!(read.signature, "u");
+        assert_eq!(Reader::new(&read.body, true).u32().unwrap(), 42);
+    }
+
+    #[test]
+    fn malformed_messages() {
+        let encoded = call().encode();
+        for len in 0..encoded.len() {
+            assert!(Message::read(&mut &encoded[..len]).is_err(), "truncated to {} bytes", len);
+        }
+        let mut order = encoded.clone();
+        order[0] = b'x';
+        assert_eq!(Message::read(&mut &order[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let mut large = encoded.clone();
+        large[4..8].copy_from_slice(&(MAX_MESSAGE as u32 + 1).to_le_bytes());
+        assert_eq!(Message::read(&mut &large[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        // a header field of type variant
+        let mut field = vec![b'l', METHOD_CALL, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 4, 0, 0, 0];
+        field.extend_from_slice(&[9, 1, b'v', 0, 0, 0, 0, 0]);
+        assert_eq!(Message::read(&mut &field[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        // a string running past the fields
+        let mut string = vec![b'l', METHOD_CALL, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 8, 0, 0, 0];
+        string.extend_from_slice(&[1, 1, b'o', 0, 0xFF, 0, 0, 0]);
+        assert!(Message::read(&mut &string[..]).is_err());
+    }
+
+    #[test]
+    fn marshalling() {
+        let mut w = Writer::default();
+        w.u8(1);
+        w.u16(0x0203);
+        w.u8(4);
+        w.u64(5);
+        assert_eq!(w.buf, [1, 0, 3, 2, 4, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut w = Writer::default();
+        w.u8(0);
+        w.properties(&[("Co2", Value::U16(420)), ("Humidity", Value::F64(40.5)), ("Status", Value::Str(String::from("ok")))]);
+        let mut r = Reader::new(&w.buf, false);
+        r.u8().unwrap();
+        let len = r.u32().unwrap() as usize;
+        // the length doesn't count the padding before the first entry
+        r.align(8).unwrap();
+        assert_eq!(r.pos, 8);
+        assert_eq!(r.pos + len, w.buf.len());
+        let entry = |r: &mut Reader| -> (String, String) {
+            r.align(8).unwrap();
+            return (r.string().unwrap(), r.signature().unwrap());
+        };
+        assert_eq!(entry(&mut r), (String::from("Co2"), String::from("q")));
+        assert_eq!(r.u16().unwrap(), 420);
+        assert_eq!(entry(&mut r), (String::from("Humidity"), String::from("d")));
+        assert_eq!(r.f64().unwrap(), 40.5);
+        assert_eq!(entry(&mut r), (String::from("Status"), String::from("s")));
+        assert_eq!(r.string().unwrap(), "ok");
+        assert_eq!(r.pos, w.buf.len());
+    }
+
+    #[test]
+    fn addresses_and_paths() {
+        assert_eq!(unescape("%2Frun%2fdbus%2Fsystem_bus_socket"), b"/run/dbus/system_bus_socket");
+        assert_eq!(unescape("a%zzb%4"), b"a%zzb%4");
+        assert_eq!(introspect("/").unwrap(), "<node>\n  <node name=\"org\"/>\n</node>\n");
+        assert_eq!(introspect("/org/scd41").unwrap(), "<node>\n  <node name=\"Exporter1\"/>\n</node>\n");
+        assert!(introspect(PATH).unwrap().contains(INTERFACE));
+        assert!(introspect("/org/other").is_none());
+    }
+}
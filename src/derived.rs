@@ -0,0 +1,143 @@
+//! module for values derived from temperature and relative humidity
+//! uses the Magnus formula with Sonntag's coefficients (valid for -45 to 60 degC over water)
+
+const MAGNUS_A: f32 = 17.62;
+const MAGNUS_B: f32 = 243.12;
+/// saturation vapor pressure at 0 degC in hPa
+const MAGNUS_C: f32 = 6.112;
+
+/// saturation vapor pressure over water in hPa
+pub(crate) fn saturation_vapor_pressure(temperature: f32) -> f32 {
+    return MAGNUS_C * (MAGNUS_A * temperature / (MAGNUS_B + temperature)).exp();
+}
+
+/// dew point in degC
+pub(crate) fn dew_point(temperature: f32, humidity: f32) -> f32 {
+    let gamma = (humidity.max(0.01) / 100_f32).ln() + MAGNUS_A * temperature / (MAGNUS_B + temperature);
+    return MAGNUS_B * gamma / (MAGNUS_A - gamma);
+}
+
+/// absolute humidity in g/m3
+pub(crate) fn absolute_humidity(temperature: f32, humidity: f32) -> f32 {
+    let vapor_pressure = humidity / 100_f32 * saturation_vapor_pressure(temperature);
+    // 216.7 = 100 (hPa to Pa) * 1000 (kg to g) / 461.5 (specific gas constant of water vapor)
+    return 216.7 * vapor_pressure / (273.15 + temperature);
+}
+
+/// vapor pressure deficit in kPa of a leaf `leaf_offset` degC warmer (negative: cooler) than the air
+pub(crate) fn vapor_pressure_deficit(temperature: f32, humidity: f32, leaf_offset: f32) -> f32 {
+    let vapor_pressure = humidity / 100_f32 * saturation_vapor_pressure(temperature);
+    return (saturation_vapor_pressure(temperature + leaf_offset) - vapor_pressure).max(0_f32) / 10_f32;
+}
+
+/// heat index (apparent temperature) in degC, NOAA's Rothfusz regression with its adjustments
+pub(crate) fn heat_index(temperature: f32, humidity: f32) -> f32 {
+    let t = temperature * 9_f32 / 5_f32 + 32_f32;
+    let rh = humidity;
+    let simple = 0.5 * (t + 61_f32 + (t - 68_f32) * 1.2 + rh * 0.094);
+    let f = if (simple + t) / 2_f32 < 80_f32 {
+        simple
+    } else {
+        let mut hi = -42.379 + 2.049_015_3 * t + 10.143_332 * rh
+            - 0.224_755_4 * t * rh
+            - 0.006_837_83 * t * t
+            - 0.054_817_17 * rh * rh
+            + 0.001_228_74 * t * t * rh
+            + 0.000_852_82 * t * rh * rh
+            - 0.000_001_99 * t * t * rh * rh;
+        if rh < 13_f32 && (80_f32..=112_f32).contains(&t) {
+            hi -= (13_f32 - rh) / 4_f32 * ((17_f32 - (t - 95_f32).abs()) / 17_f32).sqrt();
+        } else if rh > 85_f32 && (80_f32..=87_f32).contains(&t) {
+            hi += (rh - 85_f32) / 10_f32 * (87_f32 - t) / 5_f32;
+        }
+        hi
+    };
+    return (f - 32_f32) * 5_f32 / 9_f32;
+}
+
+/// humidex (Environment Canada)
+pub(crate) fn humidex(temperature: f32, humidity: f32) -> f32 {
+    let vapor_pressure = humidity / 100_f32 * saturation_vapor_pressure(temperature);
+    return temperature + 0.5555 * (vapor_pressure - 10_f32);
+}
+
+/// comfort category on Environment Canada's humidex scale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Comfort {
+    Comfortable,
+    SomeDiscomfort,
+    GreatDiscomfort,
+    Dangerous,
+}
+
+impl Comfort {
+    pub(crate) const ALL: [Comfort; 4] = [
+        Comfort::Comfortable,
+        Comfort::SomeDiscomfort,
+        Comfort::GreatDiscomfort,
+        Comfort::Dangerous,
+    ];
+
+    pub(crate) fn from_humidex(humidex: f32) -> Self {
+        return match humidex {
+            h if h < 30_f32 => Comfort::Comfortable,
+            h if h < 40_f32 => Comfort::SomeDiscomfort,
+            h if h <= 45_f32 => Comfort::GreatDiscomfort,
+            _ => Comfort::Dangerous,
+        };
+    }
+
+    pub(crate) fn label(&self) -> &'static str {
+        return match self {
+            Comfort::Comfortable => "comfortable",
+            Comfort::SomeDiscomfort => "some_discomfort",
+            Comfort::GreatDiscomfort => "great_discomfort",
+            Comfort::Dangerous => "dangerous",
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(value: f32, expected: f32, tolerance: f32) {
+        assert!((value - expected).abs() <= tolerance, "{} isn't {} +- {}", value, expected, tolerance);
+    }
+
+    #[test]
+    fn reference_values() {
+        assert_near(saturation_vapor_pressure(0.0), 6.112, 0.001);
+        assert_near(saturation_vapor_pressure(20.0), 23.33, 0.05);
+        assert_near(dew_point(20.0, 50.0), 9.3, 0.1);
+        assert_near(dew_point(25.0, 100.0), 25.0, 0.01);
+        assert_near(dew_point(-10.0, 80.0), -12.9, 0.2);
+        assert_near(absolute_humidity(20.0, 50.0), 8.6, 0.1);
+        assert_near(absolute_humidity(30.0, 80.0), 24.3, 0.2);
+        // a leaf at air temperature sees the plain deficit, a cooler one less
+        assert_near(vapor_pressure_deficit(20.0, 50.0, 0.0), 1.17, 0.01);
+        assert_near(vapor_pressure_deficit(25.0, 60.0, -2.0), 0.91, 0.01);
+        assert_eq!(vapor_pressure_deficit(20.0, 100.0, -1.0), 0.0);
+        assert_near(humidex(30.0, 60.0), 38.6, 0.5);
+        assert_near(humidex(20.0, 50.0), 20.9, 0.2);
+    }
+
+    #[test]
+    fn heat_index_ranges() {
+        // below 80 degF it's the simple formula, close to the air temperature
+        assert_near(heat_index(20.0, 50.0), 19.4, 0.2);
+        // NOAA's table: 90 degF at 70 % is 106 degF
+        assert_near(heat_index(32.2, 70.0), 41.1, 0.5);
+        // the dry and the humid adjustments
+        assert_near(heat_index(35.0, 10.0), 32.4, 0.5);
+        assert_near(heat_index(29.0, 90.0), 37.2, 0.5);
+    }
+
+    #[test]
+    fn comfort_categories() {
+        assert_eq!(Comfort::from_humidex(29.9), Comfort::Comfortable);
+        assert_eq!(Comfort::from_humidex(30.0), Comfort::SomeDiscomfort);
+        assert_eq!(Comfort::from_humidex(45.0), Comfort::GreatDiscomfort);
+        assert_eq!(Comfort::from_humidex(45.1), Comfort::Dangerous);
+    }
+}
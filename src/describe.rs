@@ -0,0 +1,208 @@
+//! module for the HELP text (and units) of every exported metric
+use metrics::Unit;
+
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+/// name, kind, unit and help of each metric
+const METRICS: &[(&str, Kind, Option<Unit>, &str)] = &[
+    // main CO2 sensor
+    ("scd41_co2_ppm", Kind::Gauge, None, "CO2 concentration in ppm"),
+    ("scd41_co2_distribution_ppm", Kind::Histogram, None, "distribution of CO2 samples in ppm"),
+    ("scd41_co2_ppm_raw", Kind::Gauge, None, "CO2 concentration in ppm before smoothing"),
+    ("scd41_temperature_celsius", Kind::Gauge, None, "temperature in degrees Celsius"),
+    ("scd41_temperature_fahrenheit", Kind::Gauge, None, "temperature in degrees Fahrenheit"),
+    ("scd41_temperature_kelvin", Kind::Gauge, None, "temperature in kelvin"),
+    ("scd41_temperature_celsius_raw", Kind::Gauge, None, "temperature in degrees Celsius before smoothing"),
+    ("scd41_humidity_rh", Kind::Gauge, Some(Unit::Percent), "relative humidity in percent"),
+    ("scd41_humidity_rh_raw", Kind::Gauge, Some(Unit::Percent), "relative humidity in percent before smoothing"),
+    ("scd41_temperature_celsius_uncompensated", Kind::Gauge, None, "temperature in degrees Celsius before the self-heating compensation"),
+    ("scd41_humidity_rh_uncompensated", Kind::Gauge, Some(Unit::Percent), "relative humidity in percent before the self-heating compensation"),
+    ("scd41_self_heating_coefficient", Kind::Gauge, None, "coefficient K of the self-heating compensation, NaN while it's learned"),
+    ("scd41_co2_ppm_min", Kind::Gauge, None, "lowest CO2 concentration in ppm over the rolling window"),
+    ("scd41_co2_ppm_max", Kind::Gauge, None, "highest CO2 concentration in ppm over the rolling window"),
+    ("scd41_co2_ppm_mean", Kind::Gauge, None, "mean CO2 concentration in ppm over the rolling window"),
+    ("scd41_temperature_celsius_min", Kind::Gauge, None, "lowest temperature in degrees Celsius over the rolling window"),
+    ("scd41_temperature_celsius_max", Kind::Gauge, None, "highest temperature in degrees Celsius over the rolling window"),
+    ("scd41_temperature_celsius_mean", Kind::Gauge, None, "mean temperature in degrees Celsius over the rolling window"),
+    ("scd41_humidity_rh_min", Kind::Gauge, Some(Unit::Percent), "lowest relative humidity in percent over the rolling window"),
+    ("scd41_humidity_rh_max", Kind::Gauge, Some(Unit::Percent), "highest relative humidity in percent over the rolling window"),
+    ("scd41_humidity_rh_mean", Kind::Gauge, Some(Unit::Percent), "mean relative humidity in percent over the rolling window"),
+    ("scd41_dew_point_celsius", Kind::Gauge, None, "dew point in degrees Celsius"),
+    ("scd41_absolute_humidity_g_m3", Kind::Gauge, None, "absolute humidity in grams per cubic meter"),
+    ("scd41_vpd_kpa", Kind::Gauge, None, "vapor pressure deficit in kPa"),
+    ("scd41_heat_index_celsius", Kind::Gauge, None, "heat index (apparent temperature) in degrees Celsius"),
+    ("scd41_humidex", Kind::Gauge, None, "humidex"),
+    ("scd41_comfort", Kind::Gauge, None, "1 for the current comfort category on the humidex scale"),
+    ("scd41_air_quality_score", Kind::Gauge, None, "air quality from 100 (fresh) to 0 (poor) by CO2 and humidity"),
+    ("scd41_ventilation_recommended", Kind::Gauge, None, "1 while opening a window is recommended"),
+    ("scd41_estimated_occupancy", Kind::Gauge, Some(Unit::Count), "people in the room estimated from CO2, its trend and the outdoor CO2"),
+    ("scd41_occupied", Kind::Gauge, None, "1 while the estimated occupancy is at least half a person"),
+    ("scd41_co2_baseline_ppm", Kind::Gauge, None, "lowest 10 minute mean CO2 of the last --baseline-days in ppm"),
+    ("scd41_co2_baseline_drift_ppm", Kind::Gauge, None, "CO2 baseline minus --outdoor-co2 in ppm"),
+    ("scd41_co2_baseline_days", Kind::Gauge, Some(Unit::Count), "days with measurements the CO2 baseline is taken over"),
+    ("scd41_co2_trend_ppm_per_minute", Kind::Gauge, None, "CO2 change over the last 5 minutes in ppm per minute"),
+    ("scd41_temperature_offset_celsius", Kind::Gauge, None, "temperature offset configured in the sensor in degrees Celsius"),
+    ("scd41_last_measured_timestamp_ms", Kind::Gauge, Some(Unit::Milliseconds), "unix time of the last measurement in milliseconds"),
+    ("scd41_last_measured_age_seconds", Kind::Gauge, Some(Unit::Seconds), "seconds since the last measurement (monotonic)"),
+    ("scd41_sensor_up", Kind::Gauge, None, "1 while measurements are fresh"),
+    ("scd41_sensor_info", Kind::Gauge, None, "sensor serial number"),
+    ("scd41_data_valid", Kind::Gauge, None, "1 while the interlock output signals valid data"),
+    ("scd41_burst_active", Kind::Gauge, None, "1 while a burst capture is running"),
+    ("scd41_consecutive_failures", Kind::Gauge, Some(Unit::Count), "failed measurements in a row"),
+    ("scd41_measurement_attempts_total", Kind::Counter, Some(Unit::Count), "measurement reads"),
+    ("scd41_measurement_successes_total", Kind::Counter, Some(Unit::Count), "measurements taken"),
+    ("scd41_measurement_failures_total", Kind::Counter, Some(Unit::Count), "failed measurement reads"),
+    ("scd41_measurement_errors_total", Kind::Counter, Some(Unit::Count), "failed measurement reads by kind of error"),
+    ("scd41_loop_panics_total", Kind::Counter, Some(Unit::Count), "measurement cycles and publications cut short by a panic"),
+    ("scd41_sensor_reinits_total", Kind::Counter, Some(Unit::Count), "reinitializations after --reinit-after consecutive failures"),
+    ("scd41_ambient_pressure_pa", Kind::Gauge, None, "ambient pressure last fed to the sensor for compensation in Pa"),
+    ("scd41_on_demand_requests_total", Kind::Counter, Some(Unit::Count), "requests served with --on-demand by result (cached, measured, failed, timeout)"),
+    ("scd41_measurement_interval_seconds", Kind::Gauge, Some(Unit::Seconds), "seconds between measurements in the current --power-profile mode"),
+    ("scd41_measurement_duration_seconds", Kind::Histogram, Some(Unit::Seconds), "duration of measurement reads in seconds"),
+    ("scd41_loop_iterations_total", Kind::Counter, Some(Unit::Count), "iterations of the sampling loop"),
+    ("scd41_outliers_suppressed_total", Kind::Counter, Some(Unit::Count), "samples dropped by the spike filter"),
+    ("scd41_eeprom_writes_total", Kind::Counter, Some(Unit::Count), "settings written to the sensor's eeprom"),
+    ("scd41_eeprom_write_budget_remaining", Kind::Gauge, Some(Unit::Count), "eeprom writes left in the budget"),
+    ("scd41_recalibration_correction_ppm", Kind::Gauge, None, "correction of the last forced recalibration in ppm"),
+    ("scd41_scheduled_recalibrations_total", Kind::Counter, Some(Unit::Count), "recalibrations on --recalibration-schedule by result"),
+    ("scd41_last_recalibration_timestamp_ms", Kind::Gauge, Some(Unit::Milliseconds), "unix time of the last forced recalibration in milliseconds, as kept in --state-file"),
+    ("scd41_scheduled_self_tests_total", Kind::Counter, Some(Unit::Count), "self-tests on --self-test-schedule by result"),
+    ("scd41_last_self_test_timestamp_ms", Kind::Gauge, Some(Unit::Milliseconds), "unix time of the last self-test in milliseconds, as kept in --state-file"),
+    ("scd41_self_test_passed", Kind::Gauge, None, "1 when the last self-test found no malfunction"),
+    // merged sensors
+    ("merged_co2_ppm", Kind::Gauge, None, "CO2 concentration in ppm merged over redundant sensors"),
+    ("merged_temperature_celsius", Kind::Gauge, None, "temperature in degrees Celsius merged over redundant sensors"),
+    ("merged_humidity_rh", Kind::Gauge, Some(Unit::Percent), "relative humidity in percent merged over redundant sensors"),
+    ("merged_fresh_sensors", Kind::Gauge, Some(Unit::Count), "sensors with a fresh measurement taking part in merging"),
+    // auxiliary sensors
+    ("bme280_pressure_pa", Kind::Gauge, None, "ambient pressure in Pa"),
+    ("weather_pressure_pa", Kind::Gauge, None, "pressure from the weather source at the sensor's altitude in Pa"),
+    ("weather_fetch_failures_total", Kind::Counter, Some(Unit::Count), "failed fetches from the weather source"),
+    ("ds3231_temperature_celsius", Kind::Gauge, None, "RTC temperature in degrees Celsius"),
+    ("ds18b20_temperature_celsius", Kind::Gauge, None, "1-Wire probe temperature in degrees Celsius"),
+    ("ads1115_voltage_volts", Kind::Gauge, None, "ADC input voltage in volts"),
+    ("gas_concentration_ppm", Kind::Gauge, None, "gas concentration from an analog sensor's calibration curve in ppm"),
+    ("sht4x_temperature_celsius", Kind::Gauge, None, "reference temperature in degrees Celsius"),
+    ("sht4x_humidity_rh", Kind::Gauge, Some(Unit::Percent), "reference relative humidity in percent"),
+    ("sen5x_pm1_0_ug_m3", Kind::Gauge, None, "PM1.0 mass concentration in micrograms per cubic meter"),
+    ("sen5x_pm2_5_ug_m3", Kind::Gauge, None, "PM2.5 mass concentration in micrograms per cubic meter"),
+    ("sen5x_pm4_0_ug_m3", Kind::Gauge, None, "PM4.0 mass concentration in micrograms per cubic meter"),
+    ("sen5x_pm10_ug_m3", Kind::Gauge, None, "PM10 mass concentration in micrograms per cubic meter"),
+    ("sen5x_humidity_rh", Kind::Gauge, Some(Unit::Percent), "relative humidity in percent"),
+    ("sen5x_temperature_celsius", Kind::Gauge, None, "temperature in degrees Celsius"),
+    ("sen5x_voc_index", Kind::Gauge, None, "VOC index (1-500)"),
+    ("sen5x_nox_index", Kind::Gauge, None, "NOx index (1-500)"),
+    ("hcho_ppb", Kind::Gauge, None, "formaldehyde concentration in ppb"),
+    ("sfa3x_humidity_rh", Kind::Gauge, Some(Unit::Percent), "relative humidity in percent"),
+    ("sfa3x_temperature_celsius", Kind::Gauge, None, "temperature in degrees Celsius"),
+    ("gps_fix", Kind::Gauge, None, "1 while the GPS has a fix"),
+    ("gps_satellites", Kind::Gauge, Some(Unit::Count), "satellites in use"),
+    ("gps_latitude_degrees", Kind::Gauge, None, "latitude in degrees"),
+    ("gps_longitude_degrees", Kind::Gauge, None, "longitude in degrees"),
+    ("gps_altitude_meters", Kind::Gauge, None, "altitude in meters"),
+    ("gps_clock_offset_seconds", Kind::Gauge, Some(Unit::Seconds), "system clock minus GPS time in seconds"),
+    // exporter
+    ("pipeline_stage_duration_seconds", Kind::Histogram, Some(Unit::Seconds), "duration of sampling pipeline stages in seconds"),
+    ("pipeline_budget_exceeded_total", Kind::Counter, Some(Unit::Count), "pipeline stages that took longer than their latency budget"),
+    ("exporter_build_info", Kind::Gauge, None, "version, git revision and compiler of the exporter"),
+    ("exporter_node_info", Kind::Gauge, None, "persistent node id"),
+    ("exporter_uptime_seconds", Kind::Gauge, Some(Unit::Seconds), "seconds since the exporter started"),
+    ("process_cpu_seconds_total", Kind::Gauge, Some(Unit::Seconds), "user and system CPU time spent in seconds"),
+    ("process_resident_memory_bytes", Kind::Gauge, Some(Unit::Bytes), "resident memory size in bytes"),
+    ("process_virtual_memory_bytes", Kind::Gauge, Some(Unit::Bytes), "virtual memory size in bytes"),
+    ("process_open_fds", Kind::Gauge, None, "number of open file descriptors"),
+    ("process_max_fds", Kind::Gauge, None, "maximum number of open file descriptors"),
+    ("process_threads", Kind::Gauge, None, "number of threads"),
+    ("process_start_time_seconds", Kind::Gauge, Some(Unit::Seconds), "start time of the process since the unix epoch in seconds"),
+    ("raspi_soc_temperature_celsius", Kind::Gauge, None, "SoC temperature in degrees Celsius"),
+    ("raspi_cpu_frequency_hertz", Kind::Gauge, None, "current frequency of the first CPU core in Hz"),
+    ("raspi_throttled", Kind::Gauge, None, "1 while the firmware reports the flag (under_voltage, frequency_capped, throttled, soft_temperature_limit)"),
+    ("raspi_throttled_since_boot", Kind::Gauge, None, "1 when the firmware reported the flag since boot"),
+    ("exporter_listener_degraded", Kind::Gauge, None, "1 when listening on the fallback address"),
+    ("federated_up", Kind::Gauge, None, "1 when the last fetch from the --federate peer succeeded"),
+    ("federated_co2_ppm", Kind::Gauge, None, "CO2 concentration in ppm of the --federate peer"),
+    ("federated_temperature_celsius", Kind::Gauge, None, "temperature in degrees Celsius of the --federate peer"),
+    ("federated_humidity_rh", Kind::Gauge, Some(Unit::Percent), "relative humidity in percent of the --federate peer"),
+    ("federated_co2_ppm_min", Kind::Gauge, None, "lowest CO2 concentration in ppm across the --federate peers"),
+    ("federated_co2_ppm_max", Kind::Gauge, None, "highest CO2 concentration in ppm across the --federate peers"),
+    ("federated_co2_ppm_mean", Kind::Gauge, None, "mean CO2 concentration in ppm across the --federate peers"),
+    ("federated_temperature_celsius_min", Kind::Gauge, None, "lowest temperature in degrees Celsius across the --federate peers"),
+    ("federated_temperature_celsius_max", Kind::Gauge, None, "highest temperature in degrees Celsius across the --federate peers"),
+    ("federated_temperature_celsius_mean", Kind::Gauge, None, "mean temperature in degrees Celsius across the --federate peers"),
+    ("federated_humidity_rh_min", Kind::Gauge, Some(Unit::Percent), "lowest relative humidity in percent across the --federate peers"),
+    ("federated_humidity_rh_max", Kind::Gauge, Some(Unit::Percent), "highest relative humidity in percent across the --federate peers"),
+    ("federated_humidity_rh_mean", Kind::Gauge, Some(Unit::Percent), "mean relative humidity in percent across the --federate peers"),
+    ("federated_room_co2_ppm_max", Kind::Gauge, None, "highest CO2 concentration in ppm across the --federate peers in the room"),
+    ("federated_rooms_co2_above_threshold", Kind::Gauge, None, "rooms of the --federate peers whose highest CO2 is above --federate-room-threshold"),
+    ("federated_fetch_failures_total", Kind::Counter, Some(Unit::Count), "failed fetches from --federate peers"),
+    ("exporter_pushes_total", Kind::Counter, Some(Unit::Count), "successful pushes to the Pushgateway"),
+    ("exporter_push_failures_total", Kind::Counter, Some(Unit::Count), "failed pushes to the Pushgateway"),
+    ("exporter_remote_write_requests_total", Kind::Counter, Some(Unit::Count), "write requests accepted by the remote_write endpoint"),
+    ("exporter_remote_write_failures_total", Kind::Counter, Some(Unit::Count), "failed remote_write requests"),
+    ("exporter_remote_write_dropped_total", Kind::Counter, Some(Unit::Count), "remote_write requests given up on"),
+    ("exporter_remote_write_buffered_bytes", Kind::Gauge, Some(Unit::Bytes), "size of the remote_write buffer in bytes"),
+    ("exporter_remote_write_buffered_requests", Kind::Gauge, Some(Unit::Count), "requests in the remote_write buffer"),
+    ("exporter_textfile_write_failures_total", Kind::Counter, Some(Unit::Count), "failed writes of the textfile collector file"),
+    ("exporter_mqtt_connected", Kind::Gauge, None, "1 while connected to the MQTT broker"),
+    ("exporter_mqtt_published_total", Kind::Counter, Some(Unit::Count), "messages published to the MQTT broker"),
+    ("exporter_mqtt_dropped_total", Kind::Counter, Some(Unit::Count), "messages dropped because the MQTT queue or buffer was full"),
+    ("exporter_mqtt_buffered_bytes", Kind::Gauge, Some(Unit::Bytes), "size of the MQTT buffer in bytes"),
+    ("exporter_mqtt_buffered_requests", Kind::Gauge, Some(Unit::Count), "batches of messages in the MQTT buffer"),
+    ("exporter_broker_connected", Kind::Gauge, None, "1 while connected to the NATS or Kafka broker"),
+    ("exporter_broker_published_total", Kind::Counter, Some(Unit::Count), "messages published to the NATS or Kafka broker"),
+    ("exporter_broker_dropped_total", Kind::Counter, Some(Unit::Count), "messages dropped because the broker queue was full or the broker rejected them"),
+    ("exporter_influx_written_points_total", Kind::Counter, Some(Unit::Count), "points written to InfluxDB"),
+    ("exporter_influx_write_failures_total", Kind::Counter, Some(Unit::Count), "failed InfluxDB writes"),
+    ("exporter_influx_dropped_points_total", Kind::Counter, Some(Unit::Count), "points given up on because InfluxDB rejected them or the queue was full"),
+    ("exporter_influx_buffered_bytes", Kind::Gauge, Some(Unit::Bytes), "size of the InfluxDB buffer in bytes"),
+    ("exporter_influx_buffered_requests", Kind::Gauge, Some(Unit::Count), "batches in the InfluxDB buffer"),
+    ("exporter_otlp_exports_total", Kind::Counter, Some(Unit::Count), "exports accepted by the OpenTelemetry collector"),
+    ("exporter_otlp_export_failures_total", Kind::Counter, Some(Unit::Count), "failed exports to the OpenTelemetry collector"),
+    ("exporter_statsd_packets_total", Kind::Counter, Some(Unit::Count), "datagrams sent to the StatsD agent"),
+    ("exporter_statsd_send_failures_total", Kind::Counter, Some(Unit::Count), "failed sends to the StatsD agent"),
+    ("exporter_graphite_connected", Kind::Gauge, None, "1 while connected to carbon"),
+    ("exporter_graphite_sent_total", Kind::Counter, Some(Unit::Count), "measurements sent to carbon"),
+    ("exporter_graphite_dropped_total", Kind::Counter, Some(Unit::Count), "measurements dropped because the graphite queue was full"),
+    ("exporter_csv_write_failures_total", Kind::Counter, Some(Unit::Count), "failed writes of the CSV log"),
+    ("exporter_csv_dropped_total", Kind::Counter, Some(Unit::Count), "measurements dropped because the CSV queue was full"),
+    ("exporter_history_points", Kind::Gauge, None, "points kept in the measurement history"),
+    ("exporter_history_write_failures_total", Kind::Counter, Some(Unit::Count), "failed writes of the measurement history"),
+    ("exporter_ble_failures_total", Kind::Counter, Some(Unit::Count), "failed updates of the bluetooth advertisements"),
+    ("exporter_dbus_connected", Kind::Gauge, None, "1 while connected to the d-bus bus"),
+    ("exporter_modbus_connections", Kind::Gauge, None, "open modbus tcp connections"),
+    ("exporter_modbus_requests_total", Kind::Counter, Some(Unit::Count), "modbus tcp requests answered"),
+    ("exporter_snmp_requests_total", Kind::Counter, Some(Unit::Count), "snmp requests answered"),
+    ("exporter_snmp_invalid_total", Kind::Counter, Some(Unit::Count), "snmp requests ignored as malformed or with a wrong community"),
+    ("exporter_coap_requests_total", Kind::Counter, Some(Unit::Count), "coap requests received"),
+    ("exporter_coap_observers", Kind::Gauge, None, "clients observing a coap resource"),
+    ("exporter_http_rejected_total", Kind::Counter, Some(Unit::Count), "http connections closed as their source isn't in --allow-cidr"),
+    ("exporter_events_total", Kind::Counter, Some(Unit::Count), "recorded events by kind"),
+    ("exporter_legacy_metric_scrapes_total", Kind::Counter, Some(Unit::Count), "scrapes of /metrics returning an old metric name during --migrate-metrics"),
+    ("exporter_alert_firing", Kind::Gauge, None, "1 while the --alert is firing"),
+    ("exporter_alert_notification_failures_total", Kind::Counter, Some(Unit::Count), "alert notifications the webhook didn't accept"),
+    ("exporter_buzzer_sounding", Kind::Gauge, None, "1 while the buzzer plays its alarm pattern"),
+    ("exporter_relay_on", Kind::Gauge, None, "1 while the ventilation relay is switched on"),
+    ("exporter_relay_switches_total", Kind::Counter, Some(Unit::Count), "times the ventilation relay switched"),
+    ("exporter_fan_duty_percent", Kind::Gauge, Some(Unit::Percent), "duty cycle commanded to the PWM fan in percent"),
+    ("i2c_transaction_duration_seconds", Kind::Histogram, Some(Unit::Seconds), "duration of I2C transactions in seconds"),
+    ("i2c_transaction_errors_total", Kind::Counter, Some(Unit::Count), "failed I2C transactions"),
+    ("fault_injected_total", Kind::Counter, Some(Unit::Count), "injected I2C faults by kind"),
+];
+
+/// register HELP and units of all metrics with the installed recorder
+pub(crate) fn describe_all() {
+    for (name, kind, unit, help) in METRICS {
+        match (kind, unit) {
+            (Kind::Counter, Some(u)) => metrics::describe_counter!(*name, *u, *help),
+            (Kind::Counter, None) => metrics::describe_counter!(*name, *help),
+            (Kind::Gauge, Some(u)) => metrics::describe_gauge!(*name, *u, *help),
+            (Kind::Gauge, None) => metrics::describe_gauge!(*name, *help),
+            (Kind::Histogram, Some(u)) => metrics::describe_histogram!(*name, *u, *help),
+            (Kind::Histogram, None) => metrics::describe_histogram!(*name, *help),
+        }
+    }
+}
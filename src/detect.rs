@@ -0,0 +1,83 @@
+//! module for detecting supported sensors on the bus
+//! each known address is probed with a command that is harmless in any device state.
+use embedded_hal::i2c;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Device {
+    Scd4x,
+    Scd30,
+    Sht4x(u8),
+    Sgp4x,
+    Sen5x,
+    Sfa3x,
+    Bme280(u8),
+}
+
+impl Device {
+    /// the address the device answered on
+    pub(crate) fn addr(self) -> u8 {
+        return match self {
+            Device::Scd4x => 0x62,
+            Device::Scd30 => 0x61,
+            Device::Sht4x(addr) | Device::Bme280(addr) => addr,
+            Device::Sgp4x => 0x59,
+            Device::Sen5x => 0x69,
+            Device::Sfa3x => 0x5D,
+        };
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        return match self {
+            Device::Scd4x => "scd4x",
+            Device::Scd30 => "scd30",
+            Device::Sht4x(_) => "sht4x",
+            Device::Sgp4x => "sgp4x",
+            Device::Sen5x => "sen5x",
+            Device::Sfa3x => "sfa3x",
+            Device::Bme280(_) => "bme280",
+        };
+    }
+}
+
+/// probe the known addresses and return what answered
+pub(crate) fn scan<I: i2c::I2c>(i2c: &mut I) -> Vec<Device> {
+    let mut found = Vec::new();
+    // stop_periodic_measurement is accepted both in idle and in periodic mode
+    if i2c.write(0x62, &[0x3F, 0x86]).is_ok() {
+        found.push(Device::Scd4x);
+    }
+    // read_firmware_version
+    if i2c.write(0x61, &[0xD1, 0x00]).is_ok() {
+        found.push(Device::Scd30);
+    }
+    // read_serial
+    for addr in [0x44, 0x45, 0x46] {
+        if i2c.write(addr, &[0x89]).is_ok() {
+            found.push(Device::Sht4x(addr));
+        }
+    }
+    // get_serial_number
+    if i2c.write(0x59, &[0x36, 0x82]).is_ok() {
+        found.push(Device::Sgp4x);
+    }
+    // read_product_name
+    if i2c.write(0x69, &[0xD0, 0x14]).is_ok() {
+        found.push(Device::Sen5x);
+    }
+    // get_device_marking
+    if i2c.write(0x5D, &[0xD0, 0x60]).is_ok() {
+        found.push(Device::Sfa3x);
+    }
+    // chip id register
+    for addr in [0x76, 0x77] {
+        let mut id = [0_u8; 1];
+        if i2c.write_read(addr, &[0xD0], &mut id).is_ok() && id[0] == 0x60 {
+            found.push(Device::Bme280(addr));
+        }
+    }
+    // leave time for the probed commands to finish before they are used for real
+    std::thread::sleep(std::time::Duration::from_millis(500));
+
+    log::info!("detected devices: {:?}", found);
+    return found;
+}
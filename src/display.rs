@@ -0,0 +1,283 @@
+//! module for showing the current reading on a display attached to the Pi
+//! a thread redraws the screen every --display-refresh with the latest CO2, temperature and humidity and
+//! an arrow for the CO2 trend. while there's no valid data it shows the sensor's status instead. during
+//! --display-off (local time) and quiet --power-profile spans the screen is switched off. shutdown blanks it.
+//! numbers, temperatures and times are written as --display-locale has them, e.g. `21,5°C` for de or `70.7°F`
+//! and `2:05pm` for en-US.
+use std::{
+    io, thread,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    clock::DailySpan,
+    font,
+    latest::{self, Status},
+    profile,
+    shutdown::{self, Pending},
+};
+
+/// CO2 change in ppm per minute shown as rising or falling
+const TREND_ARROW: f64 = 5.0;
+
+static PENDING: Pending = Pending::new("display");
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Kind {
+    /// SSD1306 OLED on the I2C bus
+    Ssd1306,
+    /// Waveshare e-paper panel on SPI0, see --epaper-model
+    Epaper,
+    /// HD44780 character LCD with a PCF8574 I2C backpack, see --lcd-size
+    Hd44780,
+    /// LED matrix of a Sense HAT, see --sense-hat-style
+    SenseHat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Trend {
+    Rising,
+    Steady,
+    Falling,
+}
+
+impl Trend {
+    pub(crate) fn arrow(self) -> char {
+        return match self {
+            Trend::Rising => '↑',
+            Trend::Steady => '→',
+            Trend::Falling => '↓',
+        };
+    }
+}
+
+/// languages writing a decimal comma
+const DECIMAL_COMMA: &[&str] = &[
+    "bg", "ca", "cs", "da", "de", "el", "es", "et", "fi", "fr", "hr", "hu", "id", "it", "lt", "lv", "nb", "nl", "nn", "no", "pl", "pt", "ro", "ru", "sk",
+    "sl", "sv", "tr", "uk", "vi",
+];
+/// regions measuring temperatures in degrees Fahrenheit
+const FAHRENHEIT: &[&str] = &["BS", "BZ", "FM", "KY", "LR", "MH", "PW", "US"];
+/// regions reading the time on a 12 hour clock
+const TWELVE_HOUR: &[&str] = &["AU", "BD", "CA", "EG", "IN", "MY", "NZ", "PH", "PK", "SA", "US"];
+
+/// how the screens write numbers, temperatures and times
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Locale {
+    pub(crate) decimal_comma: bool,
+    pub(crate) fahrenheit: bool,
+    pub(crate) twelve_hour: bool,
+}
+
+impl Locale {
+    /// `value` with `decimals` digits after the separator
+    pub(crate) fn number(&self, value: f32, decimals: usize) -> String {
+        let s = format!("{:.*}", decimals, value);
+        return if self.decimal_comma { s.replace('.', ",") } else { s };
+    }
+
+    /// `celsius` in the locale's unit with one decimal, and the unit's letter
+    pub(crate) fn temperature(&self, celsius: f32) -> (String, char) {
+        return match self.fahrenheit {
+            true => (self.number(celsius * 9.0 / 5.0 + 32.0, 1), 'F'),
+            false => (self.number(celsius, 1), 'C'),
+        };
+    }
+
+    /// the time of day `minute` minutes after midnight, 14:05 or 2:05pm
+    pub(crate) fn time(&self, minute: u16) -> String {
+        let (hour, minute) = (minute / 60 % 24, minute % 60);
+        if !self.twelve_hour {
+            return format!("{:02}:{:02}", hour, minute);
+        }
+        let suffix = if hour < 12 { "am" } else { "pm" };
+        return format!("{}:{:02}{}", (hour + 11) % 12 + 1, minute, suffix);
+    }
+}
+
+/// parse a locale like de_DE.UTF-8 or en-US, followed by overrides: `de,fahrenheit` (or celsius, 12h, 24h, comma, point)
+pub(crate) fn parse_locale(s: &str) -> Result<Locale, String> {
+    let mut parts = s.split(',');
+    let name = parts.next().unwrap_or_default();
+    let name = name.split(['.', '@']).next().unwrap_or_default();
+    let (language, region) = name.split_once(['_', '-']).unwrap_or((name, ""));
+    let (language, region) = (language.to_lowercase(), region.to_uppercase());
+    let mut locale = Locale::default();
+    if !matches!(name, "C" | "POSIX") {
+        if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(format!("invalid locale {}", name));
+        }
+        locale.decimal_comma = DECIMAL_COMMA.contains(&language.as_str());
+        locale.fahrenheit = FAHRENHEIT.contains(&region.as_str());
+        locale.twelve_hour = TWELVE_HOUR.contains(&region.as_str());
+    }
+    for option in parts {
+        match option {
+            "comma" => locale.decimal_comma = true,
+            "point" => locale.decimal_comma = false,
+            "fahrenheit" => locale.fahrenheit = true,
+            "celsius" => locale.fahrenheit = false,
+            "12h" => locale.twelve_hour = true,
+            "24h" => locale.twelve_hour = false,
+            _ => return Err(format!("unknown option {}, expected comma, point, celsius, fahrenheit, 12h or 24h", option)),
+        }
+    }
+    return Ok(locale);
+}
+
+/// what the screens show
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum View {
+    Reading { co2: u16, temperature: f32, humidity: f32, trend: Option<Trend> },
+    /// no valid data, with the sensor's status
+    Status(Status),
+}
+
+impl View {
+    /// the view of the latest reading
+    pub(crate) fn current() -> Self {
+        let (envelope, _, status) = latest::get();
+        return match (envelope, status) {
+            (Some(e), Status::Ok) => View::Reading {
+                co2: e.measurement.co2,
+                temperature: e.measurement.temperature,
+                humidity: e.measurement.humidity,
+                trend: latest::trend().map(|t| match t {
+                    t if t >= TREND_ARROW => Trend::Rising,
+                    t if t <= -TREND_ARROW => Trend::Falling,
+                    _ => Trend::Steady,
+                }),
+            },
+            (_, status) => View::Status(status),
+        };
+    }
+}
+
+/// a monochrome frame
+pub(crate) struct Canvas {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pixels: Vec<bool>,
+}
+
+impl Canvas {
+    pub(crate) fn new(width: usize, height: usize) -> Self {
+        return Canvas { width, height, pixels: vec![false; width * height] };
+    }
+
+    pub(crate) fn get(&self, x: usize, y: usize) -> bool {
+        return x < self.width && y < self.height && self.pixels[y * self.width + x];
+    }
+
+    pub(crate) fn set(&mut self, x: usize, y: usize, on: bool) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = on;
+        }
+    }
+
+    /// draw `text` with its top left at (`x`, `y`), each font pixel `scale` pixels wide. returns the width drawn.
+    pub(crate) fn text(&mut self, x: usize, y: usize, text: &str, scale: usize) -> usize {
+        let advance = (font::WIDTH + 1) * scale;
+        for (i, c) in text.chars().enumerate() {
+            for (col, bits) in font::glyph(c).iter().enumerate() {
+                for row in 0..font::HEIGHT {
+                    if bits & (1 << row) == 0 {
+                        continue;
+                    }
+                    for (dx, dy) in (0..scale).flat_map(|dx| (0..scale).map(move |dy| (dx, dy))) {
+                        self.set(x + i * advance + col * scale + dx, y + row * scale + dy, true);
+                    }
+                }
+            }
+        }
+        return text.chars().count() * advance;
+    }
+
+    /// width of `text` drawn at `scale`
+    pub(crate) fn text_width(text: &str, scale: usize) -> usize {
+        return text.chars().count() * (font::WIDTH + 1) * scale;
+    }
+}
+
+/// a display the thread drives
+pub(crate) trait Screen: Send {
+    fn show(&mut self, view: &View, locale: &Locale) -> Result<(), String>;
+    /// switch the screen on or off
+    fn power(&mut self, on: bool) -> Result<(), String>;
+    /// whether it pages through the values, so it's redrawn on every refresh
+    fn cycles(&self) -> bool {
+        return false;
+    }
+}
+
+pub(crate) struct Config {
+    pub(crate) refresh: Duration,
+    /// when the screen is off
+    pub(crate) off: Option<DailySpan>,
+    pub(crate) locale: Locale,
+}
+
+/// drive `screen` from a thread
+pub(crate) fn spawn(mut screen: impl Screen + 'static, config: Config) -> io::Result<()> {
+    PENDING.register();
+    PENDING.add(1);
+    thread::Builder::new().name(String::from("display")).spawn(move || {
+        let mut off = false;
+        let mut shown = None;
+        while !shutdown::requested() {
+            let scheduled_off = config.off.is_some_and(|span| span.contains_now()) || profile::quiet();
+            if scheduled_off != off {
+                log::info!("switch the display {}", if scheduled_off { "off" } else { "on" });
+                let _ = screen.power(!scheduled_off).inspect_err(|e| log::warn!("failed to switch the display: {}", e));
+                off = scheduled_off;
+                shown = None;
+            }
+            let view = View::current();
+            // redraw only on changes, the bus is shared with the sensor
+            if !off && (screen.cycles() || shown.as_ref() != Some(&view)) {
+                match screen.show(&view, &config.locale) {
+                    Ok(()) => shown = Some(view),
+                    Err(e) => log::warn!("failed to update the display: {}", e),
+                }
+            }
+            let until = Instant::now() + config.refresh;
+            while Instant::now() < until && !shutdown::requested() {
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+        let _ = screen.power(false).inspect_err(|e| log::warn!("failed to switch the display off: {}", e));
+        PENDING.done(1);
+    })?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locales() {
+        assert_eq!(parse_locale("C"), Ok(Locale::default()));
+        assert_eq!(parse_locale("en_GB.UTF-8"), Ok(Locale::default()));
+        assert_eq!(parse_locale("de_DE.UTF-8"), Ok(Locale { decimal_comma: true, fahrenheit: false, twelve_hour: false }));
+        assert_eq!(parse_locale("en-US"), Ok(Locale { decimal_comma: false, fahrenheit: true, twelve_hour: true }));
+        assert_eq!(parse_locale("fr,fahrenheit,12h"), Ok(Locale { decimal_comma: true, fahrenheit: true, twelve_hour: true }));
+        assert_eq!(parse_locale("en_US,celsius,24h,comma"), Ok(Locale { decimal_comma: true, fahrenheit: false, twelve_hour: false }));
+        assert!(parse_locale("german").is_err());
+        assert!(parse_locale("de,kelvin").is_err());
+    }
+
+    #[test]
+    fn formatting() {
+        let de = parse_locale("de").unwrap();
+        let us = parse_locale("en_US").unwrap();
+        assert_eq!((de.number(40.26, 1), de.number(40.5, 0)), (String::from("40,3"), String::from("40")));
+        assert_eq!(de.temperature(21.46), (String::from("21,5"), 'C'));
+        assert_eq!(us.temperature(21.5), (String::from("70.7"), 'F'));
+        assert_eq!((de.time(0), de.time(14 * 60 + 5)), (String::from("00:00"), String::from("14:05")));
+        let times: Vec<String> = [0, 5, 12 * 60, 14 * 60 + 5, 23 * 60 + 59].iter().map(|m| us.time(*m)).collect();
+        assert_eq!(times, ["12:00am", "12:05am", "12:00pm", "2:05pm", "11:59pm"]);
+    }
+}
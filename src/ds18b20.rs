@@ -0,0 +1,60 @@
+//! module for DS18B20 1-Wire temperature probes via the kernel's w1 sysfs interface
+use std::{fs, io, path::Path};
+
+use crate::plugin::{Kind, Plugin, Reading, Spec};
+
+const W1_DEVICES: &str = "/sys/bus/w1/devices";
+
+/// parse a probe given as `ID=NAME` (or just `ID`), e.g. `28-0123456789ab=greenhouse`
+pub(crate) fn parse_probe(s: &str) -> Result<Spec, String> {
+    let (id, name) = s.split_once('=').unwrap_or((s, s));
+    if !id.starts_with("28-") {
+        return Err(format!("{} is not a DS18B20 id (28-xxxxxxxxxxxx)", id));
+    }
+    let mut spec = Spec::new(Kind::Ds18b20);
+    spec.channel = Some(id.to_string());
+    spec.labels = vec![(String::from("probe"), name.to_string()), (String::from("id"), id.to_string())];
+    return Ok(spec);
+}
+
+/// read a probe's temperature in degC from its w1_slave file
+pub(crate) fn read_temperature(id: &str) -> io::Result<f32> {
+    let content = fs::read_to_string(format!("{}/{}/w1_slave", W1_DEVICES, id))?;
+    // first line ends with the crc check ("YES"), second line with "t=<millidegrees>"
+    let mut lines = content.lines();
+    if !lines.next().is_some_and(|l| l.ends_with("YES")) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "crc mismatch"));
+    }
+    let millis = lines
+        .next()
+        .and_then(|l| l.rsplit_once("t="))
+        .and_then(|(_, t)| t.trim().parse::<i32>().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing temperature"))?;
+    return Ok(millis as f32 / 1000_f32);
+}
+
+/// ds18b20 plugin reading one probe
+pub(crate) struct Ds18b20 {
+    id: String,
+}
+
+impl Ds18b20 {
+    pub(crate) fn new(id: String) -> Self {
+        return Ds18b20 { id };
+    }
+}
+
+impl Plugin for Ds18b20 {
+    fn start(&mut self) -> Result<(), String> {
+        let path = format!("{}/{}", W1_DEVICES, self.id);
+        if !Path::new(&path).exists() {
+            return Err(format!("{} not found", path));
+        }
+        return Ok(());
+    }
+
+    fn read(&mut self) -> Result<Vec<Reading>, String> {
+        let t = read_temperature(&self.id).map_err(|e| format!("{:?}", e))?;
+        return Ok(vec![("ds18b20_temperature_celsius", t as f64)]);
+    }
+}
@@ -0,0 +1,107 @@
+//! module for the DS3231 real-time clock
+//! see https://www.analog.com/media/en/technical-documentation/data-sheets/DS3231.pdf
+use std::{
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use embedded_hal::i2c;
+
+use crate::clock::{self, Clock};
+
+const DS3231_I2C_ADDR: u8 = 0x68;
+
+/// how often the clock is re-synchronized with the RTC
+const RESYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// read the current time (registers 0x00-0x06, always kept in UTC)
+pub(crate) fn read_time<I: i2c::I2c>(i2c: &mut I) -> Result<SystemTime, I::Error> {
+    let mut buf = [0_u8; 7];
+    i2c.write_read(DS3231_I2C_ADDR, &[0x00], &mut buf)?;
+
+    let seconds = bcd(buf[0] & 0x7F) as u64;
+    let minutes = bcd(buf[1] & 0x7F) as u64;
+    let hours = if buf[2] & 0x40 != 0 {
+        // 12 hour mode, bit 5 is PM
+        (bcd(buf[2] & 0x1F) % 12 + if buf[2] & 0x20 != 0 { 12 } else { 0 }) as u64
+    } else {
+        bcd(buf[2] & 0x3F) as u64
+    };
+    let day = bcd(buf[4] & 0x3F) as u32;
+    let month = bcd(buf[5] & 0x1F) as u32;
+    let year = 2000 + bcd(buf[6]) as i64 + if buf[5] & 0x80 != 0 { 100 } else { 0 };
+
+    let days = clock::days_from_civil(year, month, day);
+    let secs = days as u64 * 86400 + hours * 3600 + minutes * 60 + seconds;
+    return Ok(UNIX_EPOCH + Duration::from_secs(secs));
+}
+
+/// read the temperature of the RTC's compensation sensor (registers 0x11-0x12)
+pub(crate) fn read_temperature<I: i2c::I2c>(i2c: &mut I) -> Result<f32, I::Error> {
+    let mut buf = [0_u8; 2];
+    i2c.write_read(DS3231_I2C_ADDR, &[0x11], &mut buf)?;
+    return Ok(buf[0] as i8 as f32 + (buf[1] >> 6) as f32 * 0.25);
+}
+
+fn bcd(v: u8) -> u8 {
+    return (v >> 4) * 10 + (v & 0x0F);
+}
+
+struct Sync {
+    time: SystemTime,
+    at: Instant,
+}
+
+/// clock whose wall-clock time comes from a DS3231. the RTC is read once a minute and
+/// interpolated with the monotonic clock in between. it also exports the RTC's temperature.
+pub(crate) struct RtcClock<I> {
+    i2c: Mutex<I>,
+    sync: Mutex<Sync>,
+    temperature: metrics::Gauge,
+}
+
+impl<I: i2c::I2c> RtcClock<I> {
+    pub(crate) fn new(mut i2c: I) -> Result<Self, I::Error> {
+        let time = read_time(&mut i2c)?;
+        let clock = RtcClock {
+            i2c: Mutex::new(i2c),
+            sync: Mutex::new(Sync { time, at: Instant::now() }),
+            temperature: metrics::gauge!("ds3231_temperature_celsius"),
+        };
+        clock.read_temperature();
+        return Ok(clock);
+    }
+
+    fn read_temperature(&self) {
+        let mut i2c = self.i2c.lock().unwrap_or_else(|e| e.into_inner());
+        match read_temperature(&mut *i2c) {
+            Ok(t) => self.temperature.set(t),
+            Err(e) => log::warn!("failed to read ds3231 temperature: {:?}", e),
+        }
+    }
+}
+
+impl<I: i2c::I2c + Send> Clock for RtcClock<I> {
+    fn now(&self) -> SystemTime {
+        let mut sync = self.sync.lock().unwrap_or_else(|e| e.into_inner());
+        if sync.at.elapsed() >= RESYNC_INTERVAL {
+            let mut i2c = self.i2c.lock().unwrap_or_else(|e| e.into_inner());
+            match read_time(&mut *i2c) {
+                Ok(time) => *sync = Sync { time, at: Instant::now() },
+                Err(e) => log::warn!("failed to read ds3231 time: {:?}", e),
+            }
+            drop(i2c);
+            self.read_temperature();
+        }
+        return sync.time + sync.at.elapsed();
+    }
+
+    fn instant(&self) -> Instant {
+        return Instant::now();
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
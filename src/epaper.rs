@@ -0,0 +1,222 @@
+//! module for Waveshare SPI e-paper panels with an SSD1680 controller (2.13" V3/V4, 2.9" V2)
+//! the panel is on SPI0 CE0 with DC on GPIO 25, RST on GPIO 17 and BUSY on GPIO 24, as on Waveshare's HAT.
+//! besides the current values it draws the CO2 of the last 24 hours from the local history (--history-dir).
+//! each refresh is a full one, waking the panel from deep sleep and putting it back after, so keep
+//! --display-refresh at minutes. switching it off clears it, so it doesn't show stale values.
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use rppal::{
+    gpio::{Gpio, InputPin, OutputPin},
+    spi::{Bus, Mode, SlaveSelect, Spi},
+};
+
+use crate::{
+    clock::{self, Clock},
+    display::{Canvas, Locale, Screen, View},
+    error::Error,
+    history,
+};
+
+const DC: u8 = 25;
+const RST: u8 = 17;
+const BUSY: u8 = 24;
+const CLOCK: u32 = 4_000_000;
+/// longest a full refresh takes
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+const GRAPH_SPAN: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Model {
+    /// 2.13" V3 or V4, 250x122
+    #[value(name = "2in13")]
+    Epd2in13,
+    /// 2.9" V2, 296x128
+    #[value(name = "2in9")]
+    Epd2in9,
+}
+
+impl Model {
+    /// the panel's native portrait (width, height)
+    fn size(self) -> (usize, usize) {
+        return match self {
+            Model::Epd2in13 => (122, 250),
+            Model::Epd2in9 => (128, 296),
+        };
+    }
+}
+
+pub(crate) struct Epaper {
+    spi: Spi,
+    dc: OutputPin,
+    rst: OutputPin,
+    busy: InputPin,
+    width: usize,
+    height: usize,
+    /// the time of the graph and the refresh
+    clock: Arc<dyn Clock + Send>,
+}
+
+impl Epaper {
+    /// claim the panel's SPI bus and pins and clear it
+    pub(crate) fn new(model: Model, clock: Arc<dyn Clock + Send>) -> Result<Self, Error> {
+        let gpio = Gpio::new()?;
+        let (width, height) = model.size();
+        let mut epaper = Epaper {
+            spi: Spi::new(Bus::Spi0, SlaveSelect::Ss0, CLOCK, Mode::Mode0)?,
+            dc: gpio.get(DC)?.into_output_low(),
+            rst: gpio.get(RST)?.into_output_high(),
+            busy: gpio.get(BUSY)?.into_input(),
+            width,
+            height,
+            clock,
+        };
+        epaper.refresh(&Canvas::new(height, width)).map_err(Error::Device)?;
+        return Ok(epaper);
+    }
+
+    fn command(&mut self, command: u8, data: &[u8]) -> Result<(), String> {
+        self.dc.set_low();
+        self.spi.write(&[command]).map_err(|e| format!("{:?}", e))?;
+        if !data.is_empty() {
+            self.dc.set_high();
+            // spidev limits a transfer to 4096 bytes
+            for chunk in data.chunks(4096) {
+                self.spi.write(chunk).map_err(|e| format!("{:?}", e))?;
+            }
+        }
+        return Ok(());
+    }
+
+    fn wait(&self) -> Result<(), String> {
+        let start = Instant::now();
+        while self.busy.is_high() {
+            if start.elapsed() > BUSY_TIMEOUT {
+                return Err(String::from("the e-paper panel stays busy"));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        return Ok(());
+    }
+
+    /// wake the panel with a hardware reset and set it up
+    fn wake(&mut self) -> Result<(), String> {
+        self.rst.set_low();
+        thread::sleep(Duration::from_millis(10));
+        self.rst.set_high();
+        thread::sleep(Duration::from_millis(10));
+        self.wait()?;
+        // software reset
+        self.command(0x12, &[])?;
+        self.wait()?;
+        let last_row = (self.height - 1) as u16;
+        self.command(0x01, &[last_row as u8, (last_row >> 8) as u8, 0x00])?;
+        // x and y increment
+        self.command(0x11, &[0x03])?;
+        self.command(0x44, &[0x00, ((self.width - 1) / 8) as u8])?;
+        self.command(0x45, &[0x00, 0x00, last_row as u8, (last_row >> 8) as u8])?;
+        // white border
+        self.command(0x3C, &[0x05])?;
+        // internal temperature sensor
+        self.command(0x18, &[0x80])?;
+        self.command(0x4E, &[0x00])?;
+        self.command(0x4F, &[0x00, 0x00])?;
+        return Ok(());
+    }
+
+    /// show `canvas` (landscape, height x width) with a full refresh and sleep
+    fn refresh(&mut self, canvas: &Canvas) -> Result<(), String> {
+        self.wake()?;
+        let row_bytes = self.width.div_ceil(8);
+        let mut ram = vec![0xFF; row_bytes * self.height];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                // portrait RAM, the landscape frame turned a quarter
+                if canvas.get(y, self.width - 1 - x) {
+                    ram[y * row_bytes + x / 8] &= !(0x80 >> (x % 8));
+                }
+            }
+        }
+        self.command(0x24, &ram)?;
+        self.command(0x22, &[0xF7])?;
+        self.command(0x20, &[])?;
+        self.wait()?;
+        // deep sleep, the image stays
+        return self.command(0x10, &[0x01]);
+    }
+}
+
+impl Screen for Epaper {
+    fn show(&mut self, view: &View, locale: &Locale) -> Result<(), String> {
+        let (w, h) = (self.height, self.width);
+        let mut canvas = Canvas::new(w, h);
+        match view {
+            View::Reading { co2, temperature, humidity, trend } => {
+                let x = canvas.text(4, 4, &co2.to_string(), 4);
+                canvas.text(x + 6, 18, "ppm", 2);
+                if let Some(trend) = trend {
+                    canvas.text(w - Canvas::text_width("↑", 4) - 4, 4, &trend.arrow().to_string(), 4);
+                }
+                let (temperature, unit) = locale.temperature(*temperature);
+                canvas.text(4, 38, &format!("{}°{}  {}%RH", temperature, unit, locale.number(*humidity, 0)), 2);
+            }
+            View::Status(status) => {
+                canvas.text(4, 4, "CO2", 4);
+                canvas.text(4, 38, status.name(), 2);
+            }
+        }
+        // the panel keeps the image, so tell how old it is
+        canvas.text(4, 58, &locale.time(clock::local_weekday_minute_at(self.clock.now()).1), 1);
+        graph(&mut canvas, &*self.clock, 4, 58, w - 8, h - 62);
+        return self.refresh(&canvas);
+    }
+
+    fn power(&mut self, on: bool) -> Result<(), String> {
+        // it's asleep between refreshes anyway
+        if on {
+            return Ok(());
+        }
+        let (w, h) = (self.height, self.width);
+        return self.refresh(&Canvas::new(w, h));
+    }
+}
+
+/// draw the CO2 of the last 24 hours into the box at (`x`, `y`) of `width` x `height`
+fn graph(canvas: &mut Canvas, clock: &dyn Clock, x: usize, y: usize, width: usize, height: usize) {
+    let now = clock.now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default();
+    let span = GRAPH_SPAN.as_millis() as u64;
+    let Some(points) = history::query(clock, Some(now.saturating_sub(span)), Some(now), Some(span / width as u64)) else {
+        return;
+    };
+    if points.is_empty() {
+        return;
+    }
+    let min = points.iter().map(|p| p.co2).fold(f32::INFINITY, f32::min);
+    let max = points.iter().map(|p| p.co2).fold(f32::NEG_INFINITY, f32::max);
+    let label = format!("24h {:.0}-{:.0} ppm", min, max);
+    canvas.text(x + width - Canvas::text_width(&label, 1), y, &label, 1);
+    let (top, plot) = (y + 9, height.saturating_sub(9).max(1));
+    // a frame line at the bottom
+    for i in 0..width {
+        canvas.set(x + i, top + plot - 1, true);
+    }
+    let row = |co2: f32| top + plot - 1 - (((co2 - min) / (max - min).max(1.0)) * (plot - 1) as f32).round() as usize;
+    let mut last = None;
+    for p in &points {
+        let column = x + ((p.timestamp_ms.saturating_sub(now - span)) * width as u64 / span).min(width as u64 - 1) as usize;
+        let r = row(p.co2);
+        // join to the previous point with a vertical run
+        let (from, to) = match last {
+            Some(l) => (r.min(l), r.max(l)),
+            None => (r, r),
+        };
+        for y in from..=to {
+            canvas.set(column, y, true);
+        }
+        last = Some(r);
+    }
+}
@@ -0,0 +1,140 @@
+//! module for the exporter's error type
+//! an [`Error`] tells what went wrong, so a device that doesn't acknowledge can be told apart from a
+//! corrupted response or an invalid configuration, in code with [`Error::root`] and in the
+//! scd41_measurement_errors_total counter with [`Error::kind`]. [`Context`] adds what the exporter was doing.
+use std::{convert::Infallible, io};
+
+use embedded_hal::i2c::{self, ErrorKind, NoAcknowledgeSource};
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    /// a device on the I2C bus didn't acknowledge its address or data
+    #[error("i2c device not acknowledging ({0:?})")]
+    Nack(NoAcknowledgeSource),
+    /// any other I2C failure
+    #[error("i2c bus error ({0:?})")]
+    Bus(ErrorKind),
+    /// a response word didn't match its checksum
+    #[error("checksum mismatch in the response")]
+    Crc,
+    #[error("invalid configuration: {0}")]
+    Config(String),
+    /// serving or sending HTTP, with TLS
+    #[error("http: {0}")]
+    Http(String),
+    /// an output (push, mqtt, influxdb...) couldn't start
+    #[error("{0} output: {1}")]
+    Output(&'static str, #[source] io::Error),
+    /// GPIO, SPI, PWM, UART or a framebuffer
+    #[error("{0}")]
+    Device(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("{context}: {source}")]
+    Context {
+        context: String,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// the error under the contexts
+    pub(crate) fn root(&self) -> &Error {
+        return match self {
+            Error::Context { source, .. } => source.root(),
+            e => e,
+        };
+    }
+
+    /// the root's variant as a label value
+    pub(crate) fn kind(&self) -> &'static str {
+        return match self.root() {
+            Error::Nack(_) => "nack",
+            Error::Bus(_) => "bus",
+            Error::Crc => "crc",
+            Error::Config(_) => "config",
+            Error::Http(_) => "http",
+            Error::Output(..) => "output",
+            Error::Device(_) => "device",
+            Error::Io(_) => "io",
+            Error::Context { .. } => unreachable!(),
+        };
+    }
+
+    /// classify an I2C error
+    pub(crate) fn i2c(e: &impl i2c::Error) -> Self {
+        return match e.kind() {
+            ErrorKind::NoAcknowledge(source) => Error::Nack(source),
+            kind => Error::Bus(kind),
+        };
+    }
+}
+
+impl<E: i2c::Error> From<scd4x::Error<E>> for Error {
+    fn from(e: scd4x::Error<E>) -> Self {
+        return match e {
+            scd4x::Error::Write(e) | scd4x::Error::Read(e) => Error::i2c(&e),
+            scd4x::Error::Crc => Error::Crc,
+        };
+    }
+}
+
+impl<I: i2c::I2c> From<sensirion_i2c::i2c::Error<I>> for Error {
+    fn from(e: sensirion_i2c::i2c::Error<I>) -> Self {
+        return match e {
+            sensirion_i2c::i2c::Error::I2cWrite(e) | sensirion_i2c::i2c::Error::I2cRead(e) => Error::i2c(&e),
+            sensirion_i2c::i2c::Error::Crc => Error::Crc,
+        };
+    }
+}
+
+impl From<rppal::i2c::Error> for Error {
+    fn from(e: rppal::i2c::Error) -> Self {
+        return match e {
+            rppal::i2c::Error::Io(e) => Error::Io(e),
+            e => Error::Device(format!("i2c: {}", e)),
+        };
+    }
+}
+
+impl From<rppal::gpio::Error> for Error {
+    fn from(e: rppal::gpio::Error) -> Self {
+        return Error::Device(format!("gpio: {}", e));
+    }
+}
+
+impl From<rppal::spi::Error> for Error {
+    fn from(e: rppal::spi::Error) -> Self {
+        return Error::Device(format!("spi: {}", e));
+    }
+}
+
+impl From<rppal::pwm::Error> for Error {
+    fn from(e: rppal::pwm::Error) -> Self {
+        return Error::Device(format!("pwm: {}", e));
+    }
+}
+
+impl From<rppal::uart::Error> for Error {
+    fn from(e: rppal::uart::Error) -> Self {
+        return Error::Device(format!("uart: {}", e));
+    }
+}
+
+impl From<Infallible> for Error {
+    fn from(e: Infallible) -> Self {
+        match e {}
+    }
+}
+
+/// add what was being done to an error
+pub(crate) trait Context<T> {
+    fn context(self, context: impl Into<String>) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+    fn context(self, context: impl Into<String>) -> Result<T, Error> {
+        return self.map_err(|e| Error::Context { context: context.into(), source: Box::new(e.into()) });
+    }
+}
@@ -0,0 +1,130 @@
+//! module for recording discrete events (sensor reinit, recalibration, config reload, alerts)
+//! events are logged, counted in `exporter_events_total` and kept in memory for the HTTP API. with --history-dir
+//! they're stored along with the history as well, so a restart doesn't lose them. `/events` serves them as they
+//! are, `/api/v1/annotations` as Grafana annotations (time, timeEnd, title, text and tags) tagged with their kind,
+//! for the JSON API or Infinity data sources.
+use std::{collections::VecDeque, sync::Mutex, time::UNIX_EPOCH};
+
+use crate::{clock::Clock, history, json};
+
+/// number of events kept in memory
+const CAPACITY: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Event {
+    pub(crate) timestamp_ms: u64,
+    pub(crate) kind: String,
+    pub(crate) text: String,
+}
+
+impl Event {
+    /// the event as a JSON object of {timestamp_ms, kind, text}
+    pub(crate) fn to_json(&self) -> String {
+        return format!(r#"{{"timestamp_ms":{},"kind":{},"text":{}}}"#, self.timestamp_ms, json::quote(&self.kind), json::quote(&self.text));
+    }
+
+    /// the event of a JSON object written by `to_json`
+    pub(crate) fn from_json(value: &json::Value) -> Option<Event> {
+        let string = |key| match value.get(key) {
+            Some(json::Value::String(s)) => Some(s.clone()),
+            _ => None,
+        };
+        return Some(Event {
+            timestamp_ms: value.get("timestamp_ms")?.as_f64()? as u64,
+            kind: string("kind")?,
+            text: string("text")?,
+        });
+    }
+}
+
+static EVENTS: Mutex<VecDeque<Event>> = Mutex::new(VecDeque::new());
+
+/// record an event happening now
+pub(crate) fn record(clock: &dyn Clock, kind: &'static str, text: String) {
+    log::info!(event = kind; "event {}: {}", kind, text);
+    metrics::counter!("exporter_events_total", "kind" => kind).increment(1);
+
+    let timestamp_ms = clock
+        .now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+    let event = Event { timestamp_ms, kind: kind.to_string(), text };
+    history::record_event(&event);
+    let mut events = EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+    if events.len() >= CAPACITY {
+        events.pop_front();
+    }
+    events.push_back(event);
+}
+
+/// bring back the events stored before a restart, oldest first
+pub(crate) fn restore(stored: Vec<Event>) {
+    let mut events = EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+    let recorded = std::mem::take(&mut *events);
+    events.extend(stored);
+    events.extend(recorded);
+    while events.len() > CAPACITY {
+        events.pop_front();
+    }
+}
+
+/// events recorded between `from` and `to` (unix ms, inclusive), oldest first
+pub(crate) fn between(from: u64, to: u64) -> Vec<Event> {
+    let events = EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+    return events
+        .iter()
+        .filter(|e| e.timestamp_ms >= from && e.timestamp_ms <= to)
+        .cloned()
+        .collect();
+}
+
+/// `events` as a JSON array of {timestamp_ms, kind, text}
+pub(crate) fn to_json(events: &[Event]) -> String {
+    let items: Vec<String> = events.iter().map(Event::to_json).collect();
+    return format!("[{}]", items.join(","));
+}
+
+/// `events` as a JSON array of Grafana annotations, points in time titled and tagged with their kind
+pub(crate) fn to_annotations(events: &[Event]) -> String {
+    let items: Vec<String> = events
+        .iter()
+        .map(|e| {
+            format!(
+                r#"{{"time":{},"timeEnd":{},"title":{},"text":{},"tags":[{}]}}"#,
+                e.timestamp_ms,
+                e.timestamp_ms,
+                json::quote(&e.kind),
+                json::quote(&e.text),
+                json::quote(&e.kind)
+            )
+        })
+        .collect();
+    return format!("[{}]", items.join(","));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(timestamp_ms: u64, kind: &str, text: &str) -> Event {
+        return Event { timestamp_ms, kind: kind.to_string(), text: text.to_string() };
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let e = event(1_700_000_000_000, "reload", "configuration \"reloaded\"\napplied offset");
+        assert_eq!(Event::from_json(&json::parse(&e.to_json()).unwrap()), Some(e));
+        assert_eq!(Event::from_json(&json::parse(r#"{"timestamp_ms":1,"kind":"start"}"#).unwrap()), None);
+    }
+
+    #[test]
+    fn annotations() {
+        let events = [event(1000, "start", "sensor started"), event(2000, "alert", "stuffy firing")];
+        assert_eq!(
+            to_annotations(&events),
+            r#"[{"time":1000,"timeEnd":1000,"title":"start","text":"sensor started","tags":["start"]},{"time":2000,"timeEnd":2000,"title":"alert","text":"stuffy firing","tags":["alert"]}]"#
+        );
+        assert_eq!(to_annotations(&[]), "[]");
+    }
+}
@@ -0,0 +1,239 @@
+//! module for reading back the Prometheus text exposition format
+//! outputs that speak other protocols start from the rendered exposition, so every metric is
+//! exported the same way no matter where it is recorded. with --metrics-timestamps /metrics stamps the
+//! measurement's samples with the time they were taken. tokens scoped to labels only get the samples in their scope.
+
+/// type of a metric family as declared by its `# TYPE` line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+    Summary,
+    Untyped,
+}
+
+/// one sample of the exposition
+#[derive(Debug, Clone)]
+pub(crate) struct Sample {
+    pub(crate) name: String,
+    pub(crate) labels: Vec<(String, String)>,
+    pub(crate) value: f64,
+    /// type of the family the sample belongs to
+    pub(crate) kind: Kind,
+    /// text of the family's `# HELP` line
+    pub(crate) help: String,
+}
+
+/// samples of the text exposition format
+pub(crate) fn parse(text: &str) -> Vec<Sample> {
+    let mut samples = Vec::new();
+    // (family, kind, help) of the latest comment lines
+    let mut family = (String::new(), Kind::Untyped, String::new());
+    for line in text.lines().filter(|l| !l.is_empty()) {
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut words = comment.trim_start().splitn(3, ' ');
+            let (Some(keyword), Some(name)) = (words.next(), words.next()) else {
+                continue;
+            };
+            if family.0 != name {
+                family = (name.to_string(), Kind::Untyped, String::new());
+            }
+            let rest = words.next().unwrap_or_default().trim();
+            match keyword {
+                "HELP" => family.2 = rest.to_string(),
+                "TYPE" => {
+                    family.1 = match rest {
+                        "counter" => Kind::Counter,
+                        "gauge" => Kind::Gauge,
+                        "histogram" => Kind::Histogram,
+                        "summary" => Kind::Summary,
+                        _ => Kind::Untyped,
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        let (series, value) = match line.rfind('}') {
+            Some(i) => (&line[..=i], line[i + 1..].trim()),
+            None => match line.split_once(' ') {
+                Some((s, v)) => (s, v.trim()),
+                None => continue,
+            },
+        };
+        let Ok(value) = value.split_whitespace().next().unwrap_or_default().parse::<f64>() else {
+            log::debug!("skip unparsable sample {}", line);
+            continue;
+        };
+        let (name, labels) = series.split_once('{').unwrap_or((series, ""));
+        let in_family = name == family.0 || name.strip_prefix(family.0.as_str()).is_some_and(|s| s.starts_with('_'));
+        let (kind, help) = match in_family {
+            true => (family.1, family.2.clone()),
+            false => (Kind::Untyped, String::new()),
+        };
+        samples.push(Sample {
+            name: name.to_string(),
+            labels: parse_labels(labels.strip_suffix('}').unwrap_or(labels)),
+            value,
+            kind,
+            help,
+        });
+    }
+    return samples;
+}
+
+/// `text` with `timestamp_ms` after each sample of the metrics named `names`
+pub(crate) fn with_timestamp(text: &str, names: &[String], timestamp_ms: u64) -> String {
+    let mut out = String::with_capacity(text.len());
+    for line in text.lines() {
+        out.push_str(line);
+        let name = line.split(['{', ' ']).next().unwrap_or_default();
+        if !line.starts_with('#') && names.iter().any(|n| n == name) {
+            out.push_str(&format!(" {}", timestamp_ms));
+        }
+        out.push('\n');
+    }
+    return out;
+}
+
+/// `text` without the families named `names`, their samples and comments
+pub(crate) fn without(text: &str, names: &[String]) -> String {
+    let family = |name: &str| names.iter().any(|n| name.strip_prefix(n.as_str()).is_some_and(|s| ["", "_bucket", "_sum", "_count"].contains(&s)));
+    let mut out = String::with_capacity(text.len());
+    for line in text.lines() {
+        let name = match line.strip_prefix('#') {
+            Some(comment) => comment.split_whitespace().nth(1).unwrap_or_default(),
+            None => line.split(['{', ' ']).next().unwrap_or_default(),
+        };
+        if !family(name) {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    return out;
+}
+
+/// `text` with only the samples whose labels are in `scope`, and the comments of their families
+pub(crate) fn scoped(text: &str, scope: &[(String, String)]) -> String {
+    let mut out = String::with_capacity(text.len());
+    // the comment lines of the family being read, written before its first sample in scope
+    let mut comments: Vec<&str> = Vec::new();
+    let mut after_sample = false;
+    for line in text.lines().filter(|l| !l.is_empty()) {
+        if line.starts_with('#') {
+            // a comment after samples starts the next family
+            if after_sample {
+                comments.clear();
+                after_sample = false;
+            }
+            comments.push(line);
+            continue;
+        }
+        after_sample = true;
+        let labels = match (line.find('{'), line.rfind('}')) {
+            (Some(start), Some(end)) if start < end => parse_labels(&line[start + 1..end]),
+            _ => Vec::new(),
+        };
+        if !scope.iter().all(|m| labels.contains(m)) {
+            continue;
+        }
+        for l in comments.drain(..).chain([line]) {
+            out.push_str(l);
+            out.push('\n');
+        }
+    }
+    return out;
+}
+
+/// `key="value",...` with \\, \" and \n escapes
+fn parse_labels(s: &str) -> Vec<(String, String)> {
+    let mut labels = Vec::new();
+    let mut chars = s.chars().peekable();
+    loop {
+        let key: String = chars.by_ref().take_while(|c| *c != '=').collect::<String>().trim_start_matches(',').trim().to_string();
+        if key.is_empty() || chars.next() != Some('"') {
+            return labels;
+        }
+        let mut value = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some(c) => value.push(c),
+                    None => {}
+                },
+                c => value.push(c),
+            }
+        }
+        labels.push((key, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        let text = concat!(
+            "# HELP scd41_co2_ppm CO2 concentration\n",
+            "# TYPE scd41_co2_ppm gauge\n",
+            "scd41_co2_ppm{room=\"kitchen\"} 812\n",
+            "scd41_co2_ppm_raw 815\n",
+            "scd41_sensor_up 1\n",
+        );
+        let names = [String::from("scd41_co2_ppm")];
+        let stamped = with_timestamp(text, &names, 1_700_000_000_000);
+        assert!(stamped.contains("scd41_co2_ppm{room=\"kitchen\"} 812 1700000000000\n"));
+        assert!(stamped.contains("\nscd41_co2_ppm_raw 815\nscd41_sensor_up 1\n"));
+        assert!(stamped.starts_with("# HELP scd41_co2_ppm CO2 concentration\n# TYPE scd41_co2_ppm gauge\n"));
+        // the timestamp doesn't get in the way of reading the value back
+        assert_eq!(parse(&stamped)[0].value, 812.0);
+    }
+
+    #[test]
+    fn without_families() {
+        let text = concat!(
+            "# HELP scd41_co2_ppm CO2 concentration\n",
+            "# TYPE scd41_co2_ppm gauge\n",
+            "scd41_co2_ppm{room=\"kitchen\"} 812\n",
+            "# TYPE co2_ppm gauge\n",
+            "co2_ppm{room=\"kitchen\"} 812\n",
+            "scd41_co2_ppm_raw 815\n",
+            "# TYPE scd41_measurement_duration_seconds histogram\n",
+            "scd41_measurement_duration_seconds_bucket{le=\"+Inf\"} 3\n",
+            "scd41_measurement_duration_seconds_sum 0.1\n",
+            "scd41_measurement_duration_seconds_count 3\n",
+        );
+        let names = [String::from("scd41_co2_ppm"), String::from("scd41_measurement_duration_seconds")];
+        assert_eq!(without(text, &names), "# TYPE co2_ppm gauge\nco2_ppm{room=\"kitchen\"} 812\nscd41_co2_ppm_raw 815\n");
+        assert_eq!(without(text, &[]), text);
+    }
+
+    #[test]
+    fn scoped_samples() {
+        let text = concat!(
+            "# HELP federated_co2_ppm CO2 concentration\n",
+            "# TYPE federated_co2_ppm gauge\n",
+            "federated_co2_ppm{instance=\"a\",site=\"office-a\"} 812\n",
+            "federated_co2_ppm{instance=\"b\",site=\"office-b\"} 640\n",
+            "# TYPE federated_up gauge\n",
+            "federated_up{instance=\"b\",site=\"office-b\"} 1\n",
+            "# TYPE federated_fetch_failures_total counter\n",
+            "federated_fetch_failures_total 3\n",
+        );
+        let scope = [(String::from("site"), String::from("office-a"))];
+        assert_eq!(
+            scoped(text, &scope),
+            concat!(
+                "# HELP federated_co2_ppm CO2 concentration\n",
+                "# TYPE federated_co2_ppm gauge\n",
+                "federated_co2_ppm{instance=\"a\",site=\"office-a\"} 812\n",
+            )
+        );
+        assert_eq!(scoped(text, &[]), text);
+    }
+}
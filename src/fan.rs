@@ -0,0 +1,149 @@
+//! module for proportional ventilation with a PWM fan on the hardware PWM
+//! --fan-curve maps CO2 to a duty cycle through points like 600:0,800:30,1200:100, linear in between and
+//! flat beyond the ends. a fan that runs at all runs at least at --fan-min-duty, as fans stall below some
+//! duty. fan channel 0 is on GPIO 18 (or 12), channel 1 on GPIO 19 (or 13), as set up by the pwm overlay.
+//! without valid data the fan keeps its speed. exit stops it.
+use rppal::pwm::{Channel, Polarity, Pwm};
+
+use crate::error::Error;
+
+/// parse a curve point given as `PPM:PERCENT`
+pub(crate) fn parse_point(s: &str) -> Result<(u16, f64), String> {
+    let (ppm, duty) = s.split_once(':').ok_or_else(|| format!("{} is not PPM:PERCENT", s))?;
+    let ppm = ppm.trim().parse().map_err(|e| format!("{}: {}", s, e))?;
+    let duty: f64 = duty.trim().trim_end_matches('%').parse().map_err(|e| format!("{}: {}", s, e))?;
+    if !(0.0..=100.0).contains(&duty) {
+        return Err(format!("{}: the duty cycle is a percentage", s));
+    }
+    return Ok((ppm, duty));
+}
+
+pub(crate) struct Config {
+    /// hardware PWM channel, 0 or 1
+    pub(crate) channel: u8,
+    pub(crate) frequency: f64,
+    pub(crate) inverse: bool,
+    /// (CO2, duty percent) points in ascending CO2
+    pub(crate) curve: Vec<(u16, f64)>,
+    /// duty percent a running fan gets at least
+    pub(crate) min_duty: f64,
+}
+
+pub(crate) struct Fan {
+    pwm: Pwm,
+    curve: Vec<(u16, f64)>,
+    min_duty: f64,
+    /// the duty percent commanded, None before the first
+    duty: Option<f64>,
+    gauge: metrics::Gauge,
+}
+
+impl Fan {
+    /// set up the PWM channel with the fan stopped
+    pub(crate) fn new(config: Config) -> Result<Self, Error> {
+        if config.curve.is_empty() || config.curve.windows(2).any(|w| w[0].0 >= w[1].0) {
+            return Err(Error::Config(String::from("the fan curve needs its points in ascending CO2")));
+        }
+        let channel = match config.channel {
+            0 => Channel::Pwm0,
+            1 => Channel::Pwm1,
+            other => return Err(Error::Config(format!("there's no PWM channel {}", other))),
+        };
+        let polarity = if config.inverse { Polarity::Inverse } else { Polarity::Normal };
+        let pwm = Pwm::with_frequency(channel, config.frequency, 0.0, polarity, true)?;
+        let gauge = metrics::gauge!("exporter_fan_duty_percent");
+        gauge.set(0);
+        return Ok(Fan { pwm, curve: config.curve, min_duty: config.min_duty, duty: None, gauge });
+    }
+
+    /// run at the speed for `co2`, None while there's no valid data
+    pub(crate) fn update(&mut self, co2: Option<u16>) {
+        let Some(co2) = co2 else {
+            return;
+        };
+        let duty = duty(&self.curve, self.min_duty, co2);
+        if self.duty == Some(duty) {
+            return;
+        }
+        log::debug!("co2 at {} ppm, run the fan at {}%", co2, duty);
+        self.set(duty);
+    }
+
+    fn set(&mut self, duty: f64) {
+        if let Err(e) = self.pwm.set_duty_cycle(duty / 100.0) {
+            log::warn!("failed to set the fan's duty cycle: {:?}", e);
+            return;
+        }
+        self.duty = Some(duty);
+        self.gauge.set(duty);
+    }
+}
+
+impl Drop for Fan {
+    fn drop(&mut self) {
+        self.set(0.0);
+    }
+}
+
+/// the duty percent to run at for `co2`, in whole percents so CO2 noise doesn't keep rewriting the pulse width
+fn duty(curve: &[(u16, f64)], min_duty: f64, co2: u16) -> f64 {
+    let duty = interpolate(curve, co2);
+    if duty > 0.0 {
+        return duty.max(min_duty).round();
+    }
+    return duty.round();
+}
+
+/// the duty percent at `co2` on `curve`
+fn interpolate(curve: &[(u16, f64)], co2: u16) -> f64 {
+    let (first, last) = (curve[0], curve[curve.len() - 1]);
+    if co2 <= first.0 {
+        return first.1;
+    }
+    if co2 >= last.0 {
+        return last.1;
+    }
+    let i = curve.iter().position(|&(ppm, _)| ppm > co2).unwrap_or(curve.len() - 1);
+    let ((x0, y0), (x1, y1)) = (curve[i - 1], curve[i]);
+    return y0 + (y1 - y0) * (co2 - x0) as f64 / (x1 - x0) as f64;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVE: [(u16, f64); 3] = [(600, 0.0), (800, 30.0), (1200, 100.0)];
+
+    #[test]
+    fn interpolates() {
+        assert_eq!(interpolate(&CURVE, 700), 15.0);
+        assert_eq!(interpolate(&CURVE, 800), 30.0);
+        assert_eq!(interpolate(&CURVE, 1000), 65.0);
+        assert_eq!(interpolate(&CURVE, 1100), 82.5);
+        // flat beyond the ends
+        assert_eq!(interpolate(&CURVE, 400), 0.0);
+        assert_eq!(interpolate(&CURVE, 5000), 100.0);
+        assert_eq!(interpolate(&[(1000, 40.0)], 900), 40.0);
+        assert_eq!(interpolate(&[(1000, 40.0)], 1100), 40.0);
+    }
+
+    #[test]
+    fn clamps_duty() {
+        // a running fan runs at least at the minimum duty
+        assert_eq!(duty(&CURVE, 20.0, 600), 0.0);
+        assert_eq!(duty(&CURVE, 20.0, 601), 20.0);
+        assert_eq!(duty(&CURVE, 20.0, 750), 23.0);
+        assert_eq!(duty(&CURVE, 20.0, 1300), 100.0);
+        // whole percents
+        assert_eq!(duty(&CURVE, 0.0, 1101), 83.0);
+    }
+
+    #[test]
+    fn parses_points() {
+        assert_eq!(parse_point("800:30"), Ok((800, 30.0)));
+        assert_eq!(parse_point(" 1200 : 100% "), Ok((1200, 100.0)));
+        for invalid in ["800", "800:101", "ppm:30", "800:-1"] {
+            assert!(parse_point(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! module for injecting I2C faults to exercise recovery paths
+//! the injected errors, delays and CRC corruption are drawn from a seeded generator, so runs are reproducible.
+use std::{fmt, thread, time::Duration};
+
+use embedded_hal::i2c::{self, ErrorKind, ErrorType, Operation};
+
+/// fault injection settings
+#[derive(Debug, Clone)]
+pub(crate) struct Faults {
+    pub(crate) seed: u64,
+    /// probability of failing a transaction
+    pub(crate) error: f64,
+    /// probability of delaying a transaction
+    pub(crate) delay: f64,
+    pub(crate) max_delay: Duration,
+    /// probability of flipping a bit in read data
+    pub(crate) corrupt: f64,
+}
+
+/// parse `seed=42,error=0.01,delay=0.05,max_delay=200ms,corrupt=0.01`
+pub(crate) fn parse_faults(s: &str) -> Result<Faults, String> {
+    let mut faults = Faults {
+        seed: 1,
+        error: 0.0,
+        delay: 0.0,
+        max_delay: Duration::from_millis(100),
+        corrupt: 0.0,
+    };
+    for pair in s.split(',').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').ok_or_else(|| format!("expected key=value: {}", pair))?;
+        let probability = || -> Result<f64, String> {
+            let p: f64 = value.parse().map_err(|e| format!("{}: {}", key, e))?;
+            if !(0.0..=1.0).contains(&p) {
+                return Err(format!("{}: probability must be between 0 and 1", key));
+            }
+            return Ok(p);
+        };
+        match key {
+            "seed" => faults.seed = value.parse().map_err(|e| format!("seed: {}", e))?,
+            "error" => faults.error = probability()?,
+            "delay" => faults.delay = probability()?,
+            "corrupt" => faults.corrupt = probability()?,
+            "max_delay" => faults.max_delay = humantime::parse_duration(value).map_err(|e| format!("max_delay: {}", e))?,
+            _ => return Err(format!("unknown key: {}", key)),
+        }
+    }
+    return Ok(faults);
+}
+
+/// error of a [FaultI2c]
+#[derive(Debug)]
+pub(crate) enum FaultError<E> {
+    Injected,
+    Bus(E),
+}
+
+impl<E: fmt::Debug> fmt::Display for FaultError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            FaultError::Injected => write!(f, "injected fault"),
+            FaultError::Bus(e) => write!(f, "{:?}", e),
+        };
+    }
+}
+
+impl<E: i2c::Error> i2c::Error for FaultError<E> {
+    fn kind(&self) -> ErrorKind {
+        return match self {
+            FaultError::Injected => ErrorKind::Other,
+            FaultError::Bus(e) => e.kind(),
+        };
+    }
+}
+
+/// I2C bus wrapper which injects faults
+#[derive(Debug)]
+pub(crate) struct FaultI2c<I> {
+    inner: I,
+    faults: Option<Faults>,
+    state: u64,
+}
+
+impl<I> FaultI2c<I> {
+    /// wrap `inner`. transactions are passed through untouched when `faults` is None.
+    pub(crate) fn new(inner: I, faults: Option<Faults>) -> Self {
+        // xorshift must not start from zero
+        let state = faults.as_ref().map(|f| f.seed).unwrap_or_default().max(1);
+        return FaultI2c { inner, faults, state };
+    }
+
+    /// xorshift64*
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        return self.state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+    }
+
+    /// uniform in [0, 1)
+    fn uniform(&mut self) -> f64 {
+        return (self.next() >> 11) as f64 / (1_u64 << 53) as f64;
+    }
+}
+
+impl<I: ErrorType> ErrorType for FaultI2c<I> {
+    type Error = FaultError<I::Error>;
+}
+
+impl<I: i2c::I2c> i2c::I2c for FaultI2c<I> {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        let Some(faults) = self.faults.clone() else {
+            return self.inner.transaction(address, operations).map_err(FaultError::Bus);
+        };
+
+        if self.uniform() < faults.delay {
+            let delay = faults.max_delay.mul_f64(self.uniform());
+            log::debug!("inject {:?} delay on 0x{:02x}", delay, address);
+            metrics::counter!("fault_injected_total", "kind" => "delay").increment(1);
+            thread::sleep(delay);
+        }
+        if self.uniform() < faults.error {
+            log::debug!("inject error on 0x{:02x}", address);
+            metrics::counter!("fault_injected_total", "kind" => "error").increment(1);
+            return Err(FaultError::Injected);
+        }
+
+        self.inner.transaction(address, operations).map_err(FaultError::Bus)?;
+
+        for op in operations.iter_mut() {
+            if let Operation::Read(data) = op {
+                if !data.is_empty() && self.uniform() < faults.corrupt {
+                    let bit = self.next() as usize % (data.len() * 8);
+                    data[bit / 8] ^= 1 << (bit % 8);
+                    log::debug!("inject corruption on 0x{:02x}", address);
+                    metrics::counter!("fault_injected_total", "kind" => "corrupt").increment(1);
+                }
+            }
+        }
+        return Ok(());
+    }
+}
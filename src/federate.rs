@@ -0,0 +1,201 @@
+//! module for re-exporting the readings of other exporter instances
+//! every --federate-interval the `/api/v1/latest` of each --federate peer is fetched and exported as
+//! federated_* series with the peer's name as the instance label (scrape with honor_labels to keep it), so a
+//! small deployment has a single scrape target. --federate-aggregate adds the min, max and mean across the peers.
+//! a peer given as `NAME{room=kitchen,site=home}=URL` has those labels on its series too. peers with a room
+//! label are aggregated per room (and site, if they have one): the highest CO2 across the room's sensors, and per
+//! site the number of rooms whose highest CO2 is above --federate-room-threshold.
+use std::{collections::BTreeMap, io, time::Duration};
+
+use crate::{
+    http::{self, Url},
+    json, tasks,
+};
+
+#[derive(Debug, Clone)]
+pub(crate) struct Peer {
+    name: String,
+    url: Url,
+    /// extra labels of the peer's series, like room and site
+    labels: Vec<(String, String)>,
+}
+
+/// parse `NAME=URL` or `NAME{KEY=VALUE,...}=URL`, URL being the peer's base url like http://kitchen.local:9100
+pub(crate) fn parse_peer(s: &str) -> Result<Peer, String> {
+    let (name, labels, url) = match s.split_once('{') {
+        Some((name, rest)) if !name.contains('=') => {
+            let (labels, url) = rest.split_once('}').ok_or("unclosed { in the labels")?;
+            let url = url.strip_prefix('=').ok_or("expected NAME{LABELS}=URL")?;
+            let labels = labels
+                .split(',')
+                .filter(|l| !l.is_empty())
+                .map(|l| match l.split_once('=') {
+                    Some((k, v)) if !k.is_empty() && k != "instance" => Ok((k.to_string(), v.to_string())),
+                    _ => Err(format!("invalid label {}", l)),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            (name, labels, url)
+        }
+        _ => {
+            let (name, url) = s.split_once('=').ok_or("expected NAME=URL")?;
+            (name, Vec::new(), url)
+        }
+    };
+    if name.is_empty() {
+        return Err(String::from("the peer needs a name"));
+    }
+    return Ok(Peer { name: name.to_string(), url: http::parse_url(url)?.join("api/v1/latest"), labels });
+}
+
+impl Peer {
+    fn label(&self, key: &str) -> Option<&str> {
+        return self.labels.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+    }
+}
+
+/// per (site, room) the highest CO2 of the peers fetched, and per site the number of rooms above `threshold`.
+/// peers without a room are left out, those without a site are in the site "".
+fn rooms(peers: &[Peer], fetched: &[Option<[f64; 3]>], threshold: f64) -> (BTreeMap<(String, String), f64>, BTreeMap<String, usize>) {
+    let mut max = BTreeMap::new();
+    let mut above = BTreeMap::new();
+    for (peer, values) in peers.iter().zip(fetched) {
+        let Some(room) = peer.label("room") else {
+            continue;
+        };
+        let site = peer.label("site").unwrap_or_default().to_string();
+        above.entry(site.clone()).or_insert(0);
+        let co2 = values.map_or(f64::NAN, |v| v[0]);
+        let entry = max.entry((site, room.to_string())).or_insert(f64::NAN);
+        // NAN only while none of the room's sensors answered
+        *entry = entry.max(co2);
+    }
+    for ((site, _), co2) in &max {
+        if *co2 > threshold {
+            *above.get_mut(site).expect("every site counted") += 1;
+        }
+    }
+    return (max, above);
+}
+
+/// (field in the JSON, metric suffix)
+const VALUES: [(&str, &str); 3] = [("co2", "co2_ppm"), ("temperature", "temperature_celsius"), ("humidity", "humidity_rh")];
+
+/// the values of a peer in the order of VALUES
+fn fetch(peer: &Peer) -> Result<[f64; 3], String> {
+    let (status, body) = http::send("GET", &peer.url, &[("Accept", "application/json")], &[]).map_err(|e| e.to_string())?;
+    if status != 200 {
+        return Err(format!("answered {}", status));
+    }
+    let body = String::from_utf8(body).map_err(|e| e.to_string())?;
+    let latest = json::parse(&body).map_err(|e| e.to_string())?;
+    match latest.get("status") {
+        Some(json::Value::String(status)) if status != "ok" => return Err(format!("the sensor is {}", status)),
+        _ => {}
+    }
+    let mut values = [0.0; 3];
+    for (value, (field, _)) in values.iter_mut().zip(VALUES) {
+        *value = latest.get(field).and_then(json::Value::as_f64).ok_or_else(|| format!("no {} in the response", field))?;
+    }
+    return Ok(values);
+}
+
+/// fetch from `peers` every `interval` from a task until a shutdown
+pub(crate) fn spawn(peers: Vec<Peer>, interval: Duration, aggregate: bool, room_threshold: f64) -> io::Result<()> {
+    log::info!("federate {} peers every {:?}", peers.len(), interval);
+    let gauges: Vec<(metrics::Gauge, [metrics::Gauge; 3])> = peers
+        .iter()
+        .map(|p| {
+            let mut labels = vec![("instance".to_string(), p.name.clone())];
+            labels.extend(p.labels.iter().cloned());
+            let up = metrics::gauge!("federated_up", &labels);
+            return (up, VALUES.map(|(_, suffix)| metrics::gauge!(format!("federated_{}", suffix), &labels)));
+        })
+        .collect();
+    let stats = ["min", "max", "mean"];
+    let aggregates = aggregate.then(|| VALUES.map(|(_, suffix)| stats.map(|stat| metrics::gauge!(format!("federated_{}_{}", suffix, stat)))));
+    // labels of a room's or a site's series, without an empty site
+    let site_labels = |site: &str| if site.is_empty() { Vec::new() } else { vec![("site", site.to_string())] };
+    let failures = metrics::counter!("federated_fetch_failures_total");
+    tasks::spawn("federate", async move {
+        loop {
+            tasks::blocking(|| {
+                let mut fetched = Vec::new();
+                for (peer, (up, values)) in peers.iter().zip(&gauges) {
+                    match fetch(peer) {
+                        Ok(v) => {
+                            up.set(1);
+                            values.iter().zip(v).for_each(|(g, v)| g.set(v));
+                            fetched.push(Some(v));
+                        }
+                        Err(e) => {
+                            log::warn!("failed to fetch from {}: {}", peer.name, e);
+                            failures.increment(1);
+                            up.set(0);
+                            values.iter().for_each(|g| g.set(f64::NAN));
+                            fetched.push(None);
+                        }
+                    }
+                }
+                if let Some(aggregates) = &aggregates {
+                    let answered: Vec<[f64; 3]> = fetched.iter().flatten().copied().collect();
+                    for (i, [min, max, mean]) in aggregates.iter().enumerate() {
+                        let values = answered.iter().map(|v| v[i]);
+                        if answered.is_empty() {
+                            [min, max, mean].iter().for_each(|g| g.set(f64::NAN));
+                            continue;
+                        }
+                        min.set(values.clone().fold(f64::INFINITY, f64::min));
+                        max.set(values.clone().fold(f64::NEG_INFINITY, f64::max));
+                        mean.set(values.sum::<f64>() / answered.len() as f64);
+                    }
+                }
+                let (max, above) = rooms(&peers, &fetched, room_threshold);
+                for ((site, room), co2) in max {
+                    let mut labels = site_labels(&site);
+                    labels.push(("room", room));
+                    metrics::gauge!("federated_room_co2_ppm_max", &labels).set(co2);
+                }
+                for (site, count) in above {
+                    metrics::gauge!("federated_rooms_co2_above_threshold", &site_labels(&site)).set(count as f64);
+                }
+            });
+            if !tasks::sleep(interval).await {
+                return;
+            }
+        }
+    });
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peers_parse() {
+        let peer = parse_peer("kitchen{room=kitchen,site=home}=http://kitchen.local:9100").unwrap();
+        assert_eq!((peer.name.as_str(), peer.url.path.as_str()), ("kitchen", "/api/v1/latest"));
+        assert_eq!((peer.label("room"), peer.label("site"), peer.label("floor")), (Some("kitchen"), Some("home"), None));
+        assert!(parse_peer("attic=http://attic.local:9100/?a=b").unwrap().labels.is_empty());
+        assert!(parse_peer("attic{room=attic=http://attic.local").is_err());
+        assert!(parse_peer("attic{instance=x}=http://attic.local").is_err());
+        assert!(parse_peer("{room=attic}=http://attic.local").is_err());
+    }
+
+    #[test]
+    fn room_aggregates() {
+        let peers: Vec<Peer> = ["a{room=kitchen,site=home}", "b{room=kitchen,site=home}", "c{room=bedroom,site=home}", "d{room=lab,site=office}", "e"]
+            .iter()
+            .map(|p| parse_peer(&format!("{}=http://{}.local", p, &p[..1])).unwrap())
+            .collect();
+        let fetched = [Some([900.0, 21.0, 40.0]), Some([1200.0, 22.0, 45.0]), None, Some([700.0, 20.0, 50.0]), Some([2000.0, 20.0, 50.0])];
+        let (max, above) = rooms(&peers, &fetched, 1000.0);
+        let room = |site: &str, room: &str| max[&(site.to_string(), room.to_string())];
+        assert_eq!((room("home", "kitchen"), room("office", "lab")), (1200.0, 700.0));
+        // a room none of whose sensors answered has no value and isn't counted
+        assert!(room("home", "bedroom").is_nan());
+        // the peer without a room is left out
+        assert_eq!(max.len(), 3);
+        assert_eq!(above, BTreeMap::from([(String::from("home"), 1), (String::from("office"), 0)]));
+    }
+}
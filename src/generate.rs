@@ -0,0 +1,136 @@
+//! module for generating Prometheus rules and a Grafana dashboard matching the exporter's configuration
+use clap::ValueEnum;
+
+use crate::{json, names::Renamer};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum Bundle {
+    PrometheusRules,
+    GrafanaDashboard,
+}
+
+/// what the generated queries depend on
+pub(crate) struct Config<'a> {
+    pub(crate) names: &'a Renamer<()>,
+    /// static labels, used as the series selector
+    pub(crate) labels: &'a [(String, String)],
+    pub(crate) co2_warning: f64,
+    pub(crate) co2_critical: f64,
+    pub(crate) stale_after_secs: u64,
+}
+
+impl Config<'_> {
+    /// selector of `metric` (by its default name) restricted to the configured labels
+    fn series(&self, metric: &str) -> String {
+        let name = self.names.name(metric);
+        if self.labels.is_empty() {
+            return name;
+        }
+        let matchers: Vec<String> = self
+            .labels
+            .iter()
+            .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+            .collect();
+        return format!("{}{{{}}}", name, matchers.join(","));
+    }
+}
+
+pub(crate) fn generate(bundle: Bundle, config: &Config) -> String {
+    return match bundle {
+        Bundle::PrometheusRules => prometheus_rules(config),
+        Bundle::GrafanaDashboard => grafana_dashboard(config),
+    };
+}
+
+/// single-quoted YAML scalar
+fn yaml(s: &str) -> String {
+    return format!("'{}'", s.replace('\'', "''"));
+}
+
+fn prometheus_rules(c: &Config) -> String {
+    let co2 = c.series("scd41_co2_ppm");
+    let up = c.series("scd41_sensor_up");
+    let age = c.series("scd41_last_measured_age_seconds");
+    let alert = |name: &str, expr: &str, r#for: &str, severity: &str, summary: &str| {
+        return format!(
+            "      - alert: {}\n        expr: {}\n        for: {}\n        labels:\n          severity: {}\n        annotations:\n          summary: {}\n",
+            name,
+            yaml(expr),
+            r#for,
+            severity,
+            yaml(summary)
+        );
+    };
+    let mut out = String::from("groups:\n  - name: raspi-scd41-exporter\n    rules:\n");
+    out += &format!(
+        "      - record: {}\n        expr: {}\n",
+        yaml(&format!("{}:avg_1h", c.names.name("scd41_co2_ppm"))),
+        yaml(&format!("avg_over_time({}[1h])", co2))
+    );
+    out += &alert(
+        "Co2High",
+        &format!("{} > {}", co2, c.co2_warning),
+        "10m",
+        "warning",
+        &format!("CO2 above {} ppm, ventilate", c.co2_warning),
+    );
+    out += &alert(
+        "Co2Critical",
+        &format!("{} > {}", co2, c.co2_critical),
+        "5m",
+        "critical",
+        &format!("CO2 above {} ppm", c.co2_critical),
+    );
+    out += &alert("Co2SensorDown", &format!("{} == 0", up), "5m", "warning", "CO2 sensor stopped measuring");
+    out += &alert(
+        "Co2SensorStale",
+        &format!("{} > {}", age, c.stale_after_secs),
+        "1m",
+        "warning",
+        "no fresh CO2 measurement",
+    );
+    return out;
+}
+
+fn grafana_dashboard(c: &Config) -> String {
+    let panel = |id: usize, title: &str, expr: &str, unit: &str, thresholds: &[(f64, &str)]| {
+        let mut steps = vec![String::from("{\"color\": \"green\", \"value\": null}")];
+        steps.extend(thresholds.iter().map(|(v, color)| format!("{{\"color\": {}, \"value\": {}}}", json::quote(color), v)));
+        return format!(
+            concat!(
+                "{{\"id\": {}, \"type\": \"timeseries\", \"title\": {}, ",
+                "\"gridPos\": {{\"h\": 8, \"w\": 12, \"x\": {}, \"y\": {}}}, ",
+                "\"datasource\": {{\"type\": \"prometheus\", \"uid\": \"${{datasource}}\"}}, ",
+                "\"fieldConfig\": {{\"defaults\": {{\"unit\": {}, \"thresholds\": {{\"mode\": \"absolute\", \"steps\": [{}]}}}}, \"overrides\": []}}, ",
+                "\"targets\": [{{\"refId\": \"A\", \"expr\": {}}}]}}"
+            ),
+            id,
+            json::quote(title),
+            (id - 1) % 2 * 12,
+            (id - 1) / 2 * 8,
+            json::quote(unit),
+            steps.join(", "),
+            json::quote(expr)
+        );
+    };
+    let panels = [
+        panel(
+            1,
+            "CO2",
+            &c.series("scd41_co2_ppm"),
+            "ppm",
+            &[(c.co2_warning, "orange"), (c.co2_critical, "red")],
+        ),
+        panel(2, "Temperature", &c.series("scd41_temperature_celsius"), "celsius", &[]),
+        panel(3, "Humidity", &c.series("scd41_humidity_rh"), "humidity", &[]),
+        panel(4, "Dew point", &c.series("scd41_dew_point_celsius"), "celsius", &[]),
+    ];
+    return format!(
+        concat!(
+            "{{\"title\": \"raspi-scd41-exporter\", \"schemaVersion\": 39, \"time\": {{\"from\": \"now-24h\", \"to\": \"now\"}}, ",
+            "\"templating\": {{\"list\": [{{\"name\": \"datasource\", \"type\": \"datasource\", \"query\": \"prometheus\"}}]}}, ",
+            "\"panels\": [\n{}\n]}}\n"
+        ),
+        panels.join(",\n")
+    );
+}
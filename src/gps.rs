@@ -0,0 +1,126 @@
+//! module for reading a serial/USB GPS receiver (NMEA 0183)
+use std::{
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use rppal::uart::{self, Parity, Uart};
+
+use crate::clock::{self, Clock};
+
+struct Gauges {
+    fix: metrics::Gauge,
+    satellites: metrics::Gauge,
+    latitude: metrics::Gauge,
+    longitude: metrics::Gauge,
+    altitude: metrics::Gauge,
+    clock_offset: metrics::Gauge,
+}
+
+/// open the GPS receiver at `path` and export its fix, location and the host clock's offset from GPS time
+pub(crate) fn spawn<C: Clock + Send + 'static>(path: &str, baud_rate: u32, clock: C) -> Result<(), uart::Error> {
+    let mut uart = Uart::with_path(path, baud_rate, Parity::None, 8, 1)?;
+    uart.set_read_mode(1, Duration::ZERO)?;
+
+    let gauges = Gauges {
+        fix: metrics::gauge!("gps_fix"),
+        satellites: metrics::gauge!("gps_satellites"),
+        latitude: metrics::gauge!("gps_latitude_degrees"),
+        longitude: metrics::gauge!("gps_longitude_degrees"),
+        altitude: metrics::gauge!("gps_altitude_meters"),
+        clock_offset: metrics::gauge!("gps_clock_offset_seconds"),
+    };
+
+    thread::spawn(move || {
+        let mut line = Vec::new();
+        let mut buf = [0_u8; 256];
+        loop {
+            let n = match uart.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) => {
+                    log::warn!("failed to read gps: {:?}", e);
+                    thread::sleep(Duration::from_secs(1));
+                    continue;
+                }
+            };
+            for &b in &buf[..n] {
+                if b != b'\n' {
+                    line.push(b);
+                    continue;
+                }
+                if let Ok(sentence) = std::str::from_utf8(&line) {
+                    handle_sentence(sentence.trim(), &gauges, &clock);
+                }
+                line.clear();
+            }
+        }
+    });
+    return Ok(());
+}
+
+fn handle_sentence(sentence: &str, gauges: &Gauges, clock: &dyn Clock) {
+    let Some(body) = checked_body(sentence) else {
+        log::trace!("invalid nmea sentence: {}", sentence);
+        return;
+    };
+    let fields: Vec<&str> = body.split(',').collect();
+    // the talker id (GP, GN, GL, ...) doesn't matter
+    match fields[0].get(2..) {
+        Some("RMC") if fields.len() > 9 => {
+            let valid = fields[2] == "A";
+            gauges.fix.set(if valid { 1.0 } else { 0.0 });
+            if !valid {
+                return;
+            }
+            if let Some(t) = utc_seconds(fields[1], fields[9]) {
+                let host = clock.now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or_default();
+                gauges.clock_offset.set(host - t);
+            }
+            if let (Some(lat), Some(lon)) = (coordinate(fields[3], fields[4]), coordinate(fields[5], fields[6])) {
+                gauges.latitude.set(lat);
+                gauges.longitude.set(lon);
+            }
+        }
+        Some("GGA") if fields.len() > 9 => {
+            if let Ok(n) = fields[7].parse::<u32>() {
+                gauges.satellites.set(n);
+            }
+            if let Ok(alt) = fields[9].parse::<f64>() {
+                gauges.altitude.set(alt);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// strip `$` and `*hh`, returning the sentence body if the checksum matches
+fn checked_body(sentence: &str) -> Option<&str> {
+    let (body, checksum) = sentence.strip_prefix('$')?.split_once('*')?;
+    let expected = u8::from_str_radix(checksum, 16).ok()?;
+    if body.bytes().fold(0, |acc, b| acc ^ b) != expected {
+        return None;
+    }
+    return Some(body);
+}
+
+/// convert `ddmm.mmmm` plus hemisphere to signed degrees
+fn coordinate(value: &str, hemisphere: &str) -> Option<f64> {
+    let v = value.parse::<f64>().ok()?;
+    let degrees = (v / 100.0).trunc() + (v % 100.0) / 60.0;
+    return match hemisphere {
+        "N" | "E" => Some(degrees),
+        "S" | "W" => Some(-degrees),
+        _ => None,
+    };
+}
+
+/// convert `hhmmss.ss` and `ddmmyy` to unix seconds
+fn utc_seconds(time: &str, date: &str) -> Option<f64> {
+    if time.len() < 6 || date.len() != 6 {
+        return None;
+    }
+    let num = |s: &str, r: std::ops::Range<usize>| s.get(r)?.parse::<u32>().ok();
+    let days = clock::days_from_civil(2000 + num(date, 4..6)? as i64, num(date, 2..4)?, num(date, 0..2)?);
+    let secs = time.get(4..)?.parse::<f64>().ok()?;
+    return Some(days as f64 * 86400.0 + num(time, 0..2)? as f64 * 3600.0 + num(time, 2..4)? as f64 * 60.0 + secs);
+}
@@ -0,0 +1,150 @@
+//! module for sending measurements to Graphite (carbon) in the plaintext protocol
+//! see https://graphite.readthedocs.io/en/latest/feeding-carbon.html
+//! each sample becomes `<prefix>.co2 <value> <timestamp>` lines on a TCP connection kept by a
+//! background thread, reconnecting with backoff. a batch that failed to send is sent again after reconnecting.
+use std::{
+    io::{self, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{sensor::Envelope, shutdown::Pending};
+
+/// samples waiting while carbon is unreachable; newer ones are dropped beyond this
+const QUEUE: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    /// `host:port` of carbon's plaintext receiver
+    pub(crate) addr: String,
+    /// path of the series without the value name, e.g. `sensors.livingroom`
+    pub(crate) prefix: String,
+}
+
+struct Output {
+    prefix: String,
+    queue: SyncSender<String>,
+    dropped: metrics::Counter,
+}
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+static PENDING: Pending = Pending::new("graphite");
+
+/// connect to carbon in a background thread
+pub(crate) fn init(config: Config) -> io::Result<()> {
+    let (tx, rx) = mpsc::sync_channel(QUEUE);
+    let addr = config.addr.clone();
+    thread::Builder::new().name(String::from("graphite")).spawn(move || run(addr, rx))?;
+    let output = Output {
+        prefix: config.prefix,
+        queue: tx,
+        dropped: metrics::counter!("exporter_graphite_dropped_total"),
+    };
+    let _ = OUTPUT.set(output);
+    PENDING.register();
+    return Ok(());
+}
+
+/// replace the characters graphite treats specially in a path component
+pub(crate) fn path_component(s: &str) -> String {
+    return s.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect();
+}
+
+/// send a measurement, if the output is enabled
+pub(crate) fn send(envelope: &Envelope) {
+    let Some(output) = OUTPUT.get() else {
+        return;
+    };
+    let m = &envelope.measurement;
+    let timestamp = envelope.timestamp_ms / 1000;
+    let lines = format!(
+        "{prefix}.co2 {} {ts}\n{prefix}.temperature {} {ts}\n{prefix}.humidity {} {ts}\n",
+        m.co2,
+        m.temperature,
+        m.humidity,
+        prefix = output.prefix,
+        ts = timestamp
+    );
+    PENDING.add(1);
+    if output.queue.try_send(lines).is_err() {
+        PENDING.done(1);
+        output.dropped.increment(1);
+    }
+}
+
+fn connect(addr: &str) -> io::Result<TcpStream> {
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", addr)))?;
+    let stream = TcpStream::connect_timeout(&addr, Duration::from_secs(10))?;
+    stream.set_write_timeout(Some(Duration::from_secs(10)))?;
+    return Ok(stream);
+}
+
+fn is_closed(stream: &TcpStream) -> bool {
+    let mut buf = [0_u8; 1];
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let closed = match stream.peek(&mut buf) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => e.kind() != io::ErrorKind::WouldBlock,
+    };
+    let _ = stream.set_nonblocking(false);
+    return closed;
+}
+
+fn run(addr: String, rx: Receiver<String>) {
+    let sent = metrics::counter!("exporter_graphite_sent_total");
+    let connected = metrics::gauge!("exporter_graphite_connected");
+    let mut backoff = Duration::from_secs(1);
+    let mut pending: Option<String> = None;
+    loop {
+        let mut stream = match connect(&addr) {
+            Ok(s) => {
+                log::info!("connected to carbon at {}", addr);
+                backoff = Duration::from_secs(1);
+                connected.set(1);
+                s
+            }
+            Err(e) => {
+                log::warn!("failed to connect to carbon at {}: {:?}, retry in {:?}", addr, e, backoff);
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(Duration::from_secs(60));
+                continue;
+            }
+        };
+        loop {
+            let lines = match pending.take() {
+                Some(lines) => lines,
+                None => match rx.recv() {
+                    Ok(lines) => lines,
+                    Err(_) => return,
+                },
+            };
+            // carbon never writes, so a readable socket means it closed the connection, and a
+            // write would still succeed once and lose the lines
+            if is_closed(&stream) {
+                log::warn!("carbon closed the connection");
+                pending = Some(lines);
+                connected.set(0);
+                break;
+            }
+            if let Err(e) = stream.write_all(lines.as_bytes()) {
+                log::warn!("connection to carbon lost: {:?}", e);
+                pending = Some(lines);
+                connected.set(0);
+                break;
+            }
+            sent.increment(1);
+            PENDING.done(1);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! module for HD44780 character LCDs (16x2, 20x4) behind a PCF8574 I2C backpack
+//! the expander drives the LCD in 4 bit mode: P0 RS, P1 RW, P2 E, P3 the backlight and P4 to P7 D4 to D7.
+//! a 16x2 LCD pages through CO2, temperature and humidity on every refresh, a 20x4 one shows them all.
+use std::{thread, time::Duration};
+
+use clap::ValueEnum;
+use embedded_hal::i2c;
+
+use crate::display::{Locale, Screen, Trend, View};
+
+pub(crate) const DEFAULT_ADDR: u8 = 0x27;
+
+const RS: u8 = 0x01;
+const ENABLE: u8 = 0x04;
+const BACKLIGHT: u8 = 0x08;
+/// DDRAM address of each row's first character
+const ROW_OFFSETS: [u8; 4] = [0x00, 0x40, 0x14, 0x54];
+/// the degree sign in the A00 character ROM
+const DEGREE: char = '\u{DF}';
+/// CGRAM characters for the trend, the ROM has an arrow to the right only
+const UP: char = '\u{0}';
+const DOWN: char = '\u{1}';
+const RIGHT: char = '\u{7E}';
+const ARROWS: [[u8; 8]; 2] = [
+    [0x04, 0x0E, 0x15, 0x04, 0x04, 0x04, 0x04, 0x00],
+    [0x04, 0x04, 0x04, 0x04, 0x15, 0x0E, 0x04, 0x00],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Size {
+    #[value(name = "16x2")]
+    Lcd16x2,
+    #[value(name = "20x4")]
+    Lcd20x4,
+}
+
+impl Size {
+    fn columns_rows(self) -> (usize, usize) {
+        return match self {
+            Size::Lcd16x2 => (16, 2),
+            Size::Lcd20x4 => (20, 4),
+        };
+    }
+}
+
+pub(crate) struct Hd44780<I> {
+    i2c: I,
+    addr: u8,
+    columns: usize,
+    rows: usize,
+    backlight: u8,
+    /// the page shown next on a 16x2 LCD
+    page: usize,
+}
+
+impl<I: i2c::I2c> Hd44780<I> {
+    /// initialize the LCD at `addr` cleared with the backlight on
+    pub(crate) fn new(i2c: I, addr: u8, size: Size) -> Result<Self, String> {
+        let (columns, rows) = size.columns_rows();
+        let mut lcd = Hd44780 { i2c, addr, columns, rows, backlight: BACKLIGHT, page: 0 };
+        // the reset sequence by instruction, it may come up in 8 or 4 bit mode
+        thread::sleep(Duration::from_millis(50));
+        for delay in [4500, 150, 150] {
+            lcd.nibble(0x30, 0)?;
+            thread::sleep(Duration::from_micros(delay));
+        }
+        lcd.nibble(0x20, 0)?;
+        // 4 bit, 2 lines (4 are two folded ones), 5x8 dots
+        lcd.command(0x28)?;
+        lcd.command(0x0C)?;
+        lcd.command(0x06)?;
+        // CGRAM 0 and 1
+        lcd.command(0x40)?;
+        for row in ARROWS.iter().flatten() {
+            lcd.byte(*row, RS)?;
+        }
+        lcd.clear()?;
+        return Ok(lcd);
+    }
+
+    fn expander(&mut self, value: u8) -> Result<(), String> {
+        return self.i2c.write(self.addr, &[value | self.backlight]).map_err(|e| format!("{:?}", e));
+    }
+
+    /// clock the upper four bits of `value` in
+    fn nibble(&mut self, value: u8, mode: u8) -> Result<(), String> {
+        let bits = (value & 0xF0) | mode;
+        self.expander(bits | ENABLE)?;
+        self.expander(bits)?;
+        // most instructions take 37us
+        thread::sleep(Duration::from_micros(50));
+        return Ok(());
+    }
+
+    fn byte(&mut self, value: u8, mode: u8) -> Result<(), String> {
+        self.nibble(value, mode)?;
+        return self.nibble(value << 4, mode);
+    }
+
+    fn command(&mut self, command: u8) -> Result<(), String> {
+        return self.byte(command, 0);
+    }
+
+    fn clear(&mut self) -> Result<(), String> {
+        self.command(0x01)?;
+        thread::sleep(Duration::from_millis(2));
+        return Ok(());
+    }
+
+    /// write `lines`, each padded to the width so the previous text is overwritten
+    fn write(&mut self, lines: &[String]) -> Result<(), String> {
+        for (row, line) in lines.iter().enumerate().take(self.rows) {
+            self.command(0x80 | ROW_OFFSETS[row])?;
+            let padded: Vec<char> = line.chars().chain(std::iter::repeat(' ')).take(self.columns).collect();
+            for c in padded {
+                // the ROM's lower half is ASCII
+                let code = if (c as u32) < 0x100 { c as u8 } else { b'?' };
+                self.byte(code, RS)?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+impl<I: i2c::I2c + Send> Screen for Hd44780<I> {
+    fn show(&mut self, view: &View, locale: &Locale) -> Result<(), String> {
+        let lines = match view {
+            View::Reading { co2, temperature, humidity, trend } => {
+                let arrow = trend.map(|t| match t {
+                    Trend::Rising => UP,
+                    Trend::Falling => DOWN,
+                    Trend::Steady => RIGHT,
+                });
+                let co2 = format!("{} ppm {}", co2, arrow.unwrap_or(' '));
+                let (temperature, unit) = locale.temperature(*temperature);
+                let temperature = format!("{}{}{}", temperature, DEGREE, unit);
+                let humidity = format!("{}%RH", locale.number(*humidity, 0));
+                match self.rows {
+                    4 => vec![format!("CO2  {}", co2), format!("Temp {}", temperature), format!("Hum  {}", humidity), String::new()],
+                    _ => {
+                        let page = self.page;
+                        self.page = (page + 1) % 3;
+                        match page {
+                            0 => vec![String::from("CO2"), co2],
+                            1 => vec![String::from("Temperature"), temperature],
+                            _ => vec![String::from("Humidity"), humidity],
+                        }
+                    }
+                }
+            }
+            View::Status(status) => vec![String::from("CO2"), status.name().to_string()],
+        };
+        return self.write(&lines);
+    }
+
+    fn power(&mut self, on: bool) -> Result<(), String> {
+        self.backlight = if on { BACKLIGHT } else { 0 };
+        return self.command(if on { 0x0C } else { 0x08 });
+    }
+
+    fn cycles(&self) -> bool {
+        return self.rows < 4;
+    }
+}
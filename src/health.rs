@@ -0,0 +1,73 @@
+//! module for the health checks served at /healthz and /readyz
+//! the main loop beats once per iteration, so a hung bus or a stuck sensor call shows up as a stall
+//! even though the HTTP threads keep answering. readiness additionally needs valid data flowing.
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+/// milliseconds since STARTED at the last beat
+static BEAT: AtomicU64 = AtomicU64::new(0);
+/// milliseconds since STARTED at the last valid measurement, u64::MAX before the first one
+static VALID: AtomicU64 = AtomicU64::new(u64::MAX);
+static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+static STALE_AFTER: OnceLock<Duration> = OnceLock::new();
+
+/// start the clock, the main loop counts as stalled after `timeout` without a beat
+/// and data as stale after `stale_after` without a valid measurement
+pub(crate) fn init(timeout: Duration, stale_after: Duration) {
+    STARTED.get_or_init(Instant::now);
+    let _ = TIMEOUT.set(timeout);
+    let _ = STALE_AFTER.set(stale_after);
+}
+
+/// record that the main loop is alive
+pub(crate) fn beat() {
+    if let Some(started) = STARTED.get() {
+        BEAT.store(started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+/// record a valid (fresh and plausible) measurement
+pub(crate) fn measured() {
+    if let Some(started) = STARTED.get() {
+        VALID.store(started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+/// how long ago the main loop last beat, or started if it hasn't yet
+pub(crate) fn since_beat() -> Duration {
+    let Some(started) = STARTED.get() else {
+        return Duration::ZERO;
+    };
+    return started.elapsed().saturating_sub(Duration::from_millis(BEAT.load(Ordering::Relaxed)));
+}
+
+/// the /healthz response, 503 while the main loop is stalled
+pub(crate) fn liveness() -> (u16, String) {
+    let since = since_beat();
+    let timeout = TIMEOUT.get().copied().unwrap_or(Duration::MAX);
+    if since > timeout {
+        return (503, format!("main loop stalled for {}s\n", since.as_secs()));
+    }
+    return (200, String::from("ok\n"));
+}
+
+/// the /readyz response, 503 until a valid measurement is at most --stale-after old
+pub(crate) fn readiness() -> (u16, String) {
+    let (Some(started), valid) = (STARTED.get(), VALID.load(Ordering::Relaxed)) else {
+        return (503, String::from("not initialized\n"));
+    };
+    if valid == u64::MAX {
+        return (503, String::from("no valid measurement yet\n"));
+    }
+    let age = started.elapsed().saturating_sub(Duration::from_millis(valid));
+    if age > STALE_AFTER.get().copied().unwrap_or(Duration::MAX) {
+        return (503, format!("no valid measurement for {}s\n", age.as_secs()));
+    }
+    return (200, String::from("ok\n"));
+}
@@ -0,0 +1,425 @@
+//! module for the local measurement history
+//! keeps the exporter useful without Prometheus: every measurement is stored in a raw tier, and
+//! averaged into minute and hour tiers, each with its own retention. tiers live in memory and in
+//! append-only files of fixed size records (`<dir>/<tier>.dat`), which are compacted as points expire.
+//! `/api/v1/history` serves ranges from the finest tier that covers them, `/api/v1/export` downloads
+//! the stored points as CSV or Parquet. events are appended to `<dir>/events.jsonl` and kept as long as
+//! the hour tier, the expired ones are dropped when it's loaded.
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, SyncSender},
+        Mutex, MutexGuard, OnceLock,
+    },
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use crate::{
+    clock::Clock,
+    events::{self, Event},
+    json,
+    sensor::Envelope,
+    shutdown::Pending,
+};
+
+/// bytes of a stored point: timestamp_ms (u64), co2, temperature, humidity (f32), little endian
+const RECORD: usize = 20;
+/// most points returned by one query
+const MAX_POINTS: u64 = 10000;
+/// points returned when the query has no step
+const DEFAULT_POINTS: u64 = 500;
+const QUEUE: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) dir: PathBuf,
+    /// retention of the raw, minute and hour tiers
+    pub(crate) retention: [Duration; 3],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Point {
+    pub(crate) timestamp_ms: u64,
+    pub(crate) co2: f32,
+    pub(crate) temperature: f32,
+    pub(crate) humidity: f32,
+}
+
+impl Point {
+    fn encode(&self) -> [u8; RECORD] {
+        let mut buf = [0; RECORD];
+        buf[0..8].copy_from_slice(&self.timestamp_ms.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.co2.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.temperature.to_le_bytes());
+        buf[16..20].copy_from_slice(&self.humidity.to_le_bytes());
+        return buf;
+    }
+
+    fn decode(buf: &[u8]) -> Point {
+        let f = |i: usize| f32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+        let mut ts = [0; 8];
+        ts.copy_from_slice(&buf[0..8]);
+        return Point { timestamp_ms: u64::from_le_bytes(ts), co2: f(8), temperature: f(12), humidity: f(16) };
+    }
+}
+
+/// running sums of the points of one period, averaged into a point when the period ends
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    start_ms: u64,
+    co2: f64,
+    temperature: f64,
+    humidity: f64,
+    count: u32,
+}
+
+impl Bucket {
+    fn add(&mut self, p: &Point) {
+        self.co2 += p.co2 as f64;
+        self.temperature += p.temperature as f64;
+        self.humidity += p.humidity as f64;
+        self.count += 1;
+    }
+
+    fn mean(&self) -> Point {
+        let n = self.count.max(1) as f64;
+        return Point {
+            timestamp_ms: self.start_ms,
+            co2: (self.co2 / n) as f32,
+            temperature: (self.temperature / n) as f32,
+            humidity: (self.humidity / n) as f32,
+        };
+    }
+}
+
+struct Tier {
+    name: &'static str,
+    /// period of the points, 0 for the raw tier
+    resolution_ms: u64,
+    retention_ms: u64,
+    points: VecDeque<Point>,
+    path: PathBuf,
+    file: Option<File>,
+    /// points expired since the file was last rewritten
+    expired: usize,
+    /// period being averaged into this tier
+    bucket: Option<Bucket>,
+}
+
+impl Tier {
+    fn open(dir: &Path, name: &'static str, resolution_ms: u64, retention: Duration, now_ms: u64) -> io::Result<Tier> {
+        let path = dir.join(format!("{}.dat", name));
+        let retention_ms = retention.as_millis() as u64;
+        let points: VecDeque<Point> = match fs::read(&path) {
+            // a partial record at the end is left over from a crash, drop it
+            Ok(data) => data
+                .chunks_exact(RECORD)
+                .map(Point::decode)
+                .filter(|p| p.timestamp_ms.saturating_add(retention_ms) >= now_ms)
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => return Err(e),
+        };
+        log::info!("loaded {} {} history points", points.len(), name);
+        let mut tier = Tier { name, resolution_ms, retention_ms, points, path, file: None, expired: 0, bucket: None };
+        tier.rewrite()?;
+        return Ok(tier);
+    }
+
+    /// write the points kept in memory to a new file
+    fn rewrite(&mut self) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        let mut data = Vec::with_capacity(self.points.len() * RECORD);
+        for p in &self.points {
+            data.extend_from_slice(&p.encode());
+        }
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &self.path)?;
+        self.file = Some(OpenOptions::new().append(true).open(&self.path)?);
+        self.expired = 0;
+        return Ok(());
+    }
+
+    fn push(&mut self, p: Point, now_ms: u64) -> io::Result<()> {
+        self.points.push_back(p);
+        while self.points.front().is_some_and(|p| p.timestamp_ms.saturating_add(self.retention_ms) < now_ms) {
+            self.points.pop_front();
+            self.expired += 1;
+        }
+        // rewriting costs as much as writing all points again, so only do it once half of the file is dead
+        if self.expired > self.points.len() {
+            return self.rewrite();
+        }
+        return match self.file.as_mut() {
+            Some(file) => file.write_all(&p.encode()),
+            None => self.rewrite(),
+        };
+    }
+
+    /// feed a raw point into the period being averaged, storing the previous period once it ended
+    fn aggregate(&mut self, p: &Point, now_ms: u64) -> io::Result<()> {
+        let start_ms = p.timestamp_ms - p.timestamp_ms % self.resolution_ms;
+        let finished = match &self.bucket {
+            Some(b) if b.start_ms != start_ms => Some(b.mean()),
+            _ => None,
+        };
+        if finished.is_some() || self.bucket.is_none() {
+            self.bucket = Some(Bucket { start_ms, ..Default::default() });
+        }
+        if let Some(b) = self.bucket.as_mut() {
+            b.add(p);
+        }
+        return match finished {
+            Some(mean) => self.push(mean, now_ms),
+            None => Ok(()),
+        };
+    }
+}
+
+/// the events stored before, within `retention`, and the file rewritten with those only, to append to
+fn open_events(path: &Path, retention: Duration, now_ms: u64) -> io::Result<(Vec<Event>, File)> {
+    let retention_ms = retention.as_millis() as u64;
+    let events: Vec<Event> = match fs::read_to_string(path) {
+        // a partial line at the end is left over from a crash, it doesn't parse
+        Ok(data) => data
+            .lines()
+            .filter_map(|line| Event::from_json(&json::parse(line).ok()?))
+            .filter(|e| e.timestamp_ms.saturating_add(retention_ms) >= now_ms)
+            .collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, events.iter().map(|e| e.to_json() + "\n").collect::<String>())?;
+    fs::rename(&tmp, path)?;
+    let file = OpenOptions::new().append(true).open(path)?;
+    return Ok((events, file));
+}
+
+/// what the history thread stores
+enum Entry {
+    Point(Point),
+    Event(Event),
+}
+
+struct Store {
+    /// raw, minute and hour tiers, finest first
+    tiers: Vec<Tier>,
+    events: Option<File>,
+}
+
+static STORE: OnceLock<Mutex<Store>> = OnceLock::new();
+static QUEUE_TX: OnceLock<SyncSender<Entry>> = OnceLock::new();
+static PENDING: Pending = Pending::new("history");
+
+fn now_ms(clock: &dyn Clock) -> u64 {
+    return clock.now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default();
+}
+
+fn lock() -> Option<MutexGuard<'static, Store>> {
+    return STORE.get().map(|s| s.lock().unwrap_or_else(|e| e.into_inner()));
+}
+
+/// load the stored history and start storing measurements in a background thread
+pub(crate) fn init<C: Clock + Send + 'static>(config: Config, clock: C) -> io::Result<()> {
+    fs::create_dir_all(&config.dir)?;
+    let now = now_ms(&clock);
+    let tiers = vec![
+        Tier::open(&config.dir, "raw", 0, config.retention[0], now)?,
+        Tier::open(&config.dir, "minute", 60 * 1000, config.retention[1], now)?,
+        Tier::open(&config.dir, "hour", 60 * 60 * 1000, config.retention[2], now)?,
+    ];
+    let (stored_events, events_file) = open_events(&config.dir.join("events.jsonl"), config.retention[2], now)?;
+    log::info!("loaded {} events", stored_events.len());
+    events::restore(stored_events);
+    let _ = STORE.set(Mutex::new(Store { tiers, events: Some(events_file) }));
+
+    let (tx, rx) = mpsc::sync_channel::<Entry>(QUEUE);
+    let failures = metrics::counter!("exporter_history_write_failures_total");
+    let stored = metrics::gauge!("exporter_history_points");
+    thread::Builder::new().name(String::from("history")).spawn(move || {
+        for entry in rx {
+            let Some(mut store) = lock() else {
+                return;
+            };
+            let p = match entry {
+                Entry::Point(p) => p,
+                Entry::Event(event) => {
+                    let written = store.events.as_mut().map(|f| f.write_all((event.to_json() + "\n").as_bytes()));
+                    if let Some(Err(e)) = written {
+                        log::warn!("failed to store event: {:?}", e);
+                        failures.increment(1);
+                        store.events = None;
+                    }
+                    PENDING.done(1);
+                    continue;
+                }
+            };
+            let now = now_ms(&clock);
+            for tier in store.tiers.iter_mut() {
+                let written = match tier.resolution_ms {
+                    0 => tier.push(p, now),
+                    _ => tier.aggregate(&p, now),
+                };
+                if let Err(e) = written {
+                    log::warn!("failed to store {} history: {:?}", tier.name, e);
+                    failures.increment(1);
+                    tier.file = None;
+                }
+            }
+            stored.set(store.tiers.iter().map(|t| t.points.len()).sum::<usize>() as f64);
+            PENDING.done(1);
+        }
+    })?;
+    let _ = QUEUE_TX.set(tx);
+    PENDING.register();
+    return Ok(());
+}
+
+/// store a measurement, if the history is enabled
+pub(crate) fn record(envelope: &Envelope) {
+    let Some(tx) = QUEUE_TX.get() else {
+        return;
+    };
+    let m = &envelope.measurement;
+    let p = Point { timestamp_ms: envelope.timestamp_ms, co2: m.co2 as f32, temperature: m.temperature, humidity: m.humidity };
+    PENDING.add(1);
+    if tx.try_send(Entry::Point(p)).is_err() {
+        PENDING.done(1);
+        log::debug!("history queue is full, drop a measurement");
+    }
+}
+
+/// store an event, if the history is enabled
+pub(crate) fn record_event(event: &Event) {
+    let Some(tx) = QUEUE_TX.get() else {
+        return;
+    };
+    PENDING.add(1);
+    if tx.try_send(Entry::Event(event.clone())).is_err() {
+        PENDING.done(1);
+        log::warn!("history queue is full, drop the {} event", event.kind);
+    }
+}
+
+/// points between `from` and `to` (unix ms, default the last hour), averaged over `step` ms.
+/// without a step, the range is split into a few hundred points. None if the history is disabled.
+pub(crate) fn query(clock: &dyn Clock, from: Option<u64>, to: Option<u64>, step: Option<u64>) -> Option<Vec<Point>> {
+    let store = lock()?;
+    let now = now_ms(clock);
+    let to = to.unwrap_or(now).min(now);
+    let from = from.unwrap_or(to.saturating_sub(60 * 60 * 1000));
+    let span = to.saturating_sub(from).max(1);
+    let step = step.unwrap_or(span / DEFAULT_POINTS).max(span / MAX_POINTS).max(1);
+    // among the tiers reaching back to `from`, the coarsest one still finer than the step. when the
+    // step is finer than all of them, the finest; when none reaches back that far, the longest kept.
+    let covering: Vec<&Tier> = store.tiers.iter().filter(|t| from.saturating_add(t.retention_ms) >= now).collect();
+    let tier = covering
+        .iter()
+        .rev()
+        .find(|t| t.resolution_ms <= step)
+        .or(covering.first())
+        .copied()
+        .or(store.tiers.last())?;
+
+    let mut points = Vec::new();
+    let mut bucket: Option<Bucket> = None;
+    for p in tier.points.iter().filter(|p| p.timestamp_ms >= from && p.timestamp_ms <= to) {
+        let start_ms = p.timestamp_ms - (p.timestamp_ms - from) % step;
+        match bucket.as_mut() {
+            Some(b) if b.start_ms == start_ms => {}
+            _ => {
+                if let Some(b) = bucket.take() {
+                    points.push(b.mean());
+                }
+                bucket = Some(Bucket { start_ms, ..Default::default() });
+            }
+        }
+        if let Some(b) = bucket.as_mut() {
+            b.add(p);
+        }
+    }
+    points.extend(bucket.map(|b| b.mean()));
+    return Some(points);
+}
+
+/// the stored points between `from` and `to` (unix ms, default everything) as they are, from the finest
+/// tier reaching back to `from`. None if the history is disabled.
+pub(crate) fn export(clock: &dyn Clock, from: Option<u64>, to: Option<u64>) -> Option<Vec<Point>> {
+    let store = lock()?;
+    let now = now_ms(clock);
+    let (from, to) = (from.unwrap_or(0), to.unwrap_or(now));
+    let tier = store.tiers.iter().find(|t| from.saturating_add(t.retention_ms) >= now).or(store.tiers.last())?;
+    return Some(tier.points.iter().filter(|p| p.timestamp_ms >= from && p.timestamp_ms <= to).copied().collect());
+}
+
+/// `points` as CSV with a header, the format --replay reads
+pub(crate) fn to_csv(points: &[Point]) -> String {
+    let mut out = String::from("timestamp_ms,co2,temperature,humidity\n");
+    for p in points {
+        out.push_str(&format!("{},{:.1},{:.2},{:.2}\n", p.timestamp_ms, p.co2, p.temperature, p.humidity));
+    }
+    return out;
+}
+
+/// `points` as a JSON array of {timestamp_ms, co2, temperature, humidity}
+pub(crate) fn to_json(points: &[Point]) -> String {
+    let items: Vec<String> = points
+        .iter()
+        .map(|p| {
+            format!(
+                r#"{{"timestamp_ms":{},"co2":{:.1},"temperature":{:.2},"humidity":{:.2}}}"#,
+                p.timestamp_ms, p.co2, p.temperature, p.humidity
+            )
+        })
+        .collect();
+    return format!("[{}]", items.join(","));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(timestamp_ms: u64) -> Point {
+        return Point { timestamp_ms, co2: 420.0, temperature: 21.0, humidity: 40.0 };
+    }
+
+    #[test]
+    fn unbounded_retention_keeps_everything() {
+        let dir = std::env::temp_dir().join(format!("scd41-history-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let forever = Duration::from_millis(u64::MAX);
+        let mut tier = Tier::open(&dir, "raw", 0, forever, u64::MAX).unwrap();
+        tier.push(point(1), u64::MAX).unwrap();
+        tier.push(point(u64::MAX), u64::MAX).unwrap();
+        assert_eq!(tier.points.len(), 2);
+        let tier = Tier::open(&dir, "raw", 0, forever, u64::MAX).unwrap();
+        assert_eq!(tier.points.len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn events_survive_restarts() {
+        let dir = std::env::temp_dir().join(format!("scd41-events-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.jsonl");
+        let event = |timestamp_ms, text: &str| Event { timestamp_ms, kind: String::from("reload"), text: text.to_string() };
+        let (events, mut file) = open_events(&path, Duration::from_secs(60), 100_000).unwrap();
+        assert!(events.is_empty());
+        for e in [event(10_000, "old"), event(50_000, "kept"), event(90_000, "line\nbreak")] {
+            file.write_all((e.to_json() + "\n").as_bytes()).unwrap();
+        }
+        // a crash left half a line
+        file.write_all(br#"{"timestamp_ms":95000,"ki"#).unwrap();
+        drop(file);
+        let (events, _) = open_events(&path, Duration::from_secs(60), 100_000).unwrap();
+        assert_eq!(events, [event(50_000, "kept"), event(90_000, "line\nbreak")]);
+        // the file only keeps those
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
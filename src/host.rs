@@ -0,0 +1,51 @@
+//! module for the Raspberry Pi host metrics, built with the host-metrics feature
+//! the SoC temperature, the CPU frequency and the firmware's throttling flags (what `vcgencmd get_throttled`
+//! reports), read from sysfs with the exporter's upkeep. a warm enclosure heats the sensor too, and
+//! under-voltage explains sensor errors, without running node_exporter for it. unreadable values are left out.
+use std::{fs, path::PathBuf, sync::OnceLock};
+
+const SOC_TEMPERATURE: &str = "/sys/class/thermal/thermal_zone0/temp";
+const CPU_FREQUENCY: &str = "/sys/devices/system/cpu/cpu0/cpufreq/scaling_cur_freq";
+
+/// bit of get_throttled and flag label, the bits 16 higher tell it occurred since boot
+const FLAGS: [(u32, &str); 4] = [(0, "under_voltage"), (1, "frequency_capped"), (2, "throttled"), (3, "soft_temperature_limit")];
+
+/// get_throttled of the firmware driver, under soc:firmware or the like depending on the model
+fn throttled_path() -> Option<&'static PathBuf> {
+    static PATH: OnceLock<Option<PathBuf>> = OnceLock::new();
+    return PATH
+        .get_or_init(|| {
+            let platform = fs::read_dir("/sys/devices/platform").ok()?;
+            return platform
+                .filter_map(|e| e.ok())
+                .flat_map(|e| fs::read_dir(e.path()).into_iter().flatten().filter_map(|e| e.ok()))
+                .filter(|e| e.file_name().to_string_lossy().ends_with("firmware"))
+                .map(|e| e.path().join("get_throttled"))
+                .find(|p| p.exists());
+        })
+        .as_ref();
+}
+
+fn read(path: &str) -> Option<f64> {
+    return fs::read_to_string(path).ok()?.trim().parse().ok();
+}
+
+/// refresh the host metrics
+pub(crate) fn update() {
+    if let Some(millidegrees) = read(SOC_TEMPERATURE) {
+        metrics::gauge!("raspi_soc_temperature_celsius").set(millidegrees / 1000.0);
+    }
+    if let Some(khz) = read(CPU_FREQUENCY) {
+        metrics::gauge!("raspi_cpu_frequency_hertz").set(khz * 1000.0);
+    }
+    let Some(flags) = throttled_path()
+        .and_then(|p| fs::read_to_string(p).ok())
+        .and_then(|s| u32::from_str_radix(s.trim().trim_start_matches("0x"), 16).ok())
+    else {
+        return;
+    };
+    for (bit, flag) in FLAGS {
+        metrics::gauge!("raspi_throttled", "flag" => flag).set((flags >> bit) & 1);
+        metrics::gauge!("raspi_throttled_since_boot", "flag" => flag).set((flags >> (bit + 16)) & 1);
+    }
+}
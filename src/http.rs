@@ -0,0 +1,1130 @@
+//! module for the exporter's HTTP server and client
+//! a minimal HTTP/1.1 server handling one request per connection. each listener accepts in a task of the
+//! runtime until a shutdown, and each connection is served in a blocking task of its own.
+//! it listens on TCP or on a unix socket, for a local reverse proxy without any TCP port open.
+//! scrapers and browsers are the only clients, so there is no keep-alive or chunked encoding.
+//! streaming responses (server-sent events) write their body until the client goes away.
+//! it serves https with --tls-cert/--tls-key, verifying client certificates too with --tls-client-ca.
+//! bearer tokens may be scoped to labels: they get the node's routes only if its labels match, and scoped routes
+//! like /metrics narrow their response to the scope.
+//! the client side is just as small, with https through rustls and the system's root certificates.
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+
+use crate::tasks;
+
+/// largest request head (request line and headers) accepted
+const MAX_HEAD: usize = 16 * 1024;
+/// largest request body accepted
+const MAX_BODY: usize = 64 * 1024;
+
+/// a request or response head past MAX_HEAD, requests get a 431
+#[derive(Debug)]
+struct HeadTooLarge;
+
+impl std::fmt::Display for HeadTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f.write_str("head too large");
+    }
+}
+
+impl std::error::Error for HeadTooLarge {}
+
+/// read a line into `line`, failing with HeadTooLarge rather than buffering more than `limit` bytes
+fn read_line(reader: &mut impl BufRead, line: &mut String, limit: usize) -> io::Result<usize> {
+    let n = reader.take(limit as u64 + 1).read_line(line)?;
+    if n > limit {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, HeadTooLarge));
+    }
+    return Ok(n);
+}
+
+#[derive(Debug)]
+pub(crate) struct Request {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) query: Vec<(String, String)>,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Request {
+    /// first value of the query parameter `key`
+    pub(crate) fn param(&self, key: &str) -> Option<&str> {
+        return self.query.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+    }
+
+    /// value of the header `name` (case insensitive)
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        return self
+            .headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str());
+    }
+}
+
+/// writes a streaming response body, returning when the stream ends or the client is gone
+pub(crate) type Streamer = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    pub(crate) content_type: String,
+    /// headers besides Content-Type, Content-Length and Connection
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+    /// body of unknown length written after the head, the connection closes when it ends
+    pub(crate) stream: Option<Streamer>,
+}
+
+impl Response {
+    pub(crate) fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        return Response {
+            status,
+            content_type: content_type.to_string(),
+            headers: Vec::new(),
+            body: body.into(),
+            stream: None,
+        };
+    }
+
+    pub(crate) fn stream(content_type: &str, streamer: Streamer) -> Self {
+        let mut response = Response::new(200, content_type, Vec::new());
+        response.stream = Some(streamer);
+        return response;
+    }
+
+    pub(crate) fn text(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        return Response::new(status, "text/plain; charset=utf-8", body);
+    }
+
+    pub(crate) fn json(body: impl Into<Vec<u8>>) -> Self {
+        return Response::new(200, "application/json", body);
+    }
+
+    pub(crate) fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        return self;
+    }
+
+    /// a copy of a response without stream
+    fn copy(&self) -> Self {
+        let mut response = Response::new(self.status, &self.content_type, self.body.clone());
+        response.headers = self.headers.clone();
+        return response;
+    }
+}
+
+/// label matchers a token is limited to, like site="office-a". an empty scope is everything.
+pub(crate) type Scope = Vec<(String, String)>;
+
+/// whether series with `labels` are in `scope`
+pub(crate) fn in_scope(scope: &[(String, String)], labels: &[(String, String)]) -> bool {
+    return scope.iter().all(|m| labels.contains(m));
+}
+
+/// credentials accepted on protected routes, anything goes when there are none
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Auth {
+    tokens: Vec<(String, Scope)>,
+    /// base64 of `user:password`, as sent in the Authorization header
+    basic: Vec<String>,
+}
+
+impl Auth {
+    pub(crate) fn token(self, token: &str) -> Self {
+        return self.scoped_token(token, Vec::new());
+    }
+
+    /// accept `token` for the series in `scope` only
+    pub(crate) fn scoped_token(mut self, token: &str, scope: Scope) -> Self {
+        self.tokens.push((token.to_string(), scope));
+        return self;
+    }
+
+    /// accept `credentials` (`user:password`) as basic auth
+    pub(crate) fn basic(mut self, credentials: &str) -> Self {
+        self.basic.push(base64(credentials.as_bytes()));
+        return self;
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        return self.tokens.is_empty() && self.basic.is_empty();
+    }
+
+    /// the scope of the request's credentials, None if they aren't accepted
+    fn scope(&self, request: &Request) -> Option<&[(String, String)]> {
+        if self.is_empty() {
+            return Some(&[]);
+        }
+        let (scheme, value) = request.header("authorization").and_then(|v| v.trim().split_once(' '))?;
+        let candidates: Vec<(&str, &[(String, String)])> = match scheme {
+            s if s.eq_ignore_ascii_case("bearer") => self.tokens.iter().map(|(t, scope)| (t.as_str(), scope.as_slice())).collect(),
+            s if s.eq_ignore_ascii_case("basic") => self.basic.iter().map(|b| (b.as_str(), &[][..])).collect(),
+            _ => return None,
+        };
+        // check every candidate, so the time taken doesn't tell which one came close
+        return candidates.into_iter().fold(None, |found, (candidate, scope)| match constant_time_eq(candidate.as_bytes(), value.trim().as_bytes()) {
+            true => Some(scope),
+            false => found,
+        });
+    }
+
+    /// the 401 response, asking browsers for a password when basic auth is accepted
+    fn challenge(&self) -> Response {
+        let scheme = if self.basic.is_empty() { "Bearer" } else { "Basic" };
+        return Response::text(401, "unauthorized\n").with_header("WWW-Authenticate", &format!("{} realm=\"scd41\"", scheme));
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    return a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0;
+}
+
+type Handler = Arc<dyn Fn(&Request, &[(String, String)]) -> Response + Send + Sync>;
+
+/// who may use a route
+#[derive(Clone, Copy, PartialEq)]
+enum Access {
+    /// anyone, for health checks
+    Public,
+    /// credentials without a scope
+    Private,
+    /// any credentials while this node's labels are in their scope
+    Node,
+    /// any credentials, the handler limits the response to their scope
+    Scoped,
+}
+
+/// maps request paths to handlers
+#[derive(Clone, Default)]
+pub(crate) struct Router {
+    routes: Vec<(String, Access, Handler)>,
+    auth: Auth,
+    /// labels of this node's own series, see `node`
+    labels: Vec<(String, String)>,
+    /// also serve the routes under this path, for a reverse proxy or Home Assistant ingress
+    prefix: Option<String>,
+}
+
+/// `handler` serving its successful response again to every request within `ttl`, whatever its query.
+/// requests arriving while it's produced wait for it instead of producing it again
+pub(crate) fn cache(ttl: Duration, handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> impl Fn(&Request) -> Response + Send + Sync + 'static {
+    let cache: Mutex<Option<(Instant, Response)>> = Mutex::new(None);
+    return move |request| {
+        if ttl.is_zero() {
+            return handler(request);
+        }
+        let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((at, response)) = cache.as_ref().filter(|(at, _)| at.elapsed() < ttl) {
+            let age = at.elapsed().as_secs().to_string();
+            return response.copy().with_header("Age", &age);
+        }
+        let response = handler(request);
+        *cache = (response.status == 200 && response.stream.is_none()).then(|| (Instant::now(), response.copy()));
+        return response;
+    };
+}
+
+impl Router {
+    /// require `auth` on the routes not added with `public`
+    pub(crate) fn auth(mut self, auth: Auth) -> Self {
+        self.auth = auth;
+        return self;
+    }
+
+    /// the labels of this node's own series, which decide whether scoped credentials get its `node` routes
+    pub(crate) fn labels(mut self, labels: Vec<(String, String)>) -> Self {
+        self.labels = labels;
+        return self;
+    }
+
+    fn add(mut self, path: &str, access: Access, handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        self.routes.push((path.to_string(), access, Arc::new(move |request, _| handler(request))));
+        return self;
+    }
+
+    /// a route for credentials without a scope
+    pub(crate) fn route(self, path: &str, handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        return self.add(path, Access::Private, handler);
+    }
+
+    /// a route serving this node's own data, also to scoped credentials when its labels are in their scope
+    pub(crate) fn node(self, path: &str, handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        return self.add(path, Access::Node, handler);
+    }
+
+    /// a route for any credentials, `handler` gets their scope to limit the response to
+    pub(crate) fn scoped(mut self, path: &str, handler: impl Fn(&Request, &[(String, String)]) -> Response + Send + Sync + 'static) -> Self {
+        self.routes.push((path.to_string(), Access::Scoped, Arc::new(handler)));
+        return self;
+    }
+
+    /// a route open without credentials, for health checks
+    pub(crate) fn public(self, path: &str, handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        return self.add(path, Access::Public, handler);
+    }
+
+    /// serve every route under `prefix` as well, `/prefix/metrics` like `/metrics`
+    pub(crate) fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Some(prefix.trim_end_matches('/').to_string()).filter(|p| !p.is_empty());
+        return self;
+    }
+
+    fn handle(&self, request: &Request) -> Response {
+        let path = match &self.prefix {
+            // the dashboard's links are relative, so they need the slash
+            Some(prefix) if request.path == *prefix => return Response::new(301, "text/plain", Vec::new()).with_header("Location", &format!("{}/", prefix)),
+            Some(prefix) => request.path.strip_prefix(prefix.as_str()).filter(|p| p.starts_with('/')).unwrap_or(&request.path),
+            None => &request.path,
+        };
+        let Some((_, access, handler)) = self.routes.iter().find(|(route, _, _)| route == path) else {
+            return Response::text(404, "not found\n");
+        };
+        if *access == Access::Public {
+            return handler(request, &[]);
+        }
+        let Some(scope) = self.auth.scope(request) else {
+            log::debug!("reject unauthorized request for {}", request.path);
+            return self.auth.challenge();
+        };
+        let allowed = match access {
+            Access::Private => scope.is_empty(),
+            Access::Node => in_scope(scope, &self.labels),
+            _ => true,
+        };
+        if !allowed {
+            log::debug!("reject request for {} out of the token's scope", request.path);
+            return Response::text(403, "forbidden for this token\n");
+        }
+        return handler(request, scope);
+    }
+}
+
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// listen on `unix:PATH` or a TCP address
+    pub(crate) fn bind(addr: &str) -> io::Result<Listener> {
+        let Some(path) = addr.strip_prefix("unix:") else {
+            let addr: SocketAddr = addr.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            return Ok(Listener::Tcp(TcpListener::bind(addr)?));
+        };
+        let path = Path::new(path);
+        // a socket left behind by a previous run is in the way, unless someone still accepts on it
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is in use", path.display())));
+            }
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        SOCKETS.lock().unwrap_or_else(|e| e.into_inner()).push(path.to_path_buf());
+        return Ok(Listener::Unix(listener));
+    }
+
+    /// the TCP address listened on, None for a unix socket
+    pub(crate) fn tcp_addr(&self) -> Option<SocketAddr> {
+        return match self {
+            Listener::Tcp(l) => l.local_addr().ok(),
+            Listener::Unix(_) => None,
+        };
+    }
+}
+
+/// unix sockets bound, removed on shutdown
+static SOCKETS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// remove the unix sockets listened on, so the next start doesn't find them in the way
+pub(crate) fn remove_sockets() {
+    for path in SOCKETS.lock().unwrap_or_else(|e| e.into_inner()).drain(..) {
+        let _ = fs::remove_file(&path).inspect_err(|e| log::warn!("failed to remove {}: {:?}", path.display(), e));
+    }
+}
+
+/// an IP network, e.g. 192.168.1.0/24 or fd00::/8
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Cidr {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl Cidr {
+    pub(crate) fn contains(&self, ip: IpAddr) -> bool {
+        // clients over IPv4 on a dual-stack socket show up as ::ffff:a.b.c.d
+        return match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(n), IpAddr::V4(ip)) => {
+                let m = if self.prefix == 0 { 0 } else { u32::MAX << (32 - self.prefix) };
+                u32::from(n) & m == u32::from(ip) & m
+            }
+            (IpAddr::V6(n), IpAddr::V6(ip)) => {
+                let m = if self.prefix == 0 { 0 } else { u128::MAX << (128 - self.prefix) };
+                u128::from(n) & m == u128::from(ip) & m
+            }
+            _ => false,
+        };
+    }
+}
+
+/// parse `ADDR/PREFIX`, a bare address is a single host
+pub(crate) fn parse_cidr(s: &str) -> Result<Cidr, String> {
+    let (addr, prefix) = s.split_once('/').unwrap_or((s, ""));
+    let network: IpAddr = addr.parse().map_err(|e| format!("invalid address {}: {}", addr, e))?;
+    let width = if network.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        "" => width,
+        p => p.parse().ok().filter(|p| *p <= width).ok_or_else(|| format!("invalid prefix length {}", p))?,
+    };
+    // peers are compared canonically, so an IPv4-mapped network is an IPv4 one
+    if let (IpAddr::V6(v6), 96..) = (network, prefix) {
+        if let Some(v4) = v6.to_ipv4_mapped() {
+            return Ok(Cidr { network: IpAddr::V4(v4), prefix: prefix - 96 });
+        }
+    }
+    return Ok(Cidr { network, prefix });
+}
+
+/// a connection accepted by either listener
+trait Connection: Read + Write + Send + 'static {
+    fn set_timeouts(&self, timeout: Duration) -> io::Result<()>;
+
+    /// undo the non-blocking mode of a stream accepted by the runtime
+    fn set_blocking(&self) -> io::Result<()>;
+
+    /// the client's address, None for local clients
+    fn peer_ip(&self) -> Option<IpAddr>;
+}
+
+impl Connection for TcpStream {
+    fn set_timeouts(&self, timeout: Duration) -> io::Result<()> {
+        self.set_read_timeout(Some(timeout))?;
+        return self.set_write_timeout(Some(timeout));
+    }
+
+    fn set_blocking(&self) -> io::Result<()> {
+        return self.set_nonblocking(false);
+    }
+
+    fn peer_ip(&self) -> Option<IpAddr> {
+        // an unknown peer is rejected like a foreign one
+        return Some(self.peer_addr().map(|a| a.ip()).unwrap_or(IpAddr::from([0, 0, 0, 0])));
+    }
+}
+
+impl Connection for UnixStream {
+    fn set_timeouts(&self, timeout: Duration) -> io::Result<()> {
+        self.set_read_timeout(Some(timeout))?;
+        return self.set_write_timeout(Some(timeout));
+    }
+
+    fn set_blocking(&self) -> io::Result<()> {
+        return self.set_nonblocking(false);
+    }
+
+    fn peer_ip(&self) -> Option<IpAddr> {
+        return None;
+    }
+}
+
+/// how connections are served
+#[derive(Clone)]
+pub(crate) struct Server {
+    pub(crate) router: Router,
+    /// serve https with this configuration
+    pub(crate) tls: Option<Arc<rustls::ServerConfig>>,
+    /// networks TCP clients must be in, any if empty
+    pub(crate) allow: Vec<Cidr>,
+}
+
+/// serve on `listener` from a task until a shutdown, each connection in a blocking task of its own
+pub(crate) fn serve(listener: Listener, server: Server) -> io::Result<()> {
+    let server = Arc::new(server);
+    let _runtime = tasks::handle().enter();
+    match listener {
+        Listener::Tcp(l) => {
+            l.set_nonblocking(true)?;
+            let l = tokio::net::TcpListener::from_std(l)?;
+            tasks::spawn("http", async move {
+                while let Some(accepted) = tasks::cancellable(l.accept()).await {
+                    connection(accepted.and_then(|(s, _)| s.into_std()), &server);
+                }
+            });
+        }
+        Listener::Unix(l) => {
+            l.set_nonblocking(true)?;
+            let l = tokio::net::UnixListener::from_std(l)?;
+            tasks::spawn("http", async move {
+                while let Some(accepted) = tasks::cancellable(l.accept()).await {
+                    connection(accepted.and_then(|(s, _)| s.into_std()), &server);
+                }
+            });
+        }
+    }
+    return Ok(());
+}
+
+/// serve an accepted connection, the handlers block
+fn connection<C: Connection>(stream: io::Result<C>, server: &Arc<Server>) {
+    let stream = match stream.and_then(|s| s.set_blocking().map(|_| s)) {
+        Ok(s) => s,
+        Err(e) => {
+            log::warn!("failed to accept http connection: {:?}", e);
+            return;
+        }
+    };
+    if let (false, Some(ip)) = (server.allow.is_empty(), stream.peer_ip()) {
+        if !server.allow.iter().any(|c| c.contains(ip)) {
+            log::debug!("reject http connection from {}", ip);
+            metrics::counter!("exporter_http_rejected_total").increment(1);
+            return;
+        }
+    }
+    let server = server.clone();
+    tokio::task::spawn_blocking(move || {
+        let result = stream.set_timeouts(Duration::from_secs(10)).and_then(|_| match &server.tls {
+            // the handshake happens on the first read
+            Some(config) => {
+                let connection = rustls::ServerConnection::new(config.clone()).map_err(io::Error::other)?;
+                handle_connection(rustls::StreamOwned::new(connection, stream), &server.router)
+            }
+            None => handle_connection(stream, &server.router),
+        });
+        let _ = result.inspect_err(|e| log::debug!("http connection error: {:?}", e));
+    });
+}
+
+fn handle_connection<S: Read + Write>(stream: S, router: &Router) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let response = match read_request(&mut reader) {
+        Ok(request) => {
+            log::trace!("http {} {}", request.method, request.path);
+            router.handle(&request)
+        }
+        Err(e) if e.get_ref().is_some_and(|e| e.is::<HeadTooLarge>()) => Response::text(431, "request head too large\n"),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Response::text(400, format!("{}\n", e)),
+        Err(e) => return Err(e),
+    };
+    // anything still buffered follows the only request served, so it's dropped
+    return write_response(reader.into_inner(), response);
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    let mut line = String::new();
+    let mut head = read_line(reader, &mut line, MAX_HEAD)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid("malformed request line"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method: method.to_string(),
+        path: percent_decode(path),
+        query: parse_query(query),
+        headers: Vec::new(),
+        body: Vec::new(),
+    };
+
+    loop {
+        let mut line = String::new();
+        let n = read_line(reader, &mut line, MAX_HEAD - head)?;
+        if n == 0 {
+            return Err(invalid("unexpected end of headers"));
+        }
+        head += n;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').ok_or_else(|| invalid("malformed header"))?;
+        request.headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    if let Some(length) = request.header("content-length") {
+        let length: usize = length.parse().map_err(|_| invalid("invalid content-length"))?;
+        if length > MAX_BODY {
+            return Err(invalid("request body too large"));
+        }
+        request.body = vec![0; length];
+        reader.read_exact(&mut request.body)?;
+    }
+    return Ok(request);
+}
+
+fn write_response<S: Write>(mut stream: S, response: Response) -> io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nConnection: close\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type
+    );
+    match &response.stream {
+        // the end of the body is the end of the connection
+        Some(_) => head.push_str("Cache-Control: no-cache\r\n"),
+        None => head.push_str(&format!("Content-Length: {}\r\n", response.body.len())),
+    }
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&response.body)?;
+    if let Some(streamer) = response.stream {
+        streamer(&mut stream)?;
+    }
+    return stream.flush();
+}
+
+fn reason(status: u16) -> &'static str {
+    return match status {
+        200 => "OK",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    };
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    return query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            return (percent_decode(k), percent_decode(v));
+        })
+        .collect();
+}
+
+/// decode %XX escapes and '+' as space
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => match std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                Some(b) => {
+                    out.push(b);
+                    i += 2;
+                }
+                None => out.push(b'%'),
+            },
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    return String::from_utf8_lossy(&out).into_owned();
+}
+
+/// target of client requests, `http[s]://[user:password@]host[:port][/path]`
+#[derive(Debug, Clone)]
+pub(crate) struct Url {
+    pub(crate) tls: bool,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) path: String,
+    /// `user:password` for basic authentication
+    pub(crate) userinfo: Option<String>,
+}
+
+impl Url {
+    /// the same server with `path` appended to this url's path
+    pub(crate) fn join(&self, path: &str) -> Url {
+        let mut url = self.clone();
+        url.path = format!("{}/{}", self.path.trim_end_matches('/'), path.trim_start_matches('/'));
+        return url;
+    }
+}
+
+/// (scheme, tls, default port) of http urls
+const HTTP_SCHEMES: &[(&str, bool, u16)] = &[("http", false, 80), ("https", true, 443)];
+
+pub(crate) fn parse_url(s: &str) -> Result<Url, String> {
+    return parse_url_with(s, HTTP_SCHEMES);
+}
+
+/// parse a url of one of `schemes`, given as (scheme, tls, default port)
+pub(crate) fn parse_url_with(s: &str, schemes: &[(&str, bool, u16)]) -> Result<Url, String> {
+    let Some((tls, default_port, rest)) = schemes
+        .iter()
+        .find_map(|(scheme, tls, port)| s.strip_prefix(scheme)?.strip_prefix("://").map(|rest| (*tls, *port, rest)))
+    else {
+        let names: Vec<String> = schemes.iter().map(|(scheme, _, _)| format!("{}://", scheme)).collect();
+        return Err(format!("only {} urls are supported", names.join(" and ")));
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (userinfo, hostport) = match authority.rsplit_once('@') {
+        Some((u, h)) => (Some(u.to_string()), h),
+        None => (None, authority),
+    };
+    // [v6 address]:port
+    let split = match hostport.strip_prefix('[') {
+        Some(v6) => v6.split_once(']').map(|(h, p)| (h, p.strip_prefix(':'))),
+        None => Some(match hostport.split_once(':') {
+            Some((h, p)) => (h, Some(p)),
+            None => (hostport, None),
+        }),
+    };
+    let (host, port) = split.ok_or("unclosed [ in host")?;
+    let port = match port {
+        Some(p) => p.parse().map_err(|e| format!("port: {}", e))?,
+        None => default_port,
+    };
+    if host.is_empty() {
+        return Err(String::from("missing host"));
+    }
+    return Ok(Url {
+        tls,
+        host: host.to_string(),
+        port,
+        path: path.to_string(),
+        userinfo,
+    });
+}
+
+/// send one request and return the response status and body
+pub(crate) fn send(method: &str, url: &Url, headers: &[(&str, &str)], body: &[u8]) -> io::Result<(u16, Vec<u8>)> {
+    let stream = connect(url, Duration::from_secs(30))?;
+
+    let mut host = if url.host.contains(':') { format!("[{}]", url.host) } else { url.host.clone() };
+    if url.port != if url.tls { 443 } else { 80 } {
+        host.push_str(&format!(":{}", url.port));
+    }
+    let authorization = url.userinfo.as_ref().map(|u| format!("Basic {}", base64(u.as_bytes())));
+    let mut all: Vec<(&str, &str)> = authorization.iter().map(|a| ("Authorization", a.as_str())).collect();
+    all.extend_from_slice(headers);
+    return exchange(stream, method, &url.path, &host, &all, body);
+}
+
+/// send one request to the server on the unix socket `socket` and return the response status and body
+pub(crate) fn send_unix(socket: &Path, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> io::Result<(u16, Vec<u8>)> {
+    let stream = UnixStream::connect(socket)?;
+    // a self-test or a recalibration keeps the server busy for a while
+    stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(30)))?;
+    return exchange(stream, method, path, "localhost", headers, body);
+}
+
+fn exchange(mut stream: impl Read + Write, method: &str, path: &str, host: &str, headers: &[(&str, &str)], body: &[u8]) -> io::Result<(u16, Vec<u8>)> {
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n", method, path, host, body.len());
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    let mut head = read_line(&mut reader, &mut line, MAX_HEAD)?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed status line"))?;
+    let mut length = None;
+    let mut chunked = false;
+    loop {
+        line.clear();
+        let n = read_line(&mut reader, &mut line, MAX_HEAD - head)?;
+        head += n;
+        if n == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<u64>().ok();
+            } else if name.trim().eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.trim().eq_ignore_ascii_case("chunked");
+            }
+        }
+    }
+    let mut response = Vec::new();
+    if chunked {
+        read_chunks(&mut reader, &mut response)?;
+        return Ok((status, response));
+    }
+    let limit = length.unwrap_or(u64::MAX).min(MAX_BODY as u64);
+    match reader.take(limit).read_to_end(&mut response) {
+        // servers often close tls connections without notifying
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+        result => {
+            result?;
+        }
+    }
+    return Ok((status, response));
+}
+
+/// read a chunked body into `out`, up to MAX_BODY
+fn read_chunks(reader: &mut impl BufRead, out: &mut Vec<u8>) -> io::Result<()> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        read_line(reader, &mut line, MAX_HEAD)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed chunk size"))?;
+        if size == 0 {
+            return Ok(());
+        }
+        if out.len() + size > MAX_BODY {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "response body too large"));
+        }
+        let start = out.len();
+        out.resize(start + size, 0);
+        reader.read_exact(&mut out[start..])?;
+        line.clear();
+        read_line(reader, &mut line, MAX_HEAD)?;
+    }
+}
+
+/// plain or tls connection
+pub(crate) trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+/// connect to the server of `url`, with `timeout` for reads and writes
+pub(crate) fn connect(url: &Url, timeout: Duration) -> io::Result<Box<dyn Stream>> {
+    let tcp = tcp(url, timeout)?;
+    if !url.tls {
+        return Ok(Box::new(tcp));
+    }
+    return tls(tcp, tls_config(), &url.host);
+}
+
+/// connect to the server of `url` over TLS with `config`, sending and verifying `server_name` instead of the host if given
+pub(crate) fn connect_tls(url: &Url, timeout: Duration, config: Arc<rustls::ClientConfig>, server_name: Option<&str>) -> io::Result<Box<dyn Stream>> {
+    return tls(tcp(url, timeout)?, config, server_name.unwrap_or(&url.host));
+}
+
+fn tcp(url: &Url, timeout: Duration) -> io::Result<TcpStream> {
+    let addr = (url.host.as_str(), url.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", url.host)))?;
+    let tcp = TcpStream::connect_timeout(&addr, Duration::from_secs(10))?;
+    tcp.set_read_timeout(Some(timeout))?;
+    tcp.set_write_timeout(Some(timeout))?;
+    return Ok(tcp);
+}
+
+fn tls(tcp: TcpStream, config: Arc<rustls::ClientConfig>, server_name: &str) -> io::Result<Box<dyn Stream>> {
+    let name = rustls::pki_types::ServerName::try_from(server_name.to_string()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let connection = rustls::ClientConnection::new(config, name).map_err(io::Error::other)?;
+    return Ok(Box::new(rustls::StreamOwned::new(connection, tcp)));
+}
+
+fn invalid(path: &Path, e: &dyn std::fmt::Display) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e));
+}
+
+/// the certificates of a PEM file, at least one
+fn pem_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path).and_then(|i| i.collect::<Result<Vec<_>, _>>()).map_err(|e| invalid(path, &e))?;
+    if certs.is_empty() {
+        return Err(invalid(path, &"no certificate"));
+    }
+    return Ok(certs);
+}
+
+/// server configuration with the PEM certificate chain and key, verifying client certificates against `client_ca` if given
+pub(crate) fn server_tls_config(cert: &Path, key: &Path, client_ca: Option<&Path>) -> io::Result<Arc<rustls::ServerConfig>> {
+    let chain = pem_certs(cert)?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| invalid(key, &e))?;
+
+    let builder = rustls::ServerConfig::builder();
+    let builder = match client_ca {
+        Some(path) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for ca in pem_certs(path)? {
+                roots.add(ca).map_err(|e| invalid(path, &e))?;
+            }
+            let verifier = rustls::server::WebPkiClientVerifier::builder(Arc::new(roots)).build().map_err(|e| invalid(path, &e))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder.with_single_cert(chain, key).map_err(|e| invalid(cert, &e))?;
+    return Ok(Arc::new(config));
+}
+
+fn native_roots() -> rustls::RootCertStore {
+    let mut roots = rustls::RootCertStore::empty();
+    let native = rustls_native_certs::load_native_certs();
+    for e in &native.errors {
+        log::warn!("failed to load root certificates: {}", e);
+    }
+    let (added, ignored) = roots.add_parsable_certificates(native.certs);
+    log::debug!("loaded {} root certificates, ignored {}", added, ignored);
+    return roots;
+}
+
+/// client configuration trusting the system's root certificates
+pub(crate) fn tls_config() -> Arc<rustls::ClientConfig> {
+    static CONFIG: OnceLock<Arc<rustls::ClientConfig>> = OnceLock::new();
+    return CONFIG
+        .get_or_init(|| Arc::new(rustls::ClientConfig::builder().with_root_certificates(native_roots()).with_no_client_auth()))
+        .clone();
+}
+
+/// client configuration authenticating with the PEM certificate chain and key of `client` (cert, key), trusting the
+/// certificates in `ca` or the system's roots, and offering `alpn`
+pub(crate) fn client_tls_config(client: Option<(&Path, &Path)>, ca: Option<&Path>, alpn: &[String]) -> io::Result<Arc<rustls::ClientConfig>> {
+    let roots = match ca {
+        Some(path) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for cert in pem_certs(path)? {
+                roots.add(cert).map_err(|e| invalid(path, &e))?;
+            }
+            roots
+        }
+        None => native_roots(),
+    };
+    let builder = rustls::ClientConfig::builder().with_root_certificates(roots);
+    let mut config = match client {
+        Some((cert, key)) => {
+            let chain = pem_certs(cert)?;
+            let key = PrivateKeyDer::from_pem_file(key).map_err(|e| invalid(key, &e))?;
+            builder.with_client_auth_cert(chain, key).map_err(|e| invalid(cert, &e))?
+        }
+        None => builder.with_no_client_auth(),
+    };
+    config.alpn_protocols = alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+    return Ok(Arc::new(config));
+}
+
+/// standard base64 with padding
+pub(crate) fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    return out;
+}
+
+/// decode standard base64, padded or not. None if `s` isn't base64
+pub(crate) fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = Vec::with_capacity(s.len() / 4 * 3);
+    let (mut bits, mut n) = (0_u32, 0);
+    for c in s.trim_end_matches('=').bytes() {
+        bits = bits << 6 | ALPHABET.iter().position(|&a| a == c)? as u32;
+        n += 6;
+        if n >= 8 {
+            n -= 8;
+            out.push((bits >> n) as u8);
+        }
+    }
+    return Some(out);
+}
+
+/// escape `s` for use as a single path segment
+pub(crate) fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn too_large(e: io::Error) -> bool {
+        return e.get_ref().is_some_and(|e| e.is::<HeadTooLarge>());
+    }
+
+    #[test]
+    fn request_head_is_bounded() {
+        let request = read_request(&mut io::Cursor::new(b"GET /metrics?a=%41 HTTP/1.1\r\nAccept: text/plain\r\n\r\n".to_vec())).unwrap();
+        assert_eq!((request.path.as_str(), request.param("a"), request.header("accept")), ("/metrics", Some("A"), Some("text/plain")));
+
+        // a line without end is refused once it passes the limit, without reading the rest
+        let endless = io::Cursor::new(vec![b'a'; 10 * MAX_HEAD]);
+        let mut reader = BufReader::new(endless);
+        assert!(too_large(read_request(&mut reader).unwrap_err()));
+        assert!(reader.get_ref().position() <= 2 * MAX_HEAD as u64);
+
+        let mut many = b"GET / HTTP/1.1\r\n".to_vec();
+        while many.len() <= MAX_HEAD {
+            many.extend_from_slice(b"X-Padding: 0123456789\r\n");
+        }
+        many.extend_from_slice(b"\r\n");
+        assert!(too_large(read_request(&mut io::Cursor::new(many)).unwrap_err()));
+    }
+
+    #[test]
+    fn chunk_size_lines_are_bounded() {
+        let mut out = Vec::new();
+        read_chunks(&mut io::Cursor::new(b"5;ext=1\r\nhello\r\n0\r\n\r\n".to_vec()), &mut out).unwrap();
+        assert_eq!(out, b"hello");
+        let mut endless = vec![b'1'; 2 * MAX_HEAD];
+        endless.extend_from_slice(b"\r\n");
+        assert!(too_large(read_chunks(&mut io::Cursor::new(endless), &mut Vec::new()).unwrap_err()));
+    }
+
+    #[test]
+    fn cidr_prefixes() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let all = parse_cidr("0.0.0.0/0").unwrap();
+        assert!(all.contains(ip("10.1.2.3")) && all.contains(ip("255.255.255.255")));
+        assert!(!all.contains(ip("2001:db8::1")));
+        let host = parse_cidr("192.168.1.10/32").unwrap();
+        assert!(host.contains(ip("192.168.1.10")));
+        assert!(!host.contains(ip("192.168.1.11")));
+        // a bare address is a host
+        assert!(parse_cidr("192.168.1.10").unwrap().contains(ip("192.168.1.10")));
+        assert!(!parse_cidr("192.168.1.10").unwrap().contains(ip("192.168.1.11")));
+        let lan = parse_cidr("192.168.1.0/24").unwrap();
+        assert!(lan.contains(ip("192.168.1.255")));
+        assert!(!lan.contains(ip("192.168.2.0")));
+        // host bits in the network don't matter
+        assert!(parse_cidr("10.9.9.9/8").unwrap().contains(ip("10.0.0.1")));
+        for invalid in ["10.0.0.0/33", "::/129", "10.0.0.0/-1", "10.0.0.0/x", "10.0.0/8", "fe80::1%eth0/64"] {
+            assert!(parse_cidr(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn cidr_ipv6() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let all = parse_cidr("::/0").unwrap();
+        assert!(all.contains(ip("2001:db8::1")) && all.contains(ip("::1")));
+        assert!(!all.contains(ip("10.0.0.1")));
+        let doc = parse_cidr("2001:db8::/32").unwrap();
+        assert!(doc.contains(ip("2001:db8:ffff::1")));
+        assert!(!doc.contains(ip("2001:db9::1")));
+        // a prefix not on a 16 bit boundary
+        let ula = parse_cidr("fd00::/7").unwrap();
+        assert!(ula.contains(ip("fc12::1")) && ula.contains(ip("fdff::1")));
+        assert!(!ula.contains(ip("fe80::1")));
+        let host = parse_cidr("2001:db8::1/128").unwrap();
+        assert!(host.contains(ip("2001:db8::1")));
+        assert!(!host.contains(ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn cidr_ipv4_mapped_peers() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        // clients over IPv4 on a dual-stack socket
+        let lan = parse_cidr("192.168.1.0/24").unwrap();
+        assert!(lan.contains(ip("::ffff:192.168.1.20")));
+        assert!(!lan.contains(ip("::ffff:192.168.2.20")));
+        assert!(parse_cidr("0.0.0.0/0").unwrap().contains(ip("::ffff:8.8.8.8")));
+        // and networks written that way
+        let mapped = parse_cidr("::ffff:192.168.1.0/120").unwrap();
+        assert!(mapped.contains(ip("192.168.1.20")));
+        assert!(mapped.contains(ip("::ffff:192.168.1.20")));
+        assert!(!mapped.contains(ip("192.168.2.20")));
+        // the IPv4-compatible form is another address
+        assert!(!lan.contains(ip("::192.168.1.20")));
+    }
+
+    fn get(path: &str) -> Request {
+        return Request { method: String::from("GET"), path: path.to_string(), query: Vec::new(), headers: Vec::new(), body: Vec::new() };
+    }
+
+    #[test]
+    fn cached_routes() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+        let counter = |status| {
+            let renders = Arc::new(AtomicU32::new(0));
+            let count = renders.clone();
+            let handler = move |_: &Request| Response::text(status, count.fetch_add(1, Ordering::Relaxed).to_string());
+            return (renders, handler);
+        };
+
+        let (renders, handler) = counter(200);
+        let (failing, failure) = counter(503);
+        let (uncached, each) = counter(200);
+        let router = Router::default()
+            .route("/metrics", cache(Duration::from_secs(3600), handler))
+            .route("/failing", cache(Duration::from_secs(3600), failure))
+            .route("/each", cache(Duration::ZERO, each));
+        for _ in 0..3 {
+            let response = router.handle(&get("/metrics"));
+            assert_eq!(response.body, b"0");
+            router.handle(&get("/failing"));
+            router.handle(&get("/each"));
+        }
+        assert!(router.handle(&get("/metrics")).headers.iter().any(|(name, _)| name == "Age"));
+        // only successful responses are kept
+        assert_eq!((renders.load(Ordering::Relaxed), failing.load(Ordering::Relaxed), uncached.load(Ordering::Relaxed)), (1, 3, 3));
+    }
+
+    #[test]
+    fn prefixed_routes() {
+        let router = Router::default().route("/metrics", |_| Response::text(200, "metrics")).prefix("/exporter/");
+        assert_eq!(router.handle(&get("/metrics")).body, b"metrics");
+        assert_eq!(router.handle(&get("/exporter/metrics")).body, b"metrics");
+        assert_eq!(router.handle(&get("/exportermetrics")).status, 404);
+        let redirect = router.handle(&get("/exporter"));
+        assert_eq!((redirect.status, redirect.headers), (301, vec![(String::from("Location"), String::from("/exporter/"))]));
+    }
+
+    #[test]
+    fn scoped_tokens() {
+        let with = |token: &str, path: &str| {
+            let mut request = get(path);
+            request.headers.push((String::from("Authorization"), format!("Bearer {}", token)));
+            return request;
+        };
+        let site = |s: &str| vec![(String::from("site"), s.to_string())];
+        let auth = Auth::default().token("admin").scoped_token("tenant-a", site("office-a")).scoped_token("tenant-b", site("office-b"));
+        let router = Router::default()
+            .auth(auth)
+            .labels(site("office-a"))
+            .scoped("/metrics", |_, scope| Response::text(200, format!("{:?}", scope)))
+            .node("/api/v1/latest", |_| Response::text(200, "latest"))
+            .route("/api/v1/settings", |_| Response::text(200, "settings"));
+
+        assert_eq!(router.handle(&get("/metrics")).status, 401);
+        assert_eq!(router.handle(&with("admin", "/metrics")).body, b"[]");
+        assert_eq!(router.handle(&with("tenant-b", "/metrics")).body, br#"[("site", "office-b")]"#);
+        // the node's own data goes to the tenants its labels are in the scope of
+        assert_eq!(router.handle(&with("tenant-a", "/api/v1/latest")).status, 200);
+        assert_eq!(router.handle(&with("tenant-b", "/api/v1/latest")).status, 403);
+        assert_eq!(router.handle(&with("tenant-a", "/api/v1/settings")).status, 403);
+        assert_eq!(router.handle(&with("admin", "/api/v1/settings")).status, 200);
+        assert_eq!(router.handle(&with("tenant-c", "/api/v1/latest")).status, 401);
+    }
+}
@@ -0,0 +1,139 @@
+//! module for tracing I2C transactions
+//! every write/read is logged in hex with its elapsed time, and read data is checked against sensirion's crc8.
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, Write},
+    time::Instant,
+};
+
+use embedded_hal::i2c::{self, Error as _, ErrorType, Operation};
+use sensirion_i2c::crc8;
+
+/// destination of traced transactions
+#[derive(Debug)]
+pub(crate) enum Sink {
+    Log,
+    File(File),
+}
+
+/// I2C bus wrapper which records transactions
+#[derive(Debug)]
+pub(crate) struct TracedI2c<I> {
+    inner: I,
+    sink: Option<Sink>,
+}
+
+impl<I> TracedI2c<I> {
+    /// wrap `inner`. transactions are passed through untouched when `sink` is None.
+    pub(crate) fn new(inner: I, sink: Option<Sink>) -> Self {
+        return TracedI2c { inner, sink };
+    }
+
+    fn record(&mut self, line: &str, fields: Fields) {
+        match &mut self.sink {
+            None => {}
+            Some(Sink::Log) => log::debug!(
+                addr = fields.addr,
+                opcode = fields.opcode,
+                error_kind = fields.error_kind.as_deref(),
+                duration_ms = fields.duration_ms;
+                "{}", line
+            ),
+            Some(Sink::File(f)) => {
+                let _ = writeln!(f, "{}", line).inspect_err(|e| log::warn!("failed to write i2c trace: {:?}", e));
+            }
+        }
+    }
+}
+
+/// a transaction's fields for structured logs
+struct Fields {
+    addr: u8,
+    /// the command of the first write
+    opcode: Option<u16>,
+    error_kind: Option<String>,
+    duration_ms: f64,
+}
+
+/// open the trace sink. `None` means the debug log.
+pub(crate) fn open_sink(path: Option<&str>) -> io::Result<Sink> {
+    return match path {
+        None => Ok(Sink::Log),
+        Some(p) => File::options().create(true).append(true).open(p).map(Sink::File),
+    };
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 3);
+    for (i, b) in bytes.iter().enumerate() {
+        if i > 0 {
+            s.push(' ');
+        }
+        let _ = write!(s, "{:02x}", b);
+    }
+    return s;
+}
+
+/// crc state of a read made of sensirion words (2 data bytes + crc)
+fn crc_result(bytes: &[u8]) -> &'static str {
+    if bytes.is_empty() || !bytes.len().is_multiple_of(3) {
+        return "n/a";
+    }
+    if bytes.chunks(3).all(|w| crc8::calculate(&w[0..2]) == w[2]) {
+        return "ok";
+    }
+    return "mismatch";
+}
+
+impl<I: ErrorType> ErrorType for TracedI2c<I> {
+    type Error = I::Error;
+}
+
+impl<I: i2c::I2c> i2c::I2c for TracedI2c<I> {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        if self.sink.is_none() {
+            return self.inner.transaction(address, operations);
+        }
+
+        let start = Instant::now();
+        let result = self.inner.transaction(address, operations);
+        let elapsed = start.elapsed();
+
+        let mut line = format!("i2c 0x{:02x}", address);
+        for op in operations.iter() {
+            match op {
+                Operation::Write(data) => {
+                    if data.len() >= 2 {
+                        let _ = write!(line, " write(cmd 0x{:02x}{:02x}) [{}]", data[0], data[1], hex(data));
+                    } else {
+                        let _ = write!(line, " write [{}]", hex(data));
+                    }
+                }
+                Operation::Read(data) => {
+                    let _ = write!(line, " read [{}] crc {}", hex(data), crc_result(data));
+                }
+            }
+        }
+        match &result {
+            Ok(_) => line.push_str(" ok"),
+            Err(e) => {
+                let _ = write!(line, " error {:?}", e);
+            }
+        }
+        let _ = write!(line, " {:.3}ms", elapsed.as_secs_f64() * 1000.0);
+        let opcode = operations.iter().find_map(|op| match op {
+            Operation::Write(data) if data.len() >= 2 => Some(u16::from_be_bytes([data[0], data[1]])),
+            _ => None,
+        });
+        let fields = Fields {
+            addr: address,
+            opcode,
+            error_kind: result.as_ref().err().map(|e| format!("{:?}", e.kind())),
+            duration_ms: elapsed.as_secs_f64() * 1000.0,
+        };
+        self.record(&line, fields);
+
+        return result;
+    }
+}
@@ -0,0 +1,93 @@
+//! module for an at-a-glance CO2 indicator: traffic-light LEDs on GPIOs or a WS2812 strip
+//! --indicator-bands splits the CO2 range into levels, e.g. 800,1200 into good, fair and poor. with GPIOs
+//! there is one LED per level and the current level's is lit. a WS2812 strip on SPI0 (MOSI, GPIO 10) shows
+//! green, yellow or red on every LED. everything is dark while there's no valid data.
+use rppal::{
+    gpio::{Gpio, OutputPin},
+    spi::{Bus, Mode, SlaveSelect, Spi},
+};
+
+use crate::error::Error;
+
+/// SPI clock for WS2812 timing, each data bit becomes three SPI bits of 417ns
+const WS2812_CLOCK: u32 = 2_400_000;
+/// zero bytes holding the line low past the 50us reset
+const WS2812_RESET: usize = 32;
+/// green, yellow and red in the strip's GRB order, not too bright for a room
+const COLORS: [[u8; 3]; 3] = [[64, 0, 0], [40, 64, 0], [0, 64, 0]];
+
+enum Output {
+    Gpio(Vec<OutputPin>),
+    Ws2812 { spi: Spi, leds: usize },
+}
+
+pub(crate) struct Indicator {
+    output: Output,
+    /// ascending CO2 thresholds between the levels
+    bands: Vec<u16>,
+    /// the level shown, None while dark
+    shown: Option<Option<usize>>,
+}
+
+impl Indicator {
+    /// LEDs on GPIO `pins` (BCM numbering), one for each level from good to poor
+    pub(crate) fn gpio(pins: &[u8], bands: Vec<u16>) -> Result<Self, Error> {
+        if pins.len() != bands.len() + 1 {
+            return Err(Error::Config(format!("{} bands make {} levels, but there are {} indicator pins", bands.len(), bands.len() + 1, pins.len())));
+        }
+        let gpio = Gpio::new()?;
+        let pins = pins.iter().map(|&p| Ok(gpio.get(p)?.into_output_low())).collect::<Result<_, Error>>()?;
+        return Ok(Indicator { output: Output::Gpio(pins), bands, shown: None });
+    }
+
+    /// a strip of `leds` WS2812 LEDs on SPI0
+    pub(crate) fn ws2812(leds: usize, bands: Vec<u16>) -> Result<Self, Error> {
+        if bands.len() > 2 {
+            return Err(Error::Config(String::from("a WS2812 strip shows at most three levels, give at most two bands")));
+        }
+        let spi = Spi::new(Bus::Spi0, SlaveSelect::Ss0, WS2812_CLOCK, Mode::Mode0)?;
+        return Ok(Indicator { output: Output::Ws2812 { spi, leds }, bands, shown: None });
+    }
+
+    /// show the level of `co2`, dark for None
+    pub(crate) fn update(&mut self, co2: Option<u16>) {
+        let level = co2.map(|c| self.bands.iter().filter(|&&b| c >= b).count());
+        if self.shown == Some(level) {
+            return;
+        }
+        self.shown = Some(level);
+        match &mut self.output {
+            Output::Gpio(pins) => {
+                for (i, pin) in pins.iter_mut().enumerate() {
+                    pin.write((level == Some(i)).into());
+                }
+            }
+            Output::Ws2812 { spi, leds } => {
+                let color = level.map(|l| COLORS[l.min(COLORS.len() - 1)]).unwrap_or_default();
+                let frame = ws2812_frame(color, *leds);
+                let _ = spi.write(&frame).inspect_err(|e| log::warn!("failed to update the WS2812 strip: {:?}", e));
+            }
+        }
+    }
+}
+
+impl Drop for Indicator {
+    fn drop(&mut self) {
+        self.update(None);
+    }
+}
+
+/// the SPI bytes setting `leds` LEDs to `grb`: a 1 bit is sent as 110, a 0 bit as 100
+fn ws2812_frame(grb: [u8; 3], leds: usize) -> Vec<u8> {
+    let mut bits = Vec::with_capacity(leds * 72);
+    for _ in 0..leds {
+        for byte in grb {
+            for i in (0..8).rev() {
+                bits.extend_from_slice(if byte & (1 << i) != 0 { &[1, 1, 0] } else { &[1, 0, 0] });
+            }
+        }
+    }
+    let mut frame: Vec<u8> = bits.chunks(8).map(|c| c.iter().fold(0, |b, &bit| b << 1 | bit)).collect();
+    frame.extend_from_slice(&[0; WS2812_RESET]);
+    return frame;
+}
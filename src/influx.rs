@@ -0,0 +1,205 @@
+//! module for writing measurements to InfluxDB in line protocol
+//! see https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/
+//! points are batched by a task and written every flush interval, right away once a shutdown is requested, to the v2 API
+//! (`/api/v2/write`, org/bucket/token) or the v1 API (`/write`, database, basic auth from the url).
+//! failed batches are kept and written with the next one, up to a bound, or with --influx-buffer-dir
+//! spooled to disk and written in order once InfluxDB is back.
+use std::{
+    io,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use crate::{
+    http::{self, Url},
+    sensor::Envelope,
+    shutdown::{self, Pending},
+    spool::{self, Delivery, Spool},
+    tasks,
+};
+
+/// points kept while InfluxDB is unreachable; the oldest are dropped beyond this
+const MAX_PENDING: usize = 10000;
+
+#[derive(Debug, Clone)]
+pub(crate) enum Api {
+    V2 { org: String, bucket: String, token: Option<String> },
+    V1 { database: String },
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) url: Url,
+    pub(crate) api: Api,
+    pub(crate) measurement: String,
+    /// tags added to every point
+    pub(crate) tags: Vec<(String, String)>,
+    pub(crate) flush_interval: Duration,
+    /// where batches wait while InfluxDB is unreachable, in memory if None
+    pub(crate) buffer: Option<spool::Config>,
+}
+
+struct Output {
+    /// measurement and tags, the start of every line
+    series: String,
+    queue: Sender<String>,
+    dropped: metrics::Counter,
+}
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+static PENDING: Pending = Pending::new("influxdb");
+
+/// start writing in a task
+pub(crate) fn init(config: Config) -> io::Result<()> {
+    let mut series = escape(&config.measurement, ", ");
+    for (key, value) in &config.tags {
+        series.push_str(&format!(",{}={}", escape(key, ",= "), escape(value, ",= ")));
+    }
+    let dropped = || metrics::counter!("exporter_influx_dropped_points_total");
+    let spool = config.buffer.clone().map(|buffer| Spool::new("influx", "lp", buffer, dropped(), points)).transpose()?;
+    let (tx, rx) = mpsc::channel(MAX_PENDING);
+    tasks::spawn("influx", run(config, rx, spool));
+    let output = Output {
+        series,
+        queue: tx,
+        dropped: dropped(),
+    };
+    let _ = OUTPUT.set(output);
+    PENDING.register();
+    return Ok(());
+}
+
+/// queue a measurement, if the output is enabled
+pub(crate) fn write(envelope: &Envelope) {
+    let Some(output) = OUTPUT.get() else {
+        return;
+    };
+    let m = &envelope.measurement;
+    let line = format!(
+        "{},source={} co2={}i,temperature={},humidity={},seq={}i {}",
+        output.series,
+        escape(&envelope.source, ",= "),
+        m.co2,
+        m.temperature,
+        m.humidity,
+        envelope.seq,
+        envelope.timestamp_ms
+    );
+    PENDING.add(1);
+    if output.queue.try_send(line).is_err() {
+        PENDING.done(1);
+        output.dropped.increment(1);
+    }
+}
+
+/// backslash-escape `special` characters
+fn escape(s: &str, special: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if special.contains(c) || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    return out;
+}
+
+/// the points of a batch
+fn points(body: &[u8]) -> u64 {
+    return body.split(|&b| b == b'\n').count() as u64;
+}
+
+async fn run(config: Config, mut rx: Receiver<String>, mut spool: Option<Spool>) {
+    let (url, token) = match &config.api {
+        Api::V2 { org, bucket, token } => (
+            config.url.join(&format!(
+                "api/v2/write?org={}&bucket={}&precision=ms",
+                http::percent_encode(org),
+                http::percent_encode(bucket)
+            )),
+            token.as_ref().map(|t| format!("Token {}", t)),
+        ),
+        Api::V1 { database } => (config.url.join(&format!("write?db={}&precision=ms", http::percent_encode(database))), None),
+    };
+    let written = metrics::counter!("exporter_influx_written_points_total");
+    let failures = metrics::counter!("exporter_influx_write_failures_total");
+    let dropped = metrics::counter!("exporter_influx_dropped_points_total");
+
+    let post = |body: &[u8]| -> Delivery {
+        let mut headers = vec![("Content-Type", "text/plain; charset=utf-8")];
+        if let Some(token) = &token {
+            headers.push(("Authorization", token));
+        }
+        return match http::send("POST", &url, &headers, body) {
+            Ok((status, _)) if (200..300).contains(&status) => {
+                written.increment(points(body));
+                Delivery::Sent
+            }
+            // the points themselves are rejected, writing them again won't help
+            Ok((status, response)) if status == 400 || status == 422 => {
+                log::warn!("influxdb rejected {} points with {}: {}", points(body), status, String::from_utf8_lossy(&response).trim());
+                failures.increment(1);
+                dropped.increment(points(body));
+                Delivery::Rejected
+            }
+            Ok((status, response)) => {
+                log::warn!("influxdb write failed with {}: {}", status, String::from_utf8_lossy(&response).trim());
+                failures.increment(1);
+                Delivery::Retry
+            }
+            Err(e) => {
+                log::warn!("influxdb write failed: {:?}", e);
+                failures.increment(1);
+                Delivery::Retry
+            }
+        };
+    };
+
+    let mut pending: Vec<String> = Vec::new();
+    let mut next_flush = Instant::now() + config.flush_interval;
+    loop {
+        let received = tokio::time::timeout(next_flush.saturating_duration_since(Instant::now()), rx.recv());
+        // the shutdown interrupts the wait once, from then on the points are flushed every second
+        let received = if shutdown::requested() { Some(received.await) } else { tasks::cancellable(received).await };
+        match received {
+            Some(Ok(Some(line))) => {
+                pending.push(line);
+                continue;
+            }
+            Some(Ok(None)) => return,
+            Some(Err(_)) | None => {}
+        }
+        next_flush = Instant::now() + if shutdown::requested() { Duration::from_secs(1) } else { config.flush_interval };
+        if pending.is_empty() {
+            if let Some(spool) = spool.as_mut().filter(|s| !s.is_empty()) {
+                tasks::blocking(|| spool.flush(&post));
+            }
+            continue;
+        }
+
+        let body = pending.join("\n");
+        match spool.as_mut() {
+            // on disk the batch outlasts a restart too
+            Some(spool) => {
+                tasks::blocking(|| spool.send(body.as_bytes(), &post));
+                PENDING.done(pending.len());
+                pending.clear();
+            }
+            None => match tasks::blocking(|| post(body.as_bytes())) {
+                Delivery::Sent | Delivery::Rejected => {
+                    PENDING.done(pending.len());
+                    pending.clear();
+                }
+                Delivery::Retry => {}
+            },
+        }
+        if pending.len() > MAX_PENDING {
+            let excess = pending.len() - MAX_PENDING;
+            pending.drain(..excess);
+            dropped.increment(excess as u64);
+            PENDING.done(excess);
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! module for build information and uptime
+use std::{sync::OnceLock, time::Instant};
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// export exporter_build_info and start counting uptime
+pub(crate) fn init() {
+    STARTED.get_or_init(Instant::now);
+    metrics::gauge!(
+        "exporter_build_info",
+        "version" => env!("CARGO_PKG_VERSION"),
+        "git_sha" => env!("GIT_SHA"),
+        "rustc" => env!("RUSTC_VERSION"),
+    )
+    .set(1);
+    update_uptime();
+}
+
+/// refresh exporter_uptime_seconds
+pub(crate) fn update_uptime() {
+    if let Some(started) = STARTED.get() {
+        metrics::gauge!("exporter_uptime_seconds").set(started.elapsed().as_secs_f64());
+    }
+}
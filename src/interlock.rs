@@ -0,0 +1,68 @@
+//! module for the "data valid" safety interlock output
+//! a GPIO pin asserted only while fresh, plausible measurements are flowing, so hard-wired
+//! ventilation controllers can fall back to a safe state when the sensor or the exporter fails.
+use clap::ValueEnum;
+use rppal::gpio::{self, Gpio, OutputPin};
+
+use crate::sensor::{Measurement, CHANNELS};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum Mode {
+    /// hold the pin asserted while data is valid. the level stays as it is when the exporter is killed.
+    Level,
+    /// toggle the pin on every sampling loop iteration while data is valid, for a retriggerable
+    /// monostable or watchdog relay. this also fails safe when the exporter is killed or hangs.
+    Pulse,
+}
+
+pub(crate) struct Interlock {
+    pin: OutputPin,
+    mode: Mode,
+    active_low: bool,
+    valid: Option<bool>,
+    gauge: metrics::Gauge,
+}
+
+impl Interlock {
+    /// init GPIO `pin` (BCM numbering) deasserted
+    pub(crate) fn new(pin: u8, mode: Mode, active_low: bool) -> Result<Self, gpio::Error> {
+        let pin = Gpio::new()?.get(pin)?;
+        let pin = if active_low { pin.into_output_high() } else { pin.into_output_low() };
+        return Ok(Interlock {
+            pin,
+            mode,
+            active_low,
+            valid: None,
+            gauge: metrics::gauge!("scd41_data_valid"),
+        });
+    }
+
+    /// drive the pin for the current validity, once per loop iteration
+    pub(crate) fn update(&mut self, valid: bool) {
+        if self.valid != Some(valid) {
+            if valid {
+                log::info!("data valid, assert interlock");
+            } else {
+                log::warn!("data not valid, release interlock");
+            }
+            self.valid = Some(valid);
+            self.gauge.set(if valid { 1 } else { 0 });
+        }
+        match (self.mode, valid) {
+            (Mode::Pulse, true) => self.pin.toggle(),
+            (_, valid) => self.pin.write((valid != self.active_low).into()),
+        }
+    }
+}
+
+impl Drop for Interlock {
+    fn drop(&mut self) {
+        self.update(false);
+    }
+}
+
+/// whether `m` is within what the sensor can physically report
+pub(crate) fn plausible(m: &Measurement) -> bool {
+    let values = [m.co2 as f64, m.temperature as f64, m.humidity as f64];
+    return CHANNELS.iter().zip(values).all(|((_, _, min, max), v)| (*min..=*max).contains(&v));
+}
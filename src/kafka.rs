@@ -0,0 +1,468 @@
+//! module for the Kafka protocol of the message bus output
+//! see https://kafka.apache.org/protocol and https://kafka.apache.org/documentation/#recordbatch
+//! the partitions of a topic and their leaders are looked up with Metadata v1 on the bootstrap broker, and each
+//! partition's messages go to its leader as a Produce v3 request holding one record batch, acknowledged by all
+//! in-sync replicas. keys are partitioned with murmur2 like the Java client does, so consumers of either agree.
+use std::{collections::HashMap, io, time::Duration};
+
+use crate::{
+    broker::{Connection, Message},
+    http::{self, Stream, Url},
+    protobuf,
+};
+
+const PRODUCE: i16 = 0;
+const METADATA: i16 = 3;
+
+/// produce errors worth retrying after a metadata refresh: unknown partition, leader not available, not the leader,
+/// timed out, network error, not enough replicas and not enough replicas after append
+const RETRIABLE: [i16; 7] = [3, 5, 6, 7, 13, 19, 20];
+
+/// largest response read. metadata of a few topics and produce acknowledgements are far smaller,
+/// so a larger size means the peer isn't speaking Kafka
+const MAX_RESPONSE: usize = 1024 * 1024;
+
+pub(crate) struct Session {
+    bootstrap: Url,
+    client_id: String,
+    correlation_id: i32,
+    /// node id to host and port
+    brokers: HashMap<i32, (String, u16)>,
+    /// topic to the leader of each partition
+    topics: HashMap<String, Vec<i32>>,
+    /// the bootstrap broker is node -1
+    connections: HashMap<i32, Box<dyn Stream>>,
+    /// partition of messages without a key, the next one for every batch
+    next_partition: usize,
+}
+
+impl Session {
+    pub(crate) fn connect(url: &Url, client_id: &str) -> io::Result<Session> {
+        let stream = http::connect(url, Duration::from_secs(10))?;
+        let mut session = Session {
+            bootstrap: url.clone(),
+            client_id: client_id.to_string(),
+            correlation_id: 0,
+            brokers: HashMap::new(),
+            topics: HashMap::new(),
+            connections: HashMap::from([(-1, stream)]),
+            next_partition: 0,
+        };
+        // an empty metadata request checks the broker talks Kafka
+        session.metadata(&[])?;
+        return Ok(session);
+    }
+
+    /// send a request to `node` and read its response, without the correlation id
+    fn request(&mut self, node: i32, api_key: i16, api_version: i16, body: &[u8]) -> io::Result<Vec<u8>> {
+        if !self.connections.contains_key(&node) {
+            let (host, port) = self.brokers.get(&node).cloned().ok_or_else(|| io::Error::other(format!("unknown broker {}", node)))?;
+            let url = Url { tls: self.bootstrap.tls, host, port, path: String::from("/"), userinfo: None };
+            self.connections.insert(node, http::connect(&url, Duration::from_secs(10))?);
+        }
+        let stream = self.connections.get_mut(&node).expect("connected above");
+        self.correlation_id = self.correlation_id.wrapping_add(1);
+        let mut header = Vec::new();
+        header.extend_from_slice(&api_key.to_be_bytes());
+        header.extend_from_slice(&api_version.to_be_bytes());
+        header.extend_from_slice(&self.correlation_id.to_be_bytes());
+        string(&mut header, Some(&self.client_id));
+        let mut request = ((header.len() + body.len()) as i32).to_be_bytes().to_vec();
+        request.extend_from_slice(&header);
+        request.extend_from_slice(body);
+        stream.write_all(&request)?;
+        stream.flush()?;
+        let mut size = [0; 4];
+        stream.read_exact(&mut size)?;
+        let size = i32::from_be_bytes(size).max(0) as usize;
+        if size > MAX_RESPONSE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("response of {} bytes", size)));
+        }
+        let mut response = vec![0; size];
+        stream.read_exact(&mut response)?;
+        let mut r = Reader(&response);
+        if r.i32()? != self.correlation_id {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "response to another request"));
+        }
+        return Ok(r.0.to_vec());
+    }
+
+    /// refresh the brokers and the partition leaders of `topics`
+    fn metadata(&mut self, topics: &[&str]) -> io::Result<()> {
+        let mut body = (topics.len() as i32).to_be_bytes().to_vec();
+        for topic in topics {
+            string(&mut body, Some(topic));
+        }
+        let response = self.request(-1, METADATA, 1, &body)?;
+        let mut r = Reader(&response);
+        for _ in 0..r.i32()? {
+            let node = r.i32()?;
+            let host = r.string()?.unwrap_or_default();
+            let port = r.i32()? as u16;
+            r.string()?;
+            self.brokers.insert(node, (host, port));
+        }
+        // controller id
+        r.i32()?;
+        for _ in 0..r.i32()? {
+            let error = r.i16()?;
+            let topic = r.string()?.unwrap_or_default();
+            // is internal
+            r.take(1)?;
+            let mut leaders = Vec::new();
+            for _ in 0..r.i32()? {
+                r.i16()?;
+                let partition = r.i32()?;
+                let leader = r.i32()?;
+                for _ in 0..2 {
+                    // replicas and in-sync replicas
+                    let n = r.i32()?;
+                    r.take(n.max(0) as usize * 4)?;
+                }
+                leaders.push((partition, leader));
+            }
+            if error != 0 {
+                return Err(io::Error::other(format!("no metadata of topic {}, error code {}", topic, error)));
+            }
+            leaders.sort();
+            self.topics.insert(topic, leaders.into_iter().map(|(_, leader)| leader).collect());
+        }
+        return Ok(());
+    }
+
+    /// the partition of a message to `topic` with `key`
+    fn partition(&mut self, topic: &str, key: Option<&str>) -> io::Result<usize> {
+        if !self.topics.contains_key(topic) {
+            self.metadata(&[topic])?;
+        }
+        let n = self.topics.get(topic).map_or(0, |leaders| leaders.len());
+        if n == 0 {
+            return Err(io::Error::other(format!("topic {} has no partitions", topic)));
+        }
+        return Ok(match key {
+            Some(key) => (murmur2(key.as_bytes()) & 0x7fffffff) as usize % n,
+            None => self.next_partition % n,
+        });
+    }
+
+    /// produce `messages` to one partition, the error code
+    fn produce(&mut self, topic: &str, partition: usize, messages: &[&Message]) -> io::Result<i16> {
+        let leader = self.topics.get(topic).and_then(|leaders| leaders.get(partition)).copied().unwrap_or(-1);
+        if leader < 0 {
+            return Err(io::Error::other(format!("partition {} of {} has no leader", partition, topic)));
+        }
+        let batch = record_batch(messages);
+        let mut body = Vec::new();
+        // no transactional id
+        string(&mut body, None);
+        // acks from all in-sync replicas
+        body.extend_from_slice(&(-1_i16).to_be_bytes());
+        body.extend_from_slice(&10_000_i32.to_be_bytes());
+        body.extend_from_slice(&1_i32.to_be_bytes());
+        string(&mut body, Some(topic));
+        body.extend_from_slice(&1_i32.to_be_bytes());
+        body.extend_from_slice(&(partition as i32).to_be_bytes());
+        body.extend_from_slice(&(batch.len() as i32).to_be_bytes());
+        body.extend_from_slice(&batch);
+        let response = self.request(leader, PRODUCE, 3, &body)?;
+        let mut r = Reader(&response);
+        // one topic with one partition
+        r.i32()?;
+        r.string()?;
+        r.i32()?;
+        r.i32()?;
+        return r.i16();
+    }
+}
+
+impl Connection for Session {
+    fn publish(&mut self, batch: &[Message]) -> io::Result<usize> {
+        let mut partitions: Vec<((&str, usize), Vec<&Message>)> = Vec::new();
+        for m in batch {
+            let partition = (m.topic.as_str(), self.partition(&m.topic, m.key.as_deref())?);
+            match partitions.iter_mut().find(|(p, _)| *p == partition) {
+                Some((_, messages)) => messages.push(m),
+                None => partitions.push((partition, vec![m])),
+            }
+        }
+        self.next_partition = self.next_partition.wrapping_add(1);
+        let mut rejected = 0;
+        for ((topic, partition), messages) in partitions {
+            match self.produce(topic, partition, &messages)? {
+                0 => {}
+                error if RETRIABLE.contains(&error) => {
+                    // the leader may have moved, look it up again on the next connection
+                    return Err(io::Error::other(format!("partition {} of {} answered error code {}", partition, topic, error)));
+                }
+                error => {
+                    log::warn!("partition {} of {} rejected {} messages, error code {}", partition, topic, messages.len(), error);
+                    rejected += messages.len();
+                }
+            }
+        }
+        return Ok(rejected);
+    }
+
+    fn ping(&mut self) -> io::Result<()> {
+        return self.metadata(&[]);
+    }
+}
+
+/// `messages` as a record batch (magic 2)
+fn record_batch(messages: &[&Message]) -> Vec<u8> {
+    let first = messages.iter().map(|m| m.timestamp_ms).min().unwrap_or_default();
+    let max = messages.iter().map(|m| m.timestamp_ms).max().unwrap_or_default();
+    let mut records = Vec::new();
+    for (i, m) in messages.iter().enumerate() {
+        let mut record = vec![0];
+        varlong(&mut record, (m.timestamp_ms - first) as i64);
+        varlong(&mut record, i as i64);
+        match &m.key {
+            Some(key) => {
+                varlong(&mut record, key.len() as i64);
+                record.extend_from_slice(key.as_bytes());
+            }
+            None => varlong(&mut record, -1),
+        }
+        varlong(&mut record, m.payload.len() as i64);
+        record.extend_from_slice(&m.payload);
+        // no headers
+        varlong(&mut record, 0);
+        varlong(&mut records, record.len() as i64);
+        records.extend_from_slice(&record);
+    }
+    // from the attributes on, covered by the crc
+    let mut tail = Vec::new();
+    tail.extend_from_slice(&0_i16.to_be_bytes());
+    tail.extend_from_slice(&(messages.len() as i32 - 1).to_be_bytes());
+    tail.extend_from_slice(&(first as i64).to_be_bytes());
+    tail.extend_from_slice(&(max as i64).to_be_bytes());
+    // no producer id, epoch and sequence, the producer isn't idempotent
+    tail.extend_from_slice(&(-1_i64).to_be_bytes());
+    tail.extend_from_slice(&(-1_i16).to_be_bytes());
+    tail.extend_from_slice(&(-1_i32).to_be_bytes());
+    tail.extend_from_slice(&(messages.len() as i32).to_be_bytes());
+    tail.extend_from_slice(&records);
+    let mut batch = Vec::with_capacity(tail.len() + 21);
+    // base offset
+    batch.extend_from_slice(&0_i64.to_be_bytes());
+    // the length after this field: leader epoch, magic, crc and the rest
+    batch.extend_from_slice(&((4 + 1 + 4 + tail.len()) as i32).to_be_bytes());
+    batch.extend_from_slice(&(-1_i32).to_be_bytes());
+    batch.push(2);
+    batch.extend_from_slice(&crc32c(&tail).to_be_bytes());
+    batch.extend_from_slice(&tail);
+    return batch;
+}
+
+/// zigzag encoded varint
+fn varlong(out: &mut Vec<u8>, n: i64) {
+    protobuf::varint(out, ((n << 1) ^ (n >> 63)) as u64);
+}
+
+/// string with an i16 length, -1 for None
+fn string(out: &mut Vec<u8>, s: Option<&str>) {
+    match s {
+        Some(s) => {
+            out.extend_from_slice(&(s.len() as i16).to_be_bytes());
+            out.extend_from_slice(s.as_bytes());
+        }
+        None => out.extend_from_slice(&(-1_i16).to_be_bytes()),
+    }
+}
+
+/// the hash the Java client partitions keys with
+fn murmur2(data: &[u8]) -> u32 {
+    const M: u32 = 0x5bd1_e995;
+    let mut h = 0x9747_b28c ^ data.len() as u32;
+    let chunks = data.chunks_exact(4);
+    let rest = chunks.remainder();
+    for chunk in chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]).wrapping_mul(M);
+        k ^= k >> 24;
+        h = h.wrapping_mul(M) ^ k.wrapping_mul(M);
+    }
+    for (i, &b) in rest.iter().enumerate().rev() {
+        h ^= (b as u32) << (8 * i);
+    }
+    if !rest.is_empty() {
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    return h ^ (h >> 15);
+}
+
+/// CRC-32C (Castagnoli) of record batches
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x82f6_3b78 } else { crc >> 1 };
+        }
+    }
+    return !crc;
+}
+
+/// reads big-endian fields of a response
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let (head, rest) = self.0.split_at_checked(n).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated response"))?;
+        self.0 = rest;
+        return Ok(head);
+    }
+
+    fn i16(&mut self) -> io::Result<i16> {
+        let b = self.take(2)?;
+        return Ok(i16::from_be_bytes([b[0], b[1]]));
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let b = self.take(4)?;
+        return Ok(i32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+    }
+
+    fn string(&mut self) -> io::Result<Option<String>> {
+        let n = self.i16()?;
+        if n < 0 {
+            return Ok(None);
+        }
+        return Ok(Some(String::from_utf8_lossy(self.take(n as usize)?).into_owned()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+
+    use super::*;
+
+    /// a broker answering with `responses`, keeping what was requested
+    struct Fake {
+        responses: Cursor<Vec<u8>>,
+        requests: Vec<u8>,
+    }
+
+    impl Read for Fake {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            return self.responses.read(buf);
+        }
+    }
+
+    impl Write for Fake {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.requests.extend_from_slice(buf);
+            return Ok(buf.len());
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    fn session(responses: Vec<u8>) -> Session {
+        let fake = Fake { responses: Cursor::new(responses), requests: Vec::new() };
+        return Session {
+            bootstrap: Url { tls: false, host: String::from("localhost"), port: 9092, path: String::from("/"), userinfo: None },
+            client_id: String::from("scd41"),
+            correlation_id: 0,
+            brokers: HashMap::new(),
+            topics: HashMap::new(),
+            connections: HashMap::from([(-1, Box::new(fake) as Box<dyn Stream>)]),
+            next_partition: 0,
+        };
+    }
+
+    #[test]
+    fn crc32c_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        assert_eq!(crc32c(b""), 0);
+    }
+
+    #[test]
+    fn murmur2_like_the_java_client() {
+        // the cases of the Java client's UtilsTest
+        for (key, hash) in [
+            ("21", -973932308),
+            ("foobar", -790332482),
+            ("a-little-bit-long-string", -985981536),
+            ("a-little-bit-longer-string", -1486304829),
+            ("lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8", -58897971),
+            ("abc", 479470107),
+        ] {
+            assert_eq!(murmur2(key.as_bytes()) as i32, hash, "{}", key);
+        }
+    }
+
+    #[test]
+    fn keys_are_partitioned_like_the_java_client() {
+        let mut session = session(Vec::new());
+        session.topics.insert(String::from("scd41"), vec![1; 12]);
+        // toPositive(murmur2(key)) % partitions
+        assert_eq!(session.partition("scd41", Some("0xf8969f073bbf")).unwrap(), 6);
+        assert_eq!(session.partition("scd41", Some("21")).unwrap(), 1173551340 % 12);
+        session.next_partition = 13;
+        assert_eq!(session.partition("scd41", None).unwrap(), 1);
+    }
+
+    #[test]
+    fn zigzag_varints() {
+        for (n, encoded) in [(0, &[0x00][..]), (-1, &[0x01]), (1, &[0x02]), (-64, &[0x7F]), (64, &[0x80, 0x01]), (i64::MAX, &[0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01])] {
+            let mut out = Vec::new();
+            varlong(&mut out, n);
+            assert_eq!(out, encoded, "{}", n);
+        }
+    }
+
+    #[test]
+    fn record_batch_layout() {
+        let message = |key: Option<&str>, payload: &str, timestamp_ms| Message { topic: String::from("t"), key: key.map(String::from), payload: payload.as_bytes().to_vec(), timestamp_ms };
+        let messages = [message(Some("k"), "v", 1000), message(None, "w", 1500)];
+        let batch = record_batch(&messages.iter().collect::<Vec<_>>());
+        let mut r = Reader(&batch);
+        assert_eq!(r.take(8).unwrap(), [0; 8]);
+        assert_eq!(r.i32().unwrap() as usize, batch.len() - 12);
+        assert_eq!(r.i32().unwrap(), -1);
+        assert_eq!(r.take(1).unwrap(), [2]);
+        let crc = r.i32().unwrap() as u32;
+        assert_eq!(crc, crc32c(r.0));
+        // attributes and last offset delta
+        assert_eq!(r.i16().unwrap(), 0);
+        assert_eq!(r.i32().unwrap(), 1);
+        assert_eq!(r.take(16).unwrap(), [1000_i64.to_be_bytes(), 1500_i64.to_be_bytes()].concat());
+        // producer id, epoch and base sequence
+        assert_eq!(r.take(14).unwrap(), [0xFF; 14]);
+        assert_eq!(r.i32().unwrap(), 2);
+        // length, attributes, timestamp delta, offset delta, key, value and no headers
+        assert_eq!(r.0, [&[0x10, 0, 0, 0, 0x02, b'k', 0x02, b'v', 0][..], &[0x10, 0, 0xE8, 0x07, 0x02, 0x01, 0x02, b'w', 0]].concat());
+    }
+
+    #[test]
+    fn reader_fields() {
+        let mut r = Reader(&[0x00, 0x02, b'o', b'k', 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE, 0x00, 0x05, b'x']);
+        assert_eq!(r.string().unwrap().as_deref(), Some("ok"));
+        assert_eq!(r.string().unwrap(), None);
+        assert_eq!(r.i32().unwrap(), -2);
+        // a string longer than what's left
+        assert_eq!(r.string().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(Reader(&[0x00]).i16().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn responses_are_bounded() {
+        let mut response = 8_i32.to_be_bytes().to_vec();
+        response.extend_from_slice(&1_i32.to_be_bytes());
+        response.extend_from_slice(b"body");
+        assert_eq!(session(response).request(-1, METADATA, 1, &[]).unwrap(), b"body");
+
+        let e = session(i32::MAX.to_be_bytes().to_vec()).request(-1, METADATA, 1, &[]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        // a response to another request
+        let mut response = 4_i32.to_be_bytes().to_vec();
+        response.extend_from_slice(&7_i32.to_be_bytes());
+        assert_eq!(session(response).request(-1, METADATA, 1, &[]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,81 @@
+//! module for timing the stages of the sampling pipeline against a latency budget
+//! every stage records `pipeline_stage_duration_seconds{stage}`. stages exceeding their budget
+//! are logged and counted in `pipeline_budget_exceeded_total{stage}`.
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Stage {
+    /// I2C transfer of a measurement, including the bus wait
+    Read,
+    /// spike filter and smoothing
+    Filter,
+    /// updating the gauges and derived values
+    Export,
+    /// burst capture, merging and other consumers of a measurement
+    Sinks,
+    /// rendering the exposition for a scrape
+    Render,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [Stage::Read, Stage::Filter, Stage::Export, Stage::Sinks, Stage::Render];
+
+    fn label(self) -> &'static str {
+        return match self {
+            Stage::Read => "read",
+            Stage::Filter => "filter",
+            Stage::Export => "export",
+            Stage::Sinks => "sinks",
+            Stage::Render => "render",
+        };
+    }
+}
+
+/// parse `STAGE=DURATION`, e.g. read=50ms
+pub(crate) fn parse_budget(s: &str) -> Result<(Stage, Duration), String> {
+    let (stage, duration) = s.split_once('=').ok_or("expected STAGE=DURATION")?;
+    let stage = Stage::ALL
+        .into_iter()
+        .find(|st| st.label() == stage)
+        .ok_or_else(|| format!("unknown stage {}, expected read, filter, export, sinks or render", stage))?;
+    let duration = humantime::parse_duration(duration).map_err(|e| e.to_string())?;
+    return Ok((stage, duration));
+}
+
+struct Timer {
+    duration: metrics::Histogram,
+    exceeded: metrics::Counter,
+    budget: Option<Duration>,
+}
+
+static TIMERS: OnceLock<Vec<Timer>> = OnceLock::new();
+
+/// enable timing with the given per-stage budgets
+pub(crate) fn init(budgets: &[(Stage, Duration)]) {
+    let timers = Stage::ALL
+        .iter()
+        .map(|stage| Timer {
+            duration: metrics::histogram!("pipeline_stage_duration_seconds", "stage" => stage.label()),
+            exceeded: metrics::counter!("pipeline_budget_exceeded_total", "stage" => stage.label()),
+            budget: budgets.iter().rev().find(|(s, _)| s == stage).map(|(_, d)| *d),
+        })
+        .collect();
+    let _ = TIMERS.set(timers);
+}
+
+/// record that `stage` took since `start`
+pub(crate) fn record(stage: Stage, start: Instant) {
+    let Some(timers) = TIMERS.get() else {
+        return;
+    };
+    let elapsed = start.elapsed();
+    let timer = &timers[stage as usize];
+    timer.duration.record(elapsed.as_secs_f64());
+    if let Some(budget) = timer.budget.filter(|b| elapsed > *b) {
+        log::warn!("{} stage took {:?}, over its budget of {:?}", stage.label(), elapsed, budget);
+        timer.exceeded.increment(1);
+    }
+}
@@ -0,0 +1,119 @@
+//! module keeping the latest reading of the CO2 sensor for the JSON API
+//! `/api/v1/latest` serves it, so scripts and dashboards don't need to parse the exposition format.
+use std::sync::Mutex;
+
+use crate::{json, sensor::Envelope};
+
+/// state of the sensor as seen from outside
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Status {
+    /// no measurement yet
+    Starting,
+    /// fresh, plausible measurements are flowing
+    Ok,
+    /// the last measurement is out of the plausible range
+    Implausible,
+    /// no measurement for longer than --stale-after
+    Stale,
+    /// the last attempts failed
+    Failing,
+}
+
+impl Status {
+    pub(crate) fn name(self) -> &'static str {
+        return match self {
+            Status::Starting => "starting",
+            Status::Ok => "ok",
+            Status::Implausible => "implausible",
+            Status::Stale => "stale",
+            Status::Failing => "failing",
+        };
+    }
+
+    /// the status as a number, for protocols without strings (Modbus, SNMP)
+    pub(crate) fn code(self) -> u16 {
+        return match self {
+            Status::Starting => 0,
+            Status::Ok => 1,
+            Status::Implausible => 2,
+            Status::Stale => 3,
+            Status::Failing => 4,
+        };
+    }
+}
+
+struct Latest {
+    envelope: Option<Envelope>,
+    serial: Option<String>,
+    status: Status,
+    consecutive_failures: u32,
+    /// CO2 change in ppm per minute
+    trend: Option<f64>,
+}
+
+static LATEST: Mutex<Latest> = Mutex::new(Latest {
+    envelope: None,
+    serial: None,
+    status: Status::Starting,
+    consecutive_failures: 0,
+    trend: None,
+});
+
+fn lock() -> std::sync::MutexGuard<'static, Latest> {
+    return LATEST.lock().unwrap_or_else(|e| e.into_inner());
+}
+
+pub(crate) fn set_serial(serial: &str) {
+    lock().serial = Some(serial.to_string());
+}
+
+/// the sensor's serial, None until it's read
+pub(crate) fn serial() -> Option<String> {
+    return lock().serial.clone();
+}
+
+/// keep `envelope` as the latest reading
+pub(crate) fn record(envelope: &Envelope) {
+    lock().envelope = Some(envelope.clone());
+}
+
+/// keep the CO2 trend of the latest reading, None while it isn't known
+pub(crate) fn set_trend(trend: Option<f64>) {
+    lock().trend = trend;
+}
+
+/// the CO2 trend in ppm per minute
+pub(crate) fn trend() -> Option<f64> {
+    return lock().trend;
+}
+
+/// update the sensor status, after every attempt
+pub(crate) fn update(status: Status, consecutive_failures: u32) {
+    let mut latest = lock();
+    latest.status = status;
+    latest.consecutive_failures = consecutive_failures;
+}
+
+/// the latest reading (None before the first one), the sensor's serial and status
+pub(crate) fn get() -> (Option<Envelope>, Option<String>, Status) {
+    let latest = lock();
+    return (latest.envelope.clone(), latest.serial.clone(), latest.status);
+}
+
+/// the latest reading as a JSON object, None before the first one
+pub(crate) fn to_json() -> Option<String> {
+    let latest = lock();
+    let envelope = latest.envelope.as_ref()?;
+    let m = &envelope.measurement;
+    return Some(format!(
+        r#"{{"co2":{},"temperature":{:.2},"humidity":{:.2},"timestamp_ms":{},"seq":{},"serial":{},"status":{},"consecutive_failures":{}}}"#,
+        m.co2,
+        m.temperature,
+        m.humidity,
+        envelope.timestamp_ms,
+        envelope.seq,
+        latest.serial.as_deref().map(json::quote).unwrap_or_else(|| String::from("null")),
+        json::quote(latest.status.name()),
+        latest.consecutive_failures
+    ));
+}
@@ -0,0 +1,715 @@
+//! driver for the Sensirion SCD4x CO2 sensors (SCD40, SCD41, SCD43) on an embedded-hal 1.0 I2C bus
+//! see https://sensirion.com/media/documents/48C4B7FB/66E05452/CD_DS_SCD4x_Datasheet_D1.pdf
+//!
+//! [`Scd41`] wraps the bus and has a method for each command of the datasheet, named after it. the methods
+//! wait the command's execution time before returning, on the embedded-hal `DelayNs` they're given. the
+//! variants differ in the commands they support, [`check`] tells which ones before they time out on the bus.
+//!
+//! the crate is `no_std` without its default features. `std` adds [`StdDelay`] and `std::error::Error`
+//! for the errors, `async` the driver awaiting its commands in `asynch`, the default `exporter` builds the
+//! exporter binary.
+//!
+//! ```no_run
+//! # fn run<I: embedded_hal::i2c::I2c>(i2c: I) -> Result<(), scd4x::Error<I::Error>> {
+//! let mut scd41 = scd4x::Scd41::new(i2c, scd4x::StdDelay);
+//! scd41.start_periodic_measurement()?;
+//! std::thread::sleep(std::time::Duration::from_secs(5));
+//! if scd41.get_data_ready_status()? {
+//!     let m = scd41.read_measurement()?;
+//!     println!("{} ppm, {:.1} degC, {:.1} %RH", m.co2, m.temperature, m.humidity);
+//! }
+//! # return Ok(());
+//! # }
+//! ```
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#![allow(clippy::needless_return)]
+#![warn(missing_docs)]
+use core::{fmt, time::Duration};
+
+use embedded_hal::{delay::DelayNs, i2c::I2c};
+use sensirion_i2c::{crc8, i2c::write_command_u16};
+
+#[cfg(feature = "async")]
+pub mod asynch;
+
+/// the sensor's fixed I2C address
+pub const I2C_ADDR: u8 = 0x62;
+
+/// a measurement of the sensor
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    /// CO2 concentration in ppm
+    pub co2: u16,
+    /// degrees Celsius
+    pub temperature: f32,
+    /// relative humidity in percent
+    pub humidity: f32,
+}
+
+/// error of a command, `E` is the bus's error
+#[derive(Debug)]
+pub enum Error<E> {
+    /// writing the command failed, e.g. it wasn't acknowledged
+    Write(E),
+    /// reading the response failed
+    Read(E),
+    /// a word of the response didn't match its checksum
+    Crc,
+}
+
+impl<E: fmt::Debug> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Error::Write(e) => write!(f, "failed to write the command: {:?}", e),
+            Error::Read(e) => write!(f, "failed to read the response: {:?}", e),
+            Error::Crc => write!(f, "checksum mismatch in the response"),
+        };
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: fmt::Debug> std::error::Error for Error<E> {}
+
+impl<I: I2c> From<sensirion_i2c::i2c::Error<I>> for Error<I::Error> {
+    fn from(e: sensirion_i2c::i2c::Error<I>) -> Self {
+        return match e {
+            sensirion_i2c::i2c::Error::I2cWrite(e) => Error::Write(e),
+            sensirion_i2c::i2c::Error::I2cRead(e) => Error::Read(e),
+            sensirion_i2c::i2c::Error::Crc => Error::Crc,
+        };
+    }
+}
+
+/// the sensor's variant
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variant {
+    /// SCD40, without single shot measurements and power-down
+    Scd40,
+    /// SCD41
+    Scd41,
+    /// SCD43, with SCD41's command set
+    Scd43,
+    /// variant bits not known to this driver, every command is attempted
+    Unknown(u16),
+}
+
+impl Variant {
+    /// decode the word returned by get_sensor_variant (bits 15-12)
+    pub fn from_word(word: u16) -> Self {
+        return match word >> 12 {
+            0b0000 => Variant::Scd40,
+            0b0001 => Variant::Scd41,
+            0b0101 => Variant::Scd43,
+            _ => Variant::Unknown(word),
+        };
+    }
+}
+
+/// commands which are not available on every variant
+#[derive(Debug, Clone, Copy)]
+pub enum Command {
+    /// measure_single_shot and measure_single_shot_rht_only
+    MeasureSingleShot,
+    /// power_down
+    PowerDown,
+    /// wake_up
+    WakeUp,
+    /// the initial and standard periods of automatic self-calibration
+    AscPeriods,
+}
+
+/// per-variant command support and timings
+#[derive(Debug)]
+pub struct Quirks {
+    /// whether measure_single_shot is supported
+    pub single_shot: bool,
+    /// whether power_down and wake_up are supported
+    pub power_down: bool,
+    /// whether the ASC periods can be read and set
+    pub asc_periods: bool,
+    /// time to wait after stop_periodic_measurement
+    pub stop_delay: Duration,
+    /// execution time of measure_single_shot
+    pub single_shot_delay: Duration,
+}
+
+const SCD40: Quirks = Quirks {
+    single_shot: false,
+    power_down: false,
+    asc_periods: false,
+    stop_delay: Duration::from_millis(500),
+    single_shot_delay: Duration::from_millis(5000),
+};
+
+const SCD41: Quirks = Quirks {
+    single_shot: true,
+    power_down: true,
+    asc_periods: true,
+    stop_delay: Duration::from_millis(500),
+    single_shot_delay: Duration::from_millis(5000),
+};
+
+/// error for a command the variant doesn't support
+#[derive(Debug)]
+pub struct Unsupported {
+    /// the command
+    pub command: Command,
+    /// the variant lacking it
+    pub variant: Variant,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{:?} is not supported by {:?}", self.command, self.variant);
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Unsupported {}
+
+/// quirks of `variant`
+pub fn quirks(variant: Variant) -> &'static Quirks {
+    return match variant {
+        Variant::Scd40 => &SCD40,
+        // SCD43 shares SCD41's command set
+        Variant::Scd41 | Variant::Scd43 | Variant::Unknown(_) => &SCD41,
+    };
+}
+
+/// fail if `variant` lacks `command`
+pub fn check(variant: Variant, command: Command) -> Result<(), Unsupported> {
+    let q = quirks(variant);
+    let supported = match command {
+        Command::MeasureSingleShot => q.single_shot,
+        Command::PowerDown | Command::WakeUp => q.power_down,
+        Command::AscPeriods => q.asc_periods,
+    };
+    if !supported {
+        return Err(Unsupported { command, variant });
+    }
+    return Ok(());
+}
+
+/// `DelayNs` sleeping the thread
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdDelay;
+
+#[cfg(feature = "std")]
+impl DelayNs for StdDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        std::thread::sleep(Duration::from_nanos(ns as u64));
+    }
+}
+
+/// an SCD4x on the bus `I2C`, waiting for the commands on `D`. `&mut` of a bus or delay is one too, so
+/// they can be borrowed for a few commands.
+pub struct Scd41<I2C, D> {
+    i2c: I2C,
+    delay: D,
+}
+
+impl<I2C, D> Scd41<I2C, D> {
+    /// the sensor on `i2c`
+    pub fn new(i2c: I2C, delay: D) -> Self {
+        return Scd41 { i2c, delay };
+    }
+
+    /// the bus, e.g. for other devices on it
+    pub fn i2c(&mut self) -> &mut I2C {
+        return &mut self.i2c;
+    }
+
+    /// give the bus and delay back
+    pub fn release(self) -> (I2C, D) {
+        return (self.i2c, self.delay);
+    }
+}
+
+/// a command of the datasheet: its code and execution time
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Cmd {
+    pub(crate) code: u16,
+    pub(crate) delay: Duration,
+}
+
+const fn cmd(code: u16, ms: u64) -> Cmd {
+    return Cmd { code, delay: Duration::from_millis(ms) };
+}
+
+/// the commands, shared by the blocking driver and the async one
+pub(crate) mod commands {
+    use super::{cmd, Cmd, SCD41};
+
+    pub(crate) const START_PERIODIC_MEASUREMENT: Cmd = cmd(0x21B1, 1);
+    pub(crate) const START_LOW_POWER_PERIODIC_MEASUREMENT: Cmd = cmd(0x21AC, 1);
+    /// the delay differs by variant, see [`super::Quirks::stop_delay`]
+    pub(crate) const STOP_PERIODIC_MEASUREMENT: Cmd = Cmd { code: 0x3F86, delay: SCD41.stop_delay };
+    pub(crate) const GET_DATA_READY_STATUS: Cmd = cmd(0xE4B8, 1);
+    pub(crate) const READ_MEASUREMENT: Cmd = cmd(0xEC05, 1);
+    pub(crate) const MEASURE_SINGLE_SHOT: Cmd = Cmd { code: 0x219D, delay: SCD41.single_shot_delay };
+    pub(crate) const MEASURE_SINGLE_SHOT_RHT_ONLY: Cmd = cmd(0x2196, 50);
+    pub(crate) const GET_TEMPERATURE_OFFSET: Cmd = cmd(0x2318, 1);
+    pub(crate) const SET_TEMPERATURE_OFFSET: Cmd = cmd(0x241D, 1);
+    pub(crate) const GET_SENSOR_ALTITUDE: Cmd = cmd(0x2322, 1);
+    pub(crate) const SET_SENSOR_ALTITUDE: Cmd = cmd(0x2427, 1);
+    pub(crate) const GET_AMBIENT_PRESSURE: Cmd = cmd(0xE000, 1);
+    pub(crate) const SET_AMBIENT_PRESSURE: Cmd = cmd(0xE000, 1);
+    pub(crate) const PERFORM_FORCED_RECALIBRATION: Cmd = cmd(0x362F, 400);
+    pub(crate) const GET_ASC_ENABLED: Cmd = cmd(0x2313, 1);
+    pub(crate) const SET_ASC_ENABLED: Cmd = cmd(0x2416, 1);
+    pub(crate) const GET_ASC_TARGET: Cmd = cmd(0x233F, 1);
+    pub(crate) const SET_ASC_TARGET: Cmd = cmd(0x243A, 1);
+    pub(crate) const GET_ASC_INITIAL_PERIOD: Cmd = cmd(0x2340, 1);
+    pub(crate) const SET_ASC_INITIAL_PERIOD: Cmd = cmd(0x2445, 1);
+    pub(crate) const GET_ASC_STANDARD_PERIOD: Cmd = cmd(0x234B, 1);
+    pub(crate) const SET_ASC_STANDARD_PERIOD: Cmd = cmd(0x244E, 1);
+    pub(crate) const PERSIST_SETTINGS: Cmd = cmd(0x3615, 800);
+    pub(crate) const GET_SERIAL_NUMBER: Cmd = cmd(0x3682, 1);
+    pub(crate) const PERFORM_SELF_TEST: Cmd = cmd(0x3639, 10000);
+    pub(crate) const PERFORM_FACTORY_RESET: Cmd = cmd(0x3632, 1200);
+    pub(crate) const REINIT: Cmd = cmd(0x3646, 30);
+    pub(crate) const GET_SENSOR_VARIANT: Cmd = cmd(0x202F, 1);
+    pub(crate) const POWER_DOWN: Cmd = cmd(0x36E0, 1);
+    pub(crate) const WAKE_UP: Cmd = cmd(0x36F6, 30);
+}
+
+/// `command` with its argument word and the word's crc
+pub(crate) fn frame(command: Cmd, arg: u16) -> [u8; 5] {
+    let data = arg.to_be_bytes();
+    let mut buf = [0_u8; 5];
+    buf[0..2].copy_from_slice(&command.code.to_be_bytes());
+    buf[2..4].copy_from_slice(&data);
+    buf[4] = crc8::calculate(&data);
+    return buf;
+}
+
+/// check the crc of each word in a response
+pub(crate) fn validate<E>(buf: &[u8]) -> Result<(), Error<E>> {
+    return crc8::validate(buf).map_err(|_| Error::Crc);
+}
+
+/// the first word of a response
+pub(crate) fn word(buf: &[u8]) -> u16 {
+    return u16::from_be_bytes([buf[0], buf[1]]);
+}
+
+/// the response of read_measurement
+pub(crate) fn measurement(buf: &[u8; 9]) -> Measurement {
+    let raw_co2 = ((buf[0] as u16) << 8) | (buf[1] as u16);
+    let raw_temperature = ((buf[3] as u16) << 8) | (buf[4] as u16);
+    let raw_humidity = ((buf[6] as u16) << 8) | (buf[7] as u16);
+
+    return Measurement {
+        co2: raw_co2,
+        temperature: raw_temperature as f32 * 175_f32 / 65535_f32 - 45_f32,
+        humidity: raw_humidity as f32 * 100_f32 / 65535_f32,
+    };
+}
+
+/// the response of get_serial_number
+pub(crate) fn serial(buf: &[u8; 9]) -> u64 {
+    return ((buf[0] as u64) << 40)
+        | ((buf[1] as u64) << 32)
+        | ((buf[3] as u64) << 24)
+        | ((buf[4] as u64) << 16)
+        | ((buf[6] as u64) << 8)
+        | (buf[7] as u64);
+}
+
+/// the status word of get_data_ready_status
+pub(crate) fn ready(status: u16) -> bool {
+    return (status & 0x7FF) != 0;
+}
+
+/// the temperature offset word in degrees Celsius
+pub(crate) fn offset_celsius(word: u16) -> f32 {
+    return word as f32 * 175_f32 / 65535_f32;
+}
+
+/// the temperature offset word of `offset` degrees Celsius
+pub(crate) fn offset_word(offset: f32) -> u16 {
+    return (offset * 65535_f32 / 175_f32) as u16;
+}
+
+/// the correction of perform_forced_recalibration in ppm, None if it failed
+pub(crate) fn correction(word: u16) -> Option<i16> {
+    return match word {
+        0xFFFF => None,
+        word => Some((word as i32 - 0x8000) as i16),
+    };
+}
+
+impl<I2C: I2c, D: DelayNs> Scd41<I2C, D> {
+    /// wait a command's execution time
+    fn wait(&mut self, delay: Duration) {
+        self.delay.delay_us(delay.as_micros() as u32);
+    }
+
+    fn command(&mut self, command: Cmd) -> Result<(), Error<I2C::Error>> {
+        write_command_u16(&mut self.i2c, I2C_ADDR, command.code).map_err(Error::Write)?;
+        self.wait(command.delay);
+        return Ok(());
+    }
+
+    /// write a command with one argument word
+    fn command_with_arg(&mut self, command: Cmd, arg: u16) -> Result<(), Error<I2C::Error>> {
+        self.i2c.write(I2C_ADDR, &frame(command, arg)).map_err(Error::Write)?;
+        self.wait(command.delay);
+        return Ok(());
+    }
+
+    /// read the response words of the last command into `buf`, three bytes each
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), Error<I2C::Error>> {
+        self.i2c.read(I2C_ADDR, buf).map_err(Error::Read)?;
+        return validate(buf);
+    }
+
+    /// send a command and read its single response word
+    fn read_word(&mut self, command: Cmd) -> Result<u16, Error<I2C::Error>> {
+        self.command(command)?;
+        let mut buf = [0; 3];
+        self.read(&mut buf)?;
+        return Ok(word(&buf));
+    }
+
+    /// bring the sensor to idle, whatever state it's in: wake_up, stop_periodic_measurement and reinit.
+    /// errors are expected on the way and ignored.
+    pub fn clean_state(&mut self) {
+        let _ = self.wake_up().inspect_err(|e| log::trace!("wakeup error {:?}", e));
+        let _ = self.stop_periodic_measurement(Duration::from_millis(500)).inspect_err(|e| log::trace!("stop error {:?}", e));
+        let _ = self.reinit().inspect_err(|e| log::trace!("reinit error {:?}", e));
+    }
+
+    /// start_periodic_measurement (0x21B1), a measurement every 5s
+    pub fn start_periodic_measurement(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::START_PERIODIC_MEASUREMENT);
+    }
+
+    /// start_low_power_periodic_measurement (0x21AC), a measurement every 30s
+    pub fn start_low_power_periodic_measurement(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::START_LOW_POWER_PERIODIC_MEASUREMENT);
+    }
+
+    /// stop_periodic_measurement (0x3F86), `delay` is the variant's execution time, see [`Quirks::stop_delay`]
+    pub fn stop_periodic_measurement(&mut self, delay: Duration) -> Result<(), Error<I2C::Error>> {
+        return self.command(Cmd { delay, ..commands::STOP_PERIODIC_MEASUREMENT });
+    }
+
+    /// get_data_ready_status (0xE4B8)
+    pub fn get_data_ready_status(&mut self) -> Result<bool, Error<I2C::Error>> {
+        let status = self.read_word(commands::GET_DATA_READY_STATUS)?;
+        log::trace!("ready value {:x}", status);
+        return Ok(ready(status));
+    }
+
+    /// read_measurement (0xEC05)
+    pub fn read_measurement(&mut self) -> Result<Measurement, Error<I2C::Error>> {
+        self.command(commands::READ_MEASUREMENT)?;
+        let mut buf = [0; 9];
+        self.read(&mut buf)?;
+        return Ok(measurement(&buf));
+    }
+
+    /// measure_single_shot (0x219D), the sensor must be idle. read the result with [`Scd41::read_measurement`].
+    pub fn measure_single_shot(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::MEASURE_SINGLE_SHOT);
+    }
+
+    /// measure_single_shot_rht_only (0x2196), like measure_single_shot without CO2, which reads as 0
+    pub fn measure_single_shot_rht_only(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::MEASURE_SINGLE_SHOT_RHT_ONLY);
+    }
+
+    /// get_temperature_offset (0x2318) in degrees Celsius
+    pub fn get_temperature_offset(&mut self) -> Result<f32, Error<I2C::Error>> {
+        return self.read_word(commands::GET_TEMPERATURE_OFFSET).map(offset_celsius);
+    }
+
+    /// set_temperature_offset (0x241D) in degrees Celsius, the sensor must be idle
+    pub fn set_temperature_offset(&mut self, offset: f32) -> Result<(), Error<I2C::Error>> {
+        return self.command_with_arg(commands::SET_TEMPERATURE_OFFSET, offset_word(offset));
+    }
+
+    /// get_sensor_altitude (0x2322) in meters above sea level
+    pub fn get_sensor_altitude(&mut self) -> Result<u16, Error<I2C::Error>> {
+        return self.read_word(commands::GET_SENSOR_ALTITUDE);
+    }
+
+    /// set_sensor_altitude (0x2427) in meters above sea level, the sensor must be idle
+    pub fn set_sensor_altitude(&mut self, meters: u16) -> Result<(), Error<I2C::Error>> {
+        return self.command_with_arg(commands::SET_SENSOR_ALTITUDE, meters);
+    }
+
+    /// get_ambient_pressure (0xE000) in Pa
+    pub fn get_ambient_pressure(&mut self) -> Result<f32, Error<I2C::Error>> {
+        return self.read_word(commands::GET_AMBIENT_PRESSURE).map(|hpa| hpa as f32 * 100_f32);
+    }
+
+    /// set_ambient_pressure (0xE000), `pressure` in Pa, also while measuring. it overrides the altitude.
+    pub fn set_ambient_pressure(&mut self, pressure: f32) -> Result<(), Error<I2C::Error>> {
+        return self.command_with_arg(commands::SET_AMBIENT_PRESSURE, (pressure / 100_f32) as u16);
+    }
+
+    /// perform_forced_recalibration (0x362F) to `target` ppm, the sensor must be idle.
+    /// returns the correction in ppm, None if the recalibration failed.
+    pub fn perform_forced_recalibration(&mut self, target: u16) -> Result<Option<i16>, Error<I2C::Error>> {
+        self.command_with_arg(commands::PERFORM_FORCED_RECALIBRATION, target)?;
+        let mut buf = [0; 3];
+        self.read(&mut buf)?;
+        return Ok(correction(word(&buf)));
+    }
+
+    /// get_automatic_self_calibration_enabled (0x2313)
+    pub fn get_automatic_self_calibration_enabled(&mut self) -> Result<bool, Error<I2C::Error>> {
+        return self.read_word(commands::GET_ASC_ENABLED).map(|word| word != 0);
+    }
+
+    /// set_automatic_self_calibration_enabled (0x2416), the sensor must be idle
+    pub fn set_automatic_self_calibration_enabled(&mut self, enabled: bool) -> Result<(), Error<I2C::Error>> {
+        return self.command_with_arg(commands::SET_ASC_ENABLED, enabled as u16);
+    }
+
+    /// get_automatic_self_calibration_target (0x233F) in ppm
+    pub fn get_automatic_self_calibration_target(&mut self) -> Result<u16, Error<I2C::Error>> {
+        return self.read_word(commands::GET_ASC_TARGET);
+    }
+
+    /// set_automatic_self_calibration_target (0x243A) in ppm, the sensor must be idle
+    pub fn set_automatic_self_calibration_target(&mut self, ppm: u16) -> Result<(), Error<I2C::Error>> {
+        return self.command_with_arg(commands::SET_ASC_TARGET, ppm);
+    }
+
+    /// get_automatic_self_calibration_initial_period (0x2340) in hours
+    pub fn get_automatic_self_calibration_initial_period(&mut self) -> Result<u16, Error<I2C::Error>> {
+        return self.read_word(commands::GET_ASC_INITIAL_PERIOD);
+    }
+
+    /// set_automatic_self_calibration_initial_period (0x2445) in hours, a multiple of 4. the sensor must be idle.
+    pub fn set_automatic_self_calibration_initial_period(&mut self, hours: u16) -> Result<(), Error<I2C::Error>> {
+        return self.command_with_arg(commands::SET_ASC_INITIAL_PERIOD, hours);
+    }
+
+    /// get_automatic_self_calibration_standard_period (0x234B) in hours
+    pub fn get_automatic_self_calibration_standard_period(&mut self) -> Result<u16, Error<I2C::Error>> {
+        return self.read_word(commands::GET_ASC_STANDARD_PERIOD);
+    }
+
+    /// set_automatic_self_calibration_standard_period (0x244E) in hours, a multiple of 4. the sensor must be idle.
+    pub fn set_automatic_self_calibration_standard_period(&mut self, hours: u16) -> Result<(), Error<I2C::Error>> {
+        return self.command_with_arg(commands::SET_ASC_STANDARD_PERIOD, hours);
+    }
+
+    /// persist_settings (0x3615), writes the settings to the eeprom. it's specified for 2000 writes.
+    pub fn persist_settings(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::PERSIST_SETTINGS);
+    }
+
+    /// get_serial_number (0x3682), 48 bits
+    pub fn read_serial(&mut self) -> Result<u64, Error<I2C::Error>> {
+        self.command(commands::GET_SERIAL_NUMBER)?;
+        let mut buf = [0; 9];
+        self.read(&mut buf)?;
+        return Ok(serial(&buf));
+    }
+
+    /// perform_self_test (0x3639), the sensor must be idle. returns the result word, 0 if no malfunction was found.
+    pub fn perform_self_test(&mut self) -> Result<u16, Error<I2C::Error>> {
+        self.command(commands::PERFORM_SELF_TEST)?;
+        let mut buf = [0; 3];
+        self.read(&mut buf)?;
+        return Ok(word(&buf));
+    }
+
+    /// perform_factory_reset (0x3632), erases the settings and calibration history in the eeprom
+    pub fn perform_factory_reset(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::PERFORM_FACTORY_RESET);
+    }
+
+    /// reinit (0x3646), reloads the settings from the eeprom. the sensor must be idle.
+    pub fn reinit(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::REINIT);
+    }
+
+    /// get_sensor_variant (0x202F)
+    pub fn get_sensor_variant(&mut self) -> Result<Variant, Error<I2C::Error>> {
+        return self.get_sensor_variant_word().map(Variant::from_word);
+    }
+
+    /// get_sensor_variant (0x202F) as the raw word, the bits below the variant are undocumented
+    pub fn get_sensor_variant_word(&mut self) -> Result<u16, Error<I2C::Error>> {
+        return self.read_word(commands::GET_SENSOR_VARIANT);
+    }
+
+    /// power_down (0x36E0), the sensor must be idle. wake_up brings it back.
+    pub fn power_down(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::POWER_DOWN);
+    }
+
+    /// wake_up (0x36F6)
+    pub fn wake_up(&mut self) -> Result<(), Error<I2C::Error>> {
+        return self.command(commands::WAKE_UP);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use embedded_hal::i2c::{ErrorKind, ErrorType, Operation};
+
+    use super::*;
+
+    /// delay returning at once
+    struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        fn delay_ns(&mut self, _: u32) {}
+    }
+
+    /// delay recording the waits asked for instead of waiting
+    #[derive(Debug, Default)]
+    struct RecordingDelay(Vec<u32>);
+
+    impl DelayNs for RecordingDelay {
+        fn delay_ns(&mut self, ns: u32) {
+            self.0.push(ns);
+        }
+    }
+
+    /// I2C bus which records writes and answers reads from a queue
+    #[derive(Debug, Default)]
+    pub(crate) struct MockI2c {
+        pub(crate) writes: Vec<Vec<u8>>,
+        pub(crate) reads: VecDeque<Vec<u8>>,
+    }
+
+    impl ErrorType for MockI2c {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for MockI2c {
+        fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+            assert_eq!(address, I2C_ADDR);
+            for op in operations {
+                match op {
+                    Operation::Write(data) => self.writes.push(data.to_vec()),
+                    Operation::Read(buf) => {
+                        let data = self.reads.pop_front().ok_or(ErrorKind::Other)?;
+                        buf.copy_from_slice(&data);
+                    }
+                }
+            }
+            return Ok(());
+        }
+    }
+
+    /// encode words with sensirion's crc
+    pub(crate) fn words(values: &[u16]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for v in values {
+            let b = v.to_be_bytes();
+            out.extend_from_slice(&b);
+            out.push(crc8::calculate(&b));
+        }
+        return out;
+    }
+
+    #[test]
+    fn read_serial_assembles_words() {
+        let mut i2c = MockI2c::default();
+        i2c.reads.push_back(words(&[0xf896, 0x9f07, 0x3bbf]));
+
+        assert_eq!(Scd41::new(&mut i2c, NoDelay).read_serial().unwrap(), 0xf896_9f07_3bbf);
+        assert_eq!(i2c.writes, vec![vec![0x36, 0x82]]);
+    }
+
+    #[test]
+    fn read_measurement_converts_values() {
+        let mut i2c = MockI2c::default();
+        // datasheet example: 500 ppm, 25 degC, 37 %RH
+        i2c.reads.push_back(words(&[0x01f4, 0x6667, 0x5eb9]));
+
+        let m = Scd41::new(&mut i2c, NoDelay).read_measurement().unwrap();
+        assert_eq!(m.co2, 500);
+        assert!((m.temperature - 25.0).abs() < 0.01);
+        assert!((m.humidity - 37.0).abs() < 0.01);
+        assert_eq!(i2c.writes, vec![vec![0xec, 0x05]]);
+    }
+
+    #[test]
+    fn read_measurement_rejects_bad_crc() {
+        let mut i2c = MockI2c::default();
+        let mut data = words(&[0x01f4, 0x6667, 0x5eb9]);
+        data[2] ^= 0xff;
+        i2c.reads.push_back(data);
+
+        assert!(matches!(Scd41::new(&mut i2c, NoDelay).read_measurement(), Err(Error::Crc)));
+    }
+
+    #[test]
+    fn data_ready_masks_status() {
+        let mut i2c = MockI2c::default();
+        i2c.reads.push_back(words(&[0x8000]));
+        i2c.reads.push_back(words(&[0x8006]));
+
+        let mut scd41 = Scd41::new(&mut i2c, NoDelay);
+        assert!(!scd41.get_data_ready_status().unwrap());
+        assert!(scd41.get_data_ready_status().unwrap());
+    }
+
+    #[test]
+    fn set_temperature_offset_frames_command() {
+        let mut i2c = MockI2c::default();
+        // datasheet example: 5.4 degC is 0x07e6
+        Scd41::new(&mut i2c, NoDelay).set_temperature_offset(5.4).unwrap();
+
+        assert_eq!(i2c.writes, vec![vec![0x24, 0x1d, 0x07, 0xe6, 0x48]]);
+    }
+
+    #[test]
+    fn set_ambient_pressure_frames_command() {
+        let mut i2c = MockI2c::default();
+        // 98700 Pa is 987 hPa (0x03db)
+        Scd41::new(&mut i2c, NoDelay).set_ambient_pressure(98700.0).unwrap();
+
+        let data = [0x03, 0xdb];
+        assert_eq!(i2c.writes, vec![vec![0xe0, 0x00, data[0], data[1], crc8::calculate(&data)]]);
+    }
+
+    #[test]
+    fn forced_recalibration_decodes_correction() {
+        let mut i2c = MockI2c::default();
+        i2c.reads.push_back(words(&[0x7fe7]));
+        i2c.reads.push_back(words(&[0xffff]));
+
+        let mut scd41 = Scd41::new(&mut i2c, NoDelay);
+        assert_eq!(scd41.perform_forced_recalibration(400).unwrap(), Some(-25));
+        assert_eq!(scd41.perform_forced_recalibration(400).unwrap(), None);
+        let data = 400_u16.to_be_bytes();
+        assert_eq!(i2c.writes[0], vec![0x36, 0x2f, data[0], data[1], crc8::calculate(&data)]);
+    }
+
+    #[test]
+    fn commands_wait_on_the_delay() {
+        let mut i2c = MockI2c::default();
+        i2c.reads.push_back(words(&[0x0000]));
+        let mut delay = RecordingDelay::default();
+
+        let mut scd41 = Scd41::new(&mut i2c, &mut delay);
+        assert_eq!(scd41.perform_self_test().unwrap(), 0);
+        scd41.stop_periodic_measurement(SCD41.stop_delay).unwrap();
+        scd41.measure_single_shot().unwrap();
+        // delay_us goes through delay_ns in steps of at most u32::MAX ns
+        let total: u64 = delay.0.iter().map(|&ns| ns as u64).sum();
+        assert_eq!(total, (10_000 + SCD41.stop_delay.as_millis() as u64 + 5_000) * 1_000_000);
+    }
+
+    #[test]
+    fn temperature_offset_round_trips() {
+        let mut i2c = MockI2c::default();
+        i2c.reads.push_back(words(&[0x07e6]));
+
+        assert!((Scd41::new(&mut i2c, NoDelay).get_temperature_offset().unwrap() - 5.4).abs() < 0.01);
+    }
+}
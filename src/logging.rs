@@ -0,0 +1,372 @@
+//! module for the log output, a tracing subscriber filtered with RUST_LOG as usual
+//! the subscriber writes events to stderr as text, or with `--log-format json` one object per line with timestamp,
+//! level, target, message and the event's fields (e.g. the I2C trace's addr, opcode and error_kind), for journald
+//! or Loki to filter on. tracing spans (measurement cycles, sensor commands, I2C transactions) are timed and written
+//! when they close. `log` records, from this crate and its dependencies, are bridged into the subscriber with their
+//! key-value fields, so both formats and the RUST_LOG filter apply to everything alike.
+//! RUST_LOG takes comma separated `level`, `target` and `target=level` directives, the longest matching target wins.
+use std::{
+    collections::HashMap,
+    fmt::{self, Write as _},
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use clap::ValueEnum;
+use log::{
+    kv::{self, VisitSource},
+    Level, LevelFilter,
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Metadata, Subscriber,
+};
+
+use crate::json;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Format {
+    Text,
+    Json,
+}
+
+/// install the subscriber writing in `format` and bridge `log` into it
+pub(crate) fn init(format: Format) {
+    let filter = Filter::parse(&std::env::var("RUST_LOG").unwrap_or_default());
+    let max = filter.max();
+    let logger = Arc::new(Logger { format, filter, next: AtomicU64::new(0), spans: Mutex::new(HashMap::new()) });
+    if let Err(e) = tracing::subscriber::set_global_default(logger.clone()) {
+        eprintln!("failed to install the tracing subscriber: {}", e);
+    }
+    // `log` takes a static logger, there's only ever the one
+    if log::set_logger(Box::leak(Box::new(Bridge(logger)))).is_ok() {
+        log::set_max_level(max);
+    }
+}
+
+/// the RUST_LOG directives
+#[derive(Debug, PartialEq)]
+struct Filter {
+    /// level of the targets no directive names
+    default: LevelFilter,
+    /// levels by target prefix
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    /// parse RUST_LOG, errors only without directives. unknown levels are ignored.
+    fn parse(spec: &str) -> Filter {
+        let mut filter = Filter { default: LevelFilter::Error, targets: Vec::new() };
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => match level.parse() {
+                    Ok(level) => filter.targets.push((target.to_string(), level)),
+                    Err(_) => eprintln!("ignore invalid RUST_LOG directive {}", directive),
+                },
+                None => match directive.parse() {
+                    Ok(level) => filter.default = level,
+                    // a target alone logs everything of it
+                    Err(_) => filter.targets.push((directive.to_string(), LevelFilter::Trace)),
+                },
+            }
+        }
+        return filter;
+    }
+
+    fn enabled(&self, level: Level, target: &str) -> bool {
+        let matching = self.targets.iter().filter(|(t, _)| target.starts_with(t.as_str())).max_by_key(|(t, _)| t.len());
+        return level <= matching.map_or(self.default, |(_, l)| *l);
+    }
+
+    /// the most verbose level any target logs at
+    fn max(&self) -> LevelFilter {
+        return self.targets.iter().map(|(_, l)| *l).chain([self.default]).max().unwrap_or(LevelFilter::Error);
+    }
+}
+
+/// a field's value, numbers and booleans are unquoted in JSON
+enum Value {
+    Raw(String),
+    Text(String),
+}
+
+/// the message and fields of a line
+#[derive(Default)]
+struct Line {
+    message: String,
+    fields: Vec<(String, Value)>,
+}
+
+impl Line {
+    fn push(&mut self, key: &str, value: Value) {
+        match value {
+            Value::Raw(v) | Value::Text(v) if key == "message" => self.message.push_str(&v),
+            value => self.fields.push((key.to_string(), value)),
+        }
+    }
+
+    /// the fields as ` key=value`
+    fn text_fields(&self) -> String {
+        let mut out = String::new();
+        for (key, Value::Raw(value) | Value::Text(value)) in &self.fields {
+            let _ = write!(out, " {}={}", key, value);
+        }
+        return out;
+    }
+}
+
+impl Visit for Line {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.push(field.name(), Value::Text(format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field.name(), Value::Text(value.to_string()));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field.name(), Value::Raw(value.to_string()));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.push(field.name(), Value::Raw(value.to_string()));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        let value = if value.is_finite() { Value::Raw(value.to_string()) } else { Value::Text(value.to_string()) };
+        self.push(field.name(), value);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field.name(), Value::Raw(value.to_string()));
+    }
+}
+
+impl<'kvs> VisitSource<'kvs> for Line {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(b) = value.to_bool() {
+            Value::Raw(b.to_string())
+        } else if let Some(n) = value.to_i64() {
+            Value::Raw(n.to_string())
+        } else if let Some(n) = value.to_f64().filter(|n| n.is_finite()) {
+            Value::Raw(n.to_string())
+        } else {
+            Value::Text(value.to_string())
+        };
+        self.push(key.as_str(), value);
+        return Ok(());
+    }
+}
+
+fn to_log_level(level: &tracing::Level) -> Level {
+    return match *level {
+        tracing::Level::ERROR => Level::Error,
+        tracing::Level::WARN => Level::Warn,
+        tracing::Level::INFO => Level::Info,
+        tracing::Level::DEBUG => Level::Debug,
+        tracing::Level::TRACE => Level::Trace,
+    };
+}
+
+struct SpanData {
+    metadata: &'static Metadata<'static>,
+    fields: String,
+    created: Instant,
+    entered: Option<Instant>,
+    /// time spent inside the span
+    busy: Duration,
+    refs: usize,
+}
+
+/// tracing subscriber writing events and timed spans to stderr
+struct Logger {
+    format: Format,
+    filter: Filter,
+    next: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+impl Logger {
+    /// `line` as written in the format at `now`
+    fn render(&self, now: SystemTime, level: Level, target: &str, line: &Line) -> String {
+        return match self.format {
+            Format::Text => format!("[{} {:<5} {}] {}{}", humantime::format_rfc3339_seconds(now), level, target, line.message, line.text_fields()),
+            Format::Json => {
+                let mut out = format!(
+                    r#"{{"timestamp":"{}","level":{},"target":{},"message":{}"#,
+                    humantime::format_rfc3339_millis(now),
+                    json::quote(&level.as_str().to_lowercase()),
+                    json::quote(target),
+                    json::quote(&line.message)
+                );
+                for (key, value) in &line.fields {
+                    let value = match value {
+                        Value::Raw(v) => v.clone(),
+                        Value::Text(v) => json::quote(v),
+                    };
+                    let _ = write!(out, ",{}:{}", json::quote(key), value);
+                }
+                out.push('}');
+                out
+            }
+        };
+    }
+
+    fn write(&self, level: Level, target: &str, line: &Line) {
+        let rendered = self.render(SystemTime::now(), level, target, line);
+        let _ = writeln!(io::stderr().lock(), "{}", rendered);
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        return self.filter.enabled(to_log_level(metadata.level()), metadata.target());
+    }
+
+    fn max_level_hint(&self) -> Option<tracing::level_filters::LevelFilter> {
+        return Some(match self.filter.max() {
+            LevelFilter::Off => tracing::level_filters::LevelFilter::OFF,
+            LevelFilter::Error => tracing::level_filters::LevelFilter::ERROR,
+            LevelFilter::Warn => tracing::level_filters::LevelFilter::WARN,
+            LevelFilter::Info => tracing::level_filters::LevelFilter::INFO,
+            LevelFilter::Debug => tracing::level_filters::LevelFilter::DEBUG,
+            LevelFilter::Trace => tracing::level_filters::LevelFilter::TRACE,
+        });
+    }
+
+    fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+        let mut line = Line::default();
+        attrs.record(&mut line);
+        // ids must not be 0
+        let id = self.next.fetch_add(1, Ordering::Relaxed) + 1;
+        let data = SpanData {
+            metadata: attrs.metadata(),
+            fields: line.text_fields(),
+            created: Instant::now(),
+            entered: None,
+            busy: Duration::ZERO,
+            refs: 1,
+        };
+        self.spans.lock().unwrap_or_else(|e| e.into_inner()).insert(id, data);
+        return span::Id::from_u64(id);
+    }
+
+    fn record(&self, span: &span::Id, values: &span::Record<'_>) {
+        let mut line = Line::default();
+        values.record(&mut line);
+        if let Some(data) = self.spans.lock().unwrap_or_else(|e| e.into_inner()).get_mut(&span.into_u64()) {
+            data.fields.push_str(&line.text_fields());
+        }
+    }
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut line = Line::default();
+        event.record(&mut line);
+        let metadata = event.metadata();
+        self.write(to_log_level(metadata.level()), metadata.target(), &line);
+    }
+
+    fn enter(&self, span: &span::Id) {
+        if let Some(data) = self.spans.lock().unwrap_or_else(|e| e.into_inner()).get_mut(&span.into_u64()) {
+            data.entered = Some(Instant::now());
+        }
+    }
+
+    fn exit(&self, span: &span::Id) {
+        if let Some(data) = self.spans.lock().unwrap_or_else(|e| e.into_inner()).get_mut(&span.into_u64()) {
+            if let Some(entered) = data.entered.take() {
+                data.busy += entered.elapsed();
+            }
+        }
+    }
+
+    fn clone_span(&self, span: &span::Id) -> span::Id {
+        if let Some(data) = self.spans.lock().unwrap_or_else(|e| e.into_inner()).get_mut(&span.into_u64()) {
+            data.refs += 1;
+        }
+        return span.clone();
+    }
+
+    fn try_close(&self, span: span::Id) -> bool {
+        let mut spans = self.spans.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(data) = spans.get_mut(&span.into_u64()) else {
+            return false;
+        };
+        data.refs -= 1;
+        if data.refs > 0 {
+            return false;
+        }
+        let Some(data) = spans.remove(&span.into_u64()) else {
+            return false;
+        };
+        drop(spans);
+        let (name, busy_ms, total_ms) = (data.metadata.name(), data.busy.as_secs_f64() * 1000.0, data.created.elapsed().as_secs_f64() * 1000.0);
+        let mut line = Line { message: format!("{}{} took {:.3}ms", name, data.fields, busy_ms), fields: Vec::new() };
+        line.push("span", Value::Text(name.to_string()));
+        line.push("busy_ms", Value::Raw(format!("{:.3}", busy_ms)));
+        line.push("total_ms", Value::Raw(format!("{:.3}", total_ms)));
+        self.write(to_log_level(data.metadata.level()), data.metadata.target(), &line);
+        return true;
+    }
+}
+
+/// `log` logger handing the records to the subscriber
+struct Bridge(Arc<Logger>);
+
+impl log::Log for Bridge {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        return self.0.filter.enabled(metadata.level(), metadata.target());
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut line = Line { message: record.args().to_string(), fields: Vec::new() };
+        let _ = record.key_values().visit(&mut line);
+        self.0.write(record.level(), record.target(), &line);
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    #[test]
+    fn filter_directives() {
+        let filter = Filter::parse("info,raspi_scd41_exporter::bus=trace,rustls=off,h2");
+        assert!(filter.enabled(Level::Info, "raspi_scd41_exporter"));
+        assert!(!filter.enabled(Level::Debug, "raspi_scd41_exporter::mqtt"));
+        assert!(filter.enabled(Level::Trace, "raspi_scd41_exporter::bus"));
+        assert!(!filter.enabled(Level::Error, "rustls::conn"));
+        assert!(filter.enabled(Level::Trace, "h2::codec"));
+        assert_eq!(filter.max(), LevelFilter::Trace);
+        assert_eq!(Filter::parse(""), Filter { default: LevelFilter::Error, targets: Vec::new() });
+    }
+
+    #[test]
+    fn formats() {
+        let now = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let mut line = Line { message: String::from("i2c error"), fields: Vec::new() };
+        line.push("addr", Value::Text(String::from("0x62")));
+        line.push("retries", Value::Raw(String::from("3")));
+        let logger = |format| Logger { format, filter: Filter::parse(""), next: AtomicU64::new(0), spans: Mutex::new(HashMap::new()) };
+        assert_eq!(logger(Format::Text).render(now, Level::Warn, "raspi_scd41_exporter::bus", &line), "[2023-11-14T22:13:20Z WARN  raspi_scd41_exporter::bus] i2c error addr=0x62 retries=3");
+        assert_eq!(
+            logger(Format::Json).render(now, Level::Warn, "raspi_scd41_exporter::bus", &line),
+            r#"{"timestamp":"2023-11-14T22:13:20.123Z","level":"warn","target":"raspi_scd41_exporter::bus","message":"i2c error","addr":"0x62","retries":3}"#
+        );
+    }
+}
@@ -0,0 +1,440 @@
+//! module for advertising the HTTP endpoints with multicast DNS
+//! see RFC 6762 (mDNS) and RFC 6763 (DNS-SD)
+//! the exporter answers for `_prometheus-http._tcp.local` and `_scd41._tcp.local` with the port of
+//! its listener and TXT records carrying the paths, node id and sensor serial, so discovery-based
+//! scrape configs and apps find it. the socket shares port 5353 with Avahi, if it's running.
+use std::{
+    io,
+    mem,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    os::fd::{FromRawFd, OwnedFd},
+    sync::{Mutex, OnceLock},
+    thread,
+    time::Duration,
+};
+
+use crate::otlp;
+
+const GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const PORT: u16 = 5353;
+const SERVICES: [&str; 2] = ["_prometheus-http._tcp", "_scd41._tcp"];
+
+const A: u16 = 1;
+const PTR: u16 = 12;
+const TXT: u16 = 16;
+const SRV: u16 = 33;
+const ANY: u16 = 255;
+const IN: u16 = 1;
+/// the record is unique to this host, caches replace older ones
+const CACHE_FLUSH: u16 = 0x8000;
+
+/// TTL of the records tied to the host name, and of the others (RFC 6762 section 10)
+const HOST_TTL: u32 = 120;
+const OTHER_TTL: u32 = 4500;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    /// service instance name, e.g. "scd41 on raspberrypi"
+    pub(crate) name: String,
+    /// port of the HTTP listener
+    pub(crate) port: u16,
+    pub(crate) node_id: String,
+}
+
+struct Responder {
+    socket: UdpSocket,
+    config: Config,
+    /// host name without .local
+    host: String,
+    serial: Mutex<Option<String>>,
+}
+
+static RESPONDER: OnceLock<Responder> = OnceLock::new();
+
+/// answer queries from a background thread, after announcing the services
+pub(crate) fn init(config: Config) -> io::Result<()> {
+    let socket = bind()?;
+    socket.join_multicast_v4(&GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+    let host = otlp::hostname().unwrap_or(String::from("scd41")).split('.').next().unwrap_or_default().to_string();
+    log::info!("advertise {:?} port {} via mdns as {}.local", config.name, config.port, host);
+    let responder = RESPONDER.get_or_init(|| Responder { socket, config, host, serial: Mutex::new(None) });
+    thread::Builder::new().name(String::from("mdns")).spawn(move || responder.run())?;
+    announce();
+    return Ok(());
+}
+
+/// add the sensor's serial to the TXT records, announcing the change
+pub(crate) fn set_serial(serial: &str) {
+    let Some(responder) = RESPONDER.get() else {
+        return;
+    };
+    *responder.serial.lock().unwrap_or_else(|e| e.into_inner()) = Some(serial.to_string());
+    announce();
+}
+
+/// send the records unsolicited, twice a second apart
+fn announce() {
+    let _ = thread::Builder::new()
+        .name(String::from("mdns-announce"))
+        .spawn(|| {
+            let Some(responder) = RESPONDER.get() else {
+                return;
+            };
+            for i in 0..2 {
+                if i > 0 {
+                    thread::sleep(Duration::from_secs(1));
+                }
+                let records = responder.all();
+                let _ = responder.send(0, &[], &records, &[], None).inspect_err(|e| log::warn!("failed to announce via mdns: {:?}", e));
+            }
+        })
+        .inspect_err(|e| log::warn!("failed to spawn mdns thread: {:?}", e));
+}
+
+/// a UDP socket on port 5353 which other responders may bind too
+fn bind() -> io::Result<UdpSocket> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd is a freshly created socket nobody else owns
+    let owned = unsafe { OwnedFd::from_raw_fd(fd) };
+    let on: libc::c_int = 1;
+    for option in [libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+        let set = unsafe {
+            libc::setsockopt(fd, libc::SOL_SOCKET, option, &on as *const libc::c_int as *const libc::c_void, mem::size_of::<libc::c_int>() as libc::socklen_t)
+        };
+        if set != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    let addr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: PORT.to_be(),
+        sin_addr: libc::in_addr { s_addr: 0 },
+        sin_zero: [0; 8],
+    };
+    let bound = unsafe { libc::bind(fd, &addr as *const libc::sockaddr_in as *const libc::sockaddr, mem::size_of::<libc::sockaddr_in>() as libc::socklen_t) };
+    if bound != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    return Ok(UdpSocket::from(owned));
+}
+
+/// the IPv4 addresses of the up, non-loopback interfaces
+fn addresses() -> Vec<Ipv4Addr> {
+    let mut ifaddrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return Vec::new();
+    }
+    let mut addresses = Vec::new();
+    let mut current = ifaddrs;
+    while !current.is_null() {
+        // SAFETY: getifaddrs returned a valid list, freed below
+        let ifa = unsafe { &*current };
+        let up = ifa.ifa_flags & libc::IFF_UP as libc::c_uint != 0 && ifa.ifa_flags & libc::IFF_LOOPBACK as libc::c_uint == 0;
+        if up && !ifa.ifa_addr.is_null() && unsafe { (*ifa.ifa_addr).sa_family } as libc::c_int == libc::AF_INET {
+            let sin = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+            addresses.push(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)));
+        }
+        current = ifa.ifa_next;
+    }
+    unsafe { libc::freeifaddrs(ifaddrs) };
+    addresses.sort();
+    addresses.dedup();
+    return addresses;
+}
+
+/// a question's name and type
+type Question = (Vec<String>, u16);
+
+#[derive(Clone)]
+struct Record {
+    name: Vec<String>,
+    kind: u16,
+    class: u16,
+    ttl: u32,
+    data: Vec<u8>,
+}
+
+/// split a dotted name into labels
+fn labels(name: &str) -> Vec<String> {
+    return name.split('.').filter(|l| !l.is_empty()).map(String::from).collect();
+}
+
+fn same_name(a: &[String], b: &[String]) -> bool {
+    return a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.eq_ignore_ascii_case(b));
+}
+
+fn encode_name(out: &mut Vec<u8>, name: &[String]) {
+    for label in name {
+        let bytes = &label.as_bytes()[..label.len().min(63)];
+        out.push(bytes.len() as u8);
+        out.extend_from_slice(bytes);
+    }
+    out.push(0);
+}
+
+impl Responder {
+    fn instance(&self, service: &str) -> Vec<String> {
+        // the instance name is one label, dots and all
+        let mut name = vec![self.config.name.clone()];
+        name.extend(labels(service));
+        name.push(String::from("local"));
+        return name;
+    }
+
+    fn host(&self) -> Vec<String> {
+        return vec![self.host.clone(), String::from("local")];
+    }
+
+    fn txt(&self) -> Vec<u8> {
+        let mut entries = vec![
+            String::from("path=/metrics"),
+            String::from("api=/api/v1/latest"),
+            format!("node_id={}", self.config.node_id),
+            format!("version={}", env!("CARGO_PKG_VERSION")),
+        ];
+        if let Some(serial) = self.serial.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            entries.push(format!("serial={}", serial));
+        }
+        let mut data = Vec::new();
+        for entry in entries {
+            let bytes = &entry.as_bytes()[..entry.len().min(255)];
+            data.push(bytes.len() as u8);
+            data.extend_from_slice(bytes);
+        }
+        return data;
+    }
+
+    fn address_records(&self) -> Vec<Record> {
+        return addresses()
+            .into_iter()
+            .map(|a| Record { name: self.host(), kind: A, class: IN | CACHE_FLUSH, ttl: HOST_TTL, data: a.octets().to_vec() })
+            .collect();
+    }
+
+    /// PTR, SRV and TXT of a service
+    fn service_records(&self, service: &str) -> Vec<Record> {
+        let instance = self.instance(service);
+        let mut ptr = Vec::new();
+        encode_name(&mut ptr, &instance);
+        let mut enumeration = Vec::new();
+        encode_name(&mut enumeration, &[labels(service), vec![String::from("local")]].concat());
+        let mut srv = Vec::new();
+        srv.extend_from_slice(&[0, 0, 0, 0]);
+        srv.extend_from_slice(&self.config.port.to_be_bytes());
+        encode_name(&mut srv, &self.host());
+        return vec![
+            Record { name: [labels(service), vec![String::from("local")]].concat(), kind: PTR, class: IN, ttl: OTHER_TTL, data: ptr },
+            Record { name: labels("_services._dns-sd._udp.local"), kind: PTR, class: IN, ttl: OTHER_TTL, data: enumeration },
+            Record { name: instance.clone(), kind: SRV, class: IN | CACHE_FLUSH, ttl: HOST_TTL, data: srv },
+            Record { name: instance, kind: TXT, class: IN | CACHE_FLUSH, ttl: OTHER_TTL, data: self.txt() },
+        ];
+    }
+
+    /// every record, for announcements
+    fn all(&self) -> Vec<Record> {
+        let mut records: Vec<Record> = SERVICES.iter().flat_map(|s| self.service_records(s)).collect();
+        records.extend(self.address_records());
+        return records;
+    }
+
+    fn run(&self) {
+        let mut buf = [0_u8; 9000];
+        loop {
+            let (n, peer) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) => {
+                    log::warn!("failed to receive mdns query: {:?}", e);
+                    continue;
+                }
+            };
+            let _ = self.answer(&buf[..n], peer).inspect_err(|e| log::debug!("failed to answer mdns query from {}: {:?}", peer, e));
+        }
+    }
+
+    fn answer(&self, packet: &[u8], peer: SocketAddr) -> io::Result<()> {
+        let Some((id, questions)) = parse_query(packet) else {
+            return Ok(());
+        };
+        let mut answers = Vec::new();
+        let mut additional = Vec::new();
+        let candidates = self.all();
+        for (name, kind) in &questions {
+            for record in &candidates {
+                if same_name(&record.name, name) && (*kind == ANY || *kind == record.kind) {
+                    answers.push(record.clone());
+                }
+            }
+        }
+        if answers.is_empty() {
+            return Ok(());
+        }
+        // an instance PTR answer is useless without the SRV, TXT and address behind it
+        let instances: Vec<Vec<String>> = SERVICES
+            .iter()
+            .filter(|s| answers.iter().any(|a| a.kind == PTR && same_name(&a.name, &[labels(s), vec![String::from("local")]].concat())))
+            .map(|s| self.instance(s))
+            .collect();
+        if !instances.is_empty() {
+            for record in candidates.into_iter().filter(|r| r.kind == A || (r.kind != PTR && instances.iter().any(|i| same_name(i, &r.name)))) {
+                if !answers.iter().any(|a| a.kind == record.kind && same_name(&a.name, &record.name) && a.data == record.data) {
+                    additional.push(record);
+                }
+            }
+        }
+        // queries from a port other than 5353 are from legacy resolvers, answered directly
+        if peer.port() != PORT {
+            return self.send(id, &questions, &answers, &additional, Some(peer));
+        }
+        return self.send(0, &[], &answers, &additional, None);
+    }
+
+    fn send(&self, id: u16, questions: &[Question], answers: &[Record], additional: &[Record], to: Option<SocketAddr>) -> io::Result<()> {
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&id.to_be_bytes());
+        // response, authoritative
+        packet.extend_from_slice(&0x8400_u16.to_be_bytes());
+        for count in [questions.len(), answers.len(), 0, additional.len()] {
+            packet.extend_from_slice(&(count as u16).to_be_bytes());
+        }
+        for (name, kind) in questions {
+            encode_name(&mut packet, name);
+            packet.extend_from_slice(&kind.to_be_bytes());
+            packet.extend_from_slice(&IN.to_be_bytes());
+        }
+        for record in answers.iter().chain(additional) {
+            encode_name(&mut packet, &record.name);
+            packet.extend_from_slice(&record.kind.to_be_bytes());
+            // legacy unicast answers must not have the cache flush bit
+            let class = if to.is_some() { record.class & !CACHE_FLUSH } else { record.class };
+            packet.extend_from_slice(&class.to_be_bytes());
+            packet.extend_from_slice(&record.ttl.min(if to.is_some() { 10 } else { u32::MAX }).to_be_bytes());
+            packet.extend_from_slice(&(record.data.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&record.data);
+        }
+        self.socket.send_to(&packet, to.unwrap_or(SocketAddr::V4(SocketAddrV4::new(GROUP, PORT))))?;
+        return Ok(());
+    }
+}
+
+/// id and questions (name, type) of a query, None for responses and malformed packets
+fn parse_query(packet: &[u8]) -> Option<(u16, Vec<Question>)> {
+    let word = |i: usize| -> Option<u16> { Some(u16::from_be_bytes([*packet.get(i)?, *packet.get(i + 1)?])) };
+    let (id, flags, count) = (word(0)?, word(2)?, word(4)?);
+    // responses and other opcodes than query
+    if flags & 0xF800 != 0 {
+        return None;
+    }
+    let mut pos = 12;
+    let mut questions = Vec::new();
+    for _ in 0..count {
+        let (name, next) = read_name(packet, pos)?;
+        questions.push((name, word(next)?));
+        pos = next + 4;
+    }
+    return Some((id, questions));
+}
+
+/// the name at `pos` following compression pointers, and the position after it
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(Vec<String>, usize)> {
+    let mut name = Vec::new();
+    let mut end = None;
+    // pointers could loop, a name has at most 128 labels
+    for _ in 0..128 {
+        let len = *packet.get(pos)? as usize;
+        match len {
+            0 => return Some((name, end.unwrap_or(pos + 1))),
+            0xC0..=0xFF => {
+                let target = ((len & 0x3F) << 8) | *packet.get(pos + 1)? as usize;
+                end.get_or_insert(pos + 2);
+                pos = target;
+            }
+            1..=63 => {
+                name.push(String::from_utf8_lossy(packet.get(pos + 1..pos + 1 + len)?).into_owned());
+                pos += 1 + len;
+            }
+            _ => return None,
+        }
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a query header with `count` questions
+    fn header(flags: u16, count: u16) -> Vec<u8> {
+        return [0x12_u16, flags, count, 0, 0, 0].iter().flat_map(|w| w.to_be_bytes()).collect();
+    }
+
+    #[test]
+    fn names() {
+        let mut packet = header(0, 0);
+        encode_name(&mut packet, &labels("_scd41._tcp.local"));
+        assert_eq!(packet[12..], *b"\x06_scd41\x04_tcp\x05local\x00");
+        assert_eq!(read_name(&packet, 12), Some((labels("_scd41._tcp.local"), packet.len())));
+        // labels are cut at 63 bytes
+        let mut long = Vec::new();
+        encode_name(&mut long, &["x".repeat(70)]);
+        assert_eq!(long[0], 63);
+        assert_eq!(read_name(&long, 0).unwrap().0, ["x".repeat(63)]);
+        assert!(same_name(&labels("RaspberryPi.LOCAL"), &labels("raspberrypi.local")));
+        assert!(!same_name(&labels("raspberrypi"), &labels("raspberrypi.local")));
+    }
+
+    #[test]
+    fn compressed_names() {
+        // _tcp.local at 12, then _scd41 with a pointer to it
+        let mut packet = header(0, 0);
+        encode_name(&mut packet, &labels("_tcp.local"));
+        let at = packet.len();
+        packet.extend_from_slice(b"\x06_scd41\xC0\x0C");
+        assert_eq!(read_name(&packet, at), Some((labels("_scd41._tcp.local"), packet.len())));
+        // a pointer to a pointer
+        let again = packet.len();
+        packet.extend_from_slice(&[0xC0, at as u8]);
+        assert_eq!(read_name(&packet, again), Some((labels("_scd41._tcp.local"), packet.len())));
+    }
+
+    #[test]
+    fn compression_loops_end() {
+        // pointing at itself, and two names pointing at each other
+        assert_eq!(read_name(&[0xC0, 0x00], 0), None);
+        assert_eq!(read_name(&[0x01, b'a', 0xC0, 0x00], 0), None);
+        assert_eq!(read_name(&[0xC0, 0x02, 0xC0, 0x00], 0), None);
+        // pointing past the end, truncated pointers and labels, and the reserved 0x40 and 0x80 lengths
+        assert_eq!(read_name(&[0xC0, 0x10], 0), None);
+        assert_eq!(read_name(&[0xC0], 0), None);
+        assert_eq!(read_name(&[0x05, b'a', b'b'], 0), None);
+        assert_eq!(read_name(&[0x01, b'a'], 0), None);
+        assert_eq!(read_name(&[0x40, 0x00], 0), None);
+        assert_eq!(read_name(&[0x80, 0x00], 0), None);
+    }
+
+    #[test]
+    fn queries() {
+        let mut packet = header(0, 2);
+        encode_name(&mut packet, &labels("_prometheus-http._tcp.local"));
+        packet.extend_from_slice(&[0x00, 0x0C, 0x80, 0x01]);
+        packet.extend_from_slice(b"\x0braspberrypi\xC0\x22");
+        packet.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+        let (id, questions) = parse_query(&packet).unwrap();
+        assert_eq!(id, 0x12);
+        assert_eq!(questions, [(labels("_prometheus-http._tcp.local"), PTR), (labels("raspberrypi.local"), A)]);
+        // responses aren't queries
+        let mut response = packet.clone();
+        response[2] = 0x84;
+        assert_eq!(parse_query(&response), None);
+        // more questions than there are
+        let mut missing = packet.clone();
+        missing[5] = 3;
+        assert_eq!(parse_query(&missing), None);
+        for len in 0..packet.len() - 2 {
+            assert_eq!(parse_query(&packet[..len]), None, "truncated to {} bytes", len);
+        }
+    }
+}
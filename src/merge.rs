@@ -0,0 +1,103 @@
+//! module for merging redundant CO2 sensors into one logical series
+//! every sensor still exports its own series; the merged values are exported as `merged_*`.
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+
+use crate::sensor::{Envelope, Measurement};
+
+/// measurements older than this don't take part in merging
+const FRESHNESS: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum Policy {
+    /// median of all fresh sensors
+    Median,
+    /// the most recently updated sensor
+    MinLatency,
+    /// the primary sensor, failing over to the others when it isn't fresh
+    Primary,
+}
+
+struct Source {
+    at: Instant,
+    primary: bool,
+    measurement: Measurement,
+}
+
+struct Merger {
+    policy: Policy,
+    sources: Mutex<HashMap<String, Source>>,
+    co2: metrics::Gauge,
+    temperature: metrics::Gauge,
+    humidity: metrics::Gauge,
+    fresh: metrics::Gauge,
+}
+
+static MERGER: OnceLock<Merger> = OnceLock::new();
+
+/// enable merging with `policy`
+pub(crate) fn init(policy: Policy) {
+    let merger = Merger {
+        policy,
+        sources: Mutex::new(HashMap::new()),
+        co2: metrics::gauge!("merged_co2_ppm"),
+        temperature: metrics::gauge!("merged_temperature_celsius"),
+        humidity: metrics::gauge!("merged_humidity_rh"),
+        fresh: metrics::gauge!("merged_fresh_sensors"),
+    };
+    let _ = MERGER.set(merger);
+}
+
+/// hand a measurement to the merger, if merging is enabled
+pub(crate) fn submit(envelope: &Envelope, primary: bool) {
+    let Some(merger) = MERGER.get() else {
+        return;
+    };
+    let mut sources = merger.sources.lock().unwrap_or_else(|e| e.into_inner());
+    let source = Source {
+        at: envelope.instant,
+        primary,
+        measurement: envelope.measurement,
+    };
+    sources.insert(envelope.source.to_string(), source);
+
+    let fresh: Vec<&Source> = sources.values().filter(|s| s.at.elapsed() < FRESHNESS).collect();
+    merger.fresh.set(fresh.len() as f64);
+    let merged = match merger.policy {
+        Policy::Median => median(&fresh),
+        Policy::MinLatency => fresh.iter().max_by_key(|s| s.at).map(|s| s.measurement),
+        Policy::Primary => fresh
+            .iter()
+            .find(|s| s.primary)
+            .or_else(|| fresh.iter().max_by_key(|s| s.at))
+            .map(|s| s.measurement),
+    };
+    if let Some(m) = merged {
+        merger.co2.set(m.co2);
+        merger.temperature.set(m.temperature);
+        merger.humidity.set(m.humidity);
+    }
+}
+
+/// per-channel median
+fn median(sources: &[&Source]) -> Option<Measurement> {
+    fn mid(mut values: Vec<f32>) -> f32 {
+        values.sort_by(f32::total_cmp);
+        let n = values.len();
+        return if n % 2 == 1 { values[n / 2] } else { (values[n / 2 - 1] + values[n / 2]) / 2_f32 };
+    }
+
+    if sources.is_empty() {
+        return None;
+    }
+    return Some(Measurement {
+        co2: mid(sources.iter().map(|s| s.measurement.co2 as f32).collect()).round() as u16,
+        temperature: mid(sources.iter().map(|s| s.measurement.temperature).collect()),
+        humidity: mid(sources.iter().map(|s| s.measurement.humidity).collect()),
+    });
+}
@@ -0,0 +1,157 @@
+//! module for serving the latest reading over Modbus TCP
+//! see https://modbus.org/docs/Modbus_Application_Protocol_V1_1b3.pdf
+//! building-automation systems and PLCs poll the reading from holding (or input) registers, the
+//! map is the same for both and every unit id:
+//!
+//! | register | value |
+//! |---|---|
+//! | 0 | CO2 (ppm) |
+//! | 1 | temperature (0.01 degC, signed) |
+//! | 2 | humidity (0.01 %RH) |
+//! | 3 | status: 0 starting, 1 ok, 2 implausible, 3 stale, 4 failing |
+//! | 4 | seconds since the measurement, 65535 before the first one |
+//! | 5-6 | sequence number, high word first |
+//! | 7-8 | unix time of the measurement (s), high word first |
+//!
+//! the registers are read-only, writes are answered with an illegal function exception.
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+use crate::latest;
+
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+
+const ILLEGAL_FUNCTION: u8 = 0x01;
+const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const ILLEGAL_DATA_VALUE: u8 = 0x03;
+
+const REGISTERS: usize = 9;
+/// concurrent connections, each holds a thread
+const MAX_CONNECTIONS: usize = 16;
+/// connections without a request for this long are closed, masters poll far more often
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// listen on `addr` and serve from a background thread
+pub(crate) fn init(addr: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    log::info!("serve modbus tcp on {}", listener.local_addr()?);
+    thread::Builder::new().name(String::from("modbus")).spawn(move || {
+        let connections = metrics::gauge!("exporter_modbus_connections");
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => {
+                    log::warn!("failed to accept modbus connection: {:?}", e);
+                    continue;
+                }
+            };
+            if CONNECTIONS.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+                CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+                log::warn!("too many modbus connections, reject {:?}", stream.peer_addr());
+                continue;
+            }
+            connections.increment(1);
+            let gauge = connections.clone();
+            let spawned = thread::Builder::new().name(String::from("modbus-conn")).spawn(move || {
+                let _ = handle_connection(stream).inspect_err(|e| log::debug!("modbus connection error: {:?}", e));
+                CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+                gauge.decrement(1);
+            });
+            if let Err(e) = spawned {
+                log::warn!("failed to spawn modbus thread: {:?}", e);
+                CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+                connections.decrement(1);
+            }
+        }
+    })?;
+    return Ok(());
+}
+
+/// answer requests until the master disconnects
+fn handle_connection(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    stream.set_write_timeout(Some(Duration::from_secs(10)))?;
+    stream.set_nodelay(true)?;
+    let requests = metrics::counter!("exporter_modbus_requests_total");
+    loop {
+        // MBAP header: transaction id, protocol id (0), length of unit id and PDU, unit id
+        let mut header = [0_u8; 7];
+        match stream.read_exact(&mut header) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+        let protocol = u16::from_be_bytes([header[2], header[3]]);
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        if protocol != 0 || !(2..=254).contains(&length) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a modbus tcp frame"));
+        }
+        let mut pdu = vec![0_u8; length - 1];
+        stream.read_exact(&mut pdu)?;
+        requests.increment(1);
+
+        let response = respond(&pdu);
+        let mut frame = Vec::with_capacity(7 + response.len());
+        frame.extend_from_slice(&header[0..4]);
+        frame.extend_from_slice(&(response.len() as u16 + 1).to_be_bytes());
+        frame.push(header[6]);
+        frame.extend_from_slice(&response);
+        stream.write_all(&frame)?;
+    }
+}
+
+/// the response PDU to a request PDU
+fn respond(pdu: &[u8]) -> Vec<u8> {
+    let function = pdu[0];
+    let exception = |code: u8| vec![function | 0x80, code];
+    if function != READ_HOLDING_REGISTERS && function != READ_INPUT_REGISTERS {
+        return exception(ILLEGAL_FUNCTION);
+    }
+    if pdu.len() != 5 {
+        return exception(ILLEGAL_DATA_VALUE);
+    }
+    let start = u16::from_be_bytes([pdu[1], pdu[2]]) as usize;
+    let quantity = u16::from_be_bytes([pdu[3], pdu[4]]) as usize;
+    if !(1..=125).contains(&quantity) {
+        return exception(ILLEGAL_DATA_VALUE);
+    }
+    if start + quantity > REGISTERS {
+        return exception(ILLEGAL_DATA_ADDRESS);
+    }
+    let mut response = vec![function, (quantity * 2) as u8];
+    for value in &registers()[start..start + quantity] {
+        response.extend_from_slice(&value.to_be_bytes());
+    }
+    return response;
+}
+
+/// the register map from the latest reading
+fn registers() -> [u16; REGISTERS] {
+    let (envelope, _, status) = latest::get();
+    let status = status.code();
+    let Some(envelope) = envelope else {
+        return [0, 0, 0, status, u16::MAX, 0, 0, 0, 0];
+    };
+    let m = &envelope.measurement;
+    let age = envelope.instant.elapsed().as_secs().min(u16::MAX as u64) as u16;
+    let seq = envelope.seq as u32;
+    let timestamp = (envelope.timestamp_ms / 1000) as u32;
+    return [
+        m.co2,
+        (m.temperature * 100.0).round() as i16 as u16,
+        (m.humidity.clamp(0.0, 100.0) * 100.0).round() as u16,
+        status,
+        age,
+        (seq >> 16) as u16,
+        seq as u16,
+        (timestamp >> 16) as u16,
+        timestamp as u16,
+    ];
+}
@@ -0,0 +1,176 @@
+//! module for the `monitor` subcommand, a live view of the sensor in the terminal
+//! it redraws the screen with ANSI escapes on every sample: the latest values, sparklines of the
+//! recent history, min/max since start and the sensor's status. Ctrl-C stops measuring and exits.
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Instant,
+};
+
+use crate::{
+    error::{Context, Error},
+    interlock,
+    sensor::{Measurement, Sensor},
+};
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/// one quantity's recent history and extremes
+struct Series {
+    name: &'static str,
+    unit: &'static str,
+    precision: usize,
+    history: VecDeque<f32>,
+    min: f32,
+    max: f32,
+}
+
+impl Series {
+    fn new(name: &'static str, unit: &'static str, precision: usize) -> Self {
+        return Series {
+            name,
+            unit,
+            precision,
+            history: VecDeque::new(),
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+        };
+    }
+
+    fn push(&mut self, value: f32, capacity: usize) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.history.push_back(value);
+        while self.history.len() > capacity {
+            self.history.pop_front();
+        }
+    }
+
+    /// the history scaled between its own min and max
+    fn sparkline(&self) -> String {
+        let lo = self.history.iter().copied().fold(f32::INFINITY, f32::min);
+        let hi = self.history.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        return self
+            .history
+            .iter()
+            .map(|v| match hi - lo {
+                range if range > 0.0 => BARS[(((v - lo) / range) * (BARS.len() - 1) as f32).round() as usize],
+                _ => BARS[0],
+            })
+            .collect();
+    }
+
+    fn render(&self, out: &mut String) {
+        let p = self.precision;
+        let Some(last) = self.history.back() else {
+            out.push_str(&format!("{:<12} -\r\n", self.name));
+            return;
+        };
+        out.push_str(&format!(
+            "{:<12} {:>9.p$} {:<4} min {:.p$}  max {:.p$}\r\n{:<12} {}\r\n",
+            self.name,
+            last,
+            self.unit,
+            self.min,
+            self.max,
+            "",
+            self.sparkline()
+        ));
+    }
+}
+
+/// the sensor's state for the status lines
+#[derive(Default)]
+struct Status {
+    samples: u64,
+    implausible: u64,
+    errors: u64,
+    last_error: Option<String>,
+    last_sample: Option<Instant>,
+}
+
+/// terminal width in columns, 80 if unknown
+fn columns() -> usize {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 {
+        return size.ws_col as usize;
+    }
+    return 80;
+}
+
+/// show `sensor`'s measurements live until Ctrl-C
+pub(crate) fn run<S: Sensor>(mut sensor: S) -> Result<(), Error> {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    if unsafe { libc::signal(libc::SIGINT, handler) } == libc::SIG_ERR {
+        return Err(io::Error::last_os_error().into());
+    }
+    sensor.start().context("failed to start the sensor")?;
+    let started = Instant::now();
+    let mut series = [Series::new("co2", "ppm", 0), Series::new("temperature", "degC", 2), Series::new("humidity", "%RH", 2)];
+    let mut status = Status::default();
+    let mut out = io::stdout();
+    // hide the cursor while redrawing
+    write!(out, "\x1b[?25l")?;
+    while !INTERRUPTED.load(Ordering::Relaxed) {
+        match sensor.measure() {
+            Ok(Some(m)) => {
+                status.samples += 1;
+                status.last_sample = Some(Instant::now());
+                if !interlock::plausible(&m) {
+                    status.implausible += 1;
+                }
+                let capacity = columns().saturating_sub(13).max(1);
+                let Measurement { co2, temperature, humidity } = m;
+                for (s, v) in series.iter_mut().zip([co2 as f32, temperature, humidity]) {
+                    s.push(v, capacity);
+                }
+                draw(&mut out, &sensor, &series, &status, started)?;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                status.errors += 1;
+                let e: Error = e.into();
+                status.last_error = Some(e.to_string());
+                draw(&mut out, &sensor, &series, &status, started)?;
+            }
+        }
+        thread::sleep(sensor.poll_interval());
+    }
+    write!(out, "\x1b[?25h")?;
+    out.flush()?;
+    sensor.stop().context("failed to stop the sensor")?;
+    return Ok(());
+}
+
+fn draw<S: Sensor>(out: &mut impl Write, sensor: &S, series: &[Series], status: &Status, started: Instant) -> io::Result<()> {
+    // clear the screen and go home
+    let mut screen = String::from("\x1b[H\x1b[2J");
+    screen.push_str(&format!(
+        "sensor {}  up {}s  (Ctrl-C to quit)\r\n\r\n",
+        sensor.serial().unwrap_or_else(|| String::from("unknown")),
+        started.elapsed().as_secs()
+    ));
+    for s in series {
+        s.render(&mut screen);
+    }
+    let age = status.last_sample.map(|t| format!("{}s ago", t.elapsed().as_secs())).unwrap_or_else(|| String::from("never"));
+    screen.push_str(&format!(
+        "\r\nsamples {}  implausible {}  errors {}  last sample {}\r\n",
+        status.samples, status.implausible, status.errors, age
+    ));
+    if let Some(e) = &status.last_error {
+        screen.push_str(&format!("last error {}\r\n", e));
+    }
+    screen.push_str(&format!("settings {}\r\n", sensor.settings().to_json()));
+    out.write_all(screen.as_bytes())?;
+    return out.flush();
+}
+
@@ -0,0 +1,678 @@
+//! module for publishing measurements over MQTT (3.1.1)
+//! see https://docs.oasis-open.org/mqtt/mqtt/v3.1.1/mqtt-v3.1.1.html
+//! measurements are handed to a task which keeps the connection, reconnecting with
+//! backoff until a shutdown. `<topic>/status` is `online` while connected and `offline` as the last will, or with the zigbee2mqtt
+//! format `<topic>/availability` is `{"state":"online"}` and `{"state":"offline"}` like zigbee2mqtt's devices.
+//! with QoS 1 a message is kept until the broker acknowledged it, so it's delivered at least once.
+//! with discovery enabled, retained Home Assistant discovery configs are published on every connect, and so are the
+//! units and ranges of the values on `<topic>/meta` with --mqtt-metadata.
+//! with --mqtt-buffer-dir the messages queued while the broker is unreachable are spooled to disk and
+//! published in order after reconnecting, before the new ones.
+//! --mqtt-cloud follows the conventions of AWS IoT Core and Azure IoT Hub so the exporter publishes to them
+//! directly, authenticated with a client certificate or, on Azure, a SAS token signed with the device key. Azure
+//! only takes telemetry on `devices/<device id>/messages/events/`, so there's no status topic, will or discovery, and
+//! tokens are renewed by reconnecting before they expire.
+use std::{
+    io,
+    path::Path,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    http::{self, Stream, Url},
+    json,
+    sensor::{self, Envelope},
+    shutdown::Pending,
+    spool::{self, Delivery, Spool},
+    tasks,
+};
+
+/// (scheme, tls, default port) of broker urls
+const SCHEMES: &[(&str, bool, u16)] = &[("mqtt", false, 1883), ("mqtts", true, 8883)];
+
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// how long Azure IoT Hub SAS tokens are valid, the connection is renewed a few minutes before
+const SAS_TTL: Duration = Duration::from_secs(3600);
+
+/// the ALPN protocol AWS IoT Core needs for MQTT on port 443
+const AWS_ALPN: &str = "x-amzn-mqtt-ca";
+
+/// measurements waiting while the broker is unreachable; newer ones are dropped beyond this
+const QUEUE: usize = 1000;
+
+pub(crate) fn parse_url(s: &str) -> Result<Url, String> {
+    return http::parse_url_with(s, SCHEMES);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Format {
+    /// the measurement as one JSON object on the topic
+    Json,
+    /// each value on its own subtopic (`<topic>/co2`, ...)
+    Values,
+    /// both
+    Both,
+    /// a zigbee2mqtt device state on the topic, `{"state":"online"}` on `<topic>/availability`,
+    /// so automations written for zigbee2mqtt sensors take it as is
+    Zigbee2mqtt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Cloud {
+    /// AWS IoT Core: client certificate, x-amzn-mqtt-ca ALPN on port 443
+    AwsIot,
+    /// Azure IoT Hub: the device's telemetry topic, client certificate or SAS token with the device key
+    AzureIotHub,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) url: Url,
+    /// `{node_id}` is replaced with the node id
+    pub(crate) topic: String,
+    pub(crate) node_id: String,
+    pub(crate) client_id: String,
+    pub(crate) qos: u8,
+    pub(crate) retain: bool,
+    pub(crate) format: Format,
+    /// add the channels' units and ranges to JSON payloads and publish them retained on `<topic>/meta`
+    pub(crate) metadata: bool,
+    /// topic prefix of Home Assistant discovery, None disables discovery
+    pub(crate) discovery: Option<String>,
+    pub(crate) device_name: String,
+    /// where messages wait while the broker is unreachable, in the queue if None
+    pub(crate) buffer: Option<spool::Config>,
+    /// TLS with a client certificate, ALPN or other roots, the system's roots if None
+    pub(crate) tls: Option<Arc<rustls::ClientConfig>>,
+    /// TLS server name to send and verify instead of the url's host
+    pub(crate) server_name: Option<String>,
+    pub(crate) cloud: Option<Cloud>,
+    /// Azure IoT Hub device key SAS tokens are signed with
+    pub(crate) sas_key: Option<Vec<u8>>,
+}
+
+impl Config {
+    /// topic of the measurements
+    fn topic(&self) -> String {
+        if self.cloud == Some(Cloud::AzureIotHub) {
+            // the property bag tells the hub the body is JSON, so routes can query it
+            return format!("devices/{}/messages/events/$.ct=application%2Fjson&$.ce=utf-8", self.client_id);
+        }
+        return self.topic.replace("{node_id}", &self.node_id);
+    }
+
+    /// topic of `online` and the `offline` will, None on Azure which takes neither
+    fn status(&self) -> Option<String> {
+        if self.cloud == Some(Cloud::AzureIotHub) {
+            return None;
+        }
+        if self.format == Format::Zigbee2mqtt {
+            return Some(format!("{}/availability", self.topic()));
+        }
+        return Some(format!("{}/status", self.topic()));
+    }
+
+    /// the online payload and the offline will on the status topic
+    fn availability(&self) -> (&'static [u8], &'static [u8]) {
+        return match self.format {
+            Format::Zigbee2mqtt => (br#"{"state":"online"}"#, br#"{"state":"offline"}"#),
+            _ => (b"online", b"offline"),
+        };
+    }
+}
+
+/// TLS configuration of `url` with the client certificate and key, the roots in `ca` and the ALPN protocol, None if
+/// the default does. AWS IoT Core on port 443 gets its ALPN protocol unless another is given.
+pub(crate) fn tls_config(url: &Url, cloud: Option<Cloud>, client: Option<(&Path, &Path)>, ca: Option<&Path>, alpn: Option<&str>) -> io::Result<Option<Arc<rustls::ClientConfig>>> {
+    let alpn = alpn.or((cloud == Some(Cloud::AwsIot) && url.port == 443).then_some(AWS_ALPN));
+    if !url.tls || (client.is_none() && ca.is_none() && alpn.is_none()) {
+        return Ok(None);
+    }
+    let alpn: Vec<String> = alpn.into_iter().map(String::from).collect();
+    return http::client_tls_config(client, ca, &alpn).map(Some);
+}
+
+/// an Azure IoT Hub SAS token for the device `client_id` on `host`, valid until `expiry` (unix seconds)
+fn sas_token(host: &str, client_id: &str, key: &[u8], expiry: u64) -> String {
+    let resource = http::percent_encode(&format!("{}/devices/{}", host, client_id));
+    let signature = ring::hmac::sign(&ring::hmac::Key::new(ring::hmac::HMAC_SHA256, key), format!("{}\n{}", resource, expiry).as_bytes());
+    return format!("SharedAccessSignature sr={}&sig={}&se={}", resource, http::percent_encode(&http::base64(signature.as_ref())), expiry);
+}
+
+struct Message {
+    topic: String,
+    payload: Vec<u8>,
+    retain: bool,
+}
+
+struct Output {
+    config: Config,
+    queue: SyncSender<Message>,
+    dropped: metrics::Counter,
+}
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+static PENDING: Pending = Pending::new("mqtt");
+
+/// connect to the broker in a task
+pub(crate) fn init(mut config: Config) -> io::Result<()> {
+    if config.cloud == Some(Cloud::AzureIotHub) {
+        if config.format != Format::Json {
+            log::warn!("Azure IoT Hub takes measurements as JSON only, ignore --mqtt-format");
+            config.format = Format::Json;
+        }
+        if config.discovery.take().is_some() {
+            log::warn!("Azure IoT Hub doesn't relay Home Assistant discovery, ignore --mqtt-discovery");
+        }
+    }
+    let dropped = || metrics::counter!("exporter_mqtt_dropped_total");
+    let spool = config
+        .buffer
+        .clone()
+        .map(|buffer| Spool::new("mqtt", "mqtt", buffer, dropped(), |payload| decode(payload).map_or(1, |m| m.len() as u64)))
+        .transpose()?;
+    let (tx, rx) = mpsc::sync_channel(QUEUE);
+    let client = config.clone();
+    tasks::spawn("mqtt", run(client, rx, spool));
+    let output = Output { config, queue: tx, dropped: dropped() };
+    let _ = OUTPUT.set(output);
+    PENDING.register();
+    return Ok(());
+}
+
+/// publish a measurement, if MQTT is enabled
+pub(crate) fn publish(envelope: &Envelope) {
+    let Some(output) = OUTPUT.get() else {
+        return;
+    };
+    for (topic, payload) in messages(&output.config, envelope) {
+        let message = Message { topic, payload: payload.into_bytes(), retain: output.config.retain };
+        PENDING.add(1);
+        if output.queue.try_send(message).is_err() {
+            PENDING.done(1);
+            output.dropped.increment(1);
+        }
+    }
+}
+
+/// the (topic, payload) of `envelope` in the configured format
+fn messages(config: &Config, envelope: &Envelope) -> Vec<(String, String)> {
+    let topic = config.topic();
+    let mut messages = Vec::new();
+    let m = &envelope.measurement;
+    match config.format {
+        Format::Zigbee2mqtt => messages.push((topic.clone(), format!(r#"{{"co2":{},"humidity":{:.2},"temperature":{:.2}}}"#, m.co2, m.humidity, m.temperature))),
+        Format::Json | Format::Both if config.metadata => messages.push((topic.clone(), envelope.to_json_with_metadata())),
+        Format::Json | Format::Both => messages.push((topic.clone(), envelope.to_json())),
+        Format::Values => {}
+    }
+    if matches!(config.format, Format::Values | Format::Both) {
+        messages.push((format!("{}/co2", topic), m.co2.to_string()));
+        messages.push((format!("{}/temperature", topic), format!("{:.2}", m.temperature)));
+        messages.push((format!("{}/humidity", topic), format!("{:.2}", m.humidity)));
+    }
+    return messages;
+}
+
+/// messages as written to the spool: the retain flag, the topic and the length-prefixed payload of each
+fn encode(messages: &[Message]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for m in messages {
+        out.push(m.retain as u8);
+        string(&mut out, m.topic.as_bytes());
+        out.extend_from_slice(&(m.payload.len() as u32).to_be_bytes());
+        out.extend_from_slice(&m.payload);
+    }
+    return out;
+}
+
+/// the messages of a spooled request, None if it's malformed
+fn decode(mut b: &[u8]) -> Option<Vec<Message>> {
+    let mut messages = Vec::new();
+    while let Some((&retain, rest)) = b.split_first() {
+        let (len, rest) = rest.split_at_checked(2)?;
+        let (topic, rest) = rest.split_at_checked(u16::from_be_bytes([len[0], len[1]]) as usize)?;
+        let (len, rest) = rest.split_at_checked(4)?;
+        let (payload, rest) = rest.split_at_checked(u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize)?;
+        let topic = String::from_utf8(topic.to_vec()).ok()?;
+        messages.push(Message { topic, payload: payload.to_vec(), retain: retain != 0 });
+        b = rest;
+    }
+    return Some(messages);
+}
+
+/// move the queued messages and the one that failed to the spool
+fn spill(spool: &mut Spool, rx: &Receiver<Message>, pending: &mut Option<Message>) {
+    let messages: Vec<Message> = pending.take().into_iter().chain(rx.try_iter()).collect();
+    if messages.is_empty() {
+        return;
+    }
+    spool.push(&encode(&messages));
+    PENDING.done(messages.len());
+}
+
+async fn run(config: Config, rx: Receiver<Message>, mut spool: Option<Spool>) {
+    let status = config.status();
+    let published = metrics::counter!("exporter_mqtt_published_total");
+    let connected = metrics::gauge!("exporter_mqtt_connected");
+    let mut backoff = Duration::from_secs(1);
+    let mut pending: Option<Message> = None;
+    loop {
+        let mut session = match tasks::blocking(|| Session::connect(&config, status.as_deref())) {
+            Ok(s) => {
+                log::info!("connected to mqtt broker {}:{}", config.url.host, config.url.port);
+                backoff = Duration::from_secs(1);
+                connected.set(1);
+                s
+            }
+            Err(e) => {
+                log::warn!("failed to connect to mqtt broker: {:?}, retry in {:?}", e, backoff);
+                if let Some(spool) = spool.as_mut() {
+                    spill(spool, &rx, &mut pending);
+                }
+                if !tasks::sleep(backoff).await {
+                    return;
+                }
+                backoff = (backoff * 2).min(Duration::from_secs(60));
+                continue;
+            }
+        };
+        // Ok(true) to reconnect with a new token
+        let result = tasks::blocking(|| -> io::Result<bool> {
+            if let Some(status) = &status {
+                session.publish(status, config.availability().0, 1, true)?;
+            }
+            // Azure would take it for telemetry
+            if config.metadata && status.is_some() {
+                session.publish(&format!("{}/meta", config.topic()), sensor::metadata().as_bytes(), 1, true)?;
+            }
+            if let (Some(prefix), Some(status)) = (&config.discovery, &status) {
+                for (topic, payload) in discovery(&config, prefix, status) {
+                    session.publish(&topic, payload.as_bytes(), 1, true)?;
+                }
+            }
+            if let Some(spool) = spool.as_mut() {
+                let mut failed = None;
+                let drained = spool.flush(|payload| {
+                    let Some(messages) = decode(payload) else {
+                        log::warn!("drop a malformed buffered mqtt request");
+                        return Delivery::Rejected;
+                    };
+                    for m in messages {
+                        // the whole request is published again, so some messages may arrive twice
+                        if let Err(e) = session.publish(&m.topic, &m.payload, config.qos, m.retain) {
+                            failed = Some(e);
+                            return Delivery::Retry;
+                        }
+                        published.increment(1);
+                    }
+                    return Delivery::Sent;
+                });
+                if let Some(e) = failed.filter(|_| !drained) {
+                    return Err(e);
+                }
+            }
+            loop {
+                if session.expires.is_some_and(|t| Instant::now() >= t) {
+                    session.disconnect();
+                    return Ok(true);
+                }
+                let message = match pending.take() {
+                    Some(m) => m,
+                    None => match rx.recv_timeout(KEEP_ALIVE / 2) {
+                        Ok(m) => m,
+                        Err(RecvTimeoutError::Timeout) => {
+                            session.ping()?;
+                            continue;
+                        }
+                        Err(RecvTimeoutError::Disconnected) => return Ok(false),
+                    },
+                };
+                if let Err(e) = session.publish(&message.topic, &message.payload, config.qos, message.retain) {
+                    // QoS 0 is fire and forget, but a broken connection would lose the message anyway
+                    pending = Some(message);
+                    return Err(e);
+                }
+                published.increment(1);
+                PENDING.done(1);
+            }
+        });
+        connected.set(0);
+        match result {
+            Ok(false) => return,
+            Ok(true) => log::info!("reconnect to the mqtt broker with a new SAS token"),
+            Err(e) => log::warn!("lost mqtt connection: {:?}", e),
+        }
+    }
+}
+
+/// (field, name, device class, unit) of the entities announced to Home Assistant
+const ENTITIES: [(&str, &str, &str, &str); 3] = [
+    ("co2", "CO2", "carbon_dioxide", "ppm"),
+    ("temperature", "Temperature", "temperature", "°C"),
+    ("humidity", "Humidity", "humidity", "%"),
+];
+
+/// Home Assistant discovery configs as (topic, payload)
+fn discovery(config: &Config, prefix: &str, status: &str) -> Vec<(String, String)> {
+    let topic = config.topic();
+    let device = format!(
+        r#"{{"identifiers":[{}],"name":{},"manufacturer":"Sensirion","model":"SCD4x","sw_version":{}}}"#,
+        json::quote(&config.node_id),
+        json::quote(&config.device_name),
+        json::quote(env!("CARGO_PKG_VERSION"))
+    );
+    return ENTITIES
+        .iter()
+        .map(|(field, name, class, unit)| {
+            // prefer the value topics, which don't need a template
+            let state = match config.format {
+                Format::Json | Format::Zigbee2mqtt => format!(r#""state_topic":{},"value_template":"{{{{ value_json.{} }}}}""#, json::quote(&topic), field),
+                Format::Values | Format::Both => format!(r#""state_topic":{}"#, json::quote(&format!("{}/{}", topic, field))),
+            };
+            let availability = match config.format {
+                Format::Zigbee2mqtt => r#","availability_template":"{{ value_json.state }}""#,
+                _ => "",
+            };
+            let payload = format!(
+                r#"{{"name":{},"unique_id":{},"object_id":{},{},"device_class":"{}","unit_of_measurement":"{}","state_class":"measurement","availability_topic":{}{},"device":{}}}"#,
+                json::quote(name),
+                json::quote(&format!("{}_{}", config.node_id, field)),
+                json::quote(&format!("{}_{}", config.device_name.to_lowercase().replace(' ', "_"), field)),
+                state,
+                class,
+                unit,
+                json::quote(status),
+                availability,
+                device
+            );
+            let topic = format!("{}/sensor/{}/{}/config", prefix, config.node_id, field);
+            return (topic, payload);
+        })
+        .collect();
+}
+
+struct Session {
+    stream: Box<dyn Stream>,
+    next_id: u16,
+    /// when to reconnect before the SAS token expires
+    expires: Option<Instant>,
+}
+
+impl Session {
+    fn connect(config: &Config, status: Option<&str>) -> io::Result<Session> {
+        let timeout = Duration::from_secs(10);
+        let mut stream = match &config.tls {
+            Some(tls) => http::connect_tls(&config.url, timeout, tls.clone(), config.server_name.as_deref())?,
+            None => http::connect(&config.url, timeout)?,
+        };
+        let mut expires = None;
+        let (user, password) = match config.cloud {
+            Some(Cloud::AzureIotHub) => {
+                let host = config.server_name.as_deref().unwrap_or(&config.url.host);
+                let user = format!("{}/{}/?api-version=2021-04-12", host, config.client_id);
+                // without a key the client certificate authenticates the device
+                let password = config.sas_key.as_deref().map(|key| {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                    expires = Some(Instant::now() + SAS_TTL - Duration::from_secs(300));
+                    return sas_token(host, &config.client_id, key, (now + SAS_TTL).as_secs());
+                });
+                (Some(user), password)
+            }
+            _ => match config.url.userinfo.as_deref().map(|u| u.split_once(':').unwrap_or((u, ""))) {
+                Some((u, p)) => (Some(u.to_string()), Some(p.to_string())),
+                None => (None, None),
+            },
+        };
+
+        let mut body = Vec::new();
+        string(&mut body, b"MQTT");
+        // protocol level 4 (3.1.1)
+        body.push(4);
+        // clean session, will (QoS 1, retained)
+        let mut flags = if status.is_some() { 0x02 | 0x04 | 0x08 | 0x20 } else { 0x02 };
+        if user.is_some() {
+            flags |= 0x80;
+        }
+        if password.as_ref().is_some_and(|p| !p.is_empty()) {
+            flags |= 0x40;
+        }
+        body.push(flags);
+        body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+        string(&mut body, config.client_id.as_bytes());
+        if let Some(status) = status {
+            string(&mut body, status.as_bytes());
+            string(&mut body, config.availability().1);
+        }
+        if let Some(user) = user {
+            string(&mut body, user.as_bytes());
+        }
+        if let Some(password) = password.filter(|p| !p.is_empty()) {
+            string(&mut body, password.as_bytes());
+        }
+        write_packet(&mut *stream, 0x10, &body)?;
+
+        let (kind, body) = read_packet(&mut *stream)?;
+        if kind >> 4 != 2 || body.len() < 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "expected CONNACK"));
+        }
+        if body[1] != 0 {
+            let reason = match body[1] {
+                1 => "unacceptable protocol version",
+                2 => "client id rejected",
+                3 => "server unavailable",
+                4 => "bad user name or password",
+                5 => "not authorized",
+                _ => "unknown reason",
+            };
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("connection refused: {}", reason)));
+        }
+        return Ok(Session { stream, next_id: 1, expires });
+    }
+
+    fn publish(&mut self, topic: &str, payload: &[u8], qos: u8, retain: bool) -> io::Result<()> {
+        let mut body = Vec::with_capacity(topic.len() + payload.len() + 4);
+        string(&mut body, topic.as_bytes());
+        let id = self.next_id;
+        if qos > 0 {
+            self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+            body.extend_from_slice(&id.to_be_bytes());
+        }
+        body.extend_from_slice(payload);
+        write_packet(&mut *self.stream, 0x30 | qos << 1 | retain as u8, &body)?;
+        if qos > 0 {
+            // wait for PUBACK, skipping anything else
+            loop {
+                let (kind, body) = read_packet(&mut *self.stream)?;
+                if kind >> 4 == 4 && body.get(..2) == Some(&id.to_be_bytes()[..]) {
+                    break;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// close the connection cleanly, so the will isn't published
+    fn disconnect(&mut self) {
+        let _ = write_packet(&mut *self.stream, 0xE0, &[]);
+    }
+
+    fn ping(&mut self) -> io::Result<()> {
+        write_packet(&mut *self.stream, 0xC0, &[])?;
+        loop {
+            let (kind, _) = read_packet(&mut *self.stream)?;
+            if kind >> 4 == 13 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// length-prefixed string
+fn string(out: &mut Vec<u8>, s: &[u8]) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s);
+}
+
+fn write_packet(stream: &mut dyn Stream, header: u8, body: &[u8]) -> io::Result<()> {
+    let mut packet = vec![header];
+    let mut n = body.len();
+    loop {
+        let mut b = (n % 128) as u8;
+        n /= 128;
+        if n > 0 {
+            b |= 0x80;
+        }
+        packet.push(b);
+        if n == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    stream.write_all(&packet)?;
+    return stream.flush();
+}
+
+fn read_packet(stream: &mut dyn Stream) -> io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0; 1];
+    stream.read_exact(&mut byte)?;
+    let header = byte[0];
+    let mut length = 0_usize;
+    for shift in (0..28).step_by(7) {
+        stream.read_exact(&mut byte)?;
+        length |= ((byte[0] & 0x7F) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    // the remaining length takes 4 bytes at most
+    if byte[0] & 0x80 != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed remaining length"));
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body)?;
+    return Ok((header, body));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        clock::FakeClock,
+        sensor::{Measurement, Sequencer},
+    };
+
+    #[test]
+    fn remaining_length() {
+        for (len, encoded) in [(0, &[0x00][..]), (127, &[0x7F]), (128, &[0x80, 0x01]), (16383, &[0xFF, 0x7F]), (16384, &[0x80, 0x80, 0x01])] {
+            let body = vec![0xA5; len];
+            let mut stream = Cursor::new(Vec::new());
+            write_packet(&mut stream, 0x30, &body).unwrap();
+            let packet = stream.into_inner();
+            assert_eq!(packet[0], 0x30);
+            assert_eq!(&packet[1..1 + encoded.len()], encoded, "length {}", len);
+            assert_eq!(packet.len(), 1 + encoded.len() + len);
+
+            let (header, read) = read_packet(&mut Cursor::new(packet)).unwrap();
+            assert_eq!(header, 0x30);
+            assert_eq!(read, body);
+        }
+    }
+
+    #[test]
+    fn malformed_remaining_length() {
+        let e = read_packet(&mut Cursor::new(vec![0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0x01])).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        let e = read_packet(&mut Cursor::new(vec![0x30, 0x05, 0x00])).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn spool_round_trip() {
+        let messages = vec![
+            Message { topic: String::from("scd41/kitchen"), payload: br#"{"co2":420}"#.to_vec(), retain: true },
+            Message { topic: String::from("scd41/kitchen/co2"), payload: Vec::new(), retain: false },
+            Message { topic: "ü".repeat(200), payload: vec![0; 70000], retain: false },
+        ];
+        let encoded = encode(&messages);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(decoded.len(), messages.len());
+        for (d, m) in decoded.iter().zip(&messages) {
+            assert_eq!((&d.topic, &d.payload, d.retain), (&m.topic, &m.payload, m.retain));
+        }
+        assert!(decode(&[]).unwrap().is_empty());
+        // cut between messages it holds the ones before, cut anywhere else it's malformed
+        let ends: Vec<usize> = (1..messages.len()).map(|n| encode(&messages[..n]).len()).collect();
+        for len in 1..encoded.len() {
+            match decode(&encoded[..len]) {
+                Some(d) => assert_eq!(ends.iter().position(|&e| e == len), Some(d.len() - 1)),
+                None => assert!(!ends.contains(&len)),
+            }
+        }
+    }
+
+    fn with_format(format: Format) -> Config {
+        return Config {
+            url: parse_url("mqtt://broker").unwrap(),
+            topic: String::from("zigbee2mqtt/{node_id}"),
+            node_id: String::from("livingroom"),
+            client_id: String::from("scd41-exporter"),
+            qos: 1,
+            retain: false,
+            format,
+            metadata: false,
+            discovery: Some(String::from("homeassistant")),
+            device_name: String::from("Living Room"),
+            buffer: None,
+            tls: None,
+            server_name: None,
+            cloud: None,
+            sas_key: None,
+        };
+    }
+
+    #[test]
+    fn zigbee2mqtt_payloads() {
+        let clock = FakeClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let envelope = Sequencer::new("scd41", Duration::from_secs(5)).wrap(Measurement { co2: 812, temperature: 21.456, humidity: 40.0 }, &clock);
+        let config = with_format(Format::Zigbee2mqtt);
+        let expected = (String::from("zigbee2mqtt/livingroom"), String::from(r#"{"co2":812,"humidity":40.00,"temperature":21.46}"#));
+        assert_eq!(messages(&config, &envelope), [expected]);
+        assert_eq!(config.status().as_deref(), Some("zigbee2mqtt/livingroom/availability"));
+        assert_eq!(config.availability(), (&br#"{"state":"online"}"#[..], &br#"{"state":"offline"}"#[..]));
+        let status = config.status().unwrap();
+        let (topic, payload) = &discovery(&config, "homeassistant", &status)[0];
+        assert_eq!(topic, "homeassistant/sensor/livingroom/co2/config");
+        assert!(payload.contains(r#""availability_topic":"zigbee2mqtt/livingroom/availability","availability_template":"{{ value_json.state }}""#), "{}", payload);
+
+        let values = with_format(Format::Values);
+        assert_eq!(values.status().as_deref(), Some("zigbee2mqtt/livingroom/status"));
+        assert_eq!(messages(&values, &envelope).len(), 3);
+        assert!(!discovery(&values, "homeassistant", "x/status")[0].1.contains("availability_template"));
+    }
+
+    #[test]
+    fn metadata_payloads() {
+        let clock = FakeClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let envelope = Sequencer::new("scd41", Duration::from_secs(5)).wrap(Measurement { co2: 812, temperature: 21.456, humidity: 40.0 }, &clock);
+        let mut config = with_format(Format::Both);
+        config.metadata = true;
+        let payloads = messages(&config, &envelope);
+        let meta = r#""meta":{"co2":{"unit":"ppm","min":300,"max":40000},"temperature":{"unit":"Cel","min":-10,"max":60},"humidity":{"unit":"%RH","min":0,"max":100}}}"#;
+        assert!(payloads[0].1.starts_with(r#"{"seq":1,"#) && payloads[0].1.ends_with(&format!(r#""humidity":40.00,{}"#, meta)), "{}", payloads[0].1);
+        assert!(json::parse(&payloads[0].1).is_ok());
+        assert_eq!(payloads[1], (String::from("zigbee2mqtt/livingroom/co2"), String::from("812")));
+        // zigbee2mqtt's schema stays as it is
+        config.format = Format::Zigbee2mqtt;
+        assert!(!messages(&config, &envelope)[0].1.contains("meta"));
+    }
+}
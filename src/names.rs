@@ -0,0 +1,295 @@
+//! module for renaming exported metrics
+//! the recorder is wrapped so every metric, whichever module registers it, goes through the same renaming.
+//! during a migration period renamed metrics are also updated under their old names, which are no longer
+//! rendered once it ends. until then scrapes returning old names are counted in
+//! `exporter_legacy_metric_scrapes_total` and logged, to tell what still reads them.
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, OnceLock},
+    time::Instant,
+};
+
+use metrics::{Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString, Unit};
+use metrics_exporter_prometheus::PrometheusHandle;
+
+use crate::exposition;
+
+/// prefix of the CO2 sensor's series
+const DEFAULT_PREFIX: &str = "scd41_";
+
+/// parse a `OLD=NEW` metric name override
+pub(crate) fn parse_override(s: &str) -> Result<(String, String), String> {
+    let (old, new) = s.split_once('=').ok_or("expected OLD=NEW")?;
+    let valid = |n: &str| !n.is_empty() && n.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':');
+    if !valid(old) || !valid(new) {
+        return Err(format!("invalid metric name in {}", s));
+    }
+    return Ok((old.to_string(), new.to_string()));
+}
+
+/// the old names registered for the migration
+struct Legacy {
+    until: Instant,
+    /// with whether a scrape returning it was logged
+    names: Mutex<BTreeMap<String, bool>>,
+}
+
+impl Legacy {
+    fn register(&self, name: &str) {
+        self.names.lock().unwrap_or_else(|e| e.into_inner()).entry(name.to_string()).or_default();
+    }
+
+    /// `text` without the old names once the migration ended at `now`
+    fn strip(&self, text: String, now: Instant) -> String {
+        if now < self.until {
+            return text;
+        }
+        let names: Vec<String> = self.names.lock().unwrap_or_else(|e| e.into_inner()).keys().cloned().collect();
+        return exposition::without(&text, &names);
+    }
+
+    /// count the old names in the scraped `text` while migrating at `now`
+    fn count(&self, text: &str, now: Instant) {
+        if now >= self.until {
+            return;
+        }
+        let mut names = self.names.lock().unwrap_or_else(|e| e.into_inner());
+        for (name, logged) in names.iter_mut() {
+            if !text.lines().any(|l| l.split(['{', ' ']).next() == Some(name.as_str())) {
+                continue;
+            }
+            metrics::counter!("exporter_legacy_metric_scrapes_total", "name" => name.clone()).increment(1);
+            match std::mem::replace(logged, true) {
+                false => log::info!("a scrape still returns the old name {}, exported for another {}", name, humantime::format_duration(self.until - now)),
+                true => log::debug!("a scrape returns the old name {}", name),
+            }
+        }
+    }
+}
+
+static LEGACY: OnceLock<Legacy> = OnceLock::new();
+
+/// the exposition of `handle`, without the old names once the migration ended
+pub(crate) fn render(handle: &PrometheusHandle) -> String {
+    let text = handle.render();
+    return match LEGACY.get() {
+        Some(legacy) => legacy.strip(text, Instant::now()),
+        None => text,
+    };
+}
+
+/// the exposition of `handle` for a scrape of /metrics, counting the old names it returns
+pub(crate) fn scrape(handle: &PrometheusHandle) -> String {
+    let text = render(handle);
+    if let Some(legacy) = LEGACY.get() {
+        legacy.count(&text, Instant::now());
+    }
+    return text;
+}
+
+/// recorder which renames metrics before passing them to `inner`
+pub(crate) struct Renamer<R> {
+    inner: R,
+    prefix: Option<String>,
+    /// default name to new name, applied instead of the prefix
+    overrides: HashMap<String, String>,
+    /// keep updating the default names too until then
+    migrate_until: Option<Instant>,
+}
+
+/// handle updating a renamed metric and, during the migration period, its old name
+struct Mirror<T> {
+    new: T,
+    old: T,
+    until: Instant,
+}
+
+impl<T> Mirror<T> {
+    fn old(&self) -> Option<&T> {
+        return Some(&self.old).filter(|_| Instant::now() < self.until);
+    }
+}
+
+impl CounterFn for Mirror<Counter> {
+    fn increment(&self, value: u64) {
+        self.new.increment(value);
+        self.old().inspect(|c| c.increment(value));
+    }
+
+    fn absolute(&self, value: u64) {
+        self.new.absolute(value);
+        self.old().inspect(|c| c.absolute(value));
+    }
+}
+
+impl GaugeFn for Mirror<Gauge> {
+    fn increment(&self, value: f64) {
+        self.new.increment(value);
+        self.old().inspect(|g| g.increment(value));
+    }
+
+    fn decrement(&self, value: f64) {
+        self.new.decrement(value);
+        self.old().inspect(|g| g.decrement(value));
+    }
+
+    fn set(&self, value: f64) {
+        self.new.set(value);
+        self.old().inspect(|g| g.set(value));
+    }
+}
+
+impl HistogramFn for Mirror<Histogram> {
+    fn record(&self, value: f64) {
+        self.new.record(value);
+        self.old().inspect(|h| h.record(value));
+    }
+}
+
+impl<R> Renamer<R> {
+    pub(crate) fn new(inner: R, prefix: Option<String>, overrides: Vec<(String, String)>, migrate_until: Option<Instant>) -> Self {
+        if let Some(until) = migrate_until {
+            let _ = LEGACY.set(Legacy { until, names: Mutex::new(BTreeMap::new()) });
+        }
+        return Renamer {
+            inner,
+            prefix,
+            overrides: overrides.into_iter().collect(),
+            migrate_until,
+        };
+    }
+
+    fn rename(&self, name: &str) -> Option<String> {
+        if let Some(new) = self.overrides.get(name) {
+            return Some(new.clone());
+        }
+        let prefix = self.prefix.as_ref()?;
+        return name.strip_prefix(DEFAULT_PREFIX).map(|rest| format!("{}{}", prefix, rest));
+    }
+
+    /// the exported name of the metric registered as `name`
+    pub(crate) fn name(&self, name: &str) -> String {
+        return self.rename(name).unwrap_or_else(|| name.to_string());
+    }
+
+    fn key_name(&self, name: KeyName) -> KeyName {
+        return match self.rename(name.as_str()) {
+            Some(new) => KeyName::from(new),
+            None => name,
+        };
+    }
+
+    fn key(&self, key: &Key) -> Key {
+        return match self.rename(key.name()) {
+            Some(new) => Key::from_parts(new, key.labels().cloned().collect::<Vec<_>>()),
+            None => key.clone(),
+        };
+    }
+
+    /// register `key` under its new name and, while migrating and renamed, under its old name as well
+    fn register<T>(&self, key: &Key, register: impl Fn(&Key) -> T, mirror: impl FnOnce(Mirror<T>) -> T) -> T {
+        let renamed = self.key(key);
+        let new = register(&renamed);
+        return match self.migrate_until {
+            Some(until) if renamed.name() != key.name() && Instant::now() < until => {
+                LEGACY.get().inspect(|legacy| legacy.register(key.name()));
+                mirror(Mirror { new, old: register(key), until })
+            }
+            _ => new,
+        };
+    }
+}
+
+impl<R: Recorder> Recorder for Renamer<R> {
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        if self.migrate_until.is_some() {
+            self.inner.describe_counter(key.clone(), unit, description.clone());
+        }
+        self.inner.describe_counter(self.key_name(key), unit, description);
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        if self.migrate_until.is_some() {
+            self.inner.describe_gauge(key.clone(), unit, description.clone());
+        }
+        self.inner.describe_gauge(self.key_name(key), unit, description);
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        if self.migrate_until.is_some() {
+            self.inner.describe_histogram(key.clone(), unit, description.clone());
+        }
+        self.inner.describe_histogram(self.key_name(key), unit, description);
+    }
+
+    fn register_counter(&self, key: &Key, metadata: &Metadata<'_>) -> Counter {
+        return self.register(key, |k| self.inner.register_counter(k, metadata), |m| Counter::from_arc(Arc::new(m)));
+    }
+
+    fn register_gauge(&self, key: &Key, metadata: &Metadata<'_>) -> Gauge {
+        return self.register(key, |k| self.inner.register_gauge(k, metadata), |m| Gauge::from_arc(Arc::new(m)));
+    }
+
+    fn register_histogram(&self, key: &Key, metadata: &Metadata<'_>) -> Histogram {
+        return self.register(key, |k| self.inner.register_histogram(k, metadata), |m| Histogram::from_arc(Arc::new(m)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use metrics::Label;
+
+    use super::*;
+
+    fn renamer(prefix: Option<&str>, overrides: &[(&str, &str)]) -> Renamer<()> {
+        let overrides = overrides.iter().map(|(old, new)| (old.to_string(), new.to_string())).collect();
+        return Renamer::new((), prefix.map(String::from), overrides, None);
+    }
+
+    #[test]
+    fn prefix_and_overrides() {
+        let names = renamer(Some("office_"), &[("scd41_co2_ppm", "co2_ppm"), ("exporter_up", "sensor_up")]);
+        assert_eq!(names.name("scd41_temperature_celsius"), "office_temperature_celsius");
+        // an override wins over the prefix and applies to any name
+        assert_eq!(names.name("scd41_co2_ppm"), "co2_ppm");
+        assert_eq!(names.name("exporter_up"), "sensor_up");
+        // only the sensor's series get the prefix
+        assert_eq!(names.name("exporter_build_info"), "exporter_build_info");
+        assert_eq!(renamer(None, &[]).name("scd41_co2_ppm"), "scd41_co2_ppm");
+    }
+
+    #[test]
+    fn labels_untouched() {
+        let names = renamer(Some("office_"), &[]);
+        let labels = vec![Label::new("location", "kitchen"), Label::new("source", "scd41")];
+        let key = names.key(&Key::from_parts("scd41_co2_ppm", labels.clone()));
+        assert_eq!(key.name(), "office_co2_ppm");
+        assert_eq!(key.labels().cloned().collect::<Vec<_>>(), labels);
+        let key = Key::from_parts("exporter_up", labels);
+        assert_eq!(names.key(&key), key);
+    }
+
+    #[test]
+    fn legacy_names_expire() {
+        let now = Instant::now();
+        let legacy = Legacy { until: now + Duration::from_secs(60), names: Mutex::new(BTreeMap::new()) };
+        legacy.register("scd41_co2_ppm");
+        let text = String::from("# TYPE co2_ppm gauge\nco2_ppm 812\n# TYPE scd41_co2_ppm gauge\nscd41_co2_ppm 812\n");
+        // while migrating the old name is rendered and its scrapes noted
+        assert_eq!(legacy.strip(text.clone(), now), text);
+        legacy.count(&text, now);
+        assert_eq!(legacy.names.lock().unwrap().get("scd41_co2_ppm"), Some(&true));
+        // then it's gone, though the recorder still holds its frozen value
+        assert_eq!(legacy.strip(text, now + Duration::from_secs(60)), "# TYPE co2_ppm gauge\nco2_ppm 812\n");
+    }
+
+    #[test]
+    fn overrides_parse() {
+        assert_eq!(parse_override("scd41_co2_ppm=co2"), Ok((String::from("scd41_co2_ppm"), String::from("co2"))));
+        for invalid in ["scd41_co2_ppm", "=co2", "scd41_co2_ppm=", "scd41-co2=co2"] {
+            assert!(parse_override(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
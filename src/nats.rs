@@ -0,0 +1,101 @@
+//! module for the NATS client protocol of the message bus output
+//! see https://docs.nats.io/reference/reference-protocols/nats-protocol
+//! a batch is written as PUB commands followed by a PING, the server's PONG tells it processed them all.
+//! the url's user and password, or a user without password as the token, authenticate the connection.
+use std::{io, time::Duration};
+
+use crate::{
+    broker::{Connection, Message},
+    http::{self, Stream, Url},
+    json,
+};
+
+pub(crate) struct Session {
+    stream: Box<dyn Stream>,
+    /// read but not yet parsed
+    buffer: Vec<u8>,
+}
+
+impl Session {
+    pub(crate) fn connect(url: &Url, name: &str) -> io::Result<Session> {
+        let stream = http::connect(url, Duration::from_secs(10))?;
+        let mut session = Session { stream, buffer: Vec::new() };
+        let info = session.read_line()?;
+        if !info.starts_with("INFO ") {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "expected INFO"));
+        }
+        let auth = match url.userinfo.as_deref().map(|u| u.split_once(':')) {
+            Some(Some((user, password))) => format!(r#","user":{},"pass":{}"#, json::quote(user), json::quote(password)),
+            Some(None) => format!(r#","auth_token":{}"#, json::quote(url.userinfo.as_deref().unwrap_or_default())),
+            None => String::new(),
+        };
+        let connect = format!(
+            r#"CONNECT {{"verbose":false,"pedantic":false,"name":{},"lang":"rust","version":{},"protocol":1{}}}"#,
+            json::quote(name),
+            json::quote(env!("CARGO_PKG_VERSION")),
+            auth
+        );
+        session.stream.write_all(format!("{}\r\nPING\r\n", connect).as_bytes())?;
+        session.stream.flush()?;
+        session.pong()?;
+        return Ok(session);
+    }
+
+    /// read until the PONG, answering the server's PINGs
+    fn pong(&mut self) -> io::Result<()> {
+        loop {
+            let line = self.read_line()?;
+            match line.split_whitespace().next().unwrap_or_default() {
+                "PONG" => return Ok(()),
+                "PING" => {
+                    self.stream.write_all(b"PONG\r\n")?;
+                    self.stream.flush()?;
+                }
+                "-ERR" => {
+                    let reason = line.trim_start_matches("-ERR").trim().trim_matches('\'');
+                    return Err(io::Error::other(format!("the server answered: {}", reason)));
+                }
+                // +OK and INFO updates
+                _ => {}
+            }
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|w| w == b"\r\n") {
+                let line = String::from_utf8_lossy(&self.buffer[..end]).into_owned();
+                self.buffer.drain(..end + 2);
+                return Ok(line);
+            }
+            let mut chunk = [0; 512];
+            let n = self.stream.read(&mut chunk)?;
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the server closed the connection"));
+            }
+            self.buffer.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+impl Connection for Session {
+    fn publish(&mut self, batch: &[Message]) -> io::Result<usize> {
+        let mut out = Vec::new();
+        for m in batch {
+            out.extend_from_slice(format!("PUB {} {}\r\n", m.topic, m.payload.len()).as_bytes());
+            out.extend_from_slice(&m.payload);
+            out.extend_from_slice(b"\r\n");
+        }
+        out.extend_from_slice(b"PING\r\n");
+        self.stream.write_all(&out)?;
+        self.stream.flush()?;
+        self.pong()?;
+        return Ok(0);
+    }
+
+    fn ping(&mut self) -> io::Result<()> {
+        self.stream.write_all(b"PING\r\n")?;
+        self.stream.flush()?;
+        return self.pong();
+    }
+}
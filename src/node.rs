@@ -0,0 +1,35 @@
+//! module for the persistent node identity
+//! a random UUID is generated on first start and kept in a file, so it survives sensor replacements.
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::Path,
+};
+
+/// load the node id from `path`, generating and saving a new one when the file doesn't exist
+pub(crate) fn load_or_create(path: &str) -> io::Result<String> {
+    match fs::read_to_string(path) {
+        Ok(id) if !id.trim().is_empty() => return Ok(id.trim().to_string()),
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let id = new_uuid()?;
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, format!("{}\n", id))?;
+    log::info!("generated node id {} in {}", id, path);
+    return Ok(id);
+}
+
+/// random (version 4) UUID
+fn new_uuid() -> io::Result<String> {
+    let mut b = [0_u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut b)?;
+    b[6] = (b[6] & 0x0F) | 0x40;
+    b[8] = (b[8] & 0x3F) | 0x80;
+    let hex: String = b.iter().map(|x| format!("{:02x}", x)).collect();
+    return Ok(format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32]));
+}
@@ -0,0 +1,55 @@
+//! module for estimating how many people are in the room from CO2
+//! by the room's CO2 balance, the CO2 people breathe out makes up for what the ventilation carries away and
+//! what accumulates: N = V (dC/dt + ACH (C - outdoor)) / G, with the room's volume V (--room-volume), its air
+//! changes per hour ACH (--air-changes) and G per person. outdoor CO2 is the baseline when it's tracked.
+//! it's a rough guess, right after the door or a window opened it's off.
+
+/// CO2 a sitting adult breathes out, in m³ per hour
+const PER_PERSON: f64 = 0.018;
+/// outdoor CO2 when neither the baseline nor --outdoor-co2 tell
+const OUTDOOR: f64 = 420.0;
+/// people from which the room counts as occupied
+const OCCUPIED: f64 = 0.5;
+
+pub(crate) struct Estimator {
+    /// m³
+    volume: f64,
+    air_changes: f64,
+    outdoor: f64,
+    occupancy: metrics::Gauge,
+    occupied: metrics::Gauge,
+}
+
+impl Estimator {
+    /// `labels` are added to every series
+    pub(crate) fn new(volume: f64, air_changes: f64, outdoor: Option<u16>, labels: &[(String, String)]) -> Self {
+        let estimator = Estimator {
+            volume,
+            air_changes,
+            outdoor: outdoor.map(f64::from).unwrap_or(OUTDOOR),
+            occupancy: metrics::gauge!("scd41_estimated_occupancy", labels),
+            occupied: metrics::gauge!("scd41_occupied", labels),
+        };
+        estimator.occupancy.set(f64::NAN);
+        return estimator;
+    }
+
+    /// no estimate while the values are stale
+    pub(crate) fn clear(&self) {
+        self.occupancy.set(f64::NAN);
+        self.occupied.set(0);
+    }
+
+    /// estimate from `co2` rising by `trend` ppm per minute, against `baseline` if it's known
+    pub(crate) fn update(&self, co2: u16, trend: Option<f64>, baseline: Option<f64>) {
+        let Some(trend) = trend else {
+            return;
+        };
+        let outdoor = baseline.unwrap_or(self.outdoor);
+        // ppm per hour held by the people, then the m³ per hour of it
+        let generated = trend * 60.0 + self.air_changes * (co2 as f64 - outdoor);
+        let people = (self.volume * generated * 1e-6 / PER_PERSON).max(0.0);
+        self.occupancy.set((people * 10.0).round() / 10.0);
+        self.occupied.set(if people >= OCCUPIED { 1 } else { 0 });
+    }
+}
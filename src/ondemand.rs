@@ -0,0 +1,75 @@
+//! module for measuring only when the values are asked for
+//! with --on-demand the sensor stays powered down between single shots, and a scrape of /metrics or a request
+//! to /api/v1/latest has the measurement loop take one, waiting up to --on-demand-wait for it to be published.
+//! a measurement younger than --on-demand-cache is served as it is, so several scrapers share a shot. between
+//! requests further apart than --stale-after the values read as stale.
+use std::{
+    sync::{Condvar, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+struct Config {
+    cache: Duration,
+    wait: Duration,
+}
+
+struct State {
+    /// a request waits for the next shot
+    requested: bool,
+    /// shots published so far, successful or not
+    generation: u64,
+    /// when the last successful shot was published
+    published: Option<Instant>,
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+static STATE: Mutex<State> = Mutex::new(State { requested: false, generation: 0, published: None });
+static PUBLISHED: Condvar = Condvar::new();
+
+pub(crate) fn init(cache: Duration, wait: Duration) {
+    let _ = CONFIG.set(Config { cache, wait });
+}
+
+pub(crate) fn enabled() -> bool {
+    return CONFIG.get().is_some();
+}
+
+/// wait until a measurement from within the cache window is published, the request's handler calls it
+pub(crate) fn fresh() {
+    let Some(config) = CONFIG.get() else {
+        return;
+    };
+    let requests = |result| metrics::counter!("scd41_on_demand_requests_total", "result" => result);
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if state.published.is_some_and(|t| t.elapsed() < config.cache) {
+        requests("cached").increment(1);
+        return;
+    }
+    state.requested = true;
+    let generation = state.generation;
+    let (state, timeout) = PUBLISHED
+        .wait_timeout_while(state, config.wait, |s| s.generation == generation)
+        .unwrap_or_else(|e| e.into_inner());
+    let result = match state.published {
+        _ if timeout.timed_out() => "timeout",
+        Some(t) if t.elapsed() < config.cache => "measured",
+        _ => "failed",
+    };
+    requests(result).increment(1);
+}
+
+/// whether a shot is to be taken, the measurement loop calls it every cycle
+pub(crate) fn take() -> bool {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    return std::mem::take(&mut state.requested);
+}
+
+/// the shot taken is published, with a measurement if `ok`
+pub(crate) fn published(ok: bool) {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    state.generation += 1;
+    if ok {
+        state.published = Some(Instant::now());
+    }
+    PUBLISHED.notify_all();
+}
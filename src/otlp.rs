@@ -0,0 +1,280 @@
+//! module for exporting metrics to an OpenTelemetry collector over OTLP
+//! see https://opentelemetry.io/docs/specs/otlp/
+//! the rendered exposition is converted every interval: gauges become OTLP gauges and counters
+//! cumulative monotonic sums. histograms and summaries are left to the Prometheus outputs.
+//! requests are sent over gRPC (`MetricsService/Export`) or HTTP (`POST /v1/metrics`), both as protobuf.
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{Arc, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use clap::ValueEnum;
+use metrics_exporter_prometheus::PrometheusHandle;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+
+use crate::{
+    exposition::{self, Kind, Sample},
+    http::{self, Url},
+    names, protobuf, tasks,
+};
+
+const GRPC_PATH: &str = "/opentelemetry.proto.collector.metrics.v1.MetricsService/Export";
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Protocol {
+    /// OTLP/gRPC, usually on port 4317
+    Grpc,
+    /// OTLP/HTTP with protobuf payloads, usually on port 4318
+    Http,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) endpoint: Url,
+    pub(crate) protocol: Protocol,
+    pub(crate) interval: Duration,
+    /// sent with every request, e.g. for authentication
+    pub(crate) headers: Vec<(String, String)>,
+    /// resource attributes besides the sensor serial
+    pub(crate) resource: Vec<(String, String)>,
+}
+
+/// serial number of the CO2 sensor, known once it has started
+static SERIAL: OnceLock<String> = OnceLock::new();
+
+/// add the sensor's serial number to the resource attributes
+pub(crate) fn set_serial(serial: &str) {
+    let _ = SERIAL.set(serial.to_string());
+}
+
+/// parse a `KEY=VALUE` header or resource attribute
+pub(crate) fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s.split_once('=').ok_or("expected KEY=VALUE")?;
+    if key.is_empty() {
+        return Err(String::from("empty key"));
+    }
+    return Ok((key.to_string(), value.to_string()));
+}
+
+/// name of this host, for the host.name attribute
+pub(crate) fn hostname() -> Option<String> {
+    let mut buf = [0_u8; 256];
+    // SAFETY: the buffer is valid for its length, and one byte is left for the terminator
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len() - 1) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    return String::from_utf8(buf[..len].to_vec()).ok().filter(|h| !h.is_empty());
+}
+
+/// export the rendered metrics every interval from a task until a shutdown
+pub(crate) fn spawn(config: Config, handle: PrometheusHandle) -> io::Result<()> {
+    let start_ns = unix_nanos();
+    log::info!("export metrics over otlp/{:?} to {}:{} every {:?}", config.protocol, config.endpoint.host, config.endpoint.port, config.interval);
+    let exports = metrics::counter!("exporter_otlp_exports_total");
+    let failures = metrics::counter!("exporter_otlp_export_failures_total");
+    tasks::spawn("otlp", async move {
+        let mut client = None;
+        while tasks::sleep(config.interval).await {
+            let mut resource = config.resource.clone();
+            if let Some(serial) = SERIAL.get() {
+                resource.push((String::from("sensor.serial"), serial.clone()));
+            }
+            let request = encode(&resource, &exposition::parse(&names::render(&handle)), start_ns, unix_nanos());
+            let result = match config.protocol {
+                Protocol::Http => tasks::blocking(|| export_http(&config, &request)),
+                Protocol::Grpc => {
+                    let result = tokio::time::timeout(TIMEOUT, export_grpc(&config, &mut client, request)).await;
+                    let result = result.unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "export timed out")));
+                    if result.is_err() {
+                        // start over with a new connection
+                        client = None;
+                    }
+                    result
+                }
+            };
+            match result {
+                Ok(()) => exports.increment(1),
+                Err(e) => {
+                    log::warn!("failed to export metrics over otlp: {}", e);
+                    failures.increment(1);
+                }
+            }
+        }
+    });
+    return Ok(());
+}
+
+fn unix_nanos() -> u64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default();
+}
+
+fn export_http(config: &Config, request: &[u8]) -> io::Result<()> {
+    let url = config.endpoint.join("v1/metrics");
+    let mut headers = vec![("Content-Type", "application/x-protobuf")];
+    headers.extend(config.headers.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+    return match http::send("POST", &url, &headers, request)? {
+        (status, _) if (200..300).contains(&status) => Ok(()),
+        (status, body) => Err(io::Error::other(format!("collector answered {}: {}", status, String::from_utf8_lossy(&body).trim()))),
+    };
+}
+
+async fn export_grpc(config: &Config, client: &mut Option<h2::client::SendRequest<Bytes>>, request: Vec<u8>) -> io::Result<()> {
+    let client = match client {
+        Some(client) => client,
+        None => client.insert(connect(&config.endpoint).await?),
+    };
+    let mut builder = ::http::Request::post(format!(
+        "{}://{}:{}{}",
+        if config.endpoint.tls { "https" } else { "http" },
+        config.endpoint.host,
+        config.endpoint.port,
+        GRPC_PATH
+    ))
+    .header("content-type", "application/grpc")
+    .header("te", "trailers");
+    for (key, value) in &config.headers {
+        builder = builder.header(key.to_lowercase(), value);
+    }
+    let request_head = builder.body(()).map_err(io::Error::other)?;
+
+    // length-prefixed message, not compressed
+    let mut message = Vec::with_capacity(request.len() + 5);
+    message.push(0);
+    message.extend_from_slice(&(request.len() as u32).to_be_bytes());
+    message.extend_from_slice(&request);
+
+    *client = client.clone().ready().await.map_err(io::Error::other)?;
+    let (response, mut stream) = client.send_request(request_head, false).map_err(io::Error::other)?;
+    stream.send_data(Bytes::from(message), true).map_err(io::Error::other)?;
+    let response = response.await.map_err(io::Error::other)?;
+    if response.status() != ::http::StatusCode::OK {
+        return Err(io::Error::other(format!("collector answered {}", response.status())));
+    }
+    let (head, mut body) = response.into_parts();
+    // errors without a message come as trailers-only responses
+    let mut status = head.headers.get("grpc-status").cloned();
+    let mut message = head.headers.get("grpc-message").cloned();
+    if status.is_none() {
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(io::Error::other)?;
+            let _ = body.flow_control().release_capacity(chunk.len());
+        }
+        if let Some(trailers) = body.trailers().await.map_err(io::Error::other)? {
+            status = trailers.get("grpc-status").cloned();
+            message = trailers.get("grpc-message").cloned();
+        }
+    }
+    return match status.as_ref().map(|s| s.to_str().unwrap_or_default()) {
+        Some("0") => Ok(()),
+        Some(code) => Err(io::Error::other(format!(
+            "collector answered grpc-status {}: {}",
+            code,
+            message.as_ref().and_then(|m| m.to_str().ok()).unwrap_or_default()
+        ))),
+        None => Err(io::Error::other("collector answered without grpc-status")),
+    };
+}
+
+/// open an HTTP/2 connection, with TLS negotiating h2 over ALPN for https endpoints
+async fn connect(url: &Url) -> io::Result<h2::client::SendRequest<Bytes>> {
+    let tcp = TcpStream::connect((url.host.as_str(), url.port)).await?;
+    tcp.set_nodelay(true)?;
+    if !url.tls {
+        return handshake(tcp).await;
+    }
+    let mut config = (*http::tls_config()).clone();
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    let name = rustls::pki_types::ServerName::try_from(url.host.clone()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let tls = tokio_rustls::TlsConnector::from(Arc::new(config)).connect(name, tcp).await?;
+    return handshake(tls).await;
+}
+
+async fn handshake<T: AsyncRead + AsyncWrite + Unpin + Send + 'static>(io: T) -> io::Result<h2::client::SendRequest<Bytes>> {
+    let (client, connection) = h2::client::handshake(io).await.map_err(io::Error::other)?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            log::debug!("otlp connection closed: {}", e);
+        }
+    });
+    return Ok(client);
+}
+
+/// protobuf ExportMetricsServiceRequest with one resource and scope
+fn encode(resource: &[(String, String)], samples: &[Sample], start_ns: u64, time_ns: u64) -> Vec<u8> {
+    // data points grouped by metric, in name order
+    let mut families: BTreeMap<String, (Kind, &str, Vec<Vec<u8>>)> = BTreeMap::new();
+    for sample in samples {
+        let (name, sum) = match sample.kind {
+            Kind::Counter => (sample.name.strip_suffix("_total").unwrap_or(&sample.name), true),
+            Kind::Gauge | Kind::Untyped => (sample.name.as_str(), false),
+            Kind::Histogram | Kind::Summary => continue,
+        };
+        let mut point = Vec::new();
+        for (key, value) in &sample.labels {
+            protobuf::bytes(&mut point, 7, &key_value(key, value));
+        }
+        if sum {
+            protobuf::fixed64(&mut point, 2, start_ns);
+        }
+        protobuf::fixed64(&mut point, 3, time_ns);
+        protobuf::double(&mut point, 4, sample.value);
+        families
+            .entry(name.to_string())
+            .or_insert_with(|| (sample.kind, &sample.help, Vec::new()))
+            .2
+            .push(point);
+    }
+
+    let mut scope = Vec::new();
+    protobuf::bytes(&mut scope, 1, env!("CARGO_PKG_NAME").as_bytes());
+    protobuf::bytes(&mut scope, 2, env!("CARGO_PKG_VERSION").as_bytes());
+    let mut scope_metrics = Vec::new();
+    protobuf::bytes(&mut scope_metrics, 1, &scope);
+    for (name, (kind, help, points)) in &families {
+        let mut data = Vec::new();
+        for point in points {
+            protobuf::bytes(&mut data, 1, point);
+        }
+        let mut metric = Vec::new();
+        protobuf::bytes(&mut metric, 1, name.as_bytes());
+        protobuf::bytes(&mut metric, 2, help.as_bytes());
+        if *kind == Kind::Counter {
+            // cumulative temporality, monotonic
+            protobuf::uint(&mut data, 2, 2);
+            protobuf::uint(&mut data, 3, 1);
+            protobuf::bytes(&mut metric, 7, &data);
+        } else {
+            protobuf::bytes(&mut metric, 5, &data);
+        }
+        protobuf::bytes(&mut scope_metrics, 2, &metric);
+    }
+
+    let mut attributes = Vec::new();
+    for (key, value) in resource {
+        protobuf::bytes(&mut attributes, 1, &key_value(key, value));
+    }
+    let mut resource_metrics = Vec::new();
+    protobuf::bytes(&mut resource_metrics, 1, &attributes);
+    protobuf::bytes(&mut resource_metrics, 2, &scope_metrics);
+    let mut request = Vec::new();
+    protobuf::bytes(&mut request, 1, &resource_metrics);
+    return request;
+}
+
+/// KeyValue with a string value
+fn key_value(key: &str, value: &str) -> Vec<u8> {
+    let mut any = Vec::new();
+    protobuf::bytes(&mut any, 1, value.as_bytes());
+    let mut kv = Vec::new();
+    protobuf::bytes(&mut kv, 1, key.as_bytes());
+    protobuf::bytes(&mut kv, 2, &any);
+    return kv;
+}
@@ -0,0 +1,243 @@
+//! module for writing history points as a Parquet file by hand
+//! the file has one row group with a single uncompressed, PLAIN encoded data page per column: timestamp_ms
+//! (INT64, TIMESTAMP_MILLIS), co2, temperature and humidity (FLOAT), all required. the metadata is in the
+//! Thrift compact protocol, of which only the types the footer needs are written.
+use crate::{history::Point, protobuf::varint};
+
+const MAGIC: &[u8] = b"PAR1";
+
+// compact protocol types
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+
+// parquet.thrift enums
+const TYPE_INT64: i32 = 2;
+const TYPE_FLOAT: i32 = 4;
+const REQUIRED: i32 = 0;
+const TIMESTAMP_MILLIS: i32 = 9;
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+const DATA_PAGE: i32 = 0;
+const UNCOMPRESSED: i32 = 0;
+
+/// a Thrift compact protocol encoder
+struct Thrift {
+    out: Vec<u8>,
+    /// the last field id of each struct being written
+    last: Vec<i16>,
+}
+
+impl Thrift {
+    fn new() -> Self {
+        return Thrift { out: Vec::new(), last: vec![0] };
+    }
+
+    fn zigzag(&mut self, n: i64) {
+        varint(&mut self.out, ((n << 1) ^ (n >> 63)) as u64);
+    }
+
+    fn field(&mut self, id: i16, kind: u8) {
+        let last = self.last.last_mut().expect("field outside a struct");
+        match id - *last {
+            delta @ 1..=15 => self.out.push((delta as u8) << 4 | kind),
+            _ => {
+                self.out.push(kind);
+                varint(&mut self.out, ((id << 1) ^ (id >> 15)) as u16 as u64);
+            }
+        }
+        *last = id;
+    }
+
+    fn i32(&mut self, id: i16, n: i32) {
+        self.field(id, I32);
+        self.zigzag(n as i64);
+    }
+
+    fn i64(&mut self, id: i16, n: i64) {
+        self.field(id, I64);
+        self.zigzag(n);
+    }
+
+    fn binary(&mut self, id: i16, b: &[u8]) {
+        self.field(id, BINARY);
+        self.bytes(b);
+    }
+
+    fn bytes(&mut self, b: &[u8]) {
+        varint(&mut self.out, b.len() as u64);
+        self.out.extend_from_slice(b);
+    }
+
+    fn list(&mut self, id: i16, kind: u8, len: usize) {
+        self.field(id, LIST);
+        match len {
+            0..=14 => self.out.push((len as u8) << 4 | kind),
+            _ => {
+                self.out.push(0xF0 | kind);
+                varint(&mut self.out, len as u64);
+            }
+        }
+    }
+
+    /// a struct field, written until `end`
+    fn begin(&mut self, id: i16) {
+        self.field(id, STRUCT);
+        self.element();
+    }
+
+    /// a struct as a list element, written until `end`
+    fn element(&mut self) {
+        self.last.push(0);
+    }
+
+    fn end(&mut self) {
+        self.out.push(0);
+        self.last.pop();
+    }
+}
+
+struct Column {
+    name: &'static str,
+    kind: i32,
+    converted: Option<i32>,
+    values: Vec<u8>,
+}
+
+/// `points` as a Parquet file
+pub(crate) fn encode(points: &[Point]) -> Vec<u8> {
+    let column = |name, kind, converted, value: &dyn Fn(&Point) -> Vec<u8>| Column {
+        name,
+        kind,
+        converted,
+        values: points.iter().flat_map(value).collect(),
+    };
+    let columns = [
+        column("timestamp_ms", TYPE_INT64, Some(TIMESTAMP_MILLIS), &|p| (p.timestamp_ms as i64).to_le_bytes().to_vec()),
+        column("co2", TYPE_FLOAT, None, &|p| p.co2.to_le_bytes().to_vec()),
+        column("temperature", TYPE_FLOAT, None, &|p| p.temperature.to_le_bytes().to_vec()),
+        column("humidity", TYPE_FLOAT, None, &|p| p.humidity.to_le_bytes().to_vec()),
+    ];
+    let rows = points.len() as i64;
+
+    let mut out = MAGIC.to_vec();
+    // offset and size of each column chunk
+    let mut chunks = Vec::new();
+    for c in &columns {
+        // required columns without nesting have no levels, the page is only the values
+        let mut header = Thrift::new();
+        header.i32(1, DATA_PAGE);
+        header.i32(2, c.values.len() as i32);
+        header.i32(3, c.values.len() as i32);
+        header.begin(5);
+        header.i32(1, rows as i32);
+        header.i32(2, PLAIN);
+        header.i32(3, RLE);
+        header.i32(4, RLE);
+        header.end();
+        header.end();
+        chunks.push((out.len() as i64, (header.out.len() + c.values.len()) as i64));
+        out.extend_from_slice(&header.out);
+        out.extend_from_slice(&c.values);
+    }
+
+    let mut meta = Thrift::new();
+    meta.i32(1, 1);
+    meta.list(2, STRUCT, columns.len() + 1);
+    meta.element();
+    meta.binary(4, b"schema");
+    meta.i32(5, columns.len() as i32);
+    meta.end();
+    for c in &columns {
+        meta.element();
+        meta.i32(1, c.kind);
+        meta.i32(3, REQUIRED);
+        meta.binary(4, c.name.as_bytes());
+        if let Some(converted) = c.converted {
+            meta.i32(6, converted);
+        }
+        meta.end();
+    }
+    meta.i64(3, rows);
+    meta.list(4, STRUCT, 1);
+    meta.element();
+    meta.list(1, STRUCT, columns.len());
+    for (c, &(offset, size)) in columns.iter().zip(&chunks) {
+        meta.element();
+        meta.i64(2, offset);
+        meta.begin(3);
+        meta.i32(1, c.kind);
+        meta.list(2, I32, 1);
+        meta.zigzag(PLAIN as i64);
+        meta.list(3, BINARY, 1);
+        meta.bytes(c.name.as_bytes());
+        meta.i32(4, UNCOMPRESSED);
+        meta.i64(5, rows);
+        meta.i64(6, size);
+        meta.i64(7, size);
+        meta.i64(9, offset);
+        meta.end();
+        meta.end();
+    }
+    meta.i64(2, chunks.iter().map(|(_, size)| size).sum());
+    meta.i64(3, rows);
+    meta.end();
+    meta.binary(6, concat!("raspi-scd41-exporter ", env!("CARGO_PKG_VERSION")).as_bytes());
+    meta.end();
+
+    out.extend_from_slice(&meta.out);
+    out.extend_from_slice(&(meta.out.len() as u32).to_le_bytes());
+    out.extend_from_slice(MAGIC);
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_headers() {
+        let mut t = Thrift::new();
+        t.i32(1, 0);
+        t.i32(3, -1);
+        // a delta beyond 15 and a smaller id take the long form, a type byte and the zigzag id
+        t.i64(20, 1);
+        t.i32(4, 2);
+        t.list(5, STRUCT, 3);
+        t.list(6, I32, 15);
+        assert_eq!(t.out, [0x15, 0x00, 0x25, 0x01, 0x06, 0x28, 0x02, 0x05, 0x08, 0x04, 0x19, 0x3C, 0x19, 0xF5, 0x0F]);
+
+        // a nested struct counts its field ids from zero and ends with a stop byte
+        let mut t = Thrift::new();
+        t.i32(5, 1);
+        t.begin(6);
+        t.i32(1, 1);
+        t.end();
+        t.i32(7, 1);
+        t.end();
+        assert_eq!(t.out, [0x55, 0x02, 0x1C, 0x15, 0x02, 0x00, 0x15, 0x02, 0x00]);
+    }
+
+    #[test]
+    fn file_layout() {
+        let point = Point { timestamp_ms: 1_700_000_000_000, co2: 800.0, temperature: 21.5, humidity: 40.0 };
+        let file = encode(&[point]);
+        assert_eq!(&file[..4], MAGIC);
+        assert_eq!(&file[file.len() - 4..], MAGIC);
+
+        // the first page header: DATA_PAGE, 8 bytes, 1 value PLAIN with RLE levels, then the value
+        let header = [0x15, 0x00, 0x15, 0x10, 0x15, 0x10, 0x2C, 0x15, 0x02, 0x15, 0x00, 0x15, 0x06, 0x15, 0x06, 0x00, 0x00];
+        assert_eq!(file[4..4 + header.len()], header);
+        assert_eq!(file[4 + header.len()..4 + header.len() + 8], 1_700_000_000_000i64.to_le_bytes());
+
+        // four pages of the same header size, the footer follows them and its length precedes the magic
+        let footer = 4 + 4 * header.len() + 8 + 3 * 4;
+        let length = u32::from_le_bytes(file[file.len() - 8..file.len() - 4].try_into().unwrap()) as usize;
+        assert_eq!(footer + length + 8, file.len());
+        // FileMetaData: version 1 and a schema list of 5 structs starting with the root's name
+        assert_eq!(file[footer..footer + 5], [0x15, 0x02, 0x19, 0x5C, 0x48]);
+        assert_eq!(file[footer + length - 1], 0);
+    }
+}
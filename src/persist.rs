@@ -0,0 +1,88 @@
+//! module for scheduling writes of the sensor's settings to its EEPROM
+//! automatic self-calibration (ASC) only changes the sensor's calibration in RAM, so it is lost on power loss
+//! unless persisted. the EEPROM endures a limited number of writes, so writes are counted against a budget
+//! kept in a file, and only happen when ASC has run long enough to have updated the calibration.
+use std::{
+    fs, io,
+    time::{Duration, Instant},
+};
+
+/// scd4x's default ASC standard period
+pub(crate) const DEFAULT_ASC_PERIOD: Duration = Duration::from_secs(156 * 3600);
+
+pub(crate) struct Schedule {
+    /// how often to check whether a write is needed
+    interval: Duration,
+    next_check: Instant,
+    /// measuring time after which ASC has adjusted the calibration
+    asc_period: Duration,
+    /// start of the measuring time not persisted yet
+    since: Instant,
+    state_file: String,
+    used: u32,
+    budget: u32,
+    writes: metrics::Counter,
+    remaining: metrics::Gauge,
+}
+
+impl Schedule {
+    /// check every `interval`, allowing `budget` writes in total as recorded in `state_file`
+    pub(crate) fn new(interval: Duration, budget: u32, state_file: String) -> io::Result<Self> {
+        let used = match fs::read_to_string(&state_file) {
+            Ok(s) => s.trim().parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        let now = Instant::now();
+        let schedule = Schedule {
+            interval,
+            next_check: now + interval,
+            asc_period: DEFAULT_ASC_PERIOD,
+            since: now,
+            state_file,
+            used,
+            budget,
+            writes: metrics::counter!("scd41_eeprom_writes_total"),
+            remaining: metrics::gauge!("scd41_eeprom_write_budget_remaining"),
+        };
+        schedule.remaining.set(budget.saturating_sub(used));
+        return Ok(schedule);
+    }
+
+    pub(crate) fn set_asc_period(&mut self, period: Duration) {
+        self.asc_period = period;
+    }
+
+    /// restart the measuring time, e.g. after the sensor lost its RAM state
+    pub(crate) fn reset(&mut self, now: Instant) {
+        self.since = now;
+    }
+
+    pub(crate) fn check_due(&self, now: Instant) -> bool {
+        return now >= self.next_check;
+    }
+
+    /// whether settings should be written now. moves the next check forward.
+    pub(crate) fn should_write(&mut self, now: Instant) -> bool {
+        self.next_check = now + self.interval;
+        if now.saturating_duration_since(self.since) < self.asc_period {
+            log::debug!("asc hasn't run for a full period since the last persist, skip");
+            return false;
+        }
+        if self.used >= self.budget {
+            log::warn!("eeprom write budget of {} is used up, don't persist settings", self.budget);
+            return false;
+        }
+        return true;
+    }
+
+    /// record a successful write
+    pub(crate) fn written(&mut self, now: Instant) {
+        self.since = now;
+        self.used += 1;
+        self.writes.increment(1);
+        self.remaining.set(self.budget.saturating_sub(self.used));
+        let _ = fs::write(&self.state_file, format!("{}\n", self.used))
+            .inspect_err(|e| log::warn!("failed to save eeprom write count: {:?}", e));
+    }
+}
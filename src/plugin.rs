@@ -0,0 +1,253 @@
+//! module for auxiliary sensor plugins
+//! each plugin is declared by a spec (`type=sht4x,addr=0x44,interval=5s,label.room=kitchen`)
+//! and runs its own acquisition thread publishing gauges with the spec's labels.
+use std::{collections::HashMap, fmt, thread, time::Duration};
+
+use embedded_hal::i2c;
+
+use crate::{
+    clock::SystemClock,
+    ads1115, ds18b20, merge, scd30, scd41, sen5x,
+    sensor::{Sensor, Sequencer},
+    sfa3x, sht4x,
+};
+
+/// consecutive failures after which a plugin is asked to recover
+const MAX_CONSECUTIVE_FAILURES: u32 = 10;
+
+/// temperature offset of CO2 sensor plugins, same as the command line default
+const DEFAULT_OFFSET: f32 = 4.0;
+
+/// a value read by a plugin, published as the gauge `name`
+pub(crate) type Reading = (&'static str, f64);
+
+/// auxiliary sensor running in its own thread
+pub(crate) trait Plugin: Send {
+    /// prepare the device
+    fn start(&mut self) -> Result<(), String>;
+
+    /// read the current values. an empty result means no new data yet.
+    fn read(&mut self) -> Result<Vec<Reading>, String>;
+
+    /// try to bring a non-responding device back
+    fn recover(&mut self) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Kind {
+    Scd41,
+    Scd30,
+    Sen5x,
+    Sht4x,
+    Sfa3x,
+    Ds18b20,
+    Ads1115,
+}
+
+impl Kind {
+    fn default_interval(self) -> Duration {
+        return match self {
+            Kind::Scd41 | Kind::Scd30 | Kind::Sen5x => Duration::from_secs(1),
+            Kind::Sht4x => Duration::from_secs(5),
+            // sfa3x updates its values every 500 ms, but hcho changes slowly
+            Kind::Sfa3x => Duration::from_secs(10),
+            // a 12 bit conversion takes 750 ms per probe
+            Kind::Ds18b20 => Duration::from_secs(10),
+            // electrochemical cells respond within tens of seconds
+            Kind::Ads1115 => Duration::from_secs(5),
+        };
+    }
+}
+
+/// declaration of a plugin instance
+#[derive(Debug, Clone)]
+pub(crate) struct Spec {
+    pub(crate) kind: Kind,
+    /// I2C bus number, defaults to the CO2 sensor's bus
+    pub(crate) bus: Option<u8>,
+    pub(crate) addr: Option<u8>,
+    /// device specific channel (e.g. the DS18B20 probe id)
+    pub(crate) channel: Option<String>,
+    /// temperature offset of CO2 sensors
+    pub(crate) offset: Option<f32>,
+    /// full scale range (V) of ADC inputs
+    pub(crate) range: Option<f32>,
+    /// calibration curve of ADC inputs, (volts, value) points
+    pub(crate) curve: Vec<(f64, f64)>,
+    pub(crate) interval: Duration,
+    pub(crate) labels: Vec<(String, String)>,
+}
+
+impl Spec {
+    pub(crate) fn new(kind: Kind) -> Self {
+        return Spec {
+            kind,
+            bus: None,
+            addr: None,
+            channel: None,
+            offset: None,
+            range: None,
+            curve: Vec::new(),
+            interval: kind.default_interval(),
+            labels: Vec::new(),
+        };
+    }
+}
+
+/// parse a spec given as comma separated `key=value` pairs
+pub(crate) fn parse_spec(s: &str) -> Result<Spec, String> {
+    let mut pairs = Vec::new();
+    for pair in s.split(',').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').ok_or_else(|| format!("{} is not key=value", pair))?;
+        pairs.push((key.trim(), value.trim()));
+    }
+
+    let kind = match pairs.iter().find(|(k, _)| *k == "type").map(|(_, v)| *v) {
+        Some("scd41") => Kind::Scd41,
+        Some("scd30") => Kind::Scd30,
+        Some("sen5x") => Kind::Sen5x,
+        Some("sht4x") => Kind::Sht4x,
+        Some("sfa3x") | Some("sfa30") => Kind::Sfa3x,
+        Some("ds18b20") => Kind::Ds18b20,
+        Some("ads1115") => Kind::Ads1115,
+        Some(other) => return Err(format!("unknown plugin type {}", other)),
+        None => return Err(String::from("missing type")),
+    };
+    let mut spec = Spec::new(kind);
+    for (key, value) in pairs {
+        match key {
+            "type" => {}
+            "bus" => spec.bus = Some(value.parse().map_err(|e| format!("bus: {}", e))?),
+            "addr" => spec.addr = Some(parse_addr(value).map_err(|e| format!("addr: {}", e))?),
+            "channel" => spec.channel = Some(value.to_string()),
+            "offset" => spec.offset = Some(value.parse().map_err(|e| format!("offset: {}", e))?),
+            "range" => spec.range = Some(value.parse().map_err(|e| format!("range: {}", e))?),
+            "curve" => spec.curve = ads1115::parse_curve(value).map_err(|e| format!("curve: {}", e))?,
+            "interval" => spec.interval = humantime::parse_duration(value).map_err(|e| format!("interval: {}", e))?,
+            _ => match key.strip_prefix("label.") {
+                Some(label) => spec.labels.push((label.to_string(), value.to_string())),
+                None => return Err(format!("unknown key {}", key)),
+            },
+        }
+    }
+    if spec.kind == Kind::Ads1115 {
+        // tell the inputs of one converter apart
+        let channel = spec.channel.clone().ok_or("ads1115 needs channel=<0-3>")?;
+        spec.labels.push((String::from("channel"), channel));
+    }
+    return Ok(spec);
+}
+
+/// parse an I2C address given in hex (0x76) or decimal
+pub(crate) fn parse_addr(s: &str) -> Result<u8, std::num::ParseIntError> {
+    return match s.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+}
+
+/// create the plugin declared by `spec` on `i2c`
+pub(crate) fn build<I>(spec: &Spec, i2c: I) -> Result<Box<dyn Plugin>, String>
+where
+    I: i2c::I2c + fmt::Debug + Send + 'static,
+{
+    // the source name identifies redundant CO2 sensors when merging
+    let source = spec
+        .labels
+        .iter()
+        .find(|(k, _)| k == "sensor")
+        .map(|(_, v)| v.clone())
+        .unwrap_or_else(|| format!("bus{}", spec.bus.map(|b| b.to_string()).unwrap_or_default()));
+    let offset = spec.offset.unwrap_or(DEFAULT_OFFSET);
+    return match spec.kind {
+        Kind::Scd41 => Ok(Box::new(SensorPlugin::new(scd41::Scd41::new(i2c, offset, None), &source))),
+        Kind::Scd30 => Ok(Box::new(SensorPlugin::new(scd30::Scd30::new(i2c, offset, None), &source))),
+        Kind::Sen5x => Ok(Box::new(sen5x::Sen5x::new(i2c))),
+        Kind::Sht4x => Ok(Box::new(sht4x::Sht4x::new(i2c, spec.addr.unwrap_or(sht4x::DEFAULT_ADDR)))),
+        Kind::Sfa3x => Ok(Box::new(sfa3x::Sfa3x::new(i2c))),
+        Kind::Ds18b20 => {
+            let id = spec.channel.clone().ok_or("ds18b20 needs channel=<probe id>")?;
+            Ok(Box::new(ds18b20::Ds18b20::new(id)))
+        }
+        Kind::Ads1115 => {
+            let channel = spec.channel.as_deref().and_then(|c| c.parse().ok()).filter(|c| *c < 4).ok_or("ads1115 needs channel=<0-3>")?;
+            let addr = spec.addr.unwrap_or(ads1115::DEFAULT_ADDR);
+            let range = spec.range.unwrap_or(ads1115::DEFAULT_RANGE);
+            Ok(Box::new(ads1115::Ads1115::new(i2c, addr, channel, range, spec.curve.clone())))
+        }
+    };
+}
+
+/// CO2 sensor running as a plugin, e.g. a redundant second sensor
+struct SensorPlugin<S> {
+    sensor: S,
+    sequencer: Sequencer,
+}
+
+impl<S: Sensor> SensorPlugin<S> {
+    fn new(sensor: S, source: &str) -> Self {
+        let sequencer = Sequencer::new(source, sensor.sample_interval());
+        return SensorPlugin { sensor, sequencer };
+    }
+}
+
+impl<S: Sensor + Send> Plugin for SensorPlugin<S> {
+    fn start(&mut self) -> Result<(), String> {
+        return self.sensor.start().map_err(|e| format!("{:?}", e));
+    }
+
+    fn read(&mut self) -> Result<Vec<Reading>, String> {
+        let Some(m) = self.sensor.measure().map_err(|e| format!("{:?}", e))? else {
+            return Ok(Vec::new());
+        };
+        merge::submit(&self.sequencer.wrap(m, &SystemClock), false);
+        return Ok(vec![
+            ("scd41_co2_ppm", m.co2 as f64),
+            ("scd41_temperature_celsius", m.temperature as f64),
+            ("scd41_humidity_rh", m.humidity as f64),
+        ]);
+    }
+
+    fn recover(&mut self) {
+        self.sensor.recover();
+        self.sequencer.restart();
+    }
+}
+
+/// start `plugin` and publish its readings from a background thread
+pub(crate) fn spawn(spec: &Spec, mut plugin: Box<dyn Plugin>) -> Result<(), String> {
+    plugin.start()?;
+
+    let name = format!("{:?}", spec.kind).to_lowercase();
+    let labels = spec.labels.clone();
+    let interval = spec.interval;
+    thread::spawn(move || {
+        let mut gauges: HashMap<&'static str, metrics::Gauge> = HashMap::new();
+        let mut failures = 0;
+        loop {
+            if failures >= MAX_CONSECUTIVE_FAILURES {
+                log::warn!("{} stopped responding, try to recover", name);
+                plugin.recover();
+                failures = 0;
+            }
+
+            match plugin.read() {
+                Err(e) => {
+                    log::warn!("failed to read {}: {}", name, e);
+                    failures += 1;
+                }
+                Ok(readings) => {
+                    for (metric, value) in readings {
+                        gauges
+                            .entry(metric)
+                            .or_insert_with(|| metrics::gauge!(metric, &labels))
+                            .set(value);
+                    }
+                    failures = 0;
+                }
+            }
+            thread::sleep(interval);
+        }
+    });
+    return Ok(());
+}
@@ -0,0 +1,144 @@
+//! module for dropping root privileges once the exporter is set up
+//! the I2C bus, GPIOs, listening sockets and output files are opened first, then the process switches to
+//! --user and --group. --keep-capability retains single capabilities for the sampling thread, which calls
+//! this, and the threads it starts later; all other threads lose every capability with the switch.
+use std::{
+    ffi::{CStr, CString},
+    io, mem, ptr,
+};
+
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Capability {
+    DacOverride,
+    NetBindService,
+    NetAdmin,
+    NetRaw,
+    SysRawio,
+    SysNice,
+    SysTime,
+}
+
+impl Capability {
+    /// the CAP_* number from linux/capability.h
+    fn number(self) -> u32 {
+        return match self {
+            Capability::DacOverride => 1,
+            Capability::NetBindService => 10,
+            Capability::NetAdmin => 12,
+            Capability::NetRaw => 13,
+            Capability::SysRawio => 17,
+            Capability::SysNice => 23,
+            Capability::SysTime => 25,
+        };
+    }
+}
+
+/// whom to run as after setup
+#[derive(Debug, Default)]
+pub(crate) struct Options {
+    pub(crate) user: Option<String>,
+    /// the user's primary group if None
+    pub(crate) group: Option<String>,
+    pub(crate) keep: Vec<Capability>,
+}
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// size of the buffer for getpwnam_r and getgrnam_r
+const BUFFER_SIZE: usize = 16384;
+
+fn lookup_user(name: &CStr) -> io::Result<(libc::uid_t, libc::gid_t)> {
+    let mut pw: libc::passwd = unsafe { mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; BUFFER_SIZE];
+    let mut result = ptr::null_mut();
+    let rc = unsafe { libc::getpwnam_r(name.as_ptr(), &mut pw, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc));
+    }
+    if result.is_null() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no user {}", name.to_string_lossy())));
+    }
+    return Ok((pw.pw_uid, pw.pw_gid));
+}
+
+fn lookup_group(name: &CStr) -> io::Result<libc::gid_t> {
+    let mut gr: libc::group = unsafe { mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; BUFFER_SIZE];
+    let mut result = ptr::null_mut();
+    let rc = unsafe { libc::getgrnam_r(name.as_ptr(), &mut gr, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc));
+    }
+    if result.is_null() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no group {}", name.to_string_lossy())));
+    }
+    return Ok(gr.gr_gid);
+}
+
+fn check(rc: libc::c_int) -> io::Result<()> {
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    return Ok(());
+}
+
+fn to_cstring(s: &str) -> io::Result<CString> {
+    return CString::new(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e));
+}
+
+/// switch to `options.user`, keeping `options.keep`. nothing to do without a user.
+pub(crate) fn drop(options: &Options) -> io::Result<()> {
+    let Some(user) = &options.user else {
+        return Ok(());
+    };
+    let name = to_cstring(user)?;
+    let (uid, primary) = lookup_user(&name)?;
+    let gid = match &options.group {
+        Some(group) => lookup_group(&to_cstring(group)?)?,
+        None => primary,
+    };
+    if unsafe { libc::geteuid() } == uid {
+        log::info!("already running as {}", user);
+        return Ok(());
+    }
+
+    let keep = !options.keep.is_empty();
+    if keep {
+        // the permitted set survives the uid change only with this
+        check(unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) })?;
+    }
+    // the user's supplementary groups (e.g. i2c, gpio) instead of root's
+    check(unsafe { libc::initgroups(name.as_ptr(), gid) })?;
+    check(unsafe { libc::setgid(gid) })?;
+    check(unsafe { libc::setuid(uid) })?;
+    if keep {
+        let mask = options.keep.iter().fold(0u32, |m, c| m | 1 << c.number());
+        let header = CapHeader { version: LINUX_CAPABILITY_VERSION_3, pid: 0 };
+        let data = [CapData { effective: mask, permitted: mask, inheritable: 0 }, CapData::default()];
+        if unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        check(unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 0, 0, 0, 0) })?;
+    }
+    if unsafe { libc::setuid(0) } == 0 {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "could switch back to root after dropping privileges"));
+    }
+    log::info!("running as {} (uid {}, gid {}), keeping capabilities {:?}", user, uid, gid, options.keep);
+    return Ok(());
+}
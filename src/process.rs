@@ -0,0 +1,45 @@
+//! module for the standard process metrics
+//! the process_* series other exporters have, read from /proc: CPU time, memory, file descriptors, threads
+//! and the start time. they're refreshed with the exporter's upkeep, every few seconds.
+use std::fs;
+
+/// refresh the process metrics, those that can't be read are left as they were
+pub(crate) fn update() {
+    // SAFETY: sysconf only reads configuration values
+    let (ticks, page) = unsafe { (libc::sysconf(libc::_SC_CLK_TCK), libc::sysconf(libc::_SC_PAGESIZE)) };
+    if let Ok(stat) = fs::read_to_string("/proc/self/stat") {
+        // the command in parentheses may contain spaces, the fields after it are numbers
+        let fields: Vec<f64> = stat.rsplit_once(')').map(|(_, rest)| rest).unwrap_or_default().split_whitespace().map(|f| f.parse().unwrap_or(f64::NAN)).collect();
+        // field n of proc_pid_stat(5) is at index n - 3, after the pid and command
+        let field = |n: usize| fields.get(n - 3).copied().unwrap_or(f64::NAN);
+        if ticks > 0 {
+            // a gauge, as counters only count whole numbers
+            metrics::gauge!("process_cpu_seconds_total").set((field(14) + field(15)) / ticks as f64);
+            if let Some(boot) = boot_time() {
+                metrics::gauge!("process_start_time_seconds").set(boot + field(22) / ticks as f64);
+            }
+        }
+        metrics::gauge!("process_threads").set(field(20));
+        metrics::gauge!("process_virtual_memory_bytes").set(field(23));
+        metrics::gauge!("process_resident_memory_bytes").set(field(24) * page as f64);
+    }
+    if let Ok(fds) = fs::read_dir("/proc/self/fd") {
+        metrics::gauge!("process_open_fds").set(fds.count() as f64);
+    }
+    if let Some(max) = max_fds() {
+        metrics::gauge!("process_max_fds").set(max);
+    }
+}
+
+/// seconds since the epoch the system booted at
+fn boot_time() -> Option<f64> {
+    let stat = fs::read_to_string("/proc/stat").ok()?;
+    return stat.lines().find_map(|l| l.strip_prefix("btime "))?.trim().parse().ok();
+}
+
+/// the soft limit of open files
+fn max_fds() -> Option<f64> {
+    let limits = fs::read_to_string("/proc/self/limits").ok()?;
+    let line = limits.lines().find(|l| l.starts_with("Max open files"))?;
+    return line.strip_prefix("Max open files")?.split_whitespace().next()?.parse().ok();
+}
@@ -0,0 +1,85 @@
+//! module for switching the sensor's measurement mode by the time of day
+//! each --power-profile is a span of local time and the mode the sensor measures in during it, e.g.
+//! 22:00-07:00=single-shot:1h,quiet for a measurement an hour during the night. outside the spans it
+//! measures periodically. single shots power the sensor down in between where the variant can, less
+//! power and less self-heating. `quiet` also switches the display off and silences the buzzer.
+use std::{sync::OnceLock, time::Duration};
+
+use crate::clock::{self, DailySpan};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Mode {
+    /// a measurement every 5s
+    Periodic,
+    /// a measurement every 30s
+    LowPower,
+    /// a single shot measurement this often
+    SingleShot(Duration),
+}
+
+impl Mode {
+    /// how often the sensor measures in the mode
+    pub(crate) fn interval(self) -> Duration {
+        return match self {
+            Mode::Periodic => Duration::from_secs(5),
+            Mode::LowPower => Duration::from_secs(30),
+            Mode::SingleShot(every) => every,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Profile {
+    span: DailySpan,
+    mode: Mode,
+    quiet: bool,
+}
+
+/// parse `HH:MM-HH:MM=MODE[,quiet]` with MODE periodic, low-power or single-shot:DURATION
+pub(crate) fn parse_profile(s: &str) -> Result<Profile, String> {
+    let (span, rest) = s.split_once('=').ok_or_else(|| format!("{} is not HH:MM-HH:MM=MODE", s))?;
+    let (mode, quiet) = match rest.split_once(',') {
+        Some((mode, "quiet")) => (mode, true),
+        Some((_, other)) => return Err(format!("{} is not quiet", other)),
+        None => (rest, false),
+    };
+    let mode = match mode.split_once(':') {
+        None if mode == "periodic" => Mode::Periodic,
+        None if mode == "low-power" => Mode::LowPower,
+        Some(("single-shot", every)) => {
+            let every = humantime::parse_duration(every).map_err(|e| format!("{}: {}", every, e))?;
+            // a single shot takes 5s, and after waking up the first is discarded
+            if every < Duration::from_secs(30) {
+                return Err(String::from("single shots are at least 30s apart, measure periodically for more"));
+            }
+            Mode::SingleShot(every)
+        }
+        _ => return Err(format!("{} is not periodic, low-power or single-shot:DURATION", mode)),
+    };
+    return Ok(Profile { span: clock::parse_daily_span(span)?, mode, quiet });
+}
+
+static PROFILES: OnceLock<Vec<Profile>> = OnceLock::new();
+
+pub(crate) fn init(profiles: Vec<Profile>) {
+    let _ = PROFILES.set(profiles);
+}
+
+fn current() -> Option<&'static Profile> {
+    return PROFILES.get()?.iter().find(|p| p.span.contains_now());
+}
+
+/// the mode the sensor should measure in now
+pub(crate) fn wanted() -> Mode {
+    return current().map(|p| p.mode).unwrap_or(Mode::Periodic);
+}
+
+/// whether the display and buzzer are to be quiet now
+pub(crate) fn quiet() -> bool {
+    return current().is_some_and(|p| p.quiet);
+}
+
+/// the longest interval between measurements of `profiles`
+pub(crate) fn longest_interval(profiles: &[Profile]) -> Duration {
+    return profiles.iter().map(|p| p.mode.interval()).fold(Mode::Periodic.interval(), Duration::max);
+}
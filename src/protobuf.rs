@@ -0,0 +1,67 @@
+//! module for encoding protocol buffers messages by hand
+//! only the wire types the exporters need are supported; messages are built field by field.
+
+pub(crate) fn varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// varint field
+pub(crate) fn uint(out: &mut Vec<u8>, field: u64, n: u64) {
+    varint(out, field << 3);
+    varint(out, n);
+}
+
+/// length-delimited field
+pub(crate) fn bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    varint(out, field << 3 | 2);
+    varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// double field, fixed64 wire type
+pub(crate) fn double(out: &mut Vec<u8>, field: u64, value: f64) {
+    varint(out, field << 3 | 1);
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// fixed64 field
+pub(crate) fn fixed64(out: &mut Vec<u8>, field: u64, n: u64) {
+    varint(out, field << 3 | 1);
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varints() {
+        for (n, encoded) in [(0, &[0x00][..]), (1, &[0x01]), (127, &[0x7F]), (128, &[0x80, 0x01]), (300, &[0xAC, 0x02]), (16384, &[0x80, 0x80, 0x01])] {
+            let mut out = Vec::new();
+            varint(&mut out, n);
+            assert_eq!(out, encoded, "{}", n);
+        }
+        let mut out = Vec::new();
+        varint(&mut out, u64::MAX);
+        assert_eq!(out, [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]);
+    }
+
+    #[test]
+    fn fields() {
+        // the examples of https://protobuf.dev/programming-guides/encoding/
+        let mut out = Vec::new();
+        uint(&mut out, 1, 150);
+        assert_eq!(out, [0x08, 0x96, 0x01]);
+        let mut out = Vec::new();
+        bytes(&mut out, 2, b"testing");
+        assert_eq!(out, b"\x12\x07testing");
+        let mut out = Vec::new();
+        double(&mut out, 1, 1.0);
+        fixed64(&mut out, 16, 1);
+        assert_eq!(out, [0x09, 0, 0, 0, 0, 0, 0, 0xF0, 0x3F, 0x81, 0x01, 1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+}
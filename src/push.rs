@@ -0,0 +1,51 @@
+//! module for pushing metrics to a Prometheus Pushgateway
+//! for nodes behind NAT or CGNAT, where Prometheus can't scrape them. every push replaces the
+//! node's whole group (`/metrics/job/<job>/instance/<instance>`), so metrics that disappear locally
+//! disappear from the gateway too.
+use std::{io, time::Duration};
+
+use metrics_exporter_prometheus::PrometheusHandle;
+
+use crate::{
+    http::{self, Url},
+    names, tasks,
+};
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) url: Url,
+    pub(crate) job: String,
+    pub(crate) instance: String,
+    pub(crate) interval: Duration,
+}
+
+/// push the rendered metrics every interval from a task until a shutdown
+pub(crate) fn spawn(config: Config, handle: PrometheusHandle) -> io::Result<()> {
+    let url = config.url.join(&format!(
+        "metrics/job/{}/instance/{}",
+        http::percent_encode(&config.job),
+        http::percent_encode(&config.instance)
+    ));
+    log::info!("push metrics to {}:{}{} every {:?}", url.host, url.port, url.path, config.interval);
+    let pushes = metrics::counter!("exporter_pushes_total");
+    let failures = metrics::counter!("exporter_push_failures_total");
+    tasks::spawn("push", async move {
+        // wait first, so the sensor has measured before the group is replaced
+        while tasks::sleep(config.interval).await {
+            tasks::blocking(|| {
+                match http::send("PUT", &url, &[("Content-Type", "text/plain; version=0.0.4")], names::render(&handle).as_bytes()) {
+                    Ok((status, _)) if (200..300).contains(&status) => pushes.increment(1),
+                    Ok((status, body)) => {
+                        log::warn!("pushgateway rejected metrics with {}: {}", status, String::from_utf8_lossy(&body).trim());
+                        failures.increment(1);
+                    }
+                    Err(e) => {
+                        log::warn!("failed to push metrics: {:?}", e);
+                        failures.increment(1);
+                    }
+                }
+            });
+        }
+    });
+    return Ok(());
+}
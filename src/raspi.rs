@@ -1,8 +1,64 @@
-//! module for initialize raspi I2C
-use rppal::i2c::{Error, I2c};
+//! module for initialize raspi I2C and GPIO
+use std::{thread, time::Duration};
 
-pub(crate) fn init_raspi() -> Result<I2c, Error> {
+use embedded_hal::i2c::{self as hal, ErrorKind, NoAcknowledgeSource, Operation};
+use rppal::{
+    gpio::{self, Gpio, OutputPin},
+    i2c::{Error, I2c},
+};
+
+/// the Pi's I2C bus, telling a missing acknowledge from other I/O errors
+#[derive(Debug)]
+pub(crate) struct PiI2c(I2c);
+
+/// an error of the bus, rppal reports every I/O error as ErrorKind::Other
+#[derive(Debug)]
+pub(crate) struct PiError(pub(crate) Error);
+
+impl hal::Error for PiError {
+    fn kind(&self) -> ErrorKind {
+        return match &self.0 {
+            // what i2c-bcm2835 returns for a NACK
+            Error::Io(e) if e.raw_os_error() == Some(libc::EREMOTEIO) => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+            e => hal::Error::kind(e),
+        };
+    }
+}
+
+impl hal::ErrorType for PiI2c {
+    type Error = PiError;
+}
+
+impl hal::I2c for PiI2c {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        return self.0.transaction(address, operations).map_err(PiError);
+    }
+}
+
+pub(crate) fn init_raspi() -> Result<PiI2c, Error> {
     let i2c = I2c::new()?;
     i2c.set_timeout(100)?;
-    return Ok(i2c);
+    return Ok(PiI2c(i2c));
+}
+
+/// init I2C bus `bus` (/dev/i2c-N)
+pub(crate) fn init_bus(bus: u8) -> Result<PiI2c, Error> {
+    let i2c = I2c::with_bus(bus)?;
+    i2c.set_timeout(100)?;
+    return Ok(PiI2c(i2c));
+}
+
+/// init the GPIO pin which switches the sensor's power (high = powered)
+pub(crate) fn init_power_gpio(pin: u8) -> Result<OutputPin, gpio::Error> {
+    let pin = Gpio::new()?.get(pin)?.into_output_high();
+    return Ok(pin);
+}
+
+/// cut the sensor's power and turn it on again
+pub(crate) fn power_cycle(pin: &mut OutputPin) {
+    pin.set_low();
+    thread::sleep(Duration::from_secs(1));
+    pin.set_high();
+    // scd41 needs up to 30 ms to enter idle state after power-up
+    thread::sleep(Duration::from_millis(30));
 }
@@ -0,0 +1,53 @@
+//! module for forced recalibrations on a weekly schedule
+//! --recalibration-schedule names the days and local time the room is known to be at outdoor CO2, e.g.
+//! sun@04:00 or mon,thu@05:30, and the sensor is then recalibrated to --recalibration-target the way
+//! `POST /api/v1/calibrate` does. it's skipped while there's no valid data or CO2 isn't steady, as the
+//! room isn't at the reference then. the correction is exported as scd41_recalibration_correction_ppm.
+use std::{
+    io, thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{Clock, Schedule, SystemClock},
+    control::{self, Action},
+    latest::{self, Status},
+    shutdown,
+};
+
+/// CO2 change in ppm per minute up to which the room counts as steady
+const STEADY: f64 = 2.0;
+
+/// recalibrate to `target` ppm on `schedule` from a thread
+pub(crate) fn spawn(schedule: Schedule, target: u16) -> io::Result<()> {
+    let runs = |result| metrics::counter!("scd41_scheduled_recalibrations_total", "result" => result);
+    thread::Builder::new().name(String::from("recalibration")).spawn(move || {
+        let clock = SystemClock;
+        let mut last: Option<Instant> = None;
+        while !shutdown::requested() {
+            clock.sleep(Duration::from_secs(1));
+            if !schedule.due(&clock, &mut last) {
+                continue;
+            }
+            let (_, _, status) = latest::get();
+            let steady = latest::trend().is_some_and(|t| t.abs() <= STEADY);
+            if status != Status::Ok || !steady {
+                log::warn!("skip the scheduled recalibration, the sensor is {} and CO2 {}", status.name(), if steady { "steady" } else { "changing" });
+                runs("skipped").increment(1);
+                continue;
+            }
+            log::info!("scheduled forced recalibration to {} ppm", target);
+            match control::submit(Action::ForceRecalibration(target)) {
+                Ok(correction) => {
+                    log::info!("recalibrated to {} ppm, corrected by {} ppm", target, correction);
+                    runs("success").increment(1);
+                }
+                Err(e) => {
+                    log::warn!("scheduled recalibration failed: {}", e);
+                    runs("failure").increment(1);
+                }
+            }
+        }
+    })?;
+    return Ok(());
+}
@@ -0,0 +1,174 @@
+//! module for demand controlled ventilation through a GPIO relay
+//! the relay switches on once CO2 reaches --relay-on and off once it falls to --relay-off, and each state is
+//! held for at least --relay-min-on or --relay-min-off so the fan doesn't short-cycle. without valid data the
+//! relay keeps its state, the exporter stops controlling the fan rather than stopping it. exit switches it off.
+//! an --alert with `actuate=relay` switches it on as well while firing, still within the minimum times.
+use std::time::{Duration, Instant};
+
+use rppal::gpio::{Gpio, OutputPin};
+
+use crate::{
+    alerts::{self, Actuator},
+    error::Error,
+};
+
+pub(crate) struct Config {
+    pub(crate) pin: u8,
+    pub(crate) active_low: bool,
+    /// CO2 switching the relay on
+    pub(crate) on: u16,
+    /// CO2 switching the relay off, below `on`
+    pub(crate) off: u16,
+    pub(crate) min_on: Duration,
+    pub(crate) min_off: Duration,
+}
+
+/// the switching decisions, apart from the pin
+struct Hysteresis {
+    config: Config,
+    on: bool,
+    /// whether CO2 calls for ventilation, with the hysteresis
+    demand: bool,
+    /// when it last switched, None before the first switch
+    switched: Option<Instant>,
+}
+
+impl Hysteresis {
+    /// the state to switch to for `co2` and whether an alert actuates, None to stay
+    fn update(&mut self, co2: Option<u16>, alerted: bool, now: Instant) -> Option<bool> {
+        if let Some(co2) = co2 {
+            self.demand = match self.demand {
+                false => co2 >= self.config.on,
+                true => co2 > self.config.off,
+            };
+        }
+        let wanted = self.demand || alerted;
+        if wanted == self.on {
+            return None;
+        }
+        let held = if self.on { self.config.min_on } else { self.config.min_off };
+        if self.switched.is_some_and(|s| now - s < held) {
+            return None;
+        }
+        self.on = wanted;
+        self.switched = Some(now);
+        return Some(wanted);
+    }
+}
+
+pub(crate) struct Relay {
+    pin: OutputPin,
+    hysteresis: Hysteresis,
+    active_low: bool,
+    gauge: metrics::Gauge,
+    switches: metrics::Counter,
+}
+
+impl Relay {
+    /// init the relay's pin switched off
+    pub(crate) fn new(config: Config) -> Result<Self, Error> {
+        if config.off >= config.on {
+            return Err(Error::Config(format!("the relay switches off at {} ppm, that isn't below {} ppm switching it on", config.off, config.on)));
+        }
+        let pin = Gpio::new()?.get(config.pin)?;
+        let pin = if config.active_low { pin.into_output_high() } else { pin.into_output_low() };
+        let gauge = metrics::gauge!("exporter_relay_on");
+        gauge.set(0);
+        return Ok(Relay {
+            pin,
+            active_low: config.active_low,
+            hysteresis: Hysteresis { config, on: false, demand: false, switched: None },
+            gauge,
+            switches: metrics::counter!("exporter_relay_switches_total"),
+        });
+    }
+
+    /// switch for `co2`, None while there's no valid data
+    pub(crate) fn update(&mut self, co2: Option<u16>, now: Instant) {
+        let alerted = alerts::actuating(Actuator::Relay);
+        let Some(on) = self.hysteresis.update(co2, alerted, now) else {
+            return;
+        };
+        match (co2, alerted) {
+            (_, true) if !self.hysteresis.demand => log::info!("an alert switches the relay on"),
+            (Some(co2), _) => log::info!("co2 at {} ppm, switch the relay {}", co2, if on { "on" } else { "off" }),
+            (None, _) => log::info!("switch the relay {}", if on { "on" } else { "off" }),
+        }
+        self.switch(on);
+        self.switches.increment(1);
+    }
+
+    fn switch(&mut self, on: bool) {
+        self.pin.write((on != self.active_low).into());
+        self.gauge.set(if on { 1 } else { 0 });
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.switch(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::clock::{Clock, FakeClock};
+
+    fn hysteresis() -> Hysteresis {
+        let config = Config {
+            pin: 17,
+            active_low: false,
+            on: 1000,
+            off: 800,
+            min_on: Duration::from_secs(300),
+            min_off: Duration::from_secs(120),
+        };
+        return Hysteresis { config, on: false, demand: false, switched: None };
+    }
+
+    #[test]
+    fn switches_with_hysteresis() {
+        let clock = FakeClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut relay = hysteresis();
+        let mut update = |co2, alerted| {
+            clock.advance(Duration::from_secs(600));
+            return relay.update(co2, alerted, clock.instant());
+        };
+        assert_eq!(update(Some(999), false), None);
+        assert_eq!(update(Some(1000), false), Some(true));
+        // in between the thresholds it stays on
+        assert_eq!(update(Some(801), false), None);
+        assert_eq!(update(Some(999), false), None);
+        assert_eq!(update(Some(800), false), Some(false));
+        assert_eq!(update(Some(999), false), None);
+        // without valid data it keeps its state
+        assert_eq!(update(None, false), None);
+        // an alert switches it on, and off again once it resolves
+        assert_eq!(update(Some(900), true), Some(true));
+        assert_eq!(update(Some(900), false), Some(false));
+    }
+
+    #[test]
+    fn holds_minimum_times() {
+        let clock = FakeClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut relay = hysteresis();
+        assert_eq!(relay.update(Some(1200), false, clock.instant()), Some(true));
+        // CO2 bouncing around the thresholds doesn't chatter
+        for co2 in [700, 1100, 700, 1000, 790, 1001] {
+            clock.advance(Duration::from_secs(10));
+            assert_eq!(relay.update(Some(co2), false, clock.instant()), None);
+        }
+        clock.advance(Duration::from_secs(230));
+        assert_eq!(relay.update(Some(700), false, clock.instant()), None);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(relay.update(Some(700), false, clock.instant()), Some(false));
+        // off is held for its own minimum time, demand coming back waits for it
+        clock.advance(Duration::from_secs(119));
+        assert_eq!(relay.update(Some(1200), false, clock.instant()), None);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(relay.update(Some(1200), false, clock.instant()), Some(true));
+    }
+}
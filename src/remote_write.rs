@@ -0,0 +1,129 @@
+//! module for sending metrics with the Prometheus remote_write protocol (protobuf + snappy)
+//! see https://prometheus.io/docs/specs/remote_write_spec/
+//! every interval the exposition is rendered and sent as one write request. requests that fail
+//! with a network error, 429 or 5xx are spooled to disk and resent in order once the endpoint is back,
+//! up to --remote-write-buffer-mib and --buffer-retention.
+use std::{
+    io,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use metrics_exporter_prometheus::PrometheusHandle;
+
+use crate::{
+    exposition::{self, Sample},
+    http::{self, Url},
+    names, protobuf, snappy,
+    spool::{self, Delivery, Spool},
+    tasks,
+};
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) url: Url,
+    pub(crate) interval: Duration,
+    /// where requests wait while the endpoint is unreachable
+    pub(crate) buffer: spool::Config,
+}
+
+/// send the rendered metrics every interval from a task until a shutdown
+pub(crate) fn spawn(config: Config, handle: PrometheusHandle) -> io::Result<()> {
+    log::info!("remote write to {}:{}{} every {:?}", config.url.host, config.url.port, config.url.path, config.interval);
+    let dropped = metrics::counter!("exporter_remote_write_dropped_total");
+    let mut spool = Spool::new("remote_write", "snappy", config.buffer.clone(), dropped.clone(), |_| 1)?;
+    let sent = metrics::counter!("exporter_remote_write_requests_total");
+    let failures = metrics::counter!("exporter_remote_write_failures_total");
+    tasks::spawn("remote-write", async move {
+        while tasks::sleep(config.interval).await {
+            tasks::blocking(|| {
+                let timestamp_ms = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis() as i64)
+                    .unwrap_or_default();
+                let samples = exposition::parse(&names::render(&handle));
+                spool.send(&snappy::compress(&encode(&samples, timestamp_ms)), |payload| {
+                    let delivery = post(&config.url, payload);
+                    match delivery {
+                        Delivery::Sent => sent.increment(1),
+                        Delivery::Rejected => {
+                            failures.increment(1);
+                            dropped.increment(1);
+                        }
+                        Delivery::Retry => failures.increment(1),
+                    }
+                    return delivery;
+                });
+            });
+        }
+    });
+    return Ok(());
+}
+
+fn post(url: &Url, payload: &[u8]) -> Delivery {
+    let headers = [
+        ("Content-Type", "application/x-protobuf"),
+        ("Content-Encoding", "snappy"),
+        ("X-Prometheus-Remote-Write-Version", "0.1.0"),
+    ];
+    return match http::send("POST", url, &headers, payload) {
+        Ok((status, _)) if (200..300).contains(&status) => Delivery::Sent,
+        Ok((status, body)) => {
+            log::warn!("remote write failed with {}: {}", status, String::from_utf8_lossy(&body).trim());
+            if status == 429 || status >= 500 { Delivery::Retry } else { Delivery::Rejected }
+        }
+        Err(e) => {
+            log::warn!("remote write failed: {:?}", e);
+            Delivery::Retry
+        }
+    };
+}
+
+/// protobuf WriteRequest with one sample per series, all at `timestamp_ms`
+fn encode(samples: &[Sample], timestamp_ms: i64) -> Vec<u8> {
+    let mut request = Vec::new();
+    for sample in samples {
+        let mut series = Vec::new();
+        let name = (String::from("__name__"), sample.name.clone());
+        let mut labels: Vec<&(String, String)> = sample.labels.iter().chain([&name]).collect();
+        labels.sort();
+        for (name, value) in labels {
+            let mut label = Vec::new();
+            protobuf::bytes(&mut label, 1, name.as_bytes());
+            protobuf::bytes(&mut label, 2, value.as_bytes());
+            protobuf::bytes(&mut series, 1, &label);
+        }
+        let mut point = Vec::new();
+        protobuf::double(&mut point, 1, sample.value);
+        protobuf::uint(&mut point, 2, timestamp_ms as u64);
+        protobuf::bytes(&mut series, 2, &point);
+        protobuf::bytes(&mut request, 1, &series);
+    }
+    return request;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exposition::Kind;
+
+    #[test]
+    fn write_request() {
+        let up = Sample { name: String::from("up"), labels: vec![(String::from("job"), String::from("x"))], value: 1.0, kind: Kind::Gauge, help: String::new() };
+        let expected = [
+            &[0x0A, 40][..],
+            // labels sorted by name, __name__ first
+            &[0x0A, 14, 0x0A, 8],
+            b"__name__",
+            &[0x12, 2],
+            b"up",
+            &[0x0A, 8, 0x0A, 3],
+            b"job",
+            &[0x12, 1],
+            b"x",
+            // value 1.0 at 1000 ms
+            &[0x12, 12, 0x09, 0, 0, 0, 0, 0, 0, 0xF0, 0x3F, 0x10, 0xE8, 0x07],
+        ]
+        .concat();
+        assert_eq!(encode(&[up], 1000), expected);
+    }
+}
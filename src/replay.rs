@@ -0,0 +1,187 @@
+//! module for replaying recorded measurements
+//! CSV files need a header with `co2`, `temperature` and `humidity` columns (and optionally `timestamp_ms`).
+//! JSON Lines files need one object per line with the same keys.
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    fs,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::Clock,
+    error::{Context, Error},
+    json,
+    sensor::{Measurement, Sensor},
+};
+
+/// interval used when the recording has no timestamps (scd41's periodic measurement interval)
+const DEFAULT_INTERVAL_MS: f64 = 5000.0;
+
+struct Record {
+    timestamp_ms: Option<f64>,
+    measurement: Measurement,
+}
+
+/// sensor which replays recorded measurements at their recorded pace
+pub(crate) struct ReplaySensor {
+    clock: Arc<dyn Clock + Send>,
+    /// measurements and their offset from the start of the replay
+    pending: VecDeque<(Duration, Measurement)>,
+    started: Option<Instant>,
+}
+
+impl ReplaySensor {
+    /// load the recorded measurements in `path`. `speed` scales the recorded intervals (2.0 replays twice as fast).
+    pub(crate) fn open(path: &str, speed: f64, clock: Arc<dyn Clock + Send>) -> Result<Self, Error> {
+        if speed <= 0.0 {
+            return Err(Error::Config(String::from("replay speed must be positive")));
+        }
+
+        let content = fs::read_to_string(path).context(format!("failed to read {}", path))?;
+        let records = if content.trim_start().starts_with('{') {
+            parse_jsonl(&content)
+        } else {
+            parse_csv(&content)
+        }
+        .map_err(|e| Error::Config(format!("{}: {}", path, e)))?;
+        log::info!("replay {} measurements from {}", records.len(), path);
+
+        let mut pending = VecDeque::with_capacity(records.len());
+        let mut offset_ms = 0.0;
+        let mut prev: Option<f64> = None;
+        for record in records {
+            offset_ms += match (prev, record.timestamp_ms) {
+                _ if pending.is_empty() => 0.0,
+                (Some(p), Some(t)) => (t - p).max(0.0),
+                _ => DEFAULT_INTERVAL_MS,
+            };
+            prev = record.timestamp_ms;
+            pending.push_back((Duration::from_secs_f64(offset_ms / speed / 1000.0), record.measurement));
+        }
+
+        return Ok(ReplaySensor { clock, pending, started: None });
+    }
+}
+
+impl Sensor for ReplaySensor {
+    type Error = Infallible;
+
+    fn start(&mut self) -> Result<(), Self::Error> {
+        self.started = Some(self.clock.instant());
+        return Ok(());
+    }
+
+    fn measure(&mut self) -> Result<Option<Measurement>, Self::Error> {
+        // schedule against the start time so slow publishing doesn't accumulate drift
+        let elapsed = self.started.map(|s| self.clock.instant() - s).unwrap_or_default();
+        if self.pending.front().is_none_or(|(due, _)| *due > elapsed) {
+            return Ok(None);
+        }
+        let measurement = self.pending.pop_front().map(|(_, m)| m);
+        if self.pending.is_empty() {
+            log::info!("replay finished, keep serving the last values");
+        }
+        return Ok(measurement);
+    }
+
+    fn poll_interval(&self) -> Duration {
+        return Duration::from_millis(10);
+    }
+}
+
+fn parse_csv(content: &str) -> Result<Vec<Record>, String> {
+    let mut lines = content.lines().enumerate().filter(|(_, l)| !l.trim().is_empty());
+    let (_, header) = lines.next().ok_or("empty replay file")?;
+    let columns: Vec<&str> = header.split(',').map(|c| c.trim()).collect();
+    let index_of = |name: &str| columns.iter().position(|c| *c == name);
+    let co2 = index_of("co2").ok_or("missing co2 column")?;
+    let temperature = index_of("temperature").ok_or("missing temperature column")?;
+    let humidity = index_of("humidity").ok_or("missing humidity column")?;
+    let timestamp = index_of("timestamp_ms");
+
+    let mut records = Vec::new();
+    for (n, line) in lines {
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        let field = |i: usize| -> Result<f64, String> {
+            let f = fields.get(i).ok_or_else(|| format!("line {}: missing field", n + 1))?;
+            return f.parse::<f64>().map_err(|e| format!("line {}: {}", n + 1, e));
+        };
+        records.push(Record {
+            timestamp_ms: timestamp.map(field).transpose()?,
+            measurement: Measurement {
+                co2: field(co2)? as u16,
+                temperature: field(temperature)? as f32,
+                humidity: field(humidity)? as f32,
+            },
+        });
+    }
+    return Ok(records);
+}
+
+fn parse_jsonl(content: &str) -> Result<Vec<Record>, String> {
+    let mut records = Vec::new();
+    for (n, line) in content.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let value = json::parse(line).map_err(|e| format!("line {}: {}", n + 1, e))?;
+        let field = |key: &str| -> Result<f64, String> {
+            return value
+                .get(key)
+                .and_then(json::Value::as_f64)
+                .ok_or_else(|| format!("line {}: missing {}", n + 1, key));
+        };
+        records.push(Record {
+            timestamp_ms: value.get("timestamp_ms").and_then(json::Value::as_f64),
+            measurement: Measurement {
+                co2: field("co2")? as u16,
+                temperature: field("temperature")? as f32,
+                humidity: field("humidity")? as f32,
+            },
+        });
+    }
+    return Ok(records);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_columns_in_any_order() {
+        let records = parse_csv("humidity, co2,temperature,timestamp_ms\n40.5,600,21.25,1000\n\n41,612,21.5,6000\n").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].timestamp_ms, Some(1000.0));
+        assert_eq!(records[1].measurement.co2, 612);
+        assert_eq!(records[1].measurement.temperature, 21.5);
+        assert_eq!(records[1].measurement.humidity, 41.0);
+    }
+
+    #[test]
+    fn csv_without_timestamps() {
+        let records = parse_csv("co2,temperature,humidity\n600,21.5,40\n").unwrap();
+        assert_eq!(records[0].timestamp_ms, None);
+    }
+
+    #[test]
+    fn csv_errors_name_the_line() {
+        assert_eq!(parse_csv("").err().unwrap(), "empty replay file");
+        assert_eq!(parse_csv("co2,temperature\n600,21.5\n").err().unwrap(), "missing humidity column");
+        assert_eq!(parse_csv("co2,temperature,humidity\n600,21.5,40\n601,21.5\n").err().unwrap(), "line 3: missing field");
+        assert!(parse_csv("co2,temperature,humidity\n600,warm,40\n").err().unwrap().starts_with("line 2: "));
+    }
+
+    #[test]
+    fn jsonl_records() {
+        let records = parse_jsonl("{\"co2\":600,\"temperature\":21.5,\"humidity\":40,\"timestamp_ms\":1000}\n\n{\"humidity\":41,\"co2\":601,\"temperature\":21.75}\n").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].timestamp_ms, Some(1000.0));
+        assert_eq!(records[1].timestamp_ms, None);
+        assert_eq!(records[1].measurement.temperature, 21.75);
+    }
+
+    #[test]
+    fn jsonl_errors_name_the_line() {
+        assert_eq!(parse_jsonl("{\"co2\":600,\"temperature\":21.5,\"humidity\":40}\n{\"co2\":600,\"temperature\":21.5}\n").err().unwrap(), "line 2: missing humidity");
+        assert!(parse_jsonl("{\"co2\":").err().unwrap().starts_with("line 1: "));
+    }
+}
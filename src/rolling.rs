@@ -0,0 +1,80 @@
+//! module for the min, max and mean of the measurements over rolling windows
+//! the samples of the longest --rolling-window are kept in memory, and every window's statistics are
+//! exported as scd41_co2_ppm_max{window="1h"} and the like. scraping every few minutes still catches
+//! the peaks in between.
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::sensor::Measurement;
+
+/// the exported series the statistics are of
+const SERIES: [&str; 3] = ["scd41_co2_ppm", "scd41_temperature_celsius", "scd41_humidity_rh"];
+
+/// `window` in the largest unit it's a whole number of, 24h rather than 1d
+pub(crate) fn label(window: Duration) -> String {
+    let secs = window.as_secs();
+    return match secs {
+        s if s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    };
+}
+
+struct Window {
+    span: Duration,
+    /// min, max and mean of each series
+    gauges: [[metrics::Gauge; 3]; 3],
+}
+
+pub(crate) struct Rolling {
+    windows: Vec<Window>,
+    /// co2, temperature and humidity, the newest last
+    samples: VecDeque<(Instant, [f32; 3])>,
+}
+
+impl Rolling {
+    /// `labels` are added to every series
+    pub(crate) fn new(spans: &[Duration], labels: &[(String, String)]) -> Self {
+        let windows = spans
+            .iter()
+            .map(|&span| {
+                let mut labels = labels.to_vec();
+                labels.push((String::from("window"), label(span)));
+                let gauges = SERIES.map(|s| ["min", "max", "mean"].map(|stat| metrics::gauge!(format!("{}_{}", s, stat), &labels)));
+                return Window { span, gauges };
+            })
+            .collect();
+        return Rolling { windows, samples: VecDeque::new() };
+    }
+
+    /// add `m` taken at `now` and update the statistics
+    pub(crate) fn add(&mut self, now: Instant, m: &Measurement) {
+        let longest = self.windows.iter().map(|w| w.span).max().unwrap_or_default();
+        while self.samples.front().is_some_and(|(t, _)| now.saturating_duration_since(*t) > longest) {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, [m.co2 as f32, m.temperature, m.humidity]));
+
+        for window in &self.windows {
+            let mut min = [f32::INFINITY; 3];
+            let mut max = [f32::NEG_INFINITY; 3];
+            let mut sum = [0_f64; 3];
+            let mut count = 0;
+            for (_, sample) in self.samples.iter().rev().take_while(|(t, _)| now.saturating_duration_since(*t) <= window.span) {
+                for i in 0..3 {
+                    min[i] = min[i].min(sample[i]);
+                    max[i] = max[i].max(sample[i]);
+                    sum[i] += sample[i] as f64;
+                }
+                count += 1;
+            }
+            for (i, [min_gauge, max_gauge, mean_gauge]) in window.gauges.iter().enumerate() {
+                min_gauge.set(min[i]);
+                max_gauge.set(max[i]);
+                mean_gauge.set(sum[i] / count as f64);
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! module for exporter-side recording rules
+//! a rule is `NAME=FUNC(SERIES[WINDOW])`, e.g. `co2_1h_avg=avg_over_time(scd41_co2_ppm[1h])`.
+//! rules are evaluated whenever a new sample of their series arrives and exported as gauge NAME.
+//! they can be replaced on a config reload, keeping the samples collected so far.
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Function {
+    Avg,
+    Min,
+    Max,
+    /// last minus first sample in the window
+    Delta,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Rule {
+    name: String,
+    function: Function,
+    series: String,
+    window: Duration,
+}
+
+struct State {
+    rules: Vec<(Rule, metrics::Gauge)>,
+    /// samples per series, oldest first
+    samples: HashMap<String, VecDeque<(Instant, f64)>>,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+/// parse `NAME=FUNC(SERIES[WINDOW])`
+pub(crate) fn parse_rule(s: &str) -> Result<Rule, String> {
+    let (name, expr) = s.split_once('=').ok_or("expected NAME=EXPRESSION")?;
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("invalid rule name: {}", name));
+    }
+    let (function, rest) = expr.trim().split_once('(').ok_or("expected FUNC(SERIES[WINDOW])")?;
+    let function = match function.trim() {
+        "avg_over_time" => Function::Avg,
+        "min_over_time" => Function::Min,
+        "max_over_time" => Function::Max,
+        "delta" => Function::Delta,
+        other => return Err(format!("unknown function: {}", other)),
+    };
+    let inner = rest.trim().strip_suffix(')').ok_or("missing ')'")?;
+    let (series, window) = inner.split_once('[').ok_or("missing [WINDOW]")?;
+    let window = window.trim().strip_suffix(']').ok_or("missing ']'")?;
+    let window = humantime::parse_duration(window.trim()).map_err(|e| format!("window: {}", e))?;
+    return Ok(Rule {
+        name: name.to_string(),
+        function,
+        series: series.trim().to_string(),
+        window,
+    });
+}
+
+/// enable evaluation of `rules`, replacing the rules evaluated so far
+pub(crate) fn init(rules: Vec<Rule>) {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if rules.is_empty() {
+        *state = None;
+        return;
+    }
+    let rules = rules
+        .into_iter()
+        .map(|r| {
+            let gauge = metrics::gauge!(r.name.clone());
+            return (r, gauge);
+        })
+        .collect();
+    let samples = state.take().map(|s| s.samples).unwrap_or_default();
+    *state = Some(State { rules, samples });
+}
+
+/// record new samples and evaluate the rules over them
+pub(crate) fn observe(values: &[(&str, f64)]) {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(state) = state.as_mut() else {
+        return;
+    };
+    let now = Instant::now();
+    let samples = &mut state.samples;
+    for (series, value) in values {
+        let Some(longest) = state.rules.iter().filter(|(r, _)| r.series == *series).map(|(r, _)| r.window).max() else {
+            continue;
+        };
+        let history = samples.entry(series.to_string()).or_default();
+        history.push_back((now, *value));
+        while history.front().is_some_and(|(t, _)| now - *t > longest) {
+            history.pop_front();
+        }
+    }
+
+    for (rule, gauge) in &state.rules {
+        if !values.iter().any(|(s, _)| *s == rule.series) {
+            continue;
+        }
+        let Some(history) = samples.get(&rule.series) else {
+            continue;
+        };
+        let window: Vec<f64> = history.iter().filter(|(t, _)| now - *t <= rule.window).map(|(_, v)| *v).collect();
+        if let Some(value) = evaluate(rule.function, &window) {
+            gauge.set(value);
+        }
+    }
+}
+
+fn evaluate(function: Function, values: &[f64]) -> Option<f64> {
+    let (first, last) = (values.first()?, values.last()?);
+    return Some(match function {
+        Function::Avg => values.iter().sum::<f64>() / values.len() as f64,
+        Function::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+        Function::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        Function::Delta => last - first,
+    });
+}
@@ -0,0 +1,187 @@
+//! module for --sandbox, confining the process once it's set up
+//! Landlock leaves the filesystem readable but writable only in /dev (the I2C, GPIO and watchdog devices)
+//! and the directories the exporter writes to, and forbids executing anything. a seccomp filter on all
+//! threads makes the syscalls an exporter never needs (exec, ptrace, mount, module loading, bpf, reboot,
+//! namespaces, keyrings) fail with EPERM. kernels without Landlock get the seccomp filter only.
+use std::{
+    ffi::CString,
+    io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+/// the rights up to ABI 1, ending with ACCESS_FS_MAKE_SYM
+const ACCESS_FS_ABI_1: u64 = (1 << 13) - 1;
+const ACCESS_FS_REFER: u64 = 1 << 13;
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+// classic BPF for the seccomp filter
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JMP_JEQ_K: u16 = 0x15;
+const BPF_RET_K: u16 = 0x06;
+#[cfg(target_arch = "x86_64")]
+const BPF_JMP_JGE_K: u16 = 0x35;
+/// offsets into struct seccomp_data
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xC000_003E;
+/// set in the numbers of x32 syscalls, which share the x86_64 arch but not its numbers
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xC000_00B7;
+#[cfg(target_arch = "arm")]
+const AUDIT_ARCH: u32 = 0x4000_0028;
+
+const DENIED: [libc::c_long; 26] = [
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_reboot,
+    libc::SYS_kexec_load,
+    libc::SYS_kexec_file_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_acct,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_userfaultfd,
+];
+
+/// confine the process, `writable` are the directories it writes to after this
+pub(crate) fn apply(writable: &[PathBuf]) -> io::Result<()> {
+    // required for unprivileged Landlock and seccomp, and keeps setuid binaries from granting anything
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    match landlock(writable) {
+        Ok(abi) => log::info!("landlock (abi {}) restricts writes to /dev and {:?}", abi, writable),
+        Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP)) => {
+            log::warn!("landlock isn't available, sandbox with seccomp only")
+        }
+        Err(e) => return Err(e),
+    }
+    seccomp()?;
+    log::info!("seccomp denies {} syscalls", DENIED.len());
+    return Ok(());
+}
+
+fn landlock(writable: &[PathBuf]) -> io::Result<i64> {
+    let abi = unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, std::ptr::null::<RulesetAttr>(), 0, LANDLOCK_CREATE_RULESET_VERSION) };
+    if abi < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut handled = ACCESS_FS_ABI_1;
+    if abi >= 2 {
+        handled |= ACCESS_FS_REFER;
+    }
+    if abi >= 3 {
+        handled |= ACCESS_FS_TRUNCATE;
+    }
+    let attr = RulesetAttr { handled_access_fs: handled };
+    let ruleset = unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, &attr, std::mem::size_of::<RulesetAttr>(), 0) };
+    if ruleset < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let ruleset = ruleset as libc::c_int;
+    let result = (|| {
+        allow(ruleset, Path::new("/"), ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR)?;
+        let files = ACCESS_FS_READ_FILE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_DIR | (handled & ACCESS_FS_TRUNCATE);
+        allow(ruleset, Path::new("/dev"), files)?;
+        for dir in writable {
+            std::fs::create_dir_all(dir)?;
+            allow(ruleset, dir, handled & !ACCESS_FS_EXECUTE)?;
+        }
+        if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(());
+    })();
+    unsafe { libc::close(ruleset) };
+    return result.map(|()| abi);
+}
+
+/// grant `access` beneath `path`
+fn allow(ruleset: libc::c_int, path: &Path, access: u64) -> io::Result<()> {
+    let c = CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let fd = unsafe { libc::open(c.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        let e = io::Error::last_os_error();
+        return Err(io::Error::new(e.kind(), format!("{}: {}", path.display(), e)));
+    }
+    let rule = PathBeneathAttr { allowed_access: access, parent_fd: fd };
+    let rc = unsafe { libc::syscall(libc::SYS_landlock_add_rule, ruleset, LANDLOCK_RULE_PATH_BENEATH, &rule, 0) };
+    let e = io::Error::last_os_error();
+    unsafe { libc::close(fd) };
+    if rc < 0 {
+        return Err(e);
+    }
+    return Ok(());
+}
+
+fn statement(code: u16, jt: u8, jf: u8, k: u32) -> libc::sock_filter {
+    return libc::sock_filter { code, jt, jf, k };
+}
+
+/// deny DENIED on every thread
+fn seccomp() -> io::Result<()> {
+    let errno = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+    let mut program = vec![
+        statement(BPF_LD_W_ABS, 0, 0, SECCOMP_DATA_ARCH),
+        // syscall numbers of another architecture mean something else, refuse them
+        statement(BPF_JMP_JEQ_K, 1, 0, AUDIT_ARCH),
+        statement(BPF_RET_K, 0, 0, libc::SECCOMP_RET_KILL_PROCESS),
+        statement(BPF_LD_W_ABS, 0, 0, SECCOMP_DATA_NR),
+    ];
+    // x32 syscalls would get past the denied numbers, refuse them too
+    #[cfg(target_arch = "x86_64")]
+    program.extend([statement(BPF_JMP_JGE_K, 0, 1, X32_SYSCALL_BIT), statement(BPF_RET_K, 0, 0, libc::SECCOMP_RET_KILL_PROCESS)]);
+    for nr in DENIED {
+        program.push(statement(BPF_JMP_JEQ_K, 0, 1, nr as u32));
+        program.push(statement(BPF_RET_K, 0, 0, errno));
+    }
+    program.push(statement(BPF_RET_K, 0, 0, libc::SECCOMP_RET_ALLOW));
+    let prog = libc::sock_fprog { len: program.len() as u16, filter: program.as_mut_ptr() };
+    let rc = unsafe { libc::syscall(libc::SYS_seccomp, libc::SECCOMP_SET_MODE_FILTER, libc::SECCOMP_FILTER_FLAG_TSYNC, &prog) };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if rc > 0 {
+        return Err(io::Error::other(format!("thread {} can't take the seccomp filter", rc)));
+    }
+    return Ok(());
+}
@@ -0,0 +1,271 @@
+//! module for manipurate scd30
+//! see https://sensirion.com/media/documents/D7CEEF4A/6165372F/Sensirion_CO2_Sensors_SCD30_Interface_Description.pdf
+use std::{fmt, thread, time::Duration};
+
+use embedded_hal::i2c;
+use rppal::gpio::OutputPin;
+use sensirion_i2c::{crc8, i2c::{read_words_with_crc, write_command_u16, Error}};
+
+use crate::{
+    raspi,
+    sensor::{Measurement, Sensor, Settings},
+};
+
+const SCD30_I2C_ADDR: u8 = 0x61;
+
+/// scd30 in continuous measurement mode
+pub(crate) struct Scd30<I> {
+    i2c: I,
+    offset: f32,
+    power: Option<OutputPin>,
+    /// ambient pressure in mbar, 0 disables compensation
+    pressure: u16,
+}
+
+impl<I> Scd30<I> {
+    /// `offset` is the temperature offset applied at start. `power` is the GPIO pin switching the sensor's power, if any.
+    pub(crate) fn new(i2c: I, offset: f32, power: Option<OutputPin>) -> Self {
+        return Scd30 { i2c, offset, power, pressure: 0 };
+    }
+}
+
+impl<I: i2c::I2c + fmt::Debug> Sensor for Scd30<I> {
+    type Error = Error<I>;
+
+    fn start(&mut self) -> Result<(), Self::Error> {
+        let _ = stop_continuous_measurement(&mut self.i2c).inspect_err(|e| log::trace!("stop error {:?}", e));
+        let (major, minor) = read_firmware_version(&mut self.i2c)?;
+        log::info!("scd30's firmware version: {}.{}", major, minor);
+        set_temperature_offset(&mut self.i2c, self.offset)?;
+        start_continuous_measurement(&mut self.i2c, self.pressure).map_err(Error::I2cWrite)?;
+        return Ok(());
+    }
+
+    fn measure(&mut self) -> Result<Option<Measurement>, Self::Error> {
+        if !get_data_ready_status(&mut self.i2c)? {
+            log::trace!("scd30 is not ready, but countinue");
+            return Ok(None);
+        }
+        return read_measurement(&mut self.i2c).map(Some);
+    }
+
+    fn set_ambient_pressure(&mut self, pressure: f32) -> Result<(), Self::Error> {
+        // issuing the start command again updates the pressure compensation
+        self.pressure = (pressure / 100_f32) as u16;
+        return start_continuous_measurement(&mut self.i2c, self.pressure).map_err(Error::I2cWrite);
+    }
+
+    fn sample_interval(&self) -> Duration {
+        // the default measurement interval
+        return Duration::from_secs(2);
+    }
+
+    fn set_temperature_offset(&mut self, offset: f32) -> Result<bool, Self::Error> {
+        set_temperature_offset(&mut self.i2c, offset)?;
+        self.offset = offset;
+        return Ok(true);
+    }
+
+    fn settings(&self) -> Settings {
+        return Settings {
+            temperature_offset: Some(self.offset),
+            ambient_pressure: (self.pressure != 0).then_some(self.pressure as f32 * 100.0),
+            ..Settings::default()
+        };
+    }
+
+    fn stop(&mut self) -> Result<(), Self::Error> {
+        return stop_continuous_measurement(&mut self.i2c).map_err(Error::I2cWrite);
+    }
+
+    fn recover(&mut self) {
+        log::warn!("scd30 stopped responding, try to recover");
+        if let Some(pin) = self.power.as_mut() {
+            log::info!("power-cycle scd30");
+            raspi::power_cycle(pin);
+        }
+        let _ = soft_reset(&mut self.i2c).inspect_err(|e| log::warn!("failed to reset scd30: {:?}", e));
+        let _ = set_temperature_offset(&mut self.i2c, self.offset).inspect_err(|e| log::warn!("failed to set temperature offset: {:?}", e));
+        let _ = start_continuous_measurement(&mut self.i2c, self.pressure).inspect_err(|e| log::warn!("failed to start scd30: {:?}", e));
+    }
+}
+
+/// write a command with one argument word
+fn write_command_with_arg<I: i2c::I2c>(i2c: &mut I, command: u16, arg: u16) -> Result<(), I::Error> {
+    let data = arg.to_be_bytes();
+    let mut buf = [0_u8; 5];
+    buf[0..2].copy_from_slice(&command.to_be_bytes());
+    buf[2..4].copy_from_slice(&data);
+    buf[4] = crc8::calculate(&data);
+    return i2c.write(SCD30_I2C_ADDR, &buf);
+}
+
+/// trigger continuous measurement (0x0010), `pressure` in mbar (0 disables pressure compensation)
+pub(crate) fn start_continuous_measurement<I: i2c::I2c>(i2c: &mut I, pressure: u16) -> Result<(), I::Error> {
+    write_command_with_arg(i2c, 0x0010, pressure)?;
+    return Ok(());
+}
+
+/// stop continuous measurement (0x0104)
+pub(crate) fn stop_continuous_measurement<I: i2c::I2c>(i2c: &mut I) -> Result<(), I::Error> {
+    write_command_u16(i2c, SCD30_I2C_ADDR, 0x0104)?;
+    return Ok(());
+}
+
+/// soft reset (0xD304)
+pub(crate) fn soft_reset<I: i2c::I2c>(i2c: &mut I) -> Result<(), I::Error> {
+    write_command_u16(i2c, SCD30_I2C_ADDR, 0xD304)?;
+    thread::sleep(Duration::from_secs(2));
+    return Ok(());
+}
+
+/// read firmware version (0xD100)
+pub(crate) fn read_firmware_version<I: i2c::I2c>(i2c: &mut I) -> Result<(u8, u8), Error<I>> {
+    write_command_u16(i2c, SCD30_I2C_ADDR, 0xD100).map_err(Error::I2cWrite)?;
+    thread::sleep(Duration::from_millis(3));
+
+    let mut buf = [0; 3];
+    read_words_with_crc(i2c, SCD30_I2C_ADDR, &mut buf)?;
+    return Ok((buf[0], buf[1]));
+}
+
+/// get data ready status (0x0202)
+pub(crate) fn get_data_ready_status<I: i2c::I2c>(i2c: &mut I) -> Result<bool, Error<I>> {
+    write_command_u16(i2c, SCD30_I2C_ADDR, 0x0202).map_err(Error::I2cWrite)?;
+    thread::sleep(Duration::from_millis(3));
+
+    let mut buf = [0; 3];
+    read_words_with_crc(i2c, SCD30_I2C_ADDR, &mut buf)?;
+    return Ok(buf[1] == 1);
+}
+
+/// read measurement (0x0300). values are big-endian f32 split over two words.
+pub(crate) fn read_measurement<I: i2c::I2c>(i2c: &mut I) -> Result<Measurement, Error<I>> {
+    write_command_u16(i2c, SCD30_I2C_ADDR, 0x0300).map_err(Error::I2cWrite)?;
+    thread::sleep(Duration::from_millis(3));
+
+    let mut buf = [0; 18];
+    read_words_with_crc(i2c, SCD30_I2C_ADDR, &mut buf)?;
+    let float = |i: usize| f32::from_be_bytes([buf[i], buf[i + 1], buf[i + 3], buf[i + 4]]);
+
+    return Ok(Measurement {
+        co2: float(0).round() as u16,
+        temperature: float(6),
+        humidity: float(12),
+    });
+}
+
+/// set temperature offset (0x5403) in 0.01 degC ticks
+pub(crate) fn set_temperature_offset<I: i2c::I2c>(i2c: &mut I, offset: f32) -> Result<(), Error<I>> {
+    write_command_with_arg(i2c, 0x5403, (offset * 100_f32) as u16).map_err(Error::I2cWrite)?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, Operation};
+
+    use super::*;
+
+    /// I2C bus which records writes and answers reads from a queue
+    #[derive(Debug, Default)]
+    struct MockI2c {
+        writes: Vec<Vec<u8>>,
+        reads: VecDeque<Vec<u8>>,
+    }
+
+    impl ErrorType for MockI2c {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for MockI2c {
+        fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+            assert_eq!(address, SCD30_I2C_ADDR);
+            for op in operations {
+                match op {
+                    Operation::Write(data) => self.writes.push(data.to_vec()),
+                    Operation::Read(buf) => {
+                        let data = self.reads.pop_front().ok_or(ErrorKind::Other)?;
+                        buf.copy_from_slice(&data);
+                    }
+                }
+            }
+            return Ok(());
+        }
+    }
+
+    /// encode words with sensirion's crc
+    fn words(values: &[u16]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for v in values {
+            let b = v.to_be_bytes();
+            out.extend_from_slice(&b);
+            out.push(crc8::calculate(&b));
+        }
+        return out;
+    }
+
+    /// encode floats as two words each, as the scd30 sends them
+    fn floats(values: &[f32]) -> Vec<u8> {
+        let split = |v: &f32| {
+            let b = v.to_be_bytes();
+            return [u16::from_be_bytes([b[0], b[1]]), u16::from_be_bytes([b[2], b[3]])];
+        };
+        return words(&values.iter().flat_map(split).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn crc() {
+        // the interface description's example
+        assert_eq!(crc8::calculate(&[0xBE, 0xEF]), 0x92);
+    }
+
+    #[test]
+    fn read_measurement_converts_floats() {
+        let mut i2c = MockI2c::default();
+        // the interface description's example: 439 ppm, 27.2 degC, 48.8 %RH
+        i2c.reads.push_back(words(&[0x43DB, 0x8C2E, 0x41D9, 0xE7FF, 0x4243, 0x3A1B]));
+        let m = read_measurement(&mut i2c).unwrap();
+        assert_eq!(m.co2, 439);
+        assert!((m.temperature - 27.24).abs() < 0.01, "{}", m.temperature);
+        assert!((m.humidity - 48.81).abs() < 0.01, "{}", m.humidity);
+        assert_eq!(i2c.writes, vec![vec![0x03, 0x00]]);
+
+        i2c.reads.push_back(floats(&[1200.4, -3.5, 100.0]));
+        let m = read_measurement(&mut i2c).unwrap();
+        assert_eq!((m.co2, m.temperature, m.humidity), (1200, -3.5, 100.0));
+    }
+
+    #[test]
+    fn read_measurement_rejects_bad_crc() {
+        let mut i2c = MockI2c::default();
+        // the second word of the temperature is broken
+        let mut data = floats(&[439.0, 27.2, 48.8]);
+        data[10] ^= 0x01;
+        i2c.reads.push_back(data);
+        assert!(matches!(read_measurement(&mut i2c), Err(Error::Crc)));
+    }
+
+    #[test]
+    fn status_and_version() {
+        let mut i2c = MockI2c::default();
+        i2c.reads.push_back(words(&[0x0000]));
+        i2c.reads.push_back(words(&[0x0001]));
+        i2c.reads.push_back(words(&[0x0342]));
+        assert!(!get_data_ready_status(&mut i2c).unwrap());
+        assert!(get_data_ready_status(&mut i2c).unwrap());
+        assert_eq!(read_firmware_version(&mut i2c).unwrap(), (3, 66));
+        assert_eq!(i2c.writes, vec![vec![0x02, 0x02], vec![0x02, 0x02], vec![0xD1, 0x00]]);
+    }
+
+    #[test]
+    fn commands_with_arguments() {
+        let mut i2c = MockI2c::default();
+        start_continuous_measurement(&mut i2c, 1013).unwrap();
+        set_temperature_offset(&mut i2c, 1.5).unwrap();
+        let crc = |w: u16| crc8::calculate(&w.to_be_bytes());
+        assert_eq!(i2c.writes, vec![vec![0x00, 0x10, 0x03, 0xF5, crc(1013)], vec![0x54, 0x03, 0x00, 0x96, crc(150)]]);
+    }
+}
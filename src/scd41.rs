@@ -1,123 +1,468 @@
 //! module for manipurate scd41
-//! see https://sensirion.com/media/documents/48C4B7FB/66E05452/CD_DS_SCD4x_Datasheet_D1.pdf
-use std::{thread, time::Duration};
+//! the commands are the scd4x library's, this is the exporter's sensor on top of them.
+use std::{
+    fmt,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
 use embedded_hal::i2c;
-use sensirion_i2c::{crc8, i2c::{read_words_with_crc, write_command_u16, Error}};
+use rppal::gpio::OutputPin;
+use scd4x::{Command, Error, StdDelay, Variant};
 
-const SCD41_I2C_ADDR: u8 = 0x62;
+use crate::{
+    backup,
+    clock::{Clock, SystemClock},
+    persist::Schedule,
+    profile::Mode,
+    raspi,
+    sensor::{Measurement, Sensor, Settings},
+};
 
-pub(crate) struct Measurement {
-    pub(crate) co2: u16,
-    pub(crate) temperature: f32,
-    pub(crate) humidity: f32,
+/// how to wait for the first measurement after starting
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Settle {
+    /// poll data-ready until it is set or the timeout passes
+    Poll(Duration),
+    Delay(Duration),
+    Skip,
 }
 
-/// clean scd41's state.
-pub(crate) fn clean_state<I: i2c::I2c>(i2c: &mut I) {
-    let _ = wakeup(i2c).inspect_err(|e| log::trace!("wakeup error {:?}", e));
-    let _ = stop_periodic_measurement(i2c).inspect_err(|e| log::trace!("stop error {:?}", e));
-    let _ = reinit(i2c).inspect_err(|e| log::trace!("reinit error {:?}", e));
+/// parse `poll:TIMEOUT`, `delay:DURATION` or `skip`
+pub(crate) fn parse_settle(s: &str) -> Result<Settle, String> {
+    let duration = |d: &str| humantime::parse_duration(d).map_err(|e| e.to_string());
+    return match s.split_once(':') {
+        None if s == "skip" => Ok(Settle::Skip),
+        Some(("poll", d)) => Ok(Settle::Poll(duration(d)?)),
+        Some(("delay", d)) => Ok(Settle::Delay(duration(d)?)),
+        _ => Err(String::from("expected poll:TIMEOUT, delay:DURATION or skip")),
+    };
 }
 
-/// wakeup (0x36F6)
-pub(crate) fn wakeup<I: i2c::I2c>(i2c: &mut I) -> Result<(), I::Error> {
-    write_command_u16(i2c, SCD41_I2C_ADDR, 0x36F6)?;
-    thread::sleep(Duration::from_millis(30));
-    return Ok(());
+/// scd41 measuring periodically, in low power or in single shots
+pub(crate) struct Scd41<I> {
+    driver: scd4x::Scd41<I, StdDelay>,
+    offset: f32,
+    power: Option<OutputPin>,
+    /// detected at start, None until then
+    variant: Option<Variant>,
+    serial: Option<u64>,
+    /// schedule for persisting ASC results, if enabled
+    persist: Option<Schedule>,
+    settle: Settle,
+    /// directory of configuration snapshots, if enabled
+    backup: Option<PathBuf>,
+    /// read at start, then as set at runtime
+    altitude: Option<u16>,
+    asc: Option<bool>,
+    /// last ambient pressure fed (Pa)
+    pressure: Option<f32>,
+    mode: Mode,
+    /// when the last single shot was taken
+    shot: Option<Instant>,
+    clock: Box<dyn Clock + Send>,
 }
 
-/// start_periodic_measurement (0x21B1)
-pub(crate) fn start_periodic_measurement<I: i2c::I2c>(i2c: &mut I) -> Result<(), I::Error> {
-    write_command_u16(i2c, SCD41_I2C_ADDR, 0x21B1)?;
-    thread::sleep(Duration::from_millis(1));
-    return Ok(());
-}
+impl<I> Scd41<I> {
+    /// `offset` is the temperature offset applied at start. `power` is the GPIO pin switching the sensor's power, if any.
+    pub(crate) fn new(i2c: I, offset: f32, power: Option<OutputPin>) -> Self {
+        return Scd41 {
+            driver: scd4x::Scd41::new(i2c, StdDelay),
+            offset,
+            power,
+            variant: None,
+            serial: None,
+            persist: None,
+            settle: Settle::Delay(Duration::from_secs(5)),
+            backup: None,
+            altitude: None,
+            asc: None,
+            pressure: None,
+            mode: Mode::Periodic,
+            shot: None,
+            clock: Box::new(SystemClock),
+        };
+    }
 
-/// stop_periodic_measurement (0x3F86)
-pub(crate) fn stop_periodic_measurement<I: i2c::I2c>(i2c: &mut I) -> Result<(), I::Error> {
-    write_command_u16(i2c, SCD41_I2C_ADDR, 0x3F86)?;
-    thread::sleep(Duration::from_millis(500));
-    return Ok(());
-}
+    pub(crate) fn with_settle(mut self, settle: Settle) -> Self {
+        self.settle = settle;
+        return self;
+    }
 
-/// reinit (0x3646)
-pub(crate) fn reinit<I: i2c::I2c>(i2c: &mut I) -> Result<(), I::Error> {
-    write_command_u16(i2c, SCD41_I2C_ADDR, 0x3646)?;
-    thread::sleep(Duration::from_millis(30));
-    return Ok(());
-}
+    /// wait and time single shots with `clock` instead of the system's
+    #[cfg(test)]
+    pub(crate) fn with_clock(mut self, clock: impl Clock + Send + 'static) -> Self {
+        self.clock = Box::new(clock);
+        return self;
+    }
 
-/// read_serial (0x3682)
-pub(crate) fn read_serial<I: i2c::I2c>(i2c: &mut I) -> Result<u64, Error<I>> {
-    write_command_u16(i2c, SCD41_I2C_ADDR, 0x3682).map_err(Error::I2cWrite)?;
-    thread::sleep(Duration::from_millis(1));
-
-    let mut buf = [0; 9];
-    read_words_with_crc(i2c, SCD41_I2C_ADDR, &mut buf)?;
-    let serial = ((buf[0] as u64) << 40)
-        | ((buf[1] as u64) << 32)
-        | ((buf[3] as u64) << 24)
-        | ((buf[4] as u64) << 16)
-        | ((buf[6] as u64) << 8)
-        | (buf[7] as u64);
-    return Ok(serial);
-}
+    fn wait_settled(&mut self) -> Result<(), Error<I::Error>>
+    where
+        I: i2c::I2c,
+    {
+        match self.settle {
+            Settle::Skip => {}
+            Settle::Delay(d) => self.clock.sleep(d),
+            Settle::Poll(timeout) => {
+                let start = self.clock.instant();
+                while !self.driver.get_data_ready_status()? {
+                    if self.clock.instant() - start >= timeout {
+                        log::warn!("no data ready {:?} after starting, continue anyway", timeout);
+                        break;
+                    }
+                    self.clock.sleep(Duration::from_millis(100));
+                }
+            }
+        }
+        return Ok(());
+    }
 
-/// data ready (0xE4B8)
-pub(crate) fn get_data_ready_status<I: i2c::I2c>(i2c: &mut I) -> Result<bool, Error<I>> {
-    write_command_u16(i2c, SCD41_I2C_ADDR, 0xE4B8).map_err(Error::I2cWrite)?;
-    thread::sleep(Duration::from_millis(1));
+    /// snapshot the configuration into `dir` whenever it's changed
+    pub(crate) fn with_backup(mut self, dir: PathBuf) -> Self {
+        self.backup = Some(dir);
+        return self;
+    }
 
-    let mut buf = [0; 3];
-    read_words_with_crc(i2c, SCD41_I2C_ADDR, &mut buf)?;
-    let status = ((buf[0] as u16) << 8) | (buf[1] as u16);
-    log::info!("ready value {:x}", status);
-    return Ok((status & 0x7FF) != 0);
+    /// persist the settings changed by automatic self-calibration following `schedule`
+    pub(crate) fn with_persist(mut self, schedule: Schedule) -> Self {
+        self.persist = Some(schedule);
+        return self;
+    }
 }
 
-/// read_measurement (0xEC05)
-pub(crate) fn read_measurement<I: i2c::I2c>(i2c: &mut I) -> Result<Measurement, Error<I>> {
-    write_command_u16(i2c, SCD41_I2C_ADDR, 0xEC05).map_err(Error::I2cWrite)?;
-    thread::sleep(Duration::from_millis(1));
+impl<I: i2c::I2c> Scd41<I> {
+    /// clean the sensor's state, skipping the wakeup on variants without power-down
+    fn clean_state(&mut self) {
+        match self.variant {
+            Some(v) if scd4x::check(v, Command::WakeUp).is_err() => {
+                let _ = self.driver.stop_periodic_measurement(scd4x::quirks(v).stop_delay)
+                    .inspect_err(|e| log::trace!("stop error {:?}", e));
+                let _ = self.driver.reinit().inspect_err(|e| log::trace!("reinit error {:?}", e));
+            }
+            _ => self.driver.clean_state(),
+        }
+    }
+
+    /// execution time of stop_periodic_measurement for the detected variant
+    fn stop_delay(&self) -> Duration {
+        return self.variant.map(|v| scd4x::quirks(v).stop_delay).unwrap_or(Duration::from_millis(500));
+    }
+
+    /// whether the sensor is powered down between single shots
+    fn sleeps(&self) -> bool {
+        return matches!(self.mode, Mode::SingleShot(_)) && self.variant.is_some_and(|v| scd4x::check(v, Command::PowerDown).is_ok());
+    }
+
+    /// stop measuring, or wake the sensor from between single shots, so it takes commands
+    fn idle(&mut self) -> Result<(), Error<I::Error>> {
+        return match self.mode {
+            Mode::SingleShot(_) if self.sleeps() => {
+                // the sensor doesn't acknowledge waking up
+                let _ = self.driver.wake_up().inspect_err(|e| log::trace!("wake up error {:?}", e));
+                Ok(())
+            }
+            Mode::SingleShot(_) => Ok(()),
+            _ => {
+                let stop_delay = self.stop_delay();
+                self.driver.stop_periodic_measurement(stop_delay)
+            }
+        };
+    }
 
-    let mut buf = [0; 9];
-    read_words_with_crc(i2c, SCD41_I2C_ADDR, &mut buf)?;
+    /// measure again in the mode after `idle`
+    fn resume(&mut self) -> Result<(), Error<I::Error>> {
+        return match self.mode {
+            Mode::Periodic => self.driver.start_periodic_measurement(),
+            Mode::LowPower => self.driver.start_low_power_periodic_measurement(),
+            Mode::SingleShot(_) if self.sleeps() => self.driver.power_down(),
+            Mode::SingleShot(_) => Ok(()),
+        };
+    }
 
-    let raw_co2 = ((buf[0] as u16) << 8) | (buf[1] as u16);
-    let raw_temperature = ((buf[3] as u16) << 8) | (buf[4] as u16);
-    let raw_humidity = ((buf[6] as u16) << 8) | (buf[7] as u16);
+    /// take a single shot, discarding the first after waking up
+    fn single_shot(&mut self) -> Result<Measurement, Error<I::Error>> {
+        let sleeps = self.sleeps();
+        if sleeps {
+            self.idle()?;
+            self.driver.measure_single_shot()?;
+            let _ = self.driver.read_measurement()?;
+        }
+        self.driver.measure_single_shot()?;
+        let measurement = self.driver.read_measurement();
+        if sleeps {
+            self.driver.power_down()?;
+        }
+        return measurement;
+    }
 
-    return Ok(Measurement {
-        co2: raw_co2,
-        temperature: raw_temperature as f32 * 175_f32 / 65535_f32 - 45_f32,
-        humidity: raw_humidity as f32 * 100_f32 / 65535_f32,
-    });
+    /// store a configuration snapshot if enabled. the sensor must be idle.
+    fn backup(&mut self)
+    where
+        I: fmt::Debug,
+    {
+        let (Some(dir), Some(serial), Some(variant)) = (&self.backup, self.serial, self.variant) else {
+            return;
+        };
+        match backup::read(&mut self.driver, serial, variant) {
+            Ok(snapshot) => match backup::store(dir, &snapshot) {
+                Ok(Some(path)) => log::info!("backup scd41 configuration to {}", path.display()),
+                Ok(None) => log::debug!("scd41 configuration unchanged"),
+                Err(e) => log::warn!("failed to store configuration backup: {:?}", e),
+            },
+            Err(e) => log::warn!("failed to read configuration for backup: {:?}", e),
+        }
+    }
 }
 
-#[allow(dead_code)]
-/// get_temperature_offset (0x2318)
-pub(crate) fn get_temperature_offset<I: i2c::I2c>(i2c: &mut I) -> Result<f32, Error<I>> {
-    write_command_u16(i2c, SCD41_I2C_ADDR, 0x2318).map_err(Error::I2cWrite)?;
-    thread::sleep(Duration::from_millis(1));
+impl<I: i2c::I2c + fmt::Debug> Sensor for Scd41<I> {
+    type Error = Error<I::Error>;
+
+    fn start(&mut self) -> Result<(), Self::Error> {
+        self.clean_state();
+        let serial = self.driver.read_serial()?;
+        log::info!("scd41's serial number: 0x{:x}", serial);
+        self.serial = Some(serial);
+        let variant = self.driver.get_sensor_variant()?;
+        log::info!("scd4x variant: {:?} ({:?})", variant, scd4x::quirks(variant));
+        self.variant = Some(variant);
+        self.driver.set_temperature_offset(self.offset)?;
+        let asc = self.driver.get_automatic_self_calibration_enabled()?;
+        self.asc = Some(asc);
+        self.altitude = Some(self.driver.get_sensor_altitude()?);
+        if self.persist.is_some() {
+            if !asc {
+                log::info!("asc is disabled, nothing to persist");
+                self.persist = None;
+            } else if scd4x::check(variant, Command::AscPeriods).is_ok() {
+                let hours = self.driver.get_automatic_self_calibration_standard_period()?;
+                log::info!("asc standard period: {}h", hours);
+                if let Some(p) = self.persist.as_mut() {
+                    p.set_asc_period(Duration::from_secs(hours as u64 * 3600));
+                }
+            }
+        }
+        self.backup();
+        self.resume()?;
+        if !matches!(self.mode, Mode::SingleShot(_)) {
+            self.wait_settled()?;
+        }
+        return Ok(());
+    }
+
+    fn maintenance_due(&self, now: Instant) -> bool {
+        return self.persist.as_ref().is_some_and(|p| p.check_due(now));
+    }
+
+    fn maintain(&mut self, now: Instant) -> Result<(), Self::Error> {
+        if !self.persist.as_mut().is_some_and(|s| s.should_write(now)) {
+            return Ok(());
+        }
+        log::info!("persist scd41 settings");
+        self.idle()?;
+        let persisted = self.driver.persist_settings();
+        if persisted.is_ok() {
+            self.backup();
+        }
+        // measure again even if persisting failed
+        self.resume()?;
+        persisted?;
+        if let Some(schedule) = self.persist.as_mut() {
+            schedule.written(now);
+        }
+        return Ok(());
+    }
+
+    fn serial(&self) -> Option<String> {
+        return self.serial.map(|s| format!("0x{:x}", s));
+    }
+
+    fn measure(&mut self) -> Result<Option<Measurement>, Self::Error> {
+        if let Mode::SingleShot(every) = self.mode {
+            let now = self.clock.instant();
+            if self.shot.is_some_and(|t| now - t < every) {
+                return Ok(None);
+            }
+            self.shot = Some(now);
+            return self.single_shot().map(Some);
+        }
+        if !self.driver.get_data_ready_status()? {
+            log::trace!("scd41 is not ready, but countinue");
+            return Ok(None);
+        }
+        return self.driver.read_measurement().map(Some);
+    }
+
+    fn set_ambient_pressure(&mut self, pressure: f32) -> Result<(), Self::Error> {
+        self.driver.set_ambient_pressure(pressure)?;
+        self.pressure = Some(pressure);
+        return Ok(());
+    }
+
+    fn set_temperature_offset(&mut self, offset: f32) -> Result<bool, Self::Error> {
+        // the offset can only be written while the sensor is idle
+        self.idle()?;
+        let set = self.driver.set_temperature_offset(offset);
+        if set.is_ok() {
+            self.offset = offset;
+            self.backup();
+        }
+        self.resume()?;
+        set?;
+        return Ok(true);
+    }
 
-    let mut buf = [0; 3];
-    read_words_with_crc(i2c, SCD41_I2C_ADDR, &mut buf)?;
-    let offset = ((buf[0] as u16) << 8) | (buf[1] as u16);
-    return Ok(offset as f32 * 175_f32 / 65535_f32);
+    fn set_altitude(&mut self, meters: u16) -> Result<bool, Self::Error> {
+        // like the offset, the altitude can only be written while the sensor is idle
+        self.idle()?;
+        let set = self.driver.set_sensor_altitude(meters);
+        if set.is_ok() {
+            self.altitude = Some(meters);
+            self.backup();
+        }
+        self.resume()?;
+        set?;
+        return Ok(true);
+    }
+
+    fn set_automatic_self_calibration(&mut self, enabled: bool) -> Result<bool, Self::Error> {
+        self.idle()?;
+        let set = self.driver.set_automatic_self_calibration_enabled(enabled);
+        if set.is_ok() {
+            self.asc = Some(enabled);
+            self.backup();
+        }
+        self.resume()?;
+        set?;
+        return Ok(true);
+    }
+
+    fn settings(&self) -> Settings {
+        return Settings {
+            temperature_offset: Some(self.offset),
+            altitude: self.altitude,
+            ambient_pressure: self.pressure,
+            automatic_self_calibration: self.asc,
+        };
+    }
+
+    fn force_recalibration(&mut self, target: u16) -> Result<Option<i16>, Self::Error> {
+        self.idle()?;
+        let correction = self.driver.perform_forced_recalibration(target);
+        // measure again even if the recalibration failed
+        self.resume()?;
+        return correction;
+    }
+
+    fn self_test(&mut self) -> Result<Option<u16>, Self::Error> {
+        self.idle()?;
+        let word = self.driver.perform_self_test();
+        // measure again even if the self-test failed
+        self.resume()?;
+        return word.map(Some);
+    }
+
+    fn stop(&mut self) -> Result<(), Self::Error> {
+        return self.idle();
+    }
+
+    fn set_mode(&mut self, mode: Mode) -> Result<bool, Self::Error> {
+        if let (Mode::SingleShot(_), Some(Err(e))) = (mode, self.variant.map(|v| scd4x::check(v, Command::MeasureSingleShot))) {
+            log::info!("{}", e);
+            return Ok(false);
+        }
+        self.idle()?;
+        self.mode = mode;
+        self.shot = None;
+        self.resume()?;
+        return Ok(true);
+    }
+
+    fn sample_interval(&self) -> Duration {
+        return self.mode.interval();
+    }
+
+    fn power_down(&mut self) -> Result<bool, Self::Error> {
+        if let Some(Err(e)) = self.variant.map(|v| scd4x::check(v, Command::PowerDown)) {
+            log::info!("{}", e);
+            return Ok(false);
+        }
+        self.driver.power_down()?;
+        return Ok(true);
+    }
+
+    fn recover(&mut self) {
+        log::warn!("scd41 stopped responding, try to recover");
+        if let Some(pin) = self.power.as_mut() {
+            log::info!("power-cycle scd41");
+            raspi::power_cycle(pin);
+        }
+        self.clean_state();
+        // the sensor answers again only if this works
+        match self.driver.read_serial() {
+            Ok(serial) if self.serial.is_some_and(|s| s != serial) => {
+                log::warn!("scd41 was replaced, serial number 0x{:x}", serial);
+                self.serial = Some(serial);
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("failed to read the serial number: {:?}", e),
+        }
+        // reinit restored the settings from the eeprom, so asc starts over
+        if let Some(p) = self.persist.as_mut() {
+            p.reset(self.clock.instant());
+        }
+        let _ = self.driver.set_temperature_offset(self.offset).inspect_err(|e| log::warn!("failed to set temperature offset: {:?}", e));
+        // settings changed at runtime may not have been persisted
+        if let Some(meters) = self.altitude {
+            let _ = self.driver.set_sensor_altitude(meters).inspect_err(|e| log::warn!("failed to set altitude: {:?}", e));
+        }
+        if let Some(enabled) = self.asc {
+            let _ = self.driver.set_automatic_self_calibration_enabled(enabled).inspect_err(|e| log::warn!("failed to set asc: {:?}", e));
+        }
+        let _ = self.resume().inspect_err(|e| log::warn!("failed to start scd41: {:?}", e));
+    }
 }
 
-/// set_temperature_offset (0x241d)
-pub(crate) fn set_temperature_offset<I: i2c::I2c>(i2c: &mut I, offset: f32) -> Result<(), Error<I>> {
-    let offset = offset * 65535_f32 / 175_f32;
-    let offset = offset as u16;
-    let data = offset.to_be_bytes();
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::UNIX_EPOCH};
+
+    use embedded_hal::i2c::{ErrorKind, ErrorType, Operation};
+    use sensirion_i2c::crc8;
+
+    use super::*;
+    use crate::clock::FakeClock;
+
+    /// I2C bus of an scd41 that never has data ready
+    #[derive(Debug)]
+    struct NotReady;
+
+    impl ErrorType for NotReady {
+        type Error = ErrorKind;
+    }
+
+    impl i2c::I2c for NotReady {
+        fn transaction(&mut self, _address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+            for op in operations {
+                if let Operation::Read(buf) = op {
+                    buf.copy_from_slice(&[0x80, 0x00, crc8::calculate(&[0x80, 0x00])]);
+                }
+            }
+            return Ok(());
+        }
+    }
 
-    let mut buf = [0_u8; 5];
-    buf[0..2].copy_from_slice(&(0x241d_u16).to_be_bytes());
-    buf[2..4].copy_from_slice(&data);
-    buf[4] = crc8::calculate(&data);
+    #[test]
+    fn settling_waits_on_the_monotonic_clock() {
+        let clock = Arc::new(FakeClock::new(UNIX_EPOCH + Duration::from_secs(1_800_000_000)));
+        let start = clock.instant();
+        let mut scd41 = Scd41::new(NotReady, 0.0, None).with_settle(Settle::Poll(Duration::from_secs(1))).with_clock(clock.clone());
+        // the wall clock stepping back doesn't stretch the timeout
+        clock.set(UNIX_EPOCH);
+        scd41.wait_settled().unwrap();
+        assert_eq!(clock.instant() - start, Duration::from_secs(1));
 
-    i2c.write(SCD41_I2C_ADDR, &buf).map_err(Error::I2cWrite)?;
-    return Ok(());
+        scd41.settle = Settle::Delay(Duration::from_secs(5));
+        scd41.wait_settled().unwrap();
+        assert_eq!(clock.instant() - start, Duration::from_secs(6));
+    }
 }
@@ -0,0 +1,41 @@
+//! module for raising the sampling thread's scheduling priority
+use std::{io, mem};
+
+/// scheduling settings for the calling thread
+#[derive(Debug, Default)]
+pub(crate) struct Options {
+    /// SCHED_FIFO priority (1-99)
+    pub(crate) fifo: Option<i32>,
+    /// niceness (-20 to 19)
+    pub(crate) nice: Option<i32>,
+    /// CPU to pin to
+    pub(crate) cpu: Option<usize>,
+}
+
+/// apply `options` to the calling thread
+pub(crate) fn apply(options: &Options) -> io::Result<()> {
+    if let Some(priority) = options.fifo {
+        let param = libc::sched_param { sched_priority: priority };
+        // pid 0 is the calling thread
+        if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        log::info!("sampler thread runs with SCHED_FIFO priority {}", priority);
+    }
+    if let Some(nice) = options.nice {
+        let tid = unsafe { libc::gettid() } as libc::id_t;
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, nice) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        log::info!("sampler thread runs with niceness {}", nice);
+    }
+    if let Some(cpu) = options.cpu {
+        let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+        if unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        log::info!("sampler thread is pinned to cpu {}", cpu);
+    }
+    return Ok(());
+}
@@ -0,0 +1,41 @@
+//! module for running the sensor's self-test on a schedule
+//! --self-test-schedule names the days and local time, e.g. sun@03:00, a time nobody watches the readings.
+//! the measurement loop runs it between two measurements, stopping and restarting the measurement around
+//! it, so about 10 seconds of readings are missed. the result is exported as scd41_self_test_passed.
+use std::{
+    io, thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{Clock, Schedule, SystemClock},
+    control::{self, Action},
+    shutdown,
+};
+
+/// run the self-test on `schedule` from a thread
+pub(crate) fn spawn(schedule: Schedule) -> io::Result<()> {
+    let runs = |result| metrics::counter!("scd41_scheduled_self_tests_total", "result" => result);
+    thread::Builder::new().name(String::from("self-test")).spawn(move || {
+        let clock = SystemClock;
+        let mut last: Option<Instant> = None;
+        while !shutdown::requested() {
+            clock.sleep(Duration::from_secs(1));
+            if !schedule.due(&clock, &mut last) {
+                continue;
+            }
+            log::info!("scheduled self-test");
+            match control::submit(Action::SelfTest) {
+                Ok(_) => {
+                    log::info!("the self-test passed");
+                    runs("passed").increment(1);
+                }
+                Err(e) => {
+                    log::warn!("scheduled self-test failed: {}", e);
+                    runs("failed").increment(1);
+                }
+            }
+        }
+    })?;
+    return Ok(());
+}
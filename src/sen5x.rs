@@ -0,0 +1,129 @@
+//! module for manipurate sen5x (SEN50/SEN54/SEN55) particulate matter sensors
+//! see https://sensirion.com/media/documents/6791EFA0/62A1F68F/Sensirion_Datasheet_Environmental_Node_SEN5x.pdf
+use std::{fmt, thread, time::Duration};
+
+use embedded_hal::i2c;
+use sensirion_i2c::i2c::{read_words_with_crc, write_command_u16, Error};
+
+use crate::plugin::{Plugin, Reading};
+
+const SEN5X_I2C_ADDR: u8 = 0x69;
+
+/// measured values. fields the sensor variant doesn't support are None.
+pub(crate) struct Measurement {
+    pub(crate) pm1_0: Option<f32>,
+    pub(crate) pm2_5: Option<f32>,
+    pub(crate) pm4_0: Option<f32>,
+    pub(crate) pm10: Option<f32>,
+    pub(crate) humidity: Option<f32>,
+    pub(crate) temperature: Option<f32>,
+    pub(crate) voc_index: Option<f32>,
+    pub(crate) nox_index: Option<f32>,
+}
+
+/// start_measurement (0x0021)
+pub(crate) fn start_measurement<I: i2c::I2c>(i2c: &mut I) -> Result<(), I::Error> {
+    write_command_u16(i2c, SEN5X_I2C_ADDR, 0x0021)?;
+    thread::sleep(Duration::from_millis(50));
+    return Ok(());
+}
+
+/// device_reset (0xD304)
+pub(crate) fn device_reset<I: i2c::I2c>(i2c: &mut I) -> Result<(), I::Error> {
+    write_command_u16(i2c, SEN5X_I2C_ADDR, 0xD304)?;
+    thread::sleep(Duration::from_millis(100));
+    return Ok(());
+}
+
+/// read_product_name (0xD014)
+pub(crate) fn read_product_name<I: i2c::I2c>(i2c: &mut I) -> Result<String, Error<I>> {
+    write_command_u16(i2c, SEN5X_I2C_ADDR, 0xD014).map_err(Error::I2cWrite)?;
+    thread::sleep(Duration::from_millis(20));
+
+    let mut buf = [0; 48];
+    read_words_with_crc(i2c, SEN5X_I2C_ADDR, &mut buf)?;
+    let name: Vec<u8> = buf
+        .chunks(3)
+        .flat_map(|w| [w[0], w[1]])
+        .take_while(|b| *b != 0)
+        .collect();
+    return Ok(String::from_utf8_lossy(&name).into_owned());
+}
+
+/// read_data_ready (0x0202)
+pub(crate) fn read_data_ready<I: i2c::I2c>(i2c: &mut I) -> Result<bool, Error<I>> {
+    write_command_u16(i2c, SEN5X_I2C_ADDR, 0x0202).map_err(Error::I2cWrite)?;
+    thread::sleep(Duration::from_millis(20));
+
+    let mut buf = [0; 3];
+    read_words_with_crc(i2c, SEN5X_I2C_ADDR, &mut buf)?;
+    return Ok(buf[1] & 0x01 != 0);
+}
+
+/// read_measured_values (0x03C4)
+pub(crate) fn read_measured_values<I: i2c::I2c>(i2c: &mut I) -> Result<Measurement, Error<I>> {
+    write_command_u16(i2c, SEN5X_I2C_ADDR, 0x03C4).map_err(Error::I2cWrite)?;
+    thread::sleep(Duration::from_millis(20));
+
+    let mut buf = [0; 24];
+    read_words_with_crc(i2c, SEN5X_I2C_ADDR, &mut buf)?;
+    let word = |i: usize| [buf[i * 3], buf[i * 3 + 1]];
+    let unsigned = |i: usize, scale: f32| Some(u16::from_be_bytes(word(i))).filter(|v| *v != 0xFFFF).map(|v| v as f32 / scale);
+    let signed = |i: usize, scale: f32| Some(i16::from_be_bytes(word(i))).filter(|v| *v != 0x7FFF).map(|v| v as f32 / scale);
+
+    return Ok(Measurement {
+        pm1_0: unsigned(0, 10_f32),
+        pm2_5: unsigned(1, 10_f32),
+        pm4_0: unsigned(2, 10_f32),
+        pm10: unsigned(3, 10_f32),
+        humidity: signed(4, 100_f32),
+        temperature: signed(5, 200_f32),
+        voc_index: signed(6, 10_f32),
+        nox_index: signed(7, 10_f32),
+    });
+}
+
+/// sen5x plugin
+pub(crate) struct Sen5x<I> {
+    i2c: I,
+}
+
+impl<I> Sen5x<I> {
+    pub(crate) fn new(i2c: I) -> Self {
+        return Sen5x { i2c };
+    }
+}
+
+impl<I: i2c::I2c + fmt::Debug + Send> Plugin for Sen5x<I> {
+    fn start(&mut self) -> Result<(), String> {
+        let _ = device_reset(&mut self.i2c).inspect_err(|e| log::trace!("reset error {:?}", e));
+        let name = read_product_name(&mut self.i2c).map_err(|e| format!("{:?}", e))?;
+        log::info!("sen5x's product name: {}", name);
+        start_measurement(&mut self.i2c).map_err(|e| format!("{:?}", e))?;
+        return Ok(());
+    }
+
+    fn read(&mut self) -> Result<Vec<Reading>, String> {
+        if !read_data_ready(&mut self.i2c).map_err(|e| format!("{:?}", e))? {
+            log::trace!("sen5x is not ready, but countinue");
+            return Ok(Vec::new());
+        }
+        let m = read_measured_values(&mut self.i2c).map_err(|e| format!("{:?}", e))?;
+        let values = [
+            ("sen5x_pm1_0_ug_m3", m.pm1_0),
+            ("sen5x_pm2_5_ug_m3", m.pm2_5),
+            ("sen5x_pm4_0_ug_m3", m.pm4_0),
+            ("sen5x_pm10_ug_m3", m.pm10),
+            ("sen5x_humidity_rh", m.humidity),
+            ("sen5x_temperature_celsius", m.temperature),
+            ("sen5x_voc_index", m.voc_index),
+            ("sen5x_nox_index", m.nox_index),
+        ];
+        return Ok(values.into_iter().filter_map(|(name, v)| Some((name, v? as f64))).collect());
+    }
+
+    fn recover(&mut self) {
+        let _ = device_reset(&mut self.i2c).inspect_err(|e| log::warn!("failed to reset sen5x: {:?}", e));
+        let _ = start_measurement(&mut self.i2c).inspect_err(|e| log::warn!("failed to start sen5x: {:?}", e));
+    }
+}
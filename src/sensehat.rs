@@ -0,0 +1,145 @@
+//! module for the 8x8 LED matrix of a Raspberry Pi Sense HAT
+//! the rpisense-fb kernel driver exposes the matrix as a framebuffer of RGB565 pixels. the CO2 level is
+//! shown in a color from green through yellow to red, either filling the matrix or as the value scrolling by.
+use std::{
+    fs::{self, File},
+    io::{Seek, SeekFrom, Write},
+    thread,
+    time::Duration,
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    display::{Canvas, Locale, Screen, View},
+    shutdown,
+};
+
+const SIZE: usize = 8;
+/// name of the matrix's framebuffer in /sys/class/graphics
+const FB_NAME: &str = "RPi-Sense FB";
+/// time a scrolling text takes per column
+const SCROLL_STEP: Duration = Duration::from_millis(60);
+/// CO2 shown green, yellow and red, in between it's blended
+const GRADIENT: [(u16, [u8; 3]); 3] = [(600, [0, 255, 0]), (1000, [255, 200, 0]), (1400, [255, 0, 0])];
+/// color of the sensor's status while there's no valid data
+const STATUS_COLOR: [u8; 3] = [80, 80, 80];
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Style {
+    /// the whole matrix in the CO2 level's color
+    Fill,
+    /// the value scrolling by in the CO2 level's color
+    Scroll,
+}
+
+/// parse a rotation of the matrix in degrees
+pub(crate) fn parse_rotation(s: &str) -> Result<u16, String> {
+    return match s.parse() {
+        Ok(r @ (0 | 90 | 180 | 270)) => Ok(r),
+        _ => Err(format!("the rotation is 0, 90, 180 or 270, not {}", s)),
+    };
+}
+
+pub(crate) struct SenseHat {
+    fb: File,
+    style: Style,
+    /// percent the colors are scaled to
+    brightness: u8,
+    rotation: u16,
+}
+
+impl SenseHat {
+    /// open the matrix's framebuffer and clear it
+    pub(crate) fn new(style: Style, brightness: u8, rotation: u16) -> Result<Self, String> {
+        let device = fs::read_dir("/sys/class/graphics")
+            .map_err(|e| format!("failed to list the framebuffers: {}", e))?
+            .flatten()
+            .find(|entry| fs::read_to_string(entry.path().join("name")).is_ok_and(|name| name.trim() == FB_NAME))
+            .map(|entry| format!("/dev/{}", entry.file_name().to_string_lossy()))
+            .ok_or_else(|| String::from("no Sense HAT LED matrix found, is the rpisense-fb driver loaded?"))?;
+        let fb = File::options().write(true).open(&device).map_err(|e| format!("failed to open {}: {}", device, e))?;
+        let mut hat = SenseHat { fb, style, brightness: brightness.min(100), rotation };
+        hat.draw(&[[0; 3]; SIZE * SIZE])?;
+        return Ok(hat);
+    }
+
+    /// write the row-major `pixels` turned by the rotation
+    fn draw(&mut self, pixels: &[[u8; 3]; SIZE * SIZE]) -> Result<(), String> {
+        let mut buf = [0; SIZE * SIZE * 2];
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let (fx, fy) = match self.rotation {
+                    90 => (SIZE - 1 - y, x),
+                    180 => (SIZE - 1 - x, SIZE - 1 - y),
+                    270 => (y, SIZE - 1 - x),
+                    _ => (x, y),
+                };
+                let [r, g, b] = pixels[y * SIZE + x].map(|c| (c as u16 * self.brightness as u16 / 100) as u8);
+                let rgb565 = (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3;
+                buf[(fy * SIZE + fx) * 2..][..2].copy_from_slice(&rgb565.to_le_bytes());
+            }
+        }
+        self.fb.seek(SeekFrom::Start(0)).map_err(|e| format!("{:?}", e))?;
+        return self.fb.write_all(&buf).map_err(|e| format!("{:?}", e));
+    }
+
+    /// scroll `text` from the right edge out to the left in `color`
+    fn scroll(&mut self, text: &str, color: [u8; 3]) -> Result<(), String> {
+        let width = Canvas::text_width(text, 1) + SIZE;
+        let mut canvas = Canvas::new(width + SIZE, SIZE);
+        canvas.text(SIZE, 0, text, 1);
+        for offset in 0..width {
+            if shutdown::requested() {
+                break;
+            }
+            let mut pixels = [[0; 3]; SIZE * SIZE];
+            for (i, pixel) in pixels.iter_mut().enumerate() {
+                if canvas.get(offset + i % SIZE, i / SIZE) {
+                    *pixel = color;
+                }
+            }
+            self.draw(&pixels)?;
+            thread::sleep(SCROLL_STEP);
+        }
+        return Ok(());
+    }
+}
+
+/// the gradient's color of `co2`
+fn color(co2: u16) -> [u8; 3] {
+    let (first, last) = (GRADIENT[0], GRADIENT[GRADIENT.len() - 1]);
+    if co2 <= first.0 {
+        return first.1;
+    }
+    if co2 >= last.0 {
+        return last.1;
+    }
+    let i = GRADIENT.iter().position(|&(ppm, _)| co2 < ppm).unwrap_or(GRADIENT.len() - 1);
+    let ((low, from), (high, to)) = (GRADIENT[i - 1], GRADIENT[i]);
+    let t = (co2 - low) as f32 / (high - low) as f32;
+    return [0, 1, 2].map(|c| (from[c] as f32 + (to[c] as f32 - from[c] as f32) * t).round() as u8);
+}
+
+impl Screen for SenseHat {
+    fn show(&mut self, view: &View, _: &Locale) -> Result<(), String> {
+        return match (view, self.style) {
+            (View::Reading { co2, .. }, Style::Fill) => self.draw(&[color(*co2); SIZE * SIZE]),
+            (View::Reading { co2, .. }, Style::Scroll) => self.scroll(&format!("{} ppm", co2), color(*co2)),
+            (View::Status(_), Style::Fill) => self.draw(&[[0; 3]; SIZE * SIZE]),
+            (View::Status(status), Style::Scroll) => self.scroll(status.name(), STATUS_COLOR),
+        };
+    }
+
+    fn power(&mut self, on: bool) -> Result<(), String> {
+        // the next refresh draws it again
+        if on {
+            return Ok(());
+        }
+        return self.draw(&[[0; 3]; SIZE * SIZE]);
+    }
+
+    fn cycles(&self) -> bool {
+        return self.style == Style::Scroll;
+    }
+}
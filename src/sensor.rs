@@ -0,0 +1,336 @@
+//! module for the interface between the exporter and its measurement sources
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+pub(crate) use scd4x::Measurement;
+
+use crate::{clock::Clock, json, profile::Mode, protobuf};
+
+/// quality flags of a sample, a bit set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Quality(u8);
+
+impl Quality {
+    /// first sample after the sensor (re)started, it may still be settling
+    pub(crate) const FIRST: Quality = Quality(0x01);
+    /// more than two poll intervals passed since the previous sample
+    pub(crate) const LATE: Quality = Quality(0x02);
+    /// taken during a burst capture, which bypasses filtering and smoothing
+    pub(crate) const BURST: Quality = Quality(0x04);
+
+    pub(crate) fn contains(self, flag: Quality) -> bool {
+        return self.0 & flag.0 == flag.0;
+    }
+
+    pub(crate) fn insert(&mut self, flag: Quality) {
+        self.0 |= flag.0;
+    }
+
+    pub(crate) fn bits(self) -> u8 {
+        return self.0;
+    }
+}
+
+/// settings adjustable at runtime, None for those a sensor doesn't have
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Settings {
+    /// degC
+    pub(crate) temperature_offset: Option<f32>,
+    /// meters above sea level
+    pub(crate) altitude: Option<u16>,
+    /// Pa, the last value fed
+    pub(crate) ambient_pressure: Option<f32>,
+    pub(crate) automatic_self_calibration: Option<bool>,
+}
+
+impl Settings {
+    pub(crate) fn to_json(self) -> String {
+        let or_null = |v: Option<String>| v.unwrap_or_else(|| String::from("null"));
+        return format!(
+            r#"{{"temperature_offset":{},"altitude":{},"ambient_pressure":{},"automatic_self_calibration":{}}}"#,
+            or_null(self.temperature_offset.map(|v| format!("{:.2}", v))),
+            or_null(self.altitude.map(|v| v.to_string())),
+            or_null(self.ambient_pressure.map(|v| format!("{:.0}", v))),
+            or_null(self.automatic_self_calibration.map(|v| v.to_string())),
+        );
+    }
+}
+
+/// (name, unit, lowest and highest value the sensor reports) of a measurement's channels, the units as in SenML (RFC 8428)
+pub(crate) const CHANNELS: [(&str, &str, f64, f64); 3] = [("co2", "ppm", 300.0, 40000.0), ("temperature", "Cel", -10.0, 60.0), ("humidity", "%RH", 0.0, 100.0)];
+
+/// the unit and range of each channel as a JSON object, e.g. `{"co2":{"unit":"ppm","min":300,"max":40000},...}`
+pub(crate) fn metadata() -> String {
+    let channels: Vec<String> = CHANNELS
+        .iter()
+        .map(|(name, unit, min, max)| format!(r#"{}:{{"unit":{},"min":{},"max":{}}}"#, json::quote(name), json::quote(unit), min, max))
+        .collect();
+    return format!("{{{}}}", channels.join(","));
+}
+
+/// a measurement with everything sinks need to order, deduplicate and judge it
+#[derive(Debug, Clone)]
+pub(crate) struct Envelope {
+    /// per source, counting from 1 at process start. a skipped number means a sample was lost.
+    pub(crate) seq: u64,
+    /// monotonic time of the sample
+    pub(crate) instant: Instant,
+    /// wall time of the sample in unix ms
+    pub(crate) timestamp_ms: u64,
+    /// the sensor that took the sample
+    pub(crate) source: Arc<str>,
+    pub(crate) quality: Quality,
+    pub(crate) measurement: Measurement,
+}
+
+impl Envelope {
+    /// the envelope as a JSON object, as published by the outputs
+    pub(crate) fn to_json(&self) -> String {
+        let m = &self.measurement;
+        return format!(
+            r#"{{"seq":{},"timestamp_ms":{},"source":{},"quality":{},"co2":{},"temperature":{:.2},"humidity":{:.2}}}"#,
+            self.seq,
+            self.timestamp_ms,
+            json::quote(&self.source),
+            self.quality.bits(),
+            m.co2,
+            m.temperature,
+            m.humidity
+        );
+    }
+
+    /// the JSON object with the channels' units and ranges as `meta`, see `metadata`
+    pub(crate) fn to_json_with_metadata(&self) -> String {
+        let json = self.to_json();
+        return format!(r#"{},"meta":{}}}"#, &json[..json.len() - 1], metadata());
+    }
+
+    /// the envelope as a protocol buffers message, as published by the message bus outputs:
+    /// timestamp_ms = 1, seq = 2, source = 3, co2 = 4, temperature = 5 and humidity = 6 (doubles),
+    /// serial = 7 if known, quality = 8
+    pub(crate) fn to_protobuf(&self, serial: Option<&str>) -> Vec<u8> {
+        let m = &self.measurement;
+        let mut out = Vec::new();
+        protobuf::uint(&mut out, 1, self.timestamp_ms);
+        protobuf::uint(&mut out, 2, self.seq);
+        protobuf::bytes(&mut out, 3, self.source.as_bytes());
+        protobuf::uint(&mut out, 4, m.co2 as u64);
+        protobuf::double(&mut out, 5, m.temperature as f64);
+        protobuf::double(&mut out, 6, m.humidity as f64);
+        if let Some(serial) = serial {
+            protobuf::bytes(&mut out, 7, serial.as_bytes());
+        }
+        protobuf::uint(&mut out, 8, self.quality.bits() as u64);
+        return out;
+    }
+}
+
+/// wraps the measurements of one sensor into envelopes
+pub(crate) struct Sequencer {
+    source: Arc<str>,
+    interval: Duration,
+    next: u64,
+    last: Option<Instant>,
+    restarted: bool,
+}
+
+impl Sequencer {
+    /// `interval` is the sensor's expected sample interval
+    pub(crate) fn new(source: &str, interval: Duration) -> Self {
+        return Sequencer {
+            source: Arc::from(source),
+            interval,
+            next: 1,
+            last: None,
+            restarted: true,
+        };
+    }
+
+    /// the sensor measures every `interval` from now on
+    pub(crate) fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// the next sample is the first after a sensor restart
+    pub(crate) fn restart(&mut self) {
+        self.restarted = true;
+    }
+
+    pub(crate) fn wrap(&mut self, measurement: Measurement, clock: &dyn Clock) -> Envelope {
+        let instant = clock.instant();
+        let mut quality = Quality::default();
+        if std::mem::take(&mut self.restarted) {
+            quality.insert(Quality::FIRST);
+        }
+        if self.last.is_some_and(|last| instant.duration_since(last) > self.interval * 2) {
+            quality.insert(Quality::LATE);
+        }
+        let timestamp_ms = clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .inspect_err(|e| log::warn!("failed to get current time: {:?}", e))
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let seq = self.next;
+        self.next += 1;
+        self.last = Some(instant);
+        return Envelope {
+            seq,
+            instant,
+            timestamp_ms,
+            source: self.source.clone(),
+            quality,
+            measurement,
+        };
+    }
+}
+
+/// source of measurements
+pub(crate) trait Sensor {
+    type Error: fmt::Debug + Into<crate::error::Error>;
+
+    /// initialize the sensor and start measuring
+    fn start(&mut self) -> Result<(), Self::Error>;
+
+    /// read a new measurement, or None when no new one is available yet
+    fn measure(&mut self) -> Result<Option<Measurement>, Self::Error>;
+
+    /// feed the ambient pressure (Pa) for pressure compensation
+    fn set_ambient_pressure(&mut self, _pressure: f32) -> Result<(), Self::Error> {
+        return Ok(());
+    }
+
+    /// change the temperature offset (degC) at runtime, false if the sensor has none
+    fn set_temperature_offset(&mut self, _offset: f32) -> Result<bool, Self::Error> {
+        return Ok(false);
+    }
+
+    /// change the altitude (m) used for pressure compensation, false if the sensor has none
+    fn set_altitude(&mut self, _meters: u16) -> Result<bool, Self::Error> {
+        return Ok(false);
+    }
+
+    /// enable or disable automatic self-calibration, false if the sensor has none
+    fn set_automatic_self_calibration(&mut self, _enabled: bool) -> Result<bool, Self::Error> {
+        return Ok(false);
+    }
+
+    /// the settings as last read or written, without talking to the sensor
+    fn settings(&self) -> Settings {
+        return Settings::default();
+    }
+
+    /// forced recalibration to a reference of `target` ppm, returning the correction (ppm).
+    /// None if the sensor doesn't support it or rejected the recalibration.
+    fn force_recalibration(&mut self, _target: u16) -> Result<Option<i16>, Self::Error> {
+        return Ok(None);
+    }
+
+    /// run the sensor's self-test, returning its malfunction word (0 if it passed). None if the sensor has none.
+    fn self_test(&mut self) -> Result<Option<u16>, Self::Error> {
+        return Ok(None);
+    }
+
+    /// measure in `mode` from now on, false if the sensor can't
+    fn set_mode(&mut self, _mode: Mode) -> Result<bool, Self::Error> {
+        return Ok(false);
+    }
+
+    /// try to bring a non-responding sensor back
+    fn recover(&mut self) {}
+
+    /// stop measuring, leaving the sensor idle
+    fn stop(&mut self) -> Result<(), Self::Error> {
+        return Ok(());
+    }
+
+    /// put the idle sensor into its lowest power state. false if it has none.
+    fn power_down(&mut self) -> Result<bool, Self::Error> {
+        return Ok(false);
+    }
+
+    /// serial number read at start, if the sensor has one
+    fn serial(&self) -> Option<String> {
+        return None;
+    }
+
+    /// whether `maintain` has work to do at `now`
+    fn maintenance_due(&self, _now: Instant) -> bool {
+        return false;
+    }
+
+    /// periodic maintenance such as persisting settings
+    fn maintain(&mut self, _now: Instant) -> Result<(), Self::Error> {
+        return Ok(());
+    }
+
+    /// how often the sensor produces a new measurement
+    fn sample_interval(&self) -> Duration {
+        return Duration::from_secs(5);
+    }
+
+    /// how often `measure` should be called
+    fn poll_interval(&self) -> Duration {
+        return Duration::from_secs(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::clock::FakeClock;
+
+    const M: Measurement = Measurement { co2: 800, temperature: 21.0, humidity: 40.0 };
+
+    #[test]
+    fn sequence_numbers() {
+        let clock = FakeClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut sequencer = Sequencer::new("scd41", Duration::from_secs(5));
+        let first = sequencer.wrap(M, &clock);
+        assert_eq!((first.seq, first.quality), (1, Quality::FIRST));
+        assert_eq!(first.timestamp_ms, 1_700_000_000_000);
+        for seq in 2..10 {
+            clock.advance(Duration::from_secs(5));
+            let envelope = sequencer.wrap(M, &clock);
+            assert_eq!((envelope.seq, envelope.quality), (seq, Quality::default()));
+        }
+        // a sensor restart flags the next sample but keeps counting
+        sequencer.restart();
+        clock.advance(Duration::from_secs(5));
+        let envelope = sequencer.wrap(M, &clock);
+        assert_eq!((envelope.seq, envelope.quality), (10, Quality::FIRST));
+        // the wall clock stepping back doesn't matter either
+        clock.set(SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+        assert_eq!(sequencer.wrap(M, &clock).seq, 11);
+        // every source counts on its own
+        assert_eq!(Sequencer::new("sen5x", Duration::from_secs(1)).wrap(M, &clock).seq, 1);
+    }
+
+    #[test]
+    fn gaps() {
+        let clock = FakeClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut sequencer = Sequencer::new("scd41", Duration::from_secs(5));
+        sequencer.wrap(M, &clock);
+        // up to two intervals is on time
+        clock.advance(Duration::from_secs(10));
+        assert!(!sequencer.wrap(M, &clock).quality.contains(Quality::LATE));
+        clock.advance(Duration::from_secs(11));
+        let late = sequencer.wrap(M, &clock);
+        assert!(late.quality.contains(Quality::LATE) && !late.quality.contains(Quality::FIRST));
+        // after switching to the low power mode a 30 s gap is on time
+        sequencer.set_interval(Duration::from_secs(30));
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(sequencer.wrap(M, &clock).quality, Quality::default());
+        // a late sample after a restart carries both flags
+        sequencer.restart();
+        clock.advance(Duration::from_secs(90));
+        let quality = sequencer.wrap(M, &clock).quality;
+        assert!(quality.contains(Quality::FIRST) && quality.contains(Quality::LATE));
+        assert_eq!(quality.bits(), 0x03);
+    }
+}
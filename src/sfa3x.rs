@@ -0,0 +1,96 @@
+//! module for manipurate sfa3x (SFA30) formaldehyde sensors
+//! see https://sensirion.com/media/documents/30E98DAF/6241C132/Sensirion_Datasheet_SFA30.pdf
+use std::{fmt, thread, time::Duration};
+
+use embedded_hal::i2c;
+use sensirion_i2c::i2c::{read_words_with_crc, write_command_u16, Error};
+
+use crate::plugin::{Plugin, Reading};
+
+pub(crate) const SFA3X_I2C_ADDR: u8 = 0x5D;
+
+pub(crate) struct Measurement {
+    pub(crate) hcho: f32,
+    pub(crate) humidity: f32,
+    pub(crate) temperature: f32,
+}
+
+/// start_continuous_measurement (0x0006)
+pub(crate) fn start_continuous_measurement<I: i2c::I2c>(i2c: &mut I) -> Result<(), I::Error> {
+    write_command_u16(i2c, SFA3X_I2C_ADDR, 0x0006)?;
+    thread::sleep(Duration::from_millis(1));
+    return Ok(());
+}
+
+/// device_reset (0xD304)
+pub(crate) fn device_reset<I: i2c::I2c>(i2c: &mut I) -> Result<(), I::Error> {
+    write_command_u16(i2c, SFA3X_I2C_ADDR, 0xD304)?;
+    thread::sleep(Duration::from_millis(100));
+    return Ok(());
+}
+
+/// get_device_marking (0xD060)
+pub(crate) fn get_device_marking<I: i2c::I2c>(i2c: &mut I) -> Result<String, Error<I>> {
+    write_command_u16(i2c, SFA3X_I2C_ADDR, 0xD060).map_err(Error::I2cWrite)?;
+    thread::sleep(Duration::from_millis(2));
+
+    let mut buf = [0; 48];
+    read_words_with_crc(i2c, SFA3X_I2C_ADDR, &mut buf)?;
+    let marking: Vec<u8> = buf
+        .chunks(3)
+        .flat_map(|w| [w[0], w[1]])
+        .take_while(|b| *b != 0)
+        .collect();
+    return Ok(String::from_utf8_lossy(&marking).into_owned());
+}
+
+/// read_measured_values (0x0327)
+pub(crate) fn read_measured_values<I: i2c::I2c>(i2c: &mut I) -> Result<Measurement, Error<I>> {
+    write_command_u16(i2c, SFA3X_I2C_ADDR, 0x0327).map_err(Error::I2cWrite)?;
+    thread::sleep(Duration::from_millis(5));
+
+    let mut buf = [0; 9];
+    read_words_with_crc(i2c, SFA3X_I2C_ADDR, &mut buf)?;
+    let signed = |i: usize| i16::from_be_bytes([buf[i * 3], buf[i * 3 + 1]]) as f32;
+
+    return Ok(Measurement {
+        hcho: signed(0) / 5_f32,
+        humidity: signed(1) / 100_f32,
+        temperature: signed(2) / 200_f32,
+    });
+}
+
+/// sfa3x plugin
+pub(crate) struct Sfa3x<I> {
+    i2c: I,
+}
+
+impl<I> Sfa3x<I> {
+    pub(crate) fn new(i2c: I) -> Self {
+        return Sfa3x { i2c };
+    }
+}
+
+impl<I: i2c::I2c + fmt::Debug + Send> Plugin for Sfa3x<I> {
+    fn start(&mut self) -> Result<(), String> {
+        let _ = device_reset(&mut self.i2c).inspect_err(|e| log::trace!("reset error {:?}", e));
+        let marking = get_device_marking(&mut self.i2c).map_err(|e| format!("{:?}", e))?;
+        log::info!("sfa3x's device marking: {}", marking);
+        start_continuous_measurement(&mut self.i2c).map_err(|e| format!("{:?}", e))?;
+        return Ok(());
+    }
+
+    fn read(&mut self) -> Result<Vec<Reading>, String> {
+        let m = read_measured_values(&mut self.i2c).map_err(|e| format!("{:?}", e))?;
+        return Ok(vec![
+            ("hcho_ppb", m.hcho as f64),
+            ("sfa3x_humidity_rh", m.humidity as f64),
+            ("sfa3x_temperature_celsius", m.temperature as f64),
+        ]);
+    }
+
+    fn recover(&mut self) {
+        let _ = device_reset(&mut self.i2c).inspect_err(|e| log::warn!("failed to reset sfa3x: {:?}", e));
+        let _ = start_continuous_measurement(&mut self.i2c).inspect_err(|e| log::warn!("failed to start sfa3x: {:?}", e));
+    }
+}
@@ -0,0 +1,60 @@
+//! module for manipurate sht4x (SHT40/41/45) temperature and humidity sensors
+//! see https://sensirion.com/media/documents/33FD6951/67EB9032/HT_DS_Datasheet_SHT4x_5.pdf
+use std::{fmt, thread, time::Duration};
+
+use embedded_hal::i2c;
+use sensirion_i2c::i2c::{read_words_with_crc, write_command_u8, Error};
+
+use crate::plugin::{Plugin, Reading};
+
+pub(crate) const DEFAULT_ADDR: u8 = 0x44;
+
+/// read_serial (0x89)
+pub(crate) fn read_serial<I: i2c::I2c>(i2c: &mut I, addr: u8) -> Result<u32, Error<I>> {
+    write_command_u8(i2c, addr, 0x89).map_err(Error::I2cWrite)?;
+    thread::sleep(Duration::from_millis(1));
+
+    let mut buf = [0; 6];
+    read_words_with_crc(i2c, addr, &mut buf)?;
+    return Ok(u32::from_be_bytes([buf[0], buf[1], buf[3], buf[4]]));
+}
+
+/// measure T & RH with high precision (0xFD), returns (degC, %RH)
+pub(crate) fn measure<I: i2c::I2c>(i2c: &mut I, addr: u8) -> Result<(f32, f32), Error<I>> {
+    write_command_u8(i2c, addr, 0xFD).map_err(Error::I2cWrite)?;
+    thread::sleep(Duration::from_millis(10));
+
+    let mut buf = [0; 6];
+    read_words_with_crc(i2c, addr, &mut buf)?;
+    let raw_temperature = ((buf[0] as u16) << 8) | (buf[1] as u16);
+    let raw_humidity = ((buf[3] as u16) << 8) | (buf[4] as u16);
+
+    let temperature = raw_temperature as f32 * 175_f32 / 65535_f32 - 45_f32;
+    let humidity = raw_humidity as f32 * 125_f32 / 65535_f32 - 6_f32;
+    return Ok((temperature, humidity.clamp(0_f32, 100_f32)));
+}
+
+/// sht4x plugin
+pub(crate) struct Sht4x<I> {
+    i2c: I,
+    addr: u8,
+}
+
+impl<I> Sht4x<I> {
+    pub(crate) fn new(i2c: I, addr: u8) -> Self {
+        return Sht4x { i2c, addr };
+    }
+}
+
+impl<I: i2c::I2c + fmt::Debug + Send> Plugin for Sht4x<I> {
+    fn start(&mut self) -> Result<(), String> {
+        let serial = read_serial(&mut self.i2c, self.addr).map_err(|e| format!("{:?}", e))?;
+        log::info!("sht4x's serial number: 0x{:x}", serial);
+        return Ok(());
+    }
+
+    fn read(&mut self) -> Result<Vec<Reading>, String> {
+        let (t, h) = measure(&mut self.i2c, self.addr).map_err(|e| format!("{:?}", e))?;
+        return Ok(vec![("sht4x_temperature_celsius", t as f64), ("sht4x_humidity_rh", h as f64)]);
+    }
+}
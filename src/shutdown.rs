@@ -0,0 +1,143 @@
+//! module for shutting down gracefully on SIGTERM and SIGINT
+//! a signal cancels the runtime's tasks (see tasks): the measurement task stops the sensor's measurement,
+//! then the main loop waits a bounded time for the outputs with queues (CSV log, history, InfluxDB, Graphite,
+//! MQTT) to write what they have. the handler only writes to a pipe, a task turns that into the cancellation.
+use std::{
+    io,
+    os::{fd::AsRawFd, unix::net::UnixStream},
+    sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::watch;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static OUTPUTS: Mutex<Vec<&'static Pending>> = Mutex::new(Vec::new());
+/// write end of the pipe the signal handler wakes the signal task with
+static WAKE: AtomicI32 = AtomicI32::new(-1);
+/// the pipe's read end, until the signal task takes it
+static WOKEN: Mutex<Option<UnixStream>> = Mutex::new(None);
+static CANCEL: OnceLock<watch::Sender<bool>> = OnceLock::new();
+
+fn cancel() -> &'static watch::Sender<bool> {
+    return CANCEL.get_or_init(|| watch::Sender::new(false));
+}
+
+extern "C" fn on_signal(_: libc::c_int) {
+    REQUESTED.store(true, Ordering::Relaxed);
+    // write is async-signal-safe, a full pipe already has the wake-up in it
+    let fd = WAKE.load(Ordering::Relaxed);
+    if fd >= 0 {
+        unsafe { libc::write(fd, b"x".as_ptr().cast(), 1) };
+    }
+}
+
+/// request a shutdown on SIGTERM and SIGINT
+pub(crate) fn watch() -> io::Result<()> {
+    let (wake, woken) = UnixStream::pair()?;
+    wake.set_nonblocking(true)?;
+    WAKE.store(wake.as_raw_fd(), Ordering::Relaxed);
+    // the handler writes to it for as long as the process runs
+    std::mem::forget(wake);
+    *WOKEN.lock().unwrap_or_else(|e| e.into_inner()) = Some(woken);
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    return Ok(());
+}
+
+/// the task cancelling the others once a signal arrived, call it within the runtime
+pub(crate) fn listen() -> io::Result<impl std::future::Future<Output = ()>> {
+    let woken = WOKEN.lock().unwrap_or_else(|e| e.into_inner()).take();
+    let woken = match woken {
+        Some(w) => {
+            w.set_nonblocking(true)?;
+            let _guard = crate::tasks::handle().enter();
+            Some(tokio::net::UnixStream::from_std(w)?)
+        }
+        None => None,
+    };
+    return Ok(async move {
+        let Some(woken) = woken else {
+            return;
+        };
+        // a signal may have come before the task started
+        while !requested() {
+            if woken.readable().await.is_err() {
+                return;
+            }
+            let _ = woken.try_read(&mut [0; 16]);
+        }
+        log::info!("shutdown requested");
+        request();
+    });
+}
+
+/// request a shutdown from within, as a signal would
+pub(crate) fn request() {
+    REQUESTED.store(true, Ordering::Relaxed);
+    cancel().send_replace(true);
+}
+
+/// whether a shutdown was requested
+pub(crate) fn requested() -> bool {
+    return REQUESTED.load(Ordering::Relaxed);
+}
+
+/// resolves once a shutdown was requested and the tasks are cancelled
+pub(crate) async fn cancelled() {
+    let mut rx = cancel().subscribe();
+    let _ = rx.wait_for(|c| *c).await;
+}
+
+/// items an output has queued but not written yet
+pub(crate) struct Pending {
+    name: &'static str,
+    count: AtomicUsize,
+}
+
+impl Pending {
+    pub(crate) const fn new(name: &'static str) -> Self {
+        return Pending { name, count: AtomicUsize::new(0) };
+    }
+
+    /// wait for this output on shutdown
+    pub(crate) fn register(&'static self) {
+        OUTPUTS.lock().unwrap_or_else(|e| e.into_inner()).push(self);
+    }
+
+    /// count items about to be queued. before queueing, as the writer may be done with them first.
+    pub(crate) fn add(&self, n: usize) {
+        self.count.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn done(&self, n: usize) {
+        let _ = self.count.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| Some(c.saturating_sub(n)));
+    }
+}
+
+/// wait up to `timeout` for the registered outputs to write their queues
+pub(crate) fn drain(timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    let outputs = OUTPUTS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    loop {
+        let busy: Vec<_> = outputs.iter().filter(|p| p.count.load(Ordering::Relaxed) > 0).collect();
+        if busy.is_empty() {
+            return;
+        }
+        if Instant::now() >= deadline {
+            for p in busy {
+                log::warn!("{} still has {} unwritten items, exit anyway", p.name, p.count.load(Ordering::Relaxed));
+            }
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,125 @@
+//! module for smoothing measurements before they're exported
+use std::collections::VecDeque;
+
+use crate::sensor::Measurement;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Smoothing {
+    /// exponential moving average with this weight of the newest sample
+    Ema(f32),
+    /// moving average of the last N samples
+    Sma(usize),
+}
+
+/// parse `ema:ALPHA` or `sma:N`
+pub(crate) fn parse_smoothing(s: &str) -> Result<Smoothing, String> {
+    let (kind, value) = s.split_once(':').ok_or("expected ema:ALPHA or sma:N")?;
+    return match kind {
+        "ema" => {
+            let alpha: f32 = value.parse().map_err(|e| format!("alpha: {}", e))?;
+            if alpha <= 0.0 || alpha > 1.0 {
+                return Err(String::from("alpha must be in (0, 1]"));
+            }
+            Ok(Smoothing::Ema(alpha))
+        }
+        "sma" => {
+            let n: usize = value.parse().map_err(|e| format!("window: {}", e))?;
+            if n == 0 {
+                return Err(String::from("window must be at least 1"));
+            }
+            Ok(Smoothing::Sma(n))
+        }
+        _ => Err(format!("unknown smoothing: {}", kind)),
+    };
+}
+
+pub(crate) struct Smoother {
+    smoothing: Smoothing,
+    /// last output for ema, window for sma (co2, temperature, humidity)
+    samples: VecDeque<[f32; 3]>,
+}
+
+impl Smoother {
+    pub(crate) fn new(smoothing: Smoothing) -> Self {
+        return Smoother { smoothing, samples: VecDeque::new() };
+    }
+
+    /// add `m` and return the smoothed measurement
+    pub(crate) fn apply(&mut self, m: &Measurement) -> Measurement {
+        let sample = [m.co2 as f32, m.temperature, m.humidity];
+        let out = match self.smoothing {
+            Smoothing::Ema(alpha) => {
+                let out = match self.samples.pop_back() {
+                    None => sample,
+                    Some(prev) => [0, 1, 2].map(|i| alpha * sample[i] + (1_f32 - alpha) * prev[i]),
+                };
+                self.samples.push_back(out);
+                out
+            }
+            Smoothing::Sma(n) => {
+                self.samples.push_back(sample);
+                while self.samples.len() > n {
+                    self.samples.pop_front();
+                }
+                let len = self.samples.len() as f32;
+                [0, 1, 2].map(|i| self.samples.iter().map(|s| s[i]).sum::<f32>() / len)
+            }
+        };
+        return Measurement {
+            co2: out[0].round() as u16,
+            temperature: out[1],
+            humidity: out[2],
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn m(co2: u16, temperature: f32) -> Measurement {
+        return Measurement { co2, temperature, humidity: 40.0 };
+    }
+
+    fn assert_smoothed(out: Measurement, co2: u16, temperature: f32) {
+        assert_eq!(out.co2, co2);
+        assert!((out.temperature - temperature).abs() < 1e-4, "{} isn't {}", out.temperature, temperature);
+        assert_eq!(out.humidity, 40.0);
+    }
+
+    #[test]
+    fn parses() {
+        assert!(matches!(parse_smoothing("ema:0.3"), Ok(Smoothing::Ema(a)) if a == 0.3));
+        assert!(matches!(parse_smoothing("sma:4"), Ok(Smoothing::Sma(4))));
+        for invalid in ["ema:0", "ema:1.5", "sma:0", "sma:x", "wma:3", "ema"] {
+            assert!(parse_smoothing(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn moving_average() {
+        let mut smoother = Smoother::new(Smoothing::Sma(3));
+        // while warming up it averages what it has
+        assert_smoothed(smoother.apply(&m(800, 20.0)), 800, 20.0);
+        assert_smoothed(smoother.apply(&m(900, 21.0)), 850, 20.5);
+        assert_smoothed(smoother.apply(&m(1000, 22.0)), 900, 21.0);
+        // then the oldest sample drops out of the window
+        assert_smoothed(smoother.apply(&m(1300, 23.0)), 1067, 22.0);
+        assert_smoothed(smoother.apply(&m(1300, 23.0)), 1200, 22.666_666);
+        assert_smoothed(smoother.apply(&m(1300, 23.0)), 1300, 23.0);
+    }
+
+    #[test]
+    fn exponential_smoothing() {
+        let mut smoother = Smoother::new(Smoothing::Ema(0.5));
+        // the first sample is taken as is
+        assert_smoothed(smoother.apply(&m(800, 20.0)), 800, 20.0);
+        assert_smoothed(smoother.apply(&m(1000, 22.0)), 900, 21.0);
+        assert_smoothed(smoother.apply(&m(1000, 22.0)), 950, 21.5);
+        assert_smoothed(smoother.apply(&m(600, 17.5)), 775, 19.5);
+        // a weight of 1 doesn't smooth at all
+        let mut smoother = Smoother::new(Smoothing::Ema(1.0));
+        smoother.apply(&m(800, 20.0));
+        assert_smoothed(smoother.apply(&m(1000, 22.0)), 1000, 22.0);
+    }
+}
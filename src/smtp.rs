@@ -0,0 +1,227 @@
+//! module for mailing alert notifications over SMTP
+//! `smtp://[user:password@]host[:587]` upgrades with STARTTLS when the server offers it, and never sends
+//! credentials without TLS. `smtps://host[:465]` uses TLS from the start. credentials are sent with AUTH PLAIN.
+//! the notifications of --smtp-batch after the first are collected and mailed together, one mail per
+//! recipient, so a flapping alert doesn't flood the mailbox. a shutdown sends what's collected right away.
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Write},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+        OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    http::{self, Stream, Url},
+    shutdown::{self, Pending},
+};
+
+/// (scheme, tls, default port) of server urls
+const SCHEMES: &[(&str, bool, u16)] = &[("smtp", false, 587), ("smtps", true, 465)];
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// notifications waiting for the batch; newer ones are dropped beyond this
+const QUEUE: usize = 100;
+
+pub(crate) fn parse_url(s: &str) -> Result<Url, String> {
+    return http::parse_url_with(s, SCHEMES);
+}
+
+pub(crate) struct Config {
+    pub(crate) url: Url,
+    pub(crate) from: String,
+    pub(crate) batch: Duration,
+}
+
+/// a notification to mail: recipient, subject and text
+type Mail = (String, String, String);
+
+static MAILS: OnceLock<SyncSender<Mail>> = OnceLock::new();
+static PENDING: Pending = Pending::new("smtp");
+
+/// start the thread mailing notifications
+pub(crate) fn init(config: Config) -> io::Result<()> {
+    let (tx, rx) = mpsc::sync_channel(QUEUE);
+    thread::Builder::new().name(String::from("smtp")).spawn(move || run(config, rx))?;
+    let _ = MAILS.set(tx);
+    PENDING.register();
+    return Ok(());
+}
+
+/// whether there's a server to mail through
+pub(crate) fn enabled() -> bool {
+    return MAILS.get().is_some();
+}
+
+/// mail `text` to `to` with the next batch
+pub(crate) fn queue(to: &str, subject: String, text: String) {
+    let Some(mails) = MAILS.get() else {
+        return;
+    };
+    PENDING.add(1);
+    if mails.try_send((to.to_string(), subject, text)).is_err() {
+        PENDING.done(1);
+        log::warn!("too many mails queued, drop the one to {}", to);
+    }
+}
+
+fn run(config: Config, rx: Receiver<Mail>) {
+    let failures = metrics::counter!("exporter_alert_notification_failures_total");
+    while let Ok(first) = rx.recv() {
+        let mut batch = vec![first];
+        let deadline = Instant::now() + config.batch;
+        while !shutdown::requested() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            match rx.recv_timeout(left.min(Duration::from_secs(1))) {
+                Ok(mail) => batch.push(mail),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        let count = batch.len();
+        let mut recipients: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+        for (to, subject, text) in batch {
+            recipients.entry(to).or_default().push((subject, text));
+        }
+        for (to, mails) in recipients {
+            let (subject, text) = match mails.len() {
+                1 => mails.into_iter().next().unwrap_or_default(),
+                n => (format!("{} alert notifications", n), mails.into_iter().map(|(_, text)| text).collect::<Vec<_>>().join("\n\n")),
+            };
+            if let Err(e) = send(&config, &to, &subject, &text) {
+                log::warn!("failed to mail {} through {}:{}: {:?}", to, config.url.host, config.url.port, e);
+                failures.increment(1);
+            }
+        }
+        PENDING.done(count);
+    }
+}
+
+struct Session {
+    stream: Box<dyn Stream>,
+}
+
+impl Session {
+    /// the code of the server's (possibly multiline) reply and its lines
+    fn reply(&mut self) -> io::Result<(u16, Vec<String>)> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = Vec::new();
+            let mut byte = [0; 1];
+            while line.last() != Some(&b'\n') {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the server closed the connection"));
+                }
+                line.push(byte[0]);
+            }
+            let line = String::from_utf8_lossy(&line).trim_end().to_string();
+            let code = line.get(..3).and_then(|c| c.parse().ok()).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("malformed reply {:?}", line)))?;
+            let last = line.as_bytes().get(3) != Some(&b'-');
+            lines.push(line.get(4..).unwrap_or_default().to_string());
+            if last {
+                return Ok((code, lines));
+            }
+        }
+    }
+
+    /// send `command` and expect a reply with the first digit of `expected`
+    fn command(&mut self, command: &str, expected: u16) -> io::Result<Vec<String>> {
+        self.stream.write_all(command.as_bytes())?;
+        self.stream.write_all(b"\r\n")?;
+        self.stream.flush()?;
+        return self.expect(expected);
+    }
+
+    fn expect(&mut self, expected: u16) -> io::Result<Vec<String>> {
+        let (code, lines) = self.reply()?;
+        if code / 100 != expected / 100 {
+            return Err(io::Error::other(format!("server replied {} {}", code, lines.join(" "))));
+        }
+        return Ok(lines);
+    }
+}
+
+fn send(config: &Config, to: &str, subject: &str, text: &str) -> io::Result<()> {
+    let url = &config.url;
+    let mut session = Session { stream: http::connect(url, TIMEOUT)? };
+    session.expect(220)?;
+    let hostname = hostname();
+    let mut extensions = session.command(&format!("EHLO {}", hostname), 250)?;
+    let mut tls = url.tls;
+    if !tls && extensions.iter().any(|e| e.eq_ignore_ascii_case("STARTTLS")) {
+        session.command("STARTTLS", 220)?;
+        let name = rustls::pki_types::ServerName::try_from(url.host.clone()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let connection = rustls::ClientConnection::new(http::tls_config(), name).map_err(io::Error::other)?;
+        session.stream = Box::new(rustls::StreamOwned::new(connection, session.stream));
+        tls = true;
+        extensions = session.command(&format!("EHLO {}", hostname), 250)?;
+    }
+    if let Some(userinfo) = &url.userinfo {
+        if !tls {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the server doesn't offer STARTTLS, not sending the credentials"));
+        }
+        if !extensions.iter().any(|e| e.to_ascii_uppercase().starts_with("AUTH") && e.to_ascii_uppercase().contains("PLAIN")) {
+            log::warn!("{} doesn't announce AUTH PLAIN, try it anyway", url.host);
+        }
+        let (user, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+        let token = http::base64(format!("\0{}\0{}", user, password).as_bytes());
+        session.command(&format!("AUTH PLAIN {}", token), 235)?;
+    }
+    session.command(&format!("MAIL FROM:<{}>", config.from), 250)?;
+    session.command(&format!("RCPT TO:<{}>", to), 250)?;
+    session.command("DATA", 354)?;
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+        config.from,
+        to,
+        subject,
+        date()
+    );
+    for line in text.lines() {
+        // a line of a single dot would end the message
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message.push('.');
+    session.command(&message, 250)?;
+    let _ = session.command("QUIT", 221);
+    return Ok(());
+}
+
+fn hostname() -> String {
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) } != 0 {
+        return String::from("localhost");
+    }
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    return String::from_utf8_lossy(&name[..len]).into_owned();
+}
+
+/// the current time for the Date header, like `Tue, 14 Oct 2026 09:30:00 +0000`
+fn date() -> String {
+    const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::gmtime_r(&now, &mut tm) };
+    return format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} +0000",
+        DAYS[tm.tm_wday as usize % 7],
+        tm.tm_mday,
+        MONTHS[tm.tm_mon as usize % 12],
+        tm.tm_year + 1900,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    );
+}
@@ -0,0 +1,187 @@
+//! module for snappy block compression, as required by Prometheus remote_write
+//! see https://github.com/google/snappy/blob/main/format_description.txt
+//! a greedy single-pass compressor with a small hash table; it trades ratio for simplicity.
+
+/// input is compressed in independent blocks of this size, as the reference implementation does
+const BLOCK: usize = 64 * 1024;
+const HASH_BITS: u32 = 14;
+
+pub(crate) fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    let mut n = input.len() as u64;
+    // preamble: uncompressed length as a varint
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+    for block in input.chunks(BLOCK) {
+        compress_block(block, &mut out);
+    }
+    return out;
+}
+
+fn compress_block(block: &[u8], out: &mut Vec<u8>) {
+    let mut table = vec![0_u32; 1 << HASH_BITS];
+    let load = |i: usize| u32::from_le_bytes([block[i], block[i + 1], block[i + 2], block[i + 3]]);
+    let hash = |v: u32| (v.wrapping_mul(0x1E35_A7BD) >> (32 - HASH_BITS)) as usize;
+
+    let mut literal_start = 0;
+    let mut i = 0;
+    while i + 4 <= block.len() {
+        let v = load(i);
+        let h = hash(v);
+        let candidate = table[h] as usize;
+        table[h] = i as u32;
+        if candidate < i && load(candidate) == v {
+            let mut length = 4;
+            while i + length < block.len() && block[candidate + length] == block[i + length] {
+                length += 1;
+            }
+            emit_literal(&block[literal_start..i], out);
+            emit_copy(i - candidate, length, out);
+            i += length;
+            literal_start = i;
+        } else {
+            i += 1;
+        }
+    }
+    emit_literal(&block[literal_start..], out);
+}
+
+fn emit_literal(literal: &[u8], out: &mut Vec<u8>) {
+    if literal.is_empty() {
+        return;
+    }
+    let n = literal.len() - 1;
+    if n < 60 {
+        out.push((n as u8) << 2);
+    } else {
+        let bytes = n.to_le_bytes();
+        let width = if n < 1 << 8 { 1 } else if n < 1 << 16 { 2 } else if n < 1 << 24 { 3 } else { 4 };
+        out.push(((59 + width) as u8) << 2);
+        out.extend_from_slice(&bytes[..width]);
+    }
+    out.extend_from_slice(literal);
+}
+
+fn emit_copy(offset: usize, mut length: usize, out: &mut Vec<u8>) {
+    // copies with a 2-byte offset hold up to 64 bytes
+    while length > 0 {
+        let n = if length > 64 && length < 68 { 60 } else { length.min(64) };
+        out.push((((n - 1) as u8) << 2) | 0b10);
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        length -= n;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// decode a snappy block stream, checking the preamble against what it holds
+    fn decompress(data: &[u8]) -> Vec<u8> {
+        let (mut len, mut shift, mut i) = (0_usize, 0, 0);
+        loop {
+            len |= ((data[i] & 0x7F) as usize) << shift;
+            shift += 7;
+            i += 1;
+            if data[i - 1] < 0x80 {
+                break;
+            }
+        }
+        let mut out = Vec::new();
+        while i < data.len() {
+            let tag = data[i];
+            i += 1;
+            let (length, offset) = match tag & 0b11 {
+                0b00 => {
+                    let mut n = (tag >> 2) as usize;
+                    if n >= 60 {
+                        let width = n - 59;
+                        n = data[i..i + width].iter().rev().fold(0, |n, &b| n << 8 | b as usize);
+                        i += width;
+                    }
+                    out.extend_from_slice(&data[i..i + n + 1]);
+                    i += n + 1;
+                    continue;
+                }
+                0b01 => {
+                    let offset = ((tag >> 5) as usize) << 8 | data[i] as usize;
+                    i += 1;
+                    (((tag >> 2) & 0b111) as usize + 4, offset)
+                }
+                0b10 => {
+                    let offset = u16::from_le_bytes([data[i], data[i + 1]]) as usize;
+                    i += 2;
+                    ((tag >> 2) as usize + 1, offset)
+                }
+                _ => {
+                    let offset = u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize;
+                    i += 4;
+                    ((tag >> 2) as usize + 1, offset)
+                }
+            };
+            assert!(offset > 0 && offset <= out.len(), "copy from before the start");
+            for _ in 0..length {
+                out.push(out[out.len() - offset]);
+            }
+        }
+        assert_eq!(out.len(), len);
+        return out;
+    }
+
+    /// bytes without repeated 4-byte sequences, so they compress to literals only
+    fn noise(len: usize) -> Vec<u8> {
+        let mut x = 0x2545_F491_u32;
+        return (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+    }
+
+    #[test]
+    fn length_preamble_over_127() {
+        assert_eq!(compress(&[]), [0]);
+        assert_eq!(compress(&[7; 127])[0], 127);
+        assert_eq!(compress(&[7; 300])[..2], [0xAC, 0x02]);
+        assert_eq!(compress(&[7; 16384])[..3], [0x80, 0x80, 0x01]);
+        assert_eq!(decompress(&compress(&[7; 300])), [7; 300]);
+    }
+
+    #[test]
+    fn literals_of_60_bytes_and_more() {
+        let input = noise(1000);
+        for (len, tag) in [(60, &[59 << 2][..]), (61, &[60 << 2, 60]), (256, &[60 << 2, 255]), (1000, &[61 << 2, 0xE7, 0x03])] {
+            let compressed = compress(&input[..len]);
+            let preamble = if len < 128 { 1 } else { 2 };
+            assert_eq!(&compressed[preamble..preamble + tag.len()], tag, "literal of {} bytes", len);
+            assert_eq!(compressed.len(), preamble + tag.len() + len);
+            assert_eq!(decompress(&compressed), &input[..len]);
+        }
+    }
+
+    #[test]
+    fn copies_longer_than_64() {
+        let input = b"abcd".repeat(50);
+        let compressed = compress(&input);
+        let copy = |len: u8| [((len - 1) << 2) | 0b10, 4, 0];
+        let expected = [&[0xC8, 0x01, 3 << 2][..], b"abcd", &copy(64), &copy(64), &copy(64), &copy(4)].concat();
+        assert_eq!(compressed, expected);
+        assert_eq!(decompress(&compressed), input);
+        // a copy of 66 is split into 60 and 6 rather than 64 and 2, as the reference implementation does
+        let compressed = compress(&b"abcd".repeat(70)[..70]);
+        assert_eq!(compressed[6..], [copy(60), copy(6)].concat());
+    }
+
+    #[test]
+    fn blocks_round_trip() {
+        let mut input = noise(100_000);
+        input.extend(b"the quick brown fox ".repeat(5000));
+        assert_eq!(decompress(&compress(&input)), input);
+    }
+}
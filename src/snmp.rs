@@ -0,0 +1,518 @@
+//! module for answering SNMP v1/v2c requests for the latest reading
+//! see RFC 3416 (protocol operations) and X.690 (BER)
+//! a standalone read-only responder, so NMS tooling polls the sensor like UPSes and switches. it
+//! serves the system group (sysDescr, sysObjectID, sysUpTime, sysName, sysLocation) and the
+//! reading below the base OID (--snmp-oid):
+//!
+//! | OID | type | value |
+//! |---|---|---|
+//! | base.1.0 | Gauge32 | CO2 (ppm) |
+//! | base.2.0 | INTEGER | temperature (0.01 degC) |
+//! | base.3.0 | Gauge32 | humidity (0.01 %RH) |
+//! | base.4.0 | INTEGER | status: starting(0), ok(1), implausible(2), stale(3), failing(4) |
+//! | base.5.0 | Gauge32 | seconds since the measurement |
+//! | base.6.0 | Counter32 | sequence number of the measurement |
+//! | base.7.0 | OCTET STRING | sensor serial |
+//!
+//! the default base is in the net-snmp playpen (NET-SNMP-EXAMPLES-MIB), meant for local use;
+//! set one below your own enterprise number when polling from shared tooling.
+use std::{
+    io,
+    net::UdpSocket,
+    sync::OnceLock,
+    thread,
+    time::Instant,
+};
+
+use crate::{
+    latest,
+    otlp,
+};
+
+pub(crate) const DEFAULT_OID: &str = "1.3.6.1.4.1.8072.9999.9999.41";
+
+const SYSTEM: [u32; 7] = [1, 3, 6, 1, 2, 1, 1];
+
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const COUNTER32: u8 = 0x41;
+const GAUGE32: u8 = 0x42;
+const TIME_TICKS: u8 = 0x43;
+const NO_SUCH_OBJECT: u8 = 0x80;
+const END_OF_MIB_VIEW: u8 = 0x82;
+
+const GET_REQUEST: u8 = 0xA0;
+const GET_NEXT_REQUEST: u8 = 0xA1;
+const RESPONSE: u8 = 0xA2;
+const SET_REQUEST: u8 = 0xA3;
+const GET_BULK_REQUEST: u8 = 0xA5;
+
+const NO_SUCH_NAME: i64 = 2;
+const NOT_WRITABLE: i64 = 17;
+
+/// responses beyond this are cut short, GetBulk is answered with fewer repetitions
+const MAX_RESPONSE: usize = 1400;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) addr: String,
+    pub(crate) community: String,
+    pub(crate) base: Vec<u32>,
+    /// sysLocation, from the location label
+    pub(crate) location: Option<String>,
+}
+
+/// an object identifier, as arcs
+#[derive(Debug, Clone)]
+pub(crate) struct Oid(pub(crate) Vec<u32>);
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// parse a dotted OID such as 1.3.6.1.4.1.8072
+pub(crate) fn parse_oid(s: &str) -> Result<Oid, String> {
+    let arcs = s
+        .trim_start_matches('.')
+        .split('.')
+        .map(|a| a.parse::<u32>().map_err(|_| format!("invalid OID arc {:?}", a)))
+        .collect::<Result<Vec<u32>, String>>()?;
+    if arcs.len() < 2 || arcs[0] > 2 || (arcs[0] < 2 && arcs[1] >= 40) {
+        return Err(String::from("expected an OID such as 1.3.6.1.4.1.8072"));
+    }
+    return Ok(Oid(arcs));
+}
+
+/// listen on the configured address and answer from a background thread
+pub(crate) fn init(config: Config) -> io::Result<()> {
+    STARTED.get_or_init(Instant::now);
+    let socket = UdpSocket::bind(&config.addr)?;
+    log::info!("answer snmp requests on {}", socket.local_addr()?);
+    thread::Builder::new().name(String::from("snmp")).spawn(move || {
+        let requests = metrics::counter!("exporter_snmp_requests_total");
+        let invalid = metrics::counter!("exporter_snmp_invalid_total");
+        let mut buf = [0_u8; 65535];
+        loop {
+            let (n, peer) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) => {
+                    log::warn!("failed to receive snmp request: {:?}", e);
+                    continue;
+                }
+            };
+            // malformed requests and wrong communities get no answer, like any agent
+            let Some(response) = respond(&config, &buf[..n]) else {
+                log::debug!("ignore invalid snmp request from {}", peer);
+                invalid.increment(1);
+                continue;
+            };
+            requests.increment(1);
+            let _ = socket.send_to(&response, peer).inspect_err(|e| log::debug!("failed to answer {}: {:?}", peer, e));
+        }
+    })?;
+    return Ok(());
+}
+
+/// a value in the MIB
+enum Value {
+    Integer(i64),
+    String(Vec<u8>),
+    Oid(Vec<u32>),
+    Counter32(u32),
+    Gauge32(u32),
+    TimeTicks(u32),
+}
+
+impl Value {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Integer(v) => tlv(out, INTEGER, &integer(*v)),
+            Value::String(v) => tlv(out, OCTET_STRING, v),
+            Value::Oid(v) => tlv(out, OBJECT_IDENTIFIER, &oid(v)),
+            Value::Counter32(v) => tlv(out, COUNTER32, &integer(*v as i64)),
+            Value::Gauge32(v) => tlv(out, GAUGE32, &integer(*v as i64)),
+            Value::TimeTicks(v) => tlv(out, TIME_TICKS, &integer(*v as i64)),
+        }
+    }
+}
+
+/// the objects served, sorted by OID
+fn mib(config: &Config) -> Vec<(Vec<u32>, Value)> {
+    let object = |prefix: &[u32], arc: u32| [prefix, &[arc, 0]].concat();
+    let uptime = STARTED.get().map(|s| s.elapsed().as_millis() / 10).unwrap_or_default() as u32;
+    let mut objects = vec![
+        (object(&SYSTEM, 1), Value::String(format!("raspi-scd41-exporter {}", env!("CARGO_PKG_VERSION")).into_bytes())),
+        (object(&SYSTEM, 2), Value::Oid(config.base.clone())),
+        (object(&SYSTEM, 3), Value::TimeTicks(uptime)),
+        (object(&SYSTEM, 5), Value::String(otlp::hostname().unwrap_or_default().into_bytes())),
+        (object(&SYSTEM, 6), Value::String(config.location.clone().unwrap_or_default().into_bytes())),
+    ];
+
+    let (envelope, serial, status) = latest::get();
+    let status = status.code() as i64;
+    let base = &config.base;
+    if let Some(envelope) = &envelope {
+        let m = &envelope.measurement;
+        objects.extend([
+            (object(base, 1), Value::Gauge32(m.co2 as u32)),
+            (object(base, 2), Value::Integer((m.temperature * 100.0).round() as i64)),
+            (object(base, 3), Value::Gauge32((m.humidity.clamp(0.0, 100.0) * 100.0).round() as u32)),
+        ]);
+    }
+    objects.push((object(base, 4), Value::Integer(status)));
+    if let Some(envelope) = &envelope {
+        objects.extend([
+            (object(base, 5), Value::Gauge32(envelope.instant.elapsed().as_secs() as u32)),
+            (object(base, 6), Value::Counter32(envelope.seq as u32)),
+        ]);
+    }
+    objects.push((object(base, 7), Value::String(serial.unwrap_or_default().into_bytes())));
+    // the base may sort before the system group
+    objects.sort_by(|a, b| a.0.cmp(&b.0));
+    return objects;
+}
+
+/// the response to a request, None for requests which aren't answered
+fn respond(config: &Config, request: &[u8]) -> Option<Vec<u8>> {
+    let (tag, message, _) = read_tlv(request)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let (tag, version, rest) = read_tlv(message)?;
+    let version = (tag == INTEGER).then(|| read_integer(version)).flatten()?;
+    // 0 is v1, 1 is v2c
+    if version != 0 && version != 1 {
+        return None;
+    }
+    let (tag, community, rest) = read_tlv(rest)?;
+    if tag != OCTET_STRING || community != config.community.as_bytes() {
+        return None;
+    }
+    let (kind, pdu, _) = read_tlv(rest)?;
+    let mut fields = [0_i64; 3];
+    let mut rest = pdu;
+    for field in fields.iter_mut() {
+        let (tag, value, next) = read_tlv(rest)?;
+        if tag != INTEGER {
+            return None;
+        }
+        *field = read_integer(value)?;
+        rest = next;
+    }
+    let [request_id, non_repeaters, max_repetitions] = fields;
+    let (tag, mut bindings, _) = read_tlv(rest)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let mut names = Vec::new();
+    while !bindings.is_empty() {
+        let (tag, binding, next) = read_tlv(bindings)?;
+        let (name_tag, name, _) = read_tlv(binding)?;
+        if tag != SEQUENCE || name_tag != OBJECT_IDENTIFIER {
+            return None;
+        }
+        names.push(read_oid(name)?);
+        bindings = next;
+    }
+
+    let objects = mib(config);
+    let v1 = version == 0;
+    let get = |name: &[u32]| objects.iter().find(|(oid, _)| oid == name);
+    let next = |name: &[u32]| objects.iter().find(|(oid, _)| oid.as_slice() > name);
+    // (error status, error index) and the bindings of the response
+    let mut error = (0, 0);
+    let mut answers: Vec<(Vec<u32>, Option<&Value>, u8)> = Vec::new();
+    match kind {
+        GET_REQUEST | GET_NEXT_REQUEST => {
+            for (i, name) in names.iter().enumerate() {
+                let found = if kind == GET_REQUEST { get(name) } else { next(name) };
+                let missing = if kind == GET_REQUEST { NO_SUCH_OBJECT } else { END_OF_MIB_VIEW };
+                match found {
+                    Some((oid, value)) => answers.push((oid.clone(), Some(value), 0)),
+                    None => {
+                        if v1 && error.0 == 0 {
+                            error = (NO_SUCH_NAME, i as i64 + 1);
+                        }
+                        answers.push((name.clone(), None, missing));
+                    }
+                }
+            }
+        }
+        GET_BULK_REQUEST if !v1 => {
+            let non_repeaters = non_repeaters.clamp(0, names.len() as i64) as usize;
+            for name in &names[..non_repeaters] {
+                match next(name) {
+                    Some((oid, value)) => answers.push((oid.clone(), Some(value), 0)),
+                    None => answers.push((name.clone(), None, END_OF_MIB_VIEW)),
+                }
+            }
+            let mut cursors: Vec<Vec<u32>> = names[non_repeaters..].to_vec();
+            // the MIB is small, more repetitions than objects only repeat endOfMibView
+            for _ in 0..max_repetitions.clamp(0, objects.len() as i64 + 1) {
+                if cursors.is_empty() {
+                    break;
+                }
+                let mut ended = true;
+                for cursor in cursors.iter_mut() {
+                    match next(cursor) {
+                        Some((oid, value)) => {
+                            answers.push((oid.clone(), Some(value), 0));
+                            *cursor = oid.clone();
+                            ended = false;
+                        }
+                        None => answers.push((cursor.clone(), None, END_OF_MIB_VIEW)),
+                    }
+                }
+                if ended {
+                    break;
+                }
+            }
+        }
+        SET_REQUEST => {
+            error = (if v1 { NO_SUCH_NAME } else { NOT_WRITABLE }, 1);
+            answers.extend(names.iter().map(|n| (n.clone(), None, NULL)));
+        }
+        _ => return None,
+    }
+
+    // responses echo the request's bindings on errors, with the original values
+    let mut encoded = Vec::new();
+    for (name, value, exception) in &answers {
+        let mut binding = Vec::new();
+        tlv(&mut binding, OBJECT_IDENTIFIER, &oid(name));
+        match value {
+            Some(value) if error.0 == 0 => value.encode(&mut binding),
+            _ if error.0 != 0 || *exception == NULL => tlv(&mut binding, NULL, &[]),
+            _ => tlv(&mut binding, *exception, &[]),
+        }
+        if encoded.len() + binding.len() > MAX_RESPONSE && kind == GET_BULK_REQUEST {
+            break;
+        }
+        tlv(&mut encoded, SEQUENCE, &binding);
+    }
+    let mut pdu = Vec::new();
+    tlv(&mut pdu, INTEGER, &integer(request_id));
+    tlv(&mut pdu, INTEGER, &integer(error.0));
+    tlv(&mut pdu, INTEGER, &integer(error.1));
+    tlv(&mut pdu, SEQUENCE, &encoded);
+    let mut message = Vec::new();
+    tlv(&mut message, INTEGER, &integer(version));
+    tlv(&mut message, OCTET_STRING, config.community.as_bytes());
+    tlv(&mut message, RESPONSE, &pdu);
+    let mut out = Vec::new();
+    tlv(&mut out, SEQUENCE, &message);
+    return Some(out);
+}
+
+/// append tag, length and content
+fn tlv(out: &mut Vec<u8>, tag: u8, content: &[u8]) {
+    out.push(tag);
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = (len as u32).to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (4 - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(content);
+}
+
+/// the shortest two's complement encoding
+fn integer(v: i64) -> Vec<u8> {
+    let bytes = v.to_be_bytes();
+    let mut start = 0;
+    while start < 7 && ((bytes[start] == 0 && bytes[start + 1] & 0x80 == 0) || (bytes[start] == 0xFF && bytes[start + 1] & 0x80 != 0)) {
+        start += 1;
+    }
+    return bytes[start..].to_vec();
+}
+
+fn oid(arcs: &[u32]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let first = arcs.first().copied().unwrap_or(0) * 40 + arcs.get(1).copied().unwrap_or(0);
+    for arc in std::iter::once(first).chain(arcs.iter().skip(2).copied()) {
+        let mut groups = vec![(arc & 0x7F) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            groups.push((rest & 0x7F) as u8 | 0x80);
+            rest >>= 7;
+        }
+        out.extend(groups.iter().rev());
+    }
+    return out;
+}
+
+/// split off a tag, its content and what follows
+fn read_tlv(buf: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *buf.first()?;
+    let first = *buf.get(1)? as usize;
+    let (len, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let n = first & 0x7F;
+        if n == 0 || n > 4 {
+            return None;
+        }
+        let len = buf.get(2..2 + n)?.iter().fold(0_usize, |len, b| (len << 8) | *b as usize);
+        (len, 2 + n)
+    };
+    let content = buf.get(header..header.checked_add(len)?)?;
+    return Some((tag, content, &buf[header + len..]));
+}
+
+fn read_integer(content: &[u8]) -> Option<i64> {
+    if content.is_empty() || content.len() > 8 {
+        return None;
+    }
+    let negative = content[0] & 0x80 != 0;
+    let init = if negative { -1_i64 } else { 0 };
+    return Some(content.iter().fold(init, |v, b| (v << 8) | *b as i64));
+}
+
+fn read_oid(content: &[u8]) -> Option<Vec<u32>> {
+    let mut arcs = Vec::new();
+    let mut arc: u32 = 0;
+    for (i, b) in content.iter().enumerate() {
+        arc = arc.checked_mul(128)? | (b & 0x7F) as u32;
+        if b & 0x80 != 0 {
+            continue;
+        }
+        if arcs.is_empty() {
+            let first = (arc / 40).min(2);
+            arcs.extend([first, arc - first * 40]);
+        } else {
+            arcs.push(arc);
+        }
+        arc = 0;
+        if i + 1 == content.len() {
+            return Some(arcs);
+        }
+    }
+    // empty, or the last arc is unterminated
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        return Config { addr: String::new(), community: String::from("public"), base: parse_oid(DEFAULT_OID).unwrap().0, location: None };
+    }
+
+    /// a request of `kind` for `names`
+    fn request(version: i64, community: &str, kind: u8, names: &[&[u32]]) -> Vec<u8> {
+        let mut bindings = Vec::new();
+        for name in names {
+            let mut binding = Vec::new();
+            tlv(&mut binding, OBJECT_IDENTIFIER, &oid(name));
+            tlv(&mut binding, NULL, &[]);
+            tlv(&mut bindings, SEQUENCE, &binding);
+        }
+        let mut pdu = Vec::new();
+        for field in [42, 0, 0] {
+            tlv(&mut pdu, INTEGER, &integer(field));
+        }
+        tlv(&mut pdu, SEQUENCE, &bindings);
+        let mut message = Vec::new();
+        tlv(&mut message, INTEGER, &integer(version));
+        tlv(&mut message, OCTET_STRING, community.as_bytes());
+        tlv(&mut message, kind, &pdu);
+        let mut out = Vec::new();
+        tlv(&mut out, SEQUENCE, &message);
+        return out;
+    }
+
+    #[test]
+    fn ber_lengths() {
+        for (len, header) in [(0, &[0x04, 0x00][..]), (127, &[0x04, 0x7F]), (128, &[0x04, 0x81, 0x80]), (256, &[0x04, 0x82, 0x01, 0x00]), (70000, &[0x04, 0x83, 0x01, 0x11, 0x70])] {
+            let content = vec![0x5A; len];
+            let mut out = Vec::new();
+            tlv(&mut out, OCTET_STRING, &content);
+            assert_eq!(&out[..header.len()], header, "length {}", len);
+            out.push(0xEE);
+            assert_eq!(read_tlv(&out), Some((OCTET_STRING, &content[..], &[0xEE][..])));
+        }
+        // a long form length may have leading zeros
+        assert_eq!(read_tlv(&[0x04, 0x82, 0x00, 0x01, 0x41]), Some((OCTET_STRING, &b"A"[..], &[][..])));
+        // indefinite and overlong lengths, and contents past the end
+        assert_eq!(read_tlv(&[0x30, 0x80, 0x00, 0x00]), None);
+        assert_eq!(read_tlv(&[0x04, 0x85, 0, 0, 0, 0, 1, 0x41]), None);
+        assert_eq!(read_tlv(&[0x04, 0x84, 0xFF, 0xFF, 0xFF, 0xFF]), None);
+        assert_eq!(read_tlv(&[0x04, 0x02, 0x41]), None);
+        assert_eq!(read_tlv(&[0x04]), None);
+        assert_eq!(read_tlv(&[0x04, 0x82, 0x01]), None);
+    }
+
+    #[test]
+    fn integers() {
+        for (v, encoded) in [(0, &[0x00][..]), (127, &[0x7F]), (128, &[0x00, 0x80]), (256, &[0x01, 0x00]), (-1, &[0xFF]), (-128, &[0x80]), (-129, &[0xFF, 0x7F])] {
+            assert_eq!(integer(v), encoded, "{}", v);
+            assert_eq!(read_integer(encoded), Some(v));
+        }
+        assert_eq!(read_integer(&integer(i64::MIN)), Some(i64::MIN));
+        assert_eq!(read_integer(&[]), None);
+        assert_eq!(read_integer(&[0; 9]), None);
+    }
+
+    #[test]
+    fn oids() {
+        let enterprise = [1, 3, 6, 1, 4, 1, 8072];
+        assert_eq!(oid(&enterprise), [0x2B, 0x06, 0x01, 0x04, 0x01, 0xBF, 0x08]);
+        assert_eq!(read_oid(&oid(&enterprise)).unwrap(), enterprise);
+        // the first two arcs share a byte, large second arcs are only allowed below 2
+        assert_eq!(oid(&[2, 999, 3]), [0x88, 0x37, 0x03]);
+        assert_eq!(read_oid(&[0x88, 0x37, 0x03]).unwrap(), [2, 999, 3]);
+        assert_eq!(read_oid(&oid(&[1, 3, u32::MAX])).unwrap(), [1, 3, u32::MAX]);
+        // empty, unterminated and overflowing arcs
+        assert_eq!(read_oid(&[]), None);
+        assert_eq!(read_oid(&[0x2B, 0x86]), None);
+        assert_eq!(read_oid(&[0x2B, 0x90, 0x80, 0x80, 0x80, 0x00]), None);
+        assert!(parse_oid("1.3.6.1").is_ok());
+        assert!(parse_oid("1.40").is_err());
+        assert!(parse_oid("3.1").is_err());
+    }
+
+    #[test]
+    fn get_request() {
+        let config = config();
+        let status = [&config.base[..], &[4, 0]].concat();
+        let response = respond(&config, &request(1, "public", GET_REQUEST, &[&[1, 3, 6, 1, 2, 1, 1, 3, 0], &status])).unwrap();
+        let (_, message, _) = read_tlv(&response).unwrap();
+        let (_, version, rest) = read_tlv(message).unwrap();
+        assert_eq!(read_integer(version), Some(1));
+        let (_, community, rest) = read_tlv(rest).unwrap();
+        assert_eq!(community, b"public");
+        let (kind, pdu, _) = read_tlv(rest).unwrap();
+        assert_eq!(kind, RESPONSE);
+        let (_, request_id, rest) = read_tlv(pdu).unwrap();
+        assert_eq!(read_integer(request_id), Some(42));
+        let (_, error, rest) = read_tlv(rest).unwrap();
+        assert_eq!(read_integer(error), Some(0));
+        let (_, _, rest) = read_tlv(rest).unwrap();
+        let (_, bindings, _) = read_tlv(rest).unwrap();
+        let (_, uptime, rest) = read_tlv(bindings).unwrap();
+        let (_, name, value) = read_tlv(uptime).unwrap();
+        assert_eq!(read_oid(name).unwrap(), [1, 3, 6, 1, 2, 1, 1, 3, 0]);
+        assert_eq!(value[0], TIME_TICKS);
+        let (_, status, _) = read_tlv(rest).unwrap();
+        let (_, _, value) = read_tlv(status).unwrap();
+        assert_eq!(value[0], INTEGER);
+    }
+
+    #[test]
+    fn invalid_requests_are_ignored() {
+        let config = config();
+        let get = request(1, "public", GET_REQUEST, &[&[1, 3, 6, 1, 2, 1, 1, 1, 0]]);
+        assert!(respond(&config, &get).is_some());
+        assert!(respond(&config, &request(1, "private", GET_REQUEST, &[&[1, 3, 6, 1, 2, 1, 1, 1, 0]])).is_none());
+        assert!(respond(&config, &request(3, "public", GET_REQUEST, &[&[1, 3, 6, 1, 2, 1, 1, 1, 0]])).is_none());
+        assert!(respond(&config, &request(0, "public", GET_BULK_REQUEST, &[&[1, 3]])).is_none());
+        for len in 0..get.len() {
+            assert!(respond(&config, &get[..len]).is_none(), "truncated to {} bytes", len);
+        }
+    }
+}
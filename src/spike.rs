@@ -0,0 +1,111 @@
+//! module for rejecting single-sample spikes
+//! a sample is an outlier when it is further than `threshold` scaled MADs from the median of the recent samples.
+//! outliers are still remembered, so a real step change is accepted once it makes up most of the window.
+use std::collections::VecDeque;
+
+use crate::sensor::Measurement;
+
+/// channels of a measurement and the smallest deviation considered a spike (sensor noise floor)
+const CHANNELS: [(&str, f32); 3] = [("co2", 10.0), ("temperature", 0.2), ("humidity", 1.0)];
+
+/// MAD to standard deviation for normally distributed samples
+const MAD_SCALE: f32 = 1.4826;
+
+pub(crate) struct SpikeFilter {
+    window: usize,
+    threshold: f32,
+    samples: VecDeque<[f32; 3]>,
+    suppressed: [metrics::Counter; 3],
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_by(f32::total_cmp);
+    let n = values.len();
+    return if n % 2 == 1 { values[n / 2] } else { (values[n / 2 - 1] + values[n / 2]) / 2_f32 };
+}
+
+impl SpikeFilter {
+    pub(crate) fn new(window: usize, threshold: f32) -> Self {
+        return SpikeFilter {
+            window,
+            threshold,
+            samples: VecDeque::with_capacity(window),
+            suppressed: CHANNELS.map(|(c, _)| metrics::counter!("scd41_outliers_suppressed_total", "channel" => c)),
+        };
+    }
+
+    /// add `m` and return false when it is a spike
+    pub(crate) fn accept(&mut self, m: &Measurement) -> bool {
+        let sample = [m.co2 as f32, m.temperature, m.humidity];
+        let mut accepted = true;
+        // too few samples to tell what is normal
+        if self.samples.len() >= 3 {
+            for (i, (channel, floor)) in CHANNELS.iter().enumerate() {
+                let mut values: Vec<f32> = self.samples.iter().map(|s| s[i]).collect();
+                let center = median(&mut values);
+                let mut deviations: Vec<f32> = values.iter().map(|v| (v - center).abs()).collect();
+                let mad = median(&mut deviations) * MAD_SCALE;
+                if (sample[i] - center).abs() > (self.threshold * mad).max(*floor) {
+                    log::debug!("suppress {} outlier {} (median {})", channel, sample[i], center);
+                    self.suppressed[i].increment(1);
+                    accepted = false;
+                }
+            }
+        }
+        self.samples.push_back(sample);
+        while self.samples.len() > self.window {
+            self.samples.pop_front();
+        }
+        return accepted;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn m(co2: u16) -> Measurement {
+        return Measurement { co2, temperature: 21.0, humidity: 40.0 };
+    }
+
+    #[test]
+    fn flat_window() {
+        let mut filter = SpikeFilter::new(5, 3.0);
+        // anything goes until there are three samples
+        assert!([m(800), m(2000), m(800)].iter().all(|s| filter.accept(s)));
+        let mut filter = SpikeFilter::new(5, 3.0);
+        for _ in 0..5 {
+            assert!(filter.accept(&m(800)));
+        }
+        // without any spread the noise floor decides
+        assert!(filter.accept(&m(809)));
+        assert!(!filter.accept(&m(811)));
+        assert!(!filter.accept(&Measurement { co2: 800, temperature: 21.3, humidity: 40.0 }));
+    }
+
+    #[test]
+    fn single_outlier() {
+        let mut filter = SpikeFilter::new(7, 3.0);
+        for co2 in [800, 812, 795, 806, 790, 801, 809] {
+            assert!(filter.accept(&m(co2)));
+        }
+        assert!(!filter.accept(&m(1500)));
+        // the outlier doesn't shift what's normal
+        assert!(filter.accept(&m(798)));
+        assert!(filter.accept(&m(820)));
+    }
+
+    #[test]
+    fn persistent_step() {
+        let mut filter = SpikeFilter::new(5, 3.0);
+        for _ in 0..5 {
+            assert!(filter.accept(&m(800)));
+        }
+        // a step is suppressed until it is most of the window
+        assert!(!filter.accept(&m(1200)));
+        assert!(!filter.accept(&m(1205)));
+        assert!(!filter.accept(&m(1198)));
+        assert!(filter.accept(&m(1202)));
+        assert!(filter.accept(&m(1200)));
+    }
+}
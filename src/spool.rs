@@ -0,0 +1,164 @@
+//! module for buffering an output's unsent requests on disk
+//! while an endpoint is unreachable every request is written to a file of its own, named by the time it was
+//! spooled, and once the endpoint is back they are sent oldest first before anything new. the buffer is bounded
+//! by size and by age, the oldest requests are dropped beyond either.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// what became of a request
+pub(crate) enum Delivery {
+    Sent,
+    /// the endpoint rejected the request for good, resending won't help
+    Rejected,
+    Retry,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) dir: PathBuf,
+    /// oldest requests are dropped beyond this many bytes
+    pub(crate) limit: u64,
+    /// and once they're older than this
+    pub(crate) retention: Option<Duration>,
+}
+
+pub(crate) struct Spool {
+    config: Config,
+    extension: &'static str,
+    sequence: u64,
+    /// how many samples or messages a request holds, for the dropped counter
+    count: fn(&[u8]) -> u64,
+    dropped: metrics::Counter,
+    buffered: metrics::Gauge,
+    entries: metrics::Gauge,
+}
+
+fn now_ms() -> u64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default();
+}
+
+impl Spool {
+    /// a spool of `*.extension` files, exported as exporter_`name`_buffered_bytes and _buffered_requests
+    pub(crate) fn new(name: &str, extension: &'static str, config: Config, dropped: metrics::Counter, count: fn(&[u8]) -> u64) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        let spool = Spool {
+            config,
+            extension,
+            sequence: 0,
+            count,
+            dropped,
+            buffered: metrics::gauge!(format!("exporter_{}_buffered_bytes", name)),
+            entries: metrics::gauge!(format!("exporter_{}_buffered_requests", name)),
+        };
+        let spooled = spool.files().len();
+        if spooled > 0 {
+            log::info!("{} requests buffered in {}, send them first", spooled, spool.config.dir.display());
+        }
+        spool.trim();
+        return Ok(spool);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        return self.files().is_empty();
+    }
+
+    /// send `payload` with `post`, behind the buffered requests, and buffer what can't be sent
+    pub(crate) fn send(&mut self, payload: &[u8], mut post: impl FnMut(&[u8]) -> Delivery) {
+        if self.is_empty() {
+            match post(payload) {
+                Delivery::Sent | Delivery::Rejected => return,
+                Delivery::Retry => {}
+            }
+        }
+        // keep the order: the new request goes behind the ones waiting
+        self.push(payload);
+        self.flush(post);
+    }
+
+    /// buffer `payload`
+    pub(crate) fn push(&mut self, payload: &[u8]) {
+        if let Err(e) = self.write(payload) {
+            log::warn!("failed to buffer a request in {}: {:?}", self.config.dir.display(), e);
+            self.dropped.increment((self.count)(payload));
+        }
+        self.trim();
+    }
+
+    /// send the buffered requests with `post` until one is to be retried, true if none are left
+    pub(crate) fn flush(&mut self, mut post: impl FnMut(&[u8]) -> Delivery) -> bool {
+        let mut drained = true;
+        for path in self.files() {
+            let delivery = match fs::read(&path) {
+                Ok(payload) => post(&payload),
+                Err(e) => {
+                    log::warn!("failed to read buffered request {}: {:?}", path.display(), e);
+                    Delivery::Rejected
+                }
+            };
+            if let Delivery::Retry = delivery {
+                drained = false;
+                break;
+            }
+            let _ = fs::remove_file(&path).inspect_err(|e| log::warn!("failed to remove {}: {:?}", path.display(), e));
+        }
+        self.trim();
+        return drained;
+    }
+
+    /// buffered requests, oldest first
+    fn files(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(&self.config.dir)
+            .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| self.is_request(p)).collect())
+            .unwrap_or_default();
+        paths.sort();
+        return paths;
+    }
+
+    fn is_request(&self, path: &Path) -> bool {
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        return !name.starts_with('.') && path.extension().is_some_and(|e| e == self.extension);
+    }
+
+    fn write(&mut self, payload: &[u8]) -> io::Result<()> {
+        self.sequence += 1;
+        let name = format!("{:016}-{:06}.{}", now_ms(), self.sequence % 1_000_000, self.extension);
+        let tmp = self.config.dir.join(format!(".{}", name));
+        fs::write(&tmp, payload)?;
+        fs::rename(&tmp, self.config.dir.join(name))?;
+        return Ok(());
+    }
+
+    /// drop the oldest requests beyond the size limit or the retention
+    fn trim(&self) {
+        let spooled: Vec<(PathBuf, u64)> = self
+            .files()
+            .into_iter()
+            .map(|p| {
+                let size = fs::metadata(&p).map(|m| m.len()).unwrap_or_default();
+                return (p, size);
+            })
+            .collect();
+        let oldest = self.config.retention.map(|r| now_ms().saturating_sub(r.as_millis() as u64));
+        let mut total: u64 = spooled.iter().map(|(_, size)| size).sum();
+        let mut left = spooled.len();
+        for (path, size) in &spooled {
+            // the name starts with the time it was spooled
+            let spooled_ms = path.file_name().and_then(|n| n.to_str()).and_then(|n| n.get(..16)?.parse::<u64>().ok()).unwrap_or_default();
+            let expired = oldest.is_some_and(|oldest| spooled_ms < oldest);
+            if total <= self.config.limit && !expired {
+                break;
+            }
+            log::warn!("drop buffered request {}, {}", path.display(), if expired { "it expired" } else { "the buffer is full" });
+            let count = fs::read(path).map(|payload| (self.count)(&payload)).unwrap_or(1);
+            let _ = fs::remove_file(path);
+            self.dropped.increment(count);
+            total -= size;
+            left -= 1;
+        }
+        self.buffered.set(total as f64);
+        self.entries.set(left as f64);
+    }
+}
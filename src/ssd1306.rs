@@ -0,0 +1,103 @@
+//! module for SSD1306 OLED displays (128x64 or 128x32) on the I2C bus
+//! see https://cdn-shop.adafruit.com/datasheets/SSD1306.pdf
+//! the CO2 concentration is drawn large with the trend arrow, temperature and humidity below it.
+use embedded_hal::i2c;
+
+use crate::display::{Canvas, Locale, Screen, View};
+
+pub(crate) const DEFAULT_ADDR: u8 = 0x3C;
+
+const WIDTH: usize = 128;
+/// control byte of a command stream
+const COMMANDS: u8 = 0x00;
+/// control byte of a data stream
+const DATA: u8 = 0x40;
+
+pub(crate) struct Ssd1306<I> {
+    i2c: I,
+    addr: u8,
+    height: usize,
+}
+
+impl<I: i2c::I2c> Ssd1306<I> {
+    /// initialize the display of `height` (64 or 32) rows at `addr`, left blank and on
+    pub(crate) fn new(i2c: I, addr: u8, height: usize) -> Result<Self, String> {
+        if height != 64 && height != 32 {
+            return Err(format!("an SSD1306 has 64 or 32 rows, not {}", height));
+        }
+        let mut display = Ssd1306 { i2c, addr, height };
+        let (multiplex, com_pins) = if height == 64 { (0x3F, 0x12) } else { (0x1F, 0x02) };
+        let init = [
+            0xAE,             // display off
+            0xD5, 0x80,       // clock divide ratio and oscillator frequency
+            0xA8, multiplex,  // multiplex ratio, rows - 1
+            0xD3, 0x00,       // no display offset
+            0x40,             // start line 0
+            0x8D, 0x14,       // internal charge pump on
+            0x20, 0x00,       // horizontal addressing
+            0xA1,             // map column 127 to SEG0
+            0xC8,             // scan COM from the last row
+            0xDA, com_pins,   // COM pins configuration
+            0x81, 0xCF,       // contrast
+            0xD9, 0xF1,       // pre-charge period
+            0xDB, 0x40,       // VCOMH deselect level
+            0xA4,             // show the RAM
+            0xA6,             // not inverted
+        ];
+        display.command(&init)?;
+        display.draw(&Canvas::new(WIDTH, height))?;
+        display.command(&[0xAF])?;
+        return Ok(display);
+    }
+
+    fn command(&mut self, commands: &[u8]) -> Result<(), String> {
+        let mut buf = Vec::with_capacity(commands.len() + 1);
+        buf.push(COMMANDS);
+        buf.extend_from_slice(commands);
+        return self.i2c.write(self.addr, &buf).map_err(|e| format!("{:?}", e));
+    }
+
+    /// send `canvas` to the display RAM, a page of 8 rows at a time
+    fn draw(&mut self, canvas: &Canvas) -> Result<(), String> {
+        let pages = self.height / 8;
+        self.command(&[0x21, 0, (WIDTH - 1) as u8, 0x22, 0, (pages - 1) as u8])?;
+        for page in 0..pages {
+            let mut buf = Vec::with_capacity(WIDTH + 1);
+            buf.push(DATA);
+            for x in 0..WIDTH {
+                buf.push((0..8).fold(0, |byte, bit| byte | (canvas.get(x, page * 8 + bit) as u8) << bit));
+            }
+            self.i2c.write(self.addr, &buf).map_err(|e| format!("{:?}", e))?;
+        }
+        return Ok(());
+    }
+}
+
+impl<I: i2c::I2c + Send> Screen for Ssd1306<I> {
+    fn show(&mut self, view: &View, locale: &Locale) -> Result<(), String> {
+        let mut canvas = Canvas::new(WIDTH, self.height);
+        // the big line takes the upper part, the small one the rest
+        let (big, small, small_y) = if self.height == 64 { (3, 2, 44) } else { (2, 1, 22) };
+        match view {
+            View::Reading { co2, temperature, humidity, trend } => {
+                let value = co2.to_string();
+                let x = canvas.text(0, 0, &value, big);
+                canvas.text(x + 2, 7 * big - 7, "ppm", 1);
+                if let Some(trend) = trend {
+                    canvas.text(WIDTH - Canvas::text_width("↑", big), 0, &trend.arrow().to_string(), big);
+                }
+                let (temperature, unit) = locale.temperature(*temperature);
+                canvas.text(0, small_y, &format!("{}°{} {}%", temperature, unit, locale.number(*humidity, 0)), small);
+            }
+            View::Status(status) => {
+                canvas.text(0, 0, "CO2", big);
+                canvas.text(0, small_y, status.name(), small);
+            }
+        }
+        return self.draw(&canvas);
+    }
+
+    fn power(&mut self, on: bool) -> Result<(), String> {
+        return self.command(&[if on { 0xAF } else { 0xAE }]);
+    }
+}
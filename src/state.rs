@@ -0,0 +1,181 @@
+//! module for the exporter's state kept across restarts in --state-file
+//! the settings last applied to the sensor, the last forced recalibration and self-test and lifetime failure counts.
+//! the sensor forgets settings changed at runtime unless they were persisted, and nothing on the sensor
+//! tells when it was last calibrated, so the file is the record of both.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    clock::Clock,
+    json::{self, Value},
+    sensor::Settings,
+};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct State {
+    pub(crate) settings: Settings,
+    /// unix ms of the last successful forced recalibration
+    pub(crate) recalibrated_ms: Option<u64>,
+    pub(crate) recalibration_target: Option<u16>,
+    pub(crate) recalibration_correction: Option<i16>,
+    /// unix ms of the last self-test
+    pub(crate) self_tested_ms: Option<u64>,
+    /// its malfunction word, 0 if it passed
+    pub(crate) self_test_result: Option<u16>,
+    pub(crate) measurement_failures: u64,
+    pub(crate) reinits: u64,
+}
+
+impl State {
+    fn to_json(&self) -> String {
+        let opt = |v: Option<String>| v.unwrap_or_else(|| String::from("null"));
+        return format!(
+            "{{\n  \"settings\": {},\n  \"recalibrated_ms\": {},\n  \"recalibration_target\": {},\n  \"recalibration_correction\": {},\n  \"self_tested_ms\": {},\n  \"self_test_result\": {},\n  \"measurement_failures\": {},\n  \"reinits\": {}\n}}\n",
+            self.settings.to_json(),
+            opt(self.recalibrated_ms.map(|v| v.to_string())),
+            opt(self.recalibration_target.map(|v| v.to_string())),
+            opt(self.recalibration_correction.map(|v| v.to_string())),
+            opt(self.self_tested_ms.map(|v| v.to_string())),
+            opt(self.self_test_result.map(|v| v.to_string())),
+            self.measurement_failures,
+            self.reinits
+        );
+    }
+
+    fn from_json(s: &str) -> Result<Self, String> {
+        let value = json::parse(s).map_err(|e| e.to_string())?;
+        let number = |v: Option<&Value>| v.and_then(Value::as_f64);
+        let settings = value.get("settings");
+        let setting = |key| number(settings.and_then(|s| s.get(key)));
+        return Ok(State {
+            settings: Settings {
+                temperature_offset: setting("temperature_offset").map(|v| v as f32),
+                altitude: setting("altitude").map(|v| v as u16),
+                ambient_pressure: setting("ambient_pressure").map(|v| v as f32),
+                automatic_self_calibration: match settings.and_then(|s| s.get("automatic_self_calibration")) {
+                    Some(Value::Bool(b)) => Some(*b),
+                    _ => None,
+                },
+            },
+            recalibrated_ms: number(value.get("recalibrated_ms")).map(|v| v as u64),
+            recalibration_target: number(value.get("recalibration_target")).map(|v| v as u16),
+            recalibration_correction: number(value.get("recalibration_correction")).map(|v| v as i16),
+            self_tested_ms: number(value.get("self_tested_ms")).map(|v| v as u64),
+            self_test_result: number(value.get("self_test_result")).map(|v| v as u16),
+            measurement_failures: number(value.get("measurement_failures")).unwrap_or_default() as u64,
+            reinits: number(value.get("reinits")).unwrap_or_default() as u64,
+        });
+    }
+}
+
+static STATE: Mutex<Option<(PathBuf, State)>> = Mutex::new(None);
+
+/// load the state from `path`, empty when the file doesn't exist yet
+pub(crate) fn init(path: &Path) -> io::Result<State> {
+    let state = match fs::read_to_string(path) {
+        Ok(s) => State::from_json(&s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => State::default(),
+        Err(e) => return Err(e),
+    };
+    log::info!("settings last applied: {}", state.settings.to_json());
+    if let (Some(ms), Some(target), Some(correction)) = (state.recalibrated_ms, state.recalibration_target, state.recalibration_correction) {
+        log::info!("last forced recalibration at {} (unix ms) to {} ppm, corrected by {} ppm", ms, target, correction);
+        metrics::gauge!("scd41_last_recalibration_timestamp_ms").set(ms as f64);
+        metrics::gauge!("scd41_recalibration_correction_ppm").set(correction);
+    }
+    if let (Some(ms), Some(result)) = (state.self_tested_ms, state.self_test_result) {
+        log::info!("last self-test at {} (unix ms) {}", ms, if result == 0 { "passed" } else { "failed" });
+        self_test_gauges(ms, result);
+    }
+    *STATE.lock().unwrap_or_else(|e| e.into_inner()) = Some((path.to_path_buf(), state.clone()));
+    return Ok(state);
+}
+
+/// change the state and write it if it changed
+fn update(f: impl FnOnce(&mut State)) {
+    let mut guard = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let Some((path, state)) = guard.as_mut() else {
+        return;
+    };
+    let old = state.clone();
+    f(state);
+    if *state != old {
+        let _ = save(path, state).inspect_err(|e| log::warn!("failed to write {}: {}", path.display(), e));
+    }
+}
+
+fn save(path: &Path, state: &State) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // replace the file at once, so a power loss doesn't leave half of it
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, state.to_json())?;
+    return fs::rename(&tmp, path);
+}
+
+/// record the settings applied to the sensor
+pub(crate) fn applied(settings: Settings) {
+    // the pressure follows the weather, so a change of it alone is written with the next other change
+    let mut guard = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((_, state)) = guard.as_mut() {
+        let others = Settings { ambient_pressure: state.settings.ambient_pressure, ..settings };
+        if others == state.settings {
+            state.settings = settings;
+            return;
+        }
+    }
+    drop(guard);
+    update(|s| s.settings = settings);
+}
+
+/// record a successful forced recalibration
+pub(crate) fn recalibrated(clock: &dyn Clock, target: u16, correction: i16) {
+    let ms = clock.now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default();
+    metrics::gauge!("scd41_last_recalibration_timestamp_ms").set(ms as f64);
+    metrics::gauge!("scd41_recalibration_correction_ppm").set(correction);
+    update(|s| {
+        s.recalibrated_ms = Some(ms);
+        s.recalibration_target = Some(target);
+        s.recalibration_correction = Some(correction);
+    });
+}
+
+fn self_test_gauges(ms: u64, result: u16) {
+    metrics::gauge!("scd41_last_self_test_timestamp_ms").set(ms as f64);
+    metrics::gauge!("scd41_self_test_passed").set(if result == 0 { 1 } else { 0 });
+}
+
+/// record a self-test that ran, with its malfunction word
+pub(crate) fn self_tested(clock: &dyn Clock, result: u16) {
+    let ms = clock.now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default();
+    self_test_gauges(ms, result);
+    update(|s| {
+        s.self_tested_ms = Some(ms);
+        s.self_test_result = Some(result);
+    });
+}
+
+/// count a failed measurement. counts are written with the next other change, failures come in bursts.
+pub(crate) fn failed() {
+    if let Some((_, state)) = STATE.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        state.measurement_failures += 1;
+    }
+}
+
+/// write the state as it is, e.g. the failure counts before exiting
+pub(crate) fn flush() {
+    let guard = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((path, state)) = guard.as_ref() {
+        let _ = save(path, state).inspect_err(|e| log::warn!("failed to write {}: {}", path.display(), e));
+    }
+}
+
+/// count a reinitialization after consecutive failures
+pub(crate) fn reinitialized() {
+    update(|s| s.reinits += 1);
+}
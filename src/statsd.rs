@@ -0,0 +1,136 @@
+//! module for sending gauges to a StatsD or DogStatsD agent over UDP
+//! see https://docs.datadoghq.com/developers/dogstatsd/datagram_shell/
+//! every flush interval the gauges of the rendered exposition are sent as `name:value|g`, with the
+//! labels as DogStatsD tags or, for plain StatsD which has no tags, as dot separated name parts.
+//! lines are packed into datagrams that fit a typical MTU.
+use std::{
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use metrics_exporter_prometheus::PrometheusHandle;
+
+use crate::{
+    exposition::{self, Kind, Sample},
+    names, tasks,
+};
+
+/// payload size that stays below the MTU of ethernet with IP and UDP headers
+const MAX_DATAGRAM: usize = 1432;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Flavor {
+    /// tags as `|#key:value,...`
+    Dogstatsd,
+    /// label values appended to the name
+    Statsd,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    /// `host:port` of the agent
+    pub(crate) addr: String,
+    pub(crate) flavor: Flavor,
+    /// prepended to every name, e.g. `home.`
+    pub(crate) prefix: String,
+    pub(crate) interval: Duration,
+}
+
+/// send the gauges every interval from a task until a shutdown
+pub(crate) fn spawn(config: Config, handle: PrometheusHandle) -> io::Result<()> {
+    log::info!("send gauges to statsd at {} every {:?}", config.addr, config.interval);
+    let packets = metrics::counter!("exporter_statsd_packets_total");
+    let failures = metrics::counter!("exporter_statsd_send_failures_total");
+    tasks::spawn("statsd", async move {
+        while tasks::sleep(config.interval).await {
+            tasks::blocking(|| {
+                // resolve every time, so the agent can move (e.g. a container restarting with a new address)
+                let addr = match config.addr.to_socket_addrs().map(|mut a| a.next()) {
+                    Ok(Some(addr)) => addr,
+                    Ok(None) => {
+                        log::warn!("{} has no address", config.addr);
+                        failures.increment(1);
+                        return;
+                    }
+                    Err(e) => {
+                        log::warn!("failed to resolve {}: {:?}", config.addr, e);
+                        failures.increment(1);
+                        return;
+                    }
+                };
+                let local = if addr.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+                let socket = match UdpSocket::bind(local) {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        log::warn!("failed to open a udp socket: {:?}", e);
+                        failures.increment(1);
+                        return;
+                    }
+                };
+                let lines: Vec<String> = exposition::parse(&names::render(&handle))
+                    .iter()
+                    .filter(|s| s.kind == Kind::Gauge && s.value.is_finite())
+                    .map(|s| line(&config, s))
+                    .collect();
+                for datagram in pack(&lines) {
+                    match socket.send_to(datagram.as_bytes(), addr) {
+                        Ok(_) => packets.increment(1),
+                        Err(e) => {
+                            log::warn!("failed to send to statsd at {}: {:?}", addr, e);
+                            failures.increment(1);
+                        }
+                    }
+                }
+            });
+        }
+    });
+    return Ok(());
+}
+
+fn line(config: &Config, sample: &Sample) -> String {
+    return match config.flavor {
+        Flavor::Dogstatsd => {
+            let mut line = format!("{}{}:{}|g", config.prefix, sample.name, sample.value);
+            let tags: Vec<String> = sample.labels.iter().map(|(k, v)| format!("{}:{}", sanitize(k), sanitize(v))).collect();
+            if !tags.is_empty() {
+                line.push_str("|#");
+                line.push_str(&tags.join(","));
+            }
+            line
+        }
+        Flavor::Statsd => {
+            let mut name = format!("{}{}", config.prefix, sample.name);
+            for (_, value) in &sample.labels {
+                name.push('.');
+                name.push_str(&sanitize(value).replace('.', "_"));
+            }
+            format!("{}:{}|g", name, sample.value)
+        }
+    };
+}
+
+/// replace the characters separating the parts of a line
+fn sanitize(s: &str) -> String {
+    return s.replace([':', '|', ',', '#', '@', '\n', ' '], "_");
+}
+
+/// newline separated lines in datagrams of at most MAX_DATAGRAM bytes
+fn pack(lines: &[String]) -> Vec<String> {
+    let mut datagrams = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > MAX_DATAGRAM {
+            datagrams.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        datagrams.push(current);
+    }
+    return datagrams;
+}
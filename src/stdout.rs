@@ -0,0 +1,42 @@
+//! module for streaming measurements to stdout as JSON Lines
+//! with `--output stdout-jsonl` there's no HTTP listener and every measurement is printed as one object per
+//! line, for Telegraf's execd input, Vector or a shell pipeline. logs stay on stderr. when the reader goes away
+//! the exporter shuts down.
+use std::{
+    io::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use clap::ValueEnum;
+
+use crate::{sensor::Envelope, shutdown};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Mode {
+    /// one JSON object per measurement on stdout, instead of the HTTP listener
+    StdoutJsonl,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn init() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// print a measurement, if the output is enabled
+pub(crate) fn write(envelope: &Envelope) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut out = io::stdout().lock();
+    // each line is flushed, the reader acts on it right away
+    match writeln!(out, "{}", envelope.to_json()).and_then(|()| out.flush()) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+            log::info!("stdout was closed, shut down");
+            ENABLED.store(false, Ordering::Relaxed);
+            shutdown::request();
+        }
+        Err(e) => log::warn!("failed to write to stdout: {}", e),
+    }
+}
@@ -0,0 +1,223 @@
+//! module for streaming measurements to HTTP clients as server-sent events
+//! see https://html.spec.whatwg.org/multipage/server-sent-events.html
+//! every measurement is pushed to the subscribers of `/api/v1/stream` as a `measurement` event
+//! carrying the envelope JSON, with the sequence number as event id. idle streams get a comment
+//! now and then, so proxies keep them open and dead clients are noticed.
+//! a subscriber may narrow the stream with its query: `source=a,b` only sends those sources, `channels=co2,humidity`
+//! only those channels, and `delta=co2:10,temperature:0.1` (or `delta=0.5` for every channel) only sends a channel
+//! once it moved beyond that much from the value last sent, skipping measurements where nothing did.
+use std::{
+    io::{self, Write},
+    sync::{
+        mpsc::{self, RecvTimeoutError, SyncSender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{http, json, sensor::Envelope};
+
+/// concurrent subscribers, each holds a blocking task of the HTTP server
+const MAX_SUBSCRIBERS: usize = 32;
+/// events buffered per subscriber; a client that falls behind misses events beyond this
+const BUFFER: usize = 16;
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// channels of a measurement, as named in the envelope JSON
+const CHANNELS: [&str; 3] = ["co2", "temperature", "humidity"];
+
+/// what a subscriber asked for in its query
+#[derive(Debug, Default, PartialEq)]
+struct Filter {
+    /// sources sent, all if empty
+    sources: Vec<String>,
+    /// channels not sent
+    hidden: [bool; 3],
+    /// change it takes for a channel to be sent again, None sends every value
+    delta: [Option<f64>; 3],
+}
+
+impl Filter {
+    fn parse(request: &http::Request) -> Result<Filter, String> {
+        let mut filter = Filter::default();
+        let list = |key| request.param(key).into_iter().flat_map(|v| v.split(',')).filter(|s| !s.is_empty());
+        let channel = |name: &str| CHANNELS.iter().position(|c| *c == name).ok_or(format!("unknown channel {}", name));
+        let delta = |v: &str| v.parse::<f64>().ok().filter(|d| *d >= 0.0).ok_or(format!("invalid delta {}", v));
+        filter.sources = list("source").map(String::from).collect();
+        if request.param("channels").is_some() {
+            filter.hidden = [true; 3];
+            for name in list("channels") {
+                filter.hidden[channel(name)?] = false;
+            }
+        }
+        for item in list("delta") {
+            match item.split_once(':') {
+                Some((name, d)) => filter.delta[channel(name)?] = Some(delta(d)?),
+                None => filter.delta = [Some(delta(item)?); 3],
+            }
+        }
+        return Ok(filter);
+    }
+}
+
+struct Subscriber {
+    tx: SyncSender<String>,
+    filter: Filter,
+    /// per source the channel values last sent
+    sent: Vec<(Arc<str>, [f64; 3])>,
+}
+
+impl Subscriber {
+    /// the event for `envelope` and the values it sends, None if the filter leaves nothing to send
+    fn event(&self, envelope: &Envelope) -> Option<(String, [f64; 3])> {
+        let filter = &self.filter;
+        if !filter.sources.is_empty() && !filter.sources.iter().any(|s| **s == *envelope.source) {
+            return None;
+        }
+        if filter == &Filter::default() {
+            return Some((format!("event: measurement\nid: {}\ndata: {}\n\n", envelope.seq, envelope.to_json()), [f64::NAN; 3]));
+        }
+        let m = &envelope.measurement;
+        let values = [m.co2 as f64, m.temperature as f64, m.humidity as f64];
+        let last = self.sent.iter().find(|(s, _)| *s == envelope.source).map(|(_, v)| *v).unwrap_or([f64::NAN; 3]);
+        let mut sent = last;
+        let mut fields = Vec::new();
+        for (i, name) in CHANNELS.iter().enumerate() {
+            let changed = filter.delta[i].is_none_or(|d| last[i].is_nan() || (values[i] - last[i]).abs() > d);
+            if filter.hidden[i] || !changed {
+                continue;
+            }
+            sent[i] = values[i];
+            let value = if i == 0 { m.co2.to_string() } else { format!("{:.2}", values[i]) };
+            fields.push(format!(r#","{}":{}"#, name, value));
+        }
+        if fields.is_empty() {
+            return None;
+        }
+        let data = format!(
+            r#"{{"seq":{},"timestamp_ms":{},"source":{},"quality":{}{}}}"#,
+            envelope.seq,
+            envelope.timestamp_ms,
+            json::quote(&envelope.source),
+            envelope.quality.bits(),
+            fields.concat()
+        );
+        return Some((format!("event: measurement\nid: {}\ndata: {}\n\n", envelope.seq, data), sent));
+    }
+
+    /// send the event for `envelope`, false once the subscriber is gone
+    fn send(&mut self, envelope: &Envelope) -> bool {
+        let Some((event, values)) = self.event(envelope) else {
+            return true;
+        };
+        // a full buffer only loses this event, a disconnected subscriber is gone for good
+        match self.tx.try_send(event) {
+            Ok(()) => {}
+            Err(mpsc::TrySendError::Full(_)) => return true,
+            Err(mpsc::TrySendError::Disconnected(_)) => return false,
+        }
+        match self.sent.iter_mut().find(|(s, _)| *s == envelope.source) {
+            Some((_, sent)) => *sent = values,
+            None => self.sent.push((envelope.source.clone(), values)),
+        }
+        return true;
+    }
+}
+
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+
+/// push a measurement to the subscribers
+pub(crate) fn publish(envelope: &Envelope) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap_or_else(|e| e.into_inner());
+    subscribers.retain_mut(|s| s.send(envelope));
+}
+
+/// response streaming events to a new subscriber, filtered by the request's query
+pub(crate) fn subscribe(request: &http::Request) -> http::Response {
+    let filter = match Filter::parse(request) {
+        Ok(f) => f,
+        Err(e) => return http::Response::text(400, format!("{}\n", e)),
+    };
+    let (tx, rx) = mpsc::sync_channel(BUFFER);
+    {
+        let mut subscribers = SUBSCRIBERS.lock().unwrap_or_else(|e| e.into_inner());
+        if subscribers.len() >= MAX_SUBSCRIBERS {
+            return http::Response::text(503, "too many subscribers\n");
+        }
+        subscribers.push(Subscriber { tx, filter, sent: Vec::new() });
+    }
+    return http::Response::stream(
+        "text/event-stream",
+        Box::new(move |out: &mut dyn Write| -> io::Result<()> {
+            // reconnect quickly after the exporter restarted
+            out.write_all(b"retry: 5000\n\n")?;
+            out.flush()?;
+            loop {
+                match rx.recv_timeout(KEEP_ALIVE) {
+                    Ok(event) => out.write_all(event.as_bytes())?,
+                    Err(RecvTimeoutError::Timeout) => out.write_all(b": keep-alive\n\n")?,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+                out.flush()?;
+            }
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::{
+        clock::FakeClock,
+        sensor::{Measurement, Sequencer},
+    };
+
+    fn request(query: &str) -> http::Request {
+        let query = query.split('&').filter_map(|p| p.split_once('=')).map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        return http::Request { method: String::from("GET"), path: String::from("/api/v1/stream"), query, headers: Vec::new(), body: Vec::new() };
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(Filter::parse(&request("")), Ok(Filter::default()));
+        let filter = Filter::parse(&request("source=scd41,sen5x&channels=co2,humidity&delta=0.5,co2:10")).unwrap();
+        assert_eq!(filter.sources, ["scd41", "sen5x"]);
+        assert_eq!((filter.hidden, filter.delta), ([false, true, false], [Some(10.0), Some(0.5), Some(0.5)]));
+        assert!(Filter::parse(&request("channels=pm25")).is_err());
+        assert!(Filter::parse(&request("delta=co2:-1")).is_err());
+        assert!(Filter::parse(&request("delta=co2")).is_err());
+    }
+
+    #[test]
+    fn delta_updates() {
+        let (tx, rx) = mpsc::sync_channel(BUFFER);
+        let filter = Filter::parse(&request("channels=co2,temperature&delta=co2:10,temperature:0.1")).unwrap();
+        let mut subscriber = Subscriber { tx, filter, sent: Vec::new() };
+        let clock = FakeClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut sequencer = Sequencer::new("scd41", Duration::from_secs(5));
+        let mut send = |co2, temperature| {
+            let envelope = sequencer.wrap(Measurement { co2, temperature, humidity: 40.0 }, &clock);
+            assert!(subscriber.send(&envelope));
+            return rx.try_recv().ok();
+        };
+
+        // the first measurement sends every channel asked for
+        let first = send(800, 21.0).unwrap();
+        assert!(first.contains(r#""source":"scd41","quality":1,"co2":800,"temperature":21.00}"#), "{}", first);
+        assert_eq!(send(805, 21.05), None);
+        // changes add up against the value last sent, not the previous measurement
+        let third = send(811, 21.05).unwrap();
+        assert!(third.starts_with("event: measurement\nid: 3\n") && third.ends_with(",\"co2\":811}\n\n"), "{}", third);
+        let fourth = send(811, 20.9).unwrap();
+        assert!(fourth.ends_with(",\"temperature\":20.90}\n\n"), "{}", fourth);
+
+        // other sources are filtered out
+        let (tx, rx) = mpsc::sync_channel(BUFFER);
+        let mut other = Subscriber { tx, filter: Filter::parse(&request("source=sen5x")).unwrap(), sent: Vec::new() };
+        let envelope = Sequencer::new("scd41", Duration::from_secs(5)).wrap(Measurement { co2: 800, temperature: 21.0, humidity: 40.0 }, &clock);
+        assert!(other.send(&envelope));
+        assert!(rx.try_recv().is_err());
+    }
+}
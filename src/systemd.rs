@@ -0,0 +1,116 @@
+//! module for the systemd notification protocol (sd_notify)
+//! under `Type=notify` the service is reported ready once the sensor delivers valid data, the status line
+//! shows the latest reading, and with `WatchdogSec=` the main loop pings the watchdog, so a loop stuck on a
+//! hung I2C transaction gets the service restarted. without NOTIFY_SOCKET everything here does nothing.
+//! started by a `.socket` unit, the http server accepts on the sockets systemd passed (sd_listen_fds), so the
+//! service starts on the first scrape and may listen on a privileged port without running as root.
+use std::{
+    env, io,
+    net::TcpListener,
+    os::{
+        fd::{FromRawFd, RawFd},
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram, UnixListener},
+    },
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::http::Listener;
+
+/// the first file descriptor passed by systemd
+const LISTEN_FDS_START: RawFd = 3;
+
+struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    /// half the watchdog timeout, None without a watchdog
+    watchdog: Option<Duration>,
+}
+
+static NOTIFIER: OnceLock<Notifier> = OnceLock::new();
+static LAST_PING: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// connect to $NOTIFY_SOCKET if systemd set it
+pub(crate) fn init() {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let path = path.to_string_lossy().into_owned();
+    // '@' stands for the abstract namespace
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        None => SocketAddr::from_pathname(&path),
+    };
+    let socket = match (UnixDatagram::unbound(), addr) {
+        (Ok(socket), Ok(addr)) => (socket, addr),
+        (Err(e), _) | (_, Err(e)) => {
+            log::warn!("failed to use NOTIFY_SOCKET {}: {:?}", path, e);
+            return;
+        }
+    };
+    // the watchdog is meant for us, unless WATCHDOG_PID names another process
+    let ours = std::env::var("WATCHDOG_PID").map(|pid| pid == std::process::id().to_string()).unwrap_or(true);
+    let watchdog = std::env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|_| ours)
+        .map(|usec| Duration::from_micros(usec) / 2);
+    if let Some(interval) = watchdog {
+        log::info!("ping the systemd watchdog every {:?}", interval);
+    }
+    let _ = NOTIFIER.set(Notifier { socket: socket.0, addr: socket.1, watchdog });
+}
+
+/// send `state`, e.g. "READY=1"
+pub(crate) fn notify(state: &str) {
+    let Some(n) = NOTIFIER.get() else {
+        return;
+    };
+    if let Err(e) = n.socket.send_to_addr(state.as_bytes(), &n.addr) {
+        log::debug!("failed to notify systemd: {:?}", e);
+    }
+}
+
+/// ping the watchdog if half its timeout passed since the last ping
+pub(crate) fn watchdog() {
+    let Some(interval) = NOTIFIER.get().and_then(|n| n.watchdog) else {
+        return;
+    };
+    let mut last = LAST_PING.lock().unwrap_or_else(|e| e.into_inner());
+    if last.is_some_and(|t| t.elapsed() < interval) {
+        return;
+    }
+    *last = Some(Instant::now());
+    notify("WATCHDOG=1");
+}
+
+/// the listening sockets passed by systemd with their names (LISTEN_FDNAMES, else "fd N"), empty when not socket activated
+pub(crate) fn listeners() -> io::Result<Vec<(String, Listener)>> {
+    let ours = env::var("LISTEN_PID").is_ok_and(|pid| pid == std::process::id().to_string());
+    let count = env::var("LISTEN_FDS").ok().and_then(|n| n.parse::<RawFd>().ok()).filter(|_| ours).unwrap_or(0);
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+    // the sockets are for this process, not for the plugins it runs
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(var);
+    }
+    let mut listeners = Vec::new();
+    for (i, fd) in (LISTEN_FDS_START..LISTEN_FDS_START + count).enumerate() {
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        if unsafe { libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let listener = match addr.ss_family as libc::c_int {
+            libc::AF_INET | libc::AF_INET6 => Listener::Tcp(unsafe { TcpListener::from_raw_fd(fd) }),
+            libc::AF_UNIX => Listener::Unix(unsafe { UnixListener::from_raw_fd(fd) }),
+            family => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("fd {} is of unsupported address family {}", fd, family))),
+        };
+        let name = names.split(':').nth(i).filter(|n| !n.is_empty()).map(String::from).unwrap_or_else(|| format!("fd {}", fd));
+        listeners.push((name, listener));
+    }
+    return Ok(listeners);
+}
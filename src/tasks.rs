@@ -0,0 +1,93 @@
+//! module for the async runtime the exporter's concerns run on as tasks
+//! the measurement loop, the publishing of its measurements, the HTTP listeners, signal handling and the
+//! periodic outputs (remote_write, OTLP, Pushgateway, textfile, federation, InfluxDB) are tasks of one
+//! multi-threaded tokio runtime rather than a thread each. a shutdown cancels them: `sleep` and `cancellable`
+//! return early, and each task winds down from there. sensor commands and network requests still block, they
+//! run through `blocking` so the other tasks carry on meanwhile.
+use std::{
+    future::{poll_fn, Future},
+    io,
+    pin::pin,
+    sync::OnceLock,
+    task::Poll,
+    time::Duration,
+};
+
+use tokio::{
+    runtime::{Builder, Handle, Runtime},
+    task::JoinHandle,
+};
+
+use crate::shutdown;
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+fn build() -> io::Result<Runtime> {
+    return Builder::new_multi_thread().worker_threads(2).thread_name("tokio-worker").enable_io().enable_time().build();
+}
+
+fn runtime() -> &'static Runtime {
+    return RUNTIME.get_or_init(|| build().expect("failed to build the async runtime"));
+}
+
+/// start the runtime and the signal handling task. after the sandbox, so it covers the worker threads too.
+pub(crate) fn init() -> io::Result<()> {
+    if RUNTIME.get().is_none() {
+        let _ = RUNTIME.set(build()?);
+    }
+    let signals = shutdown::listen()?;
+    spawn("signals", signals);
+    return Ok(());
+}
+
+/// the runtime's handle, e.g. to enter it for tokio's I/O types
+pub(crate) fn handle() -> &'static Handle {
+    return runtime().handle();
+}
+
+/// run `task` on the runtime until it's done
+pub(crate) fn spawn<F: Future<Output = ()> + Send + 'static>(name: &'static str, task: F) -> JoinHandle<()> {
+    log::debug!("start task {}", name);
+    return runtime().spawn(task);
+}
+
+/// block the calling thread, outside the runtime, on `future`
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    return runtime().block_on(future);
+}
+
+/// run the blocking `f` within a task, moving the task's other work off this worker meanwhile
+pub(crate) fn blocking<R>(f: impl FnOnce() -> R) -> R {
+    return tokio::task::block_in_place(f);
+}
+
+/// `future`'s output, or None once a shutdown is requested before it's ready
+pub(crate) async fn cancellable<T>(future: impl Future<Output = T>) -> Option<T> {
+    let mut future = pin!(future);
+    let mut cancelled = pin!(shutdown::cancelled());
+    return poll_fn(|cx| {
+        if cancelled.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(None);
+        }
+        return future.as_mut().poll(cx).map(Some);
+    })
+    .await;
+}
+
+/// wait `duration`, false if a shutdown was requested instead
+pub(crate) async fn sleep(duration: Duration) -> bool {
+    return cancellable(tokio::time::sleep(duration)).await.is_some();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancellation() {
+        block_on(async {
+            assert!(sleep(Duration::from_millis(1)).await);
+            assert_eq!(cancellable(async { 7 }).await, Some(7));
+        });
+    }
+}
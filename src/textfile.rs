@@ -0,0 +1,32 @@
+//! module for node_exporter's textfile collector
+//! the exposition is written to `<dir>/<name>.prom` through a temporary file and a rename, so
+//! node_exporter never reads a partially written file.
+use std::{fs, io, path::PathBuf, time::Duration};
+
+use metrics_exporter_prometheus::PrometheusHandle;
+
+use crate::{names, tasks};
+
+/// file name in the collector directory
+const FILE_NAME: &str = "raspi_scd41_exporter.prom";
+
+/// write the rendered metrics into `dir` every `interval` from a task until a shutdown
+pub(crate) fn spawn(dir: PathBuf, interval: Duration, handle: PrometheusHandle) -> io::Result<()> {
+    let path = dir.join(FILE_NAME);
+    // node_exporter only reads *.prom, so the temporary file is ignored
+    let tmp = dir.join(format!(".{}.tmp", FILE_NAME));
+    log::info!("write metrics to {} every {:?}", path.display(), interval);
+    let failures = metrics::counter!("exporter_textfile_write_failures_total");
+    tasks::spawn("textfile", async move {
+        while tasks::sleep(interval).await {
+            tasks::blocking(|| {
+                let written = fs::write(&tmp, names::render(&handle)).and_then(|_| fs::rename(&tmp, &path));
+                if let Err(e) = written {
+                    log::warn!("failed to write {}: {:?}", path.display(), e);
+                    failures.increment(1);
+                }
+            });
+        }
+    });
+    return Ok(());
+}
@@ -0,0 +1,408 @@
+//! module for minimal TOML parsing, enough for the config file
+//! tables, arrays of tables, inline tables, arrays, strings, numbers and booleans. numbers and dates
+//! keep their text, as they end up as command line arguments anyway.
+use std::{error::Error, fmt};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    String(String),
+    /// an integer, float or date as written, without underscores
+    Number(String),
+    Bool(bool),
+    Array(Vec<Value>),
+    Table(Vec<(String, Value)>),
+}
+
+#[derive(Debug)]
+pub(crate) struct ParseError {
+    line: usize,
+    msg: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{} on line {}", self.msg, self.line);
+    }
+}
+
+impl Error for ParseError {}
+
+/// parse a TOML document into its root table
+pub(crate) fn parse(input: &str) -> Result<Vec<(String, Value)>, ParseError> {
+    let mut parser = Parser { bytes: input.as_bytes(), pos: 0 };
+    let mut root = Vec::new();
+    // path of the table the following keys go into
+    let mut current: Vec<String> = Vec::new();
+    // headers of the tables so far, each may only be given once
+    let mut defined: Vec<Vec<String>> = Vec::new();
+    loop {
+        parser.skip_blank();
+        match parser.peek() {
+            None => return Ok(root),
+            Some(b'[') => {
+                parser.pos += 1;
+                let array = parser.peek() == Some(b'[');
+                if array {
+                    parser.pos += 1;
+                }
+                let path = parser.key()?;
+                parser.expect(b']')?;
+                if array {
+                    parser.expect(b']')?;
+                }
+                parser.end_of_line()?;
+                let (last, parent) = path.split_last().ok_or_else(|| parser.error("empty table name"))?;
+                let parent = table_at(&mut root, parent).map_err(|e| parser.error(&e))?;
+                match (parent.iter_mut().find(|(k, _)| k == last), array) {
+                    (None, false) => parent.push((last.clone(), Value::Table(Vec::new()))),
+                    (None, true) => parent.push((last.clone(), Value::Array(vec![Value::Table(Vec::new())]))),
+                    (Some((_, Value::Array(tables))), true) => tables.push(Value::Table(Vec::new())),
+                    // defined implicitly by a dotted header before
+                    (Some((_, Value::Table(_))), false) if !defined.contains(&path) => {}
+                    _ => return Err(parser.error(&format!("{} is defined twice", path.join(".")))),
+                }
+                if array {
+                    // the sub-tables of the previous table of the array are given again for the new one
+                    defined.retain(|d| !d.starts_with(&path));
+                } else {
+                    defined.push(path.clone());
+                }
+                current = path;
+            }
+            Some(_) => {
+                let path = parser.key()?;
+                parser.skip_space();
+                parser.expect(b'=')?;
+                let value = parser.value()?;
+                parser.end_of_line()?;
+                let (last, parent) = path.split_last().ok_or_else(|| parser.error("empty key"))?;
+                let full: Vec<String> = current.iter().chain(parent).cloned().collect();
+                let table = table_at(&mut root, &full).map_err(|e| parser.error(&e))?;
+                if table.iter().any(|(k, _)| k == last) {
+                    return Err(parser.error(&format!("{} is defined twice", last)));
+                }
+                table.push((last.clone(), value));
+            }
+        }
+    }
+}
+
+/// the table at `path` below `root`, created as needed. an array of tables stands for its last table.
+fn table_at<'a>(root: &'a mut Vec<(String, Value)>, path: &[String]) -> Result<&'a mut Vec<(String, Value)>, String> {
+    let Some((first, rest)) = path.split_first() else {
+        return Ok(root);
+    };
+    if !root.iter().any(|(k, _)| k == first) {
+        root.push((first.clone(), Value::Table(Vec::new())));
+    }
+    let value = root.iter_mut().find(|(k, _)| k == first).map(|(_, v)| v);
+    let table = match value {
+        Some(Value::Table(table)) => table,
+        Some(Value::Array(items)) => match items.last_mut() {
+            Some(Value::Table(table)) => table,
+            _ => return Err(format!("{} is not a table", first)),
+        },
+        _ => return Err(format!("{} is not a table", first)),
+    };
+    return table_at(table, rest);
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, msg: &str) -> ParseError {
+        let line = self.bytes[..self.pos.min(self.bytes.len())].iter().filter(|b| **b == b'\n').count() + 1;
+        return ParseError { line, msg: msg.to_string() };
+    }
+
+    fn peek(&self) -> Option<u8> {
+        return self.bytes.get(self.pos).copied();
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), ParseError> {
+        self.skip_space();
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("expected '{}'", c as char)));
+        }
+        self.pos += 1;
+        return Ok(());
+    }
+
+    /// skip spaces and tabs
+    fn skip_space(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.pos += 1;
+        }
+    }
+
+    /// skip whitespace, newlines and comments
+    fn skip_blank(&mut self) {
+        loop {
+            match self.peek() {
+                Some(b' ' | b'\t' | b'\r' | b'\n') => self.pos += 1,
+                Some(b'#') => {
+                    while !matches!(self.peek(), None | Some(b'\n')) {
+                        self.pos += 1;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// nothing but a comment may follow on the line
+    fn end_of_line(&mut self) -> Result<(), ParseError> {
+        self.skip_space();
+        match self.peek() {
+            None | Some(b'\n' | b'#') => return Ok(()),
+            Some(b'\r') if self.bytes.get(self.pos + 1) == Some(&b'\n') => return Ok(()),
+            _ => return Err(self.error("expected the end of the line")),
+        }
+    }
+
+    /// a dotted key of bare or quoted parts
+    fn key(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut parts = Vec::new();
+        loop {
+            self.skip_space();
+            let part = match self.peek() {
+                Some(b'"') => self.basic_string()?,
+                Some(b'\'') => self.literal_string()?,
+                _ => {
+                    let start = self.pos;
+                    while matches!(self.peek(), Some(b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-')) {
+                        self.pos += 1;
+                    }
+                    if start == self.pos {
+                        return Err(self.error("expected a key"));
+                    }
+                    String::from_utf8_lossy(&self.bytes[start..self.pos]).into_owned()
+                }
+            };
+            parts.push(part);
+            self.skip_space();
+            if self.peek() != Some(b'.') {
+                return Ok(parts);
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        self.skip_space();
+        return match self.peek() {
+            None => Err(self.error("expected a value")),
+            Some(b'"') => self.basic_string().map(Value::String),
+            Some(b'\'') => self.literal_string().map(Value::String),
+            Some(b'[') => self.array(),
+            Some(b'{') => self.inline_table(),
+            Some(_) => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-' | b'+' | b'.' | b':')) {
+                    self.pos += 1;
+                }
+                match &self.bytes[start..self.pos] {
+                    b"" => Err(self.error("expected a value")),
+                    b"true" => Ok(Value::Bool(true)),
+                    b"false" => Ok(Value::Bool(false)),
+                    word if word[0].is_ascii_digit() || matches!(word[0], b'+' | b'-') => {
+                        Ok(Value::Number(String::from_utf8_lossy(word).replace('_', "")))
+                    }
+                    _ => Err(self.error("invalid value, strings need quotes")),
+                }
+            }
+        };
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank();
+            if self.peek() == Some(b']') {
+                self.pos += 1;
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value()?);
+            self.skip_blank();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {}
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, ParseError> {
+        self.expect(b'{')?;
+        let mut table = Vec::new();
+        self.skip_space();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Table(table));
+        }
+        loop {
+            let path = self.key()?;
+            self.expect(b'=')?;
+            let value = self.value()?;
+            let (last, parent) = path.split_last().ok_or_else(|| self.error("empty key"))?;
+            let target = table_at(&mut table, parent).map_err(|e| self.error(&e))?;
+            if target.iter().any(|(k, _)| k == last) {
+                return Err(self.error(&format!("{} is defined twice", last)));
+            }
+            target.push((last.clone(), value));
+            self.skip_space();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Table(table));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, ParseError> {
+        self.expect(b'\'')?;
+        let start = self.pos;
+        while !matches!(self.peek(), None | Some(b'\'' | b'\n')) {
+            self.pos += 1;
+        }
+        if self.peek() != Some(b'\'') {
+            return Err(self.error("unterminated string"));
+        }
+        let s = String::from_utf8_lossy(&self.bytes[start..self.pos]).into_owned();
+        self.pos += 1;
+        return Ok(s);
+    }
+
+    fn basic_string(&mut self) -> Result<String, ParseError> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(b'\n') => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(String::from_utf8_lossy(&out).into_owned());
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos += 1;
+                    let c = match escaped {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' | b'U' => {
+                            let len = if escaped == b'u' { 4 } else { 8 };
+                            let hex = self.bytes.get(self.pos..self.pos + len).ok_or_else(|| self.error("invalid escape"))?;
+                            self.pos += len;
+                            u32::from_str_radix(&String::from_utf8_lossy(hex), 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error("invalid escape"))?
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Some(b) => {
+                    out.push(b);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        return Value::String(s.to_string());
+    }
+
+    fn table(pairs: &[(&str, Value)]) -> Value {
+        return Value::Table(pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect());
+    }
+
+    #[test]
+    fn dotted_keys() {
+        let root = parse("mqtt.url = \"mqtt://broker\"\n[label]\nroom.name = 'kitchen'\n\"quoted.key\" = 1\n").unwrap();
+        assert_eq!(
+            root,
+            vec![
+                (String::from("mqtt"), table(&[("url", string("mqtt://broker"))])),
+                (String::from("label"), table(&[("room", table(&[("name", string("kitchen"))])), ("quoted.key", Value::Number(String::from("1")))])),
+            ]
+        );
+    }
+
+    #[test]
+    fn arrays_of_tables_with_sub_tables() {
+        let root = parse("[[plugin]]\ntype = \"sht4x\"\n[plugin.label]\nroom = \"kitchen\"\n[[plugin]]\ntype = \"sen5x\"\n").unwrap();
+        let plugins = Value::Array(vec![
+            table(&[("type", string("sht4x")), ("label", table(&[("room", string("kitchen"))]))]),
+            table(&[("type", string("sen5x"))]),
+        ]);
+        assert_eq!(root, vec![(String::from("plugin"), plugins)]);
+    }
+
+    #[test]
+    fn inline_tables() {
+        let root = parse("plugin = [{ type = \"sht4x\", label.room = \"den\" }, {}]\nflag = { on = true }\n").unwrap();
+        let plugins = Value::Array(vec![table(&[("type", string("sht4x")), ("label", table(&[("room", string("den"))]))]), table(&[])]);
+        assert_eq!(root, vec![(String::from("plugin"), plugins), (String::from("flag"), table(&[("on", Value::Bool(true))]))]);
+        assert!(parse("t = { a = 1, a = 2 }\n").is_err());
+        assert!(parse("t = { a = 1\n").is_err());
+    }
+
+    #[test]
+    fn unicode_escapes() {
+        let root = parse(r#"s = "café \U0001F600 \t""#).unwrap();
+        assert_eq!(root, vec![(String::from("s"), string("café 😀 \t"))]);
+        // a lone surrogate isn't a char
+        assert!(parse(r#"s = "\uD800""#).is_err());
+        assert!(parse(r#"s = "\u12""#).is_err());
+        assert!(parse(r#"s = "\x41""#).is_err());
+    }
+
+    #[test]
+    fn duplicate_keys_are_errors() {
+        let e = parse("offset = 1\noffset = 2\n").unwrap_err();
+        assert_eq!(e.to_string(), "offset is defined twice on line 2");
+        let e = parse("[mqtt]\nurl = \"a\"\n\n[mqtt]\n").unwrap_err();
+        assert_eq!(e.to_string(), "mqtt is defined twice on line 4");
+        assert!(parse("[mqtt]\nurl = \"a\"\n[mqtt.url]\n").is_err());
+        assert!(parse("a.b = 1\na.b = 2\n").is_err());
+        // sub-tables of an array of tables may be given again in each of its tables
+        assert!(parse("[[plugin]]\n[plugin.label]\n[[plugin]]\n[plugin.label]\n").is_ok());
+        assert!(parse("[mqtt.tls]\n[mqtt]\n").is_ok());
+    }
+
+    #[test]
+    fn crlf_line_ends() {
+        let root = parse("# comment\r\noffset = 4.5 # trailing\r\n[mqtt]\r\nurl = \"mqtt://broker\"\r\nlist = [\r\n  1,\r\n  2,\r\n]\r\n").unwrap();
+        assert_eq!(
+            root,
+            vec![
+                (String::from("offset"), Value::Number(String::from("4.5"))),
+                (
+                    String::from("mqtt"),
+                    table(&[("url", string("mqtt://broker")), ("list", Value::Array(vec![Value::Number(String::from("1")), Value::Number(String::from("2"))]))])
+                ),
+            ]
+        );
+        assert_eq!(parse("a = 1\r\nb = 2 c\r\n").unwrap_err().to_string(), "expected the end of the line on line 2");
+    }
+}
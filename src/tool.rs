@@ -0,0 +1,277 @@
+//! module for the one-shot subcommands working on the sensor instead of serving metrics
+//! they use the same bus as `serve` (with --trace-i2c and --inject-faults). while an exporter is running,
+//! calibrate, self-test, offset and settings go through its --admin-socket instead, so the two don't talk
+//! to the sensor at once. self-test, offset, settings and reset are scd4x commands.
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use embedded_hal::i2c;
+
+use crate::{
+    clock::SystemClock,
+    backup, detect,
+    error::{Context, Error},
+    http, interlock,
+    json::{self, Value},
+    sensor::{Sensor, Sequencer},
+};
+
+/// print measurements of `sensor` as they come, until interrupted. with `once` only the first plausible
+/// one, then stop measuring, or fail after `timeout` without one.
+pub(crate) fn read<S: Sensor>(mut sensor: S, once: bool, json: bool, timeout: Duration) -> Result<(), Error> {
+    sensor.start().context("failed to start the sensor")?;
+    let mut sequencer = Sequencer::new(&sensor.serial().unwrap_or_else(|| String::from("primary")), sensor.sample_interval());
+    let started = Instant::now();
+    loop {
+        thread::sleep(sensor.poll_interval());
+        if let Some(m) = sensor.measure().context("failed to measure")? {
+            if !once || interlock::plausible(&m) {
+                let e = sequencer.wrap(m, &SystemClock);
+                if json {
+                    println!("{}", e.to_json());
+                } else {
+                    println!("co2={} temperature={:.2} humidity={:.2}", m.co2, m.temperature, m.humidity);
+                }
+                if once {
+                    break;
+                }
+            } else {
+                log::debug!("ignore implausible measurement {:?}", m);
+            }
+        }
+        if once && started.elapsed() > timeout {
+            let _ = sensor.stop().inspect_err(|e| log::warn!("failed to stop the sensor: {:?}", e));
+            return Err(Error::Device(format!("no plausible measurement within {}s", timeout.as_secs())));
+        }
+    }
+    sensor.stop().context("failed to stop the sensor")?;
+    return Ok(());
+}
+
+/// measure for `warmup`, then recalibrate `sensor` to `target` ppm
+pub(crate) fn calibrate<S: Sensor>(mut sensor: S, target: u16, warmup: Duration) -> Result<(), Error> {
+    sensor.start().context("failed to start the sensor")?;
+    eprintln!("measuring for {}s before the recalibration, keep the sensor at {} ppm", warmup.as_secs(), target);
+    thread::sleep(warmup);
+    let correction = sensor
+        .force_recalibration(target)
+        .context("failed to recalibrate")?
+        .ok_or_else(|| Error::Device(String::from("the sensor rejected the recalibration")))?;
+    println!("recalibrated to {} ppm, correction {} ppm", target, correction);
+    return Ok(());
+}
+
+/// run the scd4x's self test, an error if it found a malfunction
+pub(crate) fn self_test<I: i2c::I2c + fmt::Debug>(i2c: &mut I) -> Result<(), Error> {
+    let mut scd41 = scd4x::Scd41::new(i2c, scd4x::StdDelay);
+    scd41.clean_state();
+    eprintln!("running the self test, this takes 10s");
+    match scd41.perform_self_test()? {
+        0 => println!("self test passed"),
+        word => return Err(Error::Device(format!("self test failed (0x{:04x})", word))),
+    }
+    return Ok(());
+}
+
+/// print the scd4x's temperature offset, or set it to `value` and optionally persist it
+pub(crate) fn offset<I: i2c::I2c + fmt::Debug>(i2c: &mut I, value: Option<f32>, persist: bool) -> Result<(), Error> {
+    let mut scd41 = scd4x::Scd41::new(i2c, scd4x::StdDelay);
+    scd41.clean_state();
+    if let Some(value) = value {
+        scd41.set_temperature_offset(value)?;
+        if persist {
+            scd41.persist_settings()?;
+        }
+    }
+    let offset = scd41.get_temperature_offset()?;
+    println!("temperature offset {:.2} degC{}", offset, if value.is_some() && persist { ", persisted" } else { "" });
+    return Ok(());
+}
+
+/// print the settings stored in the scd4x's eeprom, as a table or JSON
+pub(crate) fn settings<I: i2c::I2c + fmt::Debug>(i2c: &mut I, json: bool) -> Result<(), Error> {
+    let mut scd41 = scd4x::Scd41::new(i2c, scd4x::StdDelay);
+    // reinit in clean_state loads the eeprom, so this is what the sensor starts with
+    scd41.clean_state();
+    let word = scd41.get_sensor_variant_word()?;
+    let variant = scd4x::Variant::from_word(word);
+    let serial = scd41.read_serial()?;
+    let snapshot = backup::read(&mut scd41, serial, variant)?;
+    let target = scd41.get_automatic_self_calibration_target()?;
+    let initial_period = match scd4x::check(variant, scd4x::Command::AscPeriods) {
+        Ok(()) => Some(scd41.get_automatic_self_calibration_initial_period()?),
+        Err(_) => None,
+    };
+    let hours = |h: Option<u16>| h.map(|h| h.to_string());
+    let rows = [
+        ("serial", json::quote(&snapshot.serial), snapshot.serial.clone()),
+        ("variant", json::quote(&format!("{:?}", variant)), format!("{:?}", variant)),
+        ("variant_word", word.to_string(), format!("0x{:04x}", word)),
+        ("temperature_offset", format!("{:.2}", snapshot.temperature_offset), format!("{:.2} degC", snapshot.temperature_offset)),
+        ("altitude", snapshot.altitude.to_string(), format!("{} m", snapshot.altitude)),
+        ("asc_enabled", snapshot.asc_enabled.to_string(), snapshot.asc_enabled.to_string()),
+        ("asc_target", target.to_string(), format!("{} ppm", target)),
+        (
+            "asc_initial_period",
+            hours(initial_period).unwrap_or_else(|| String::from("null")),
+            hours(initial_period).map(|h| h + " h").unwrap_or_else(|| String::from("-")),
+        ),
+        (
+            "asc_standard_period",
+            hours(snapshot.asc_standard_period).unwrap_or_else(|| String::from("null")),
+            hours(snapshot.asc_standard_period).map(|h| h + " h").unwrap_or_else(|| String::from("-")),
+        ),
+    ];
+    if json {
+        println!("{{{}}}", rows.iter().map(|(k, v, _)| format!("{}:{}", json::quote(k), v)).collect::<Vec<_>>().join(","));
+    } else {
+        for (k, _, text) in &rows {
+            println!("{:<20} {}", k.replace('_', " "), text);
+        }
+    }
+    return Ok(());
+}
+
+/// reinitialize the scd4x from its eeprom, or restore the factory settings
+pub(crate) fn reset<I: i2c::I2c + fmt::Debug>(i2c: &mut I, factory: bool) -> Result<(), Error> {
+    let mut scd41 = scd4x::Scd41::new(i2c, scd4x::StdDelay);
+    // clean_state ends with reinit
+    scd41.clean_state();
+    if factory {
+        scd41.perform_factory_reset()?;
+        println!("restored the factory settings");
+    } else {
+        println!("reinitialized from the eeprom");
+    }
+    return Ok(());
+}
+
+/// print the supported devices answering on the bus
+pub(crate) fn scan<I: i2c::I2c>(i2c: &mut I) {
+    let found = detect::scan(i2c);
+    if found.is_empty() {
+        println!("no supported device found");
+    }
+    for device in found {
+        println!("0x{:02x} {}", device.addr(), device.name());
+    }
+}
+
+/// a running exporter reached through its admin socket
+pub(crate) struct Daemon {
+    socket: PathBuf,
+}
+
+impl Daemon {
+    /// the exporter accepting on `socket`, None if none is
+    pub(crate) fn find(socket: &Path) -> Option<Daemon> {
+        return std::os::unix::net::UnixStream::connect(socket).is_ok().then(|| Daemon { socket: socket.to_path_buf() });
+    }
+
+    /// send a request to the admin API and return the JSON it answered with
+    fn call(&self, method: &str, path: &str, body: &str) -> Result<Value, Error> {
+        let (status, response) =
+            http::send_unix(&self.socket, method, path, &[("Content-Type", "application/json")], body.as_bytes()).context(format!("failed to reach the exporter on {}", self.socket.display()))?;
+        let response = String::from_utf8_lossy(&response);
+        let value = json::parse(&response).map_err(|e| Error::Http(format!("{} {}: {}", method, path, e)))?;
+        if status != 200 {
+            let message = match value.get("error") {
+                Some(Value::String(e)) => e.clone(),
+                _ => format!("status {}", status),
+            };
+            return Err(Error::Device(message));
+        }
+        return Ok(value);
+    }
+
+    /// let the exporter recalibrate to `target` ppm after `warmup`, it keeps measuring meanwhile
+    pub(crate) fn calibrate(&self, target: u16, warmup: Duration) -> Result<(), Error> {
+        eprintln!("the exporter is running, recalibrating through it in {}s, keep the sensor at {} ppm", warmup.as_secs(), target);
+        thread::sleep(warmup);
+        let value = self.call("POST", "/api/v1/calibrate", &format!(r#"{{"target_ppm":{}}}"#, target))?;
+        let correction = value.get("correction_ppm").and_then(Value::as_f64).unwrap_or_default();
+        println!("recalibrated to {} ppm, correction {} ppm", target, correction);
+        return Ok(());
+    }
+
+    pub(crate) fn self_test(&self) -> Result<(), Error> {
+        eprintln!("the exporter is running, running the self test through it, this takes 10s");
+        self.call("POST", "/api/v1/self-test", "")?;
+        println!("self test passed");
+        return Ok(());
+    }
+
+    /// print the temperature offset, or set it to `value`. persisting it is left to the exporter.
+    pub(crate) fn offset(&self, value: Option<f32>, persist: bool) -> Result<(), Error> {
+        if persist {
+            return Err(Error::Device(String::from("the exporter is running, --persist needs the bus to itself")));
+        }
+        let settings = match value {
+            Some(v) => self.call("PUT", "/api/v1/settings", &format!(r#"{{"temperature_offset":{}}}"#, v))?,
+            None => self.call("GET", "/api/v1/settings", "")?,
+        };
+        match settings.get("temperature_offset").and_then(Value::as_f64) {
+            Some(offset) => println!("temperature offset {:.2} degC", offset),
+            None => println!("the sensor has no temperature offset"),
+        }
+        return Ok(());
+    }
+
+    /// print the settings the exporter applied, as a table or JSON
+    pub(crate) fn settings(&self, json: bool) -> Result<(), Error> {
+        let Value::Object(settings) = self.call("GET", "/api/v1/settings", "")? else {
+            return Err(Error::Http(String::from("the settings aren't a JSON object")));
+        };
+        if json {
+            let members: Vec<String> = settings.iter().map(|(k, v)| format!("{}:{}", json::quote(k), scalar(v).unwrap_or_else(|| String::from("null")))).collect();
+            println!("{{{}}}", members.join(","));
+            return Ok(());
+        }
+        for (k, v) in &settings {
+            println!("{:<20} {}", k.replace('_', " "), scalar(v).unwrap_or_else(|| String::from("-")));
+        }
+        return Ok(());
+    }
+}
+
+/// a setting's value as JSON, None for null
+fn scalar(value: &Value) -> Option<String> {
+    return match value {
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Number(n) => Some(json::number(*n)),
+        Value::String(s) => Some(json::quote(s)),
+        _ => None,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn through_daemon() {
+        let socket = std::env::temp_dir().join(format!("scd41-admin-{}.sock", std::process::id()));
+        assert!(Daemon::find(&socket).is_none());
+        let router = http::Router::default()
+            .route("/api/v1/settings", |r| match r.method.as_str() {
+                "PUT" => http::Response::json(format!(r#"{{"temperature_offset":{},"altitude":null}}"#, String::from_utf8_lossy(&r.body).len())),
+                _ => http::Response::json(r#"{"temperature_offset":4.00,"altitude":null}"#),
+            })
+            .route("/api/v1/self-test", |_| http::Response::new(409, "application/json", r#"{"error":"the self-test found a malfunction (0x0001)"}"#));
+        http::serve(http::Listener::bind(&format!("unix:{}", socket.display())).unwrap(), http::Server { router, tls: None, allow: Vec::new() }).unwrap();
+
+        let daemon = Daemon::find(&socket).unwrap();
+        assert_eq!(daemon.call("GET", "/api/v1/settings", "").unwrap().get("temperature_offset"), Some(&Value::Number(4.0)));
+        // the body arrives whole, its length comes back as the offset
+        let body = r#"{"temperature_offset":5}"#;
+        assert_eq!(daemon.call("PUT", "/api/v1/settings", body).unwrap().get("temperature_offset"), Some(&Value::Number(body.len() as f64)));
+        let failed = daemon.self_test().unwrap_err();
+        assert_eq!(failed.to_string(), "the self-test found a malfunction (0x0001)");
+        assert!(daemon.offset(Some(5.0), true).is_err());
+        let _ = std::fs::remove_file(&socket);
+    }
+}
@@ -0,0 +1,98 @@
+//! module for feeding the board's hardware watchdog (/dev/watchdog)
+//! it's fed only while valid measurements flow and the http server answers, so a wedged I2C driver or a
+//! hung exporter ends in a reboot of an unattended board. for --hardware-watchdog-grace after start it's
+//! fed regardless of the measurements, as bringing up the sensor takes a while. a clean exit disarms it.
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream},
+    os::fd::AsRawFd,
+    path::PathBuf,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::health;
+
+/// WDIOC_SETTIMEOUT, _IOWR('W', 6, int)
+const WDIOC_SETTIMEOUT: u32 = 0xC004_5706;
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+static DEVICE: Mutex<Option<File>> = Mutex::new(None);
+
+pub(crate) struct Config {
+    pub(crate) device: PathBuf,
+    pub(crate) timeout: Duration,
+    pub(crate) grace: Duration,
+    /// http listener to probe, None without one
+    pub(crate) probe: Option<SocketAddr>,
+}
+
+/// open the watchdog and feed it from a thread while the exporter is healthy
+pub(crate) fn spawn(config: Config) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).open(&config.device)?;
+    let mut secs = config.timeout.as_secs() as libc::c_int;
+    if unsafe { libc::ioctl(file.as_raw_fd(), WDIOC_SETTIMEOUT as _, &mut secs) } < 0 {
+        log::warn!("failed to set the watchdog timeout, keep the driver's: {:?}", io::Error::last_os_error());
+    }
+    // the driver rounds to what the hardware supports and reports it back
+    log::info!("feed {} with a {}s timeout", config.device.display(), secs);
+    file.write_all(b"\0")?;
+    *DEVICE.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+
+    let interval = Duration::from_secs(secs.max(1) as u64) / 3;
+    let started = Instant::now();
+    thread::Builder::new().name(String::from("watchdog")).spawn(move || loop {
+        thread::sleep(interval);
+        match healthy(&config, started) {
+            Ok(()) => feed(),
+            Err(reason) => log::warn!("not feeding the watchdog: {}", reason),
+        }
+    })?;
+    return Ok(());
+}
+
+fn healthy(config: &Config, started: Instant) -> Result<(), String> {
+    let (status, body) = health::readiness();
+    if status != 200 && started.elapsed() > config.grace {
+        return Err(body.trim_end().to_string());
+    }
+    if let Some(addr) = config.probe {
+        probe(addr).map_err(|e| format!("http server at {} doesn't answer: {:?}", addr, e))?;
+    }
+    return Ok(());
+}
+
+/// whether the http server at `addr` answers. any reply counts, so an error from --allow-cidr or a
+/// TLS alert to the plain request does too.
+fn probe(addr: SocketAddr) -> io::Result<()> {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    let mut stream = TcpStream::connect_timeout(&SocketAddr::new(ip, addr.port()), PROBE_TIMEOUT)?;
+    stream.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    stream.set_write_timeout(Some(PROBE_TIMEOUT))?;
+    stream.write_all(b"GET /healthz HTTP/1.0\r\n\r\n")?;
+    let mut reply = [0; 1];
+    if stream.read(&mut reply)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "closed without a reply"));
+    }
+    return Ok(());
+}
+
+fn feed() {
+    if let Some(file) = DEVICE.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        let _ = file.write_all(b"\0").inspect_err(|e| log::warn!("failed to feed the watchdog: {:?}", e));
+    }
+}
+
+/// stop the watchdog on a clean exit, unless the driver was built with nowayout
+pub(crate) fn disarm() {
+    if let Some(mut file) = DEVICE.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        // the magic close character
+        let _ = file.write_all(b"V").inspect_err(|e| log::warn!("failed to disarm the watchdog: {:?}", e));
+    }
+}
@@ -0,0 +1,88 @@
+//! module for fetching the ambient pressure from an online weather source
+//! for installations without a barometer, the sea-level pressure is fetched from --weather-url every
+//! --weather-interval, e.g. Open-Meteo's `current=pressure_msl`, reduced to the pressure at --weather-altitude
+//! and fed to the sensor for compensation like a BME280's. --weather-field is the dotted path of the
+//! value in hPa in the JSON response.
+use std::{
+    io,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    http::{self, Url},
+    json, shutdown,
+};
+
+/// wait before retrying a failed fetch
+const RETRY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) url: Url,
+    pub(crate) field: String,
+    /// meters above sea level
+    pub(crate) altitude: f64,
+    pub(crate) interval: Duration,
+}
+
+/// the pressure `altitude` meters above sea level at `sea_level` pressure, by the barometric formula
+fn at_altitude(sea_level: f64, altitude: f64) -> f64 {
+    return sea_level * (1.0 - 2.25577e-5 * altitude).powf(5.25588);
+}
+
+/// fetch the sea-level pressure in Pa
+fn fetch(config: &Config) -> Result<f64, String> {
+    let (status, body) = http::send("GET", &config.url, &[("Accept", "application/json")], &[]).map_err(|e| e.to_string())?;
+    if !(200..300).contains(&status) {
+        return Err(format!("the weather source answered {}: {}", status, String::from_utf8_lossy(&body).trim()));
+    }
+    let body = String::from_utf8(body).map_err(|e| e.to_string())?;
+    let value = json::parse(&body).map_err(|e| e.to_string())?;
+    let hpa = config
+        .field
+        .split('.')
+        .try_fold(&value, |v, key| v.get(key))
+        .and_then(json::Value::as_f64)
+        .ok_or_else(|| format!("the response has no number at {}", config.field))?;
+    // anything outside of it is a wrong field or unit
+    if !(870.0..=1090.0).contains(&hpa) {
+        return Err(format!("{} hPa is no sea-level pressure", hpa));
+    }
+    return Ok(hpa * 100.0);
+}
+
+/// fetch the pressure from a thread, the pressures (Pa) at the altitude are sent to the returned receiver
+pub(crate) fn spawn(config: Config) -> io::Result<Receiver<f32>> {
+    log::info!("fetch the pressure from {}:{}{} every {:?}", config.url.host, config.url.port, config.url.path, config.interval);
+    let gauge = metrics::gauge!("weather_pressure_pa");
+    let failures = metrics::counter!("weather_fetch_failures_total");
+    let (tx, rx) = mpsc::channel();
+    thread::Builder::new().name(String::from("weather")).spawn(move || {
+        while !shutdown::requested() {
+            let wait = match fetch(&config) {
+                Ok(sea_level) => {
+                    let p = at_altitude(sea_level, config.altitude);
+                    log::debug!("sea-level pressure {} Pa, {} Pa at {} m", sea_level, p, config.altitude);
+                    gauge.set(p);
+                    if tx.send(p as f32).is_err() {
+                        return;
+                    }
+                    config.interval
+                }
+                Err(e) => {
+                    log::warn!("failed to fetch the pressure: {}", e);
+                    failures.increment(1);
+                    RETRY.min(config.interval)
+                }
+            };
+            // a long interval mustn't hold up shutting down
+            let until = Instant::now() + wait;
+            while !shutdown::requested() && Instant::now() < until {
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    })?;
+    return Ok(rx);
+}